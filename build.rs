@@ -9,6 +9,7 @@ use walkdir::WalkDir;
 
 const CRATE: &str = env!("CARGO_MANIFEST_DIR");
 const TEST_BINS_IN: &str = "tests/bin";
+const TEST_CRATES_IN: &str = "tests/crates";
 
 fn find_files_with_extensions<'a>(
     exts: &'a [&'a str],
@@ -28,7 +29,7 @@ fn find_files_with_extensions<'a>(
 }
 
 fn rerun_src(path: impl AsRef<Path>) {
-    for entry in find_files_with_extensions(&["rs", "s", "S"], &path) {
+    for entry in find_files_with_extensions(&["rs", "s", "S", "c", "lds"], &path) {
         if let Some(path) = entry.to_str() {
             println!("cargo:rerun-if-changed={}", path)
         }
@@ -107,6 +108,63 @@ fn build_cc_tests(in_path: &Path, out_path: &Path) {
     }
 }
 
+fn build_cargo_tests(in_path: &Path, out_path: &Path, target_dir: &Path, profile: &[&str]) {
+    let filtered_env: HashMap<String, String> = std::env::vars()
+        .filter(|&(ref k, _)| {
+            k == "TERM" || k == "TZ" || k == "LANG" || k == "PATH" || k == "RUSTUP_HOME"
+        })
+        .collect();
+
+    let target_name = "x86_64-unknown-linux-musl";
+
+    for entry in std::fs::read_dir(in_path).unwrap() {
+        let path = entry.unwrap().path();
+        let name = path.file_name().unwrap().to_owned();
+
+        println!("cargo:rerun-if-changed={}/Cargo.toml", path.display());
+        println!("cargo:rerun-if-changed={}/Cargo.lock", path.display());
+        rerun_src(&path);
+
+        let stdout: Stdio = OpenOptions::new()
+            .write(true)
+            .open("/dev/tty")
+            .map(Stdio::from)
+            .unwrap_or_else(|_| Stdio::inherit());
+
+        let stderr: Stdio = OpenOptions::new()
+            .write(true)
+            .open("/dev/tty")
+            .map(Stdio::from)
+            .unwrap_or_else(|_| Stdio::inherit());
+
+        let status = Command::new("cargo")
+            .current_dir(&path)
+            .env_clear()
+            .envs(&filtered_env)
+            .stdout(stdout)
+            .stderr(stderr)
+            .arg("+nightly")
+            .arg("build")
+            .args(profile)
+            .arg("--target-dir")
+            .arg(target_dir)
+            .arg("--target")
+            .arg(target_name)
+            .status()
+            .unwrap_or_else(|_| panic!("failed to build {:#?}", &path));
+
+        assert!(status.success(), "Failed to build {:?}", &path);
+
+        let bin = target_dir
+            .join(target_name)
+            .join(&std::env::var("PROFILE").unwrap())
+            .join(&name);
+
+        std::fs::copy(&bin, out_path.join(&name))
+            .unwrap_or_else(|_| panic!("failed to copy {:#?}", &bin));
+    }
+}
+
 fn create(path: &Path) {
     match std::fs::create_dir(&path) {
         Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
@@ -148,6 +206,13 @@ fn main() {
         _ => &[],
     };
 
+    build_cargo_tests(
+        &Path::new(CRATE).join(TEST_CRATES_IN),
+        &out_dir_bin,
+        &out_dir.join(TEST_CRATES_IN),
+        profile,
+    );
+
     let target_name = "x86_64-unknown-linux-musl";
 
     let filtered_env: HashMap<String, String> = std::env::vars()
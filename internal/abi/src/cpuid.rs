@@ -0,0 +1,171 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Verification of the `cpuid` answers of the host to an SGX enclave
+//!
+//! The host executes `cpuid` for the enclave, so it can forge the answers.
+//! The answers are checked against what is known in the enclave: it runs
+//! on a 64-bit CPU with SGX, and its XFRM fixes the extended state, which
+//! the payload can use. The loader chooses the XFRM, but the CPU reports
+//! it to the enclave. Features of extended state outside of the XFRM are
+//! hidden, because they fault in the enclave, even if the CPU of the host
+//! has them. The SGX bit of leaf 7 is not checked, because a CPUID policy
+//! of the loader may hide it.
+//!
+//! shim-sgx terminates the enclave on answers contradicting this. The
+//! loader refuses a CPUID policy with such answers before it starts the
+//! enclave.
+
+use core::ops::RangeInclusive;
+
+use signals::xsave;
+
+/// The highest basic leaf, which is plausible
+const MAX_BASIC_LEAF: u64 = 0xff;
+
+/// The extended leaves, which are plausible as the highest one
+const MAX_EXTENDED_LEAF: RangeInclusive<u64> = 0x8000_0001..=0x8000_00ff;
+
+/// The leaf of the SGX capabilities
+const LEAF_SGX: u64 = 0x12;
+
+/// The leaf of the extended state
+const LEAF_XSTATE: u64 = 0xd;
+
+/// The leaves and subleaves, whose answers can contradict what the enclave knows
+pub const VERIFIED: &[(u64, u64)] = &[
+    (0x0, 0),
+    (0x1, 0),
+    (LEAF_XSTATE, 2),
+    (LEAF_XSTATE, 3),
+    (LEAF_XSTATE, 4),
+    (LEAF_XSTATE, 5),
+    (LEAF_XSTATE, 6),
+    (LEAF_XSTATE, 7),
+    (LEAF_XSTATE, 8),
+    (LEAF_XSTATE, 9),
+    (LEAF_SGX, 0),
+    (0x8000_0000, 0),
+    (0x8000_0001, 0),
+];
+
+/// Leaf 1 `edx` of every 64-bit CPU: FPU, TSC, CX8, CMOV, FXSR, SSE and SSE2
+const LEAF1_EDX: u64 = 0x0700_8111;
+
+/// Leaf 1 `ecx` of every CPU with SGX: XSAVE
+const LEAF1_ECX: u64 = 1 << 26;
+
+/// Leaf 1 `ecx` features needing AVX state: FMA, AVX and F16C
+const LEAF1_ECX_AVX: u64 = 0x3000_1000;
+
+/// Leaf 7 `ebx` features needing AVX state: AVX2
+const LEAF7_EBX_AVX: u64 = 1 << 5;
+
+/// Leaf 7 `ebx` features needing AVX-512 state: F, DQ, IFMA, PF, ER, CD, BW and VL
+const LEAF7_EBX_AVX512: u64 = 0xdc23_0000;
+
+/// Leaf 7 `ecx` features needing AVX-512 state: VBMI, VBMI2, VNNI, BITALG and VPOPCNTDQ
+const LEAF7_ECX_AVX512: u64 = 0x0000_5842;
+
+/// Leaf 7 `ecx` features needing AVX state: VAES and VPCLMULQDQ
+const LEAF7_ECX_AVX: u64 = 0x0000_0600;
+
+/// Leaf 7 `edx` features needing AVX-512 state: 4VNNIW, 4FMAPS, VP2INTERSECT and FP16
+const LEAF7_EDX_AVX512: u64 = 0x0080_010c;
+
+/// Leaf 7 `ebx` features needing MPX state: MPX
+const LEAF7_EBX_MPX: u64 = 1 << 14;
+
+/// Leaf 7 `ecx` features needing PKRU state: PKU and OSPKE
+const LEAF7_ECX_PKRU: u64 = 0x0000_0018;
+
+/// Leaf 0x80000001 `edx` of every 64-bit CPU: long mode
+const EXT1_EDX: u64 = 1 << 29;
+
+/// Leaf 0x12 `eax` of every CPU with SGX: SGX1
+const SGX_EAX: u64 = 1 << 0;
+
+/// The XFRM bits of the AVX state
+const XFRM_AVX: u64 = 1 << 2;
+
+/// The XFRM bits of the MPX state
+const XFRM_MPX: u64 = 0x18;
+
+/// The XFRM bits of the AVX-512 state
+const XFRM_AVX512: u64 = 0xe0;
+
+/// The XFRM bits of the PKRU state
+const XFRM_PKRU: u64 = 1 << 9;
+
+/// Clear the `features` in `reg`, unless `xfrm` has all `state`
+fn hide(reg: &mut u64, features: u64, xfrm: u64, state: u64) {
+    if xfrm & state != state {
+        *reg &= !features;
+    }
+}
+
+/// Verify the `cpuid` answer `regs` of the host for `leaf` and `subleaf` to an enclave with `xfrm`
+///
+/// Returns the answer for the payload, or `None` if the answer contradicts
+/// what the enclave knows.
+pub fn verify(leaf: u64, subleaf: u64, regs: [u64; 4], xfrm: u64) -> Option<[u64; 4]> {
+    let [mut eax, mut ebx, mut ecx, mut edx] = regs;
+
+    // `cpuid` only answers with 32-bit registers.
+    if regs.iter().any(|reg| reg >> 32 != 0) {
+        return None;
+    }
+
+    let consistent = match leaf {
+        0x0 => (LEAF_SGX..=MAX_BASIC_LEAF).contains(&eax),
+
+        0x1 => {
+            hide(&mut ecx, LEAF1_ECX_AVX, xfrm, XFRM_AVX);
+            edx & LEAF1_EDX == LEAF1_EDX && ecx & LEAF1_ECX == LEAF1_ECX
+        }
+
+        0x7 if subleaf == 0 => {
+            hide(&mut ebx, LEAF7_EBX_AVX, xfrm, XFRM_AVX);
+            hide(&mut ebx, LEAF7_EBX_AVX512, xfrm, XFRM_AVX512);
+            hide(&mut ebx, LEAF7_EBX_MPX, xfrm, XFRM_MPX);
+            hide(&mut ecx, LEAF7_ECX_AVX, xfrm, XFRM_AVX);
+            hide(&mut ecx, LEAF7_ECX_AVX512, xfrm, XFRM_AVX512);
+            hide(&mut ecx, LEAF7_ECX_PKRU, xfrm, XFRM_PKRU);
+            hide(&mut edx, LEAF7_EDX_AVX512, xfrm, XFRM_AVX512);
+            true
+        }
+
+        // The state of the enclave is the XFRM, whatever the host has.
+        LEAF_XSTATE if subleaf == 0 => {
+            eax &= xfrm & 0xffff_ffff;
+            edx &= xfrm >> 32;
+            ebx = xsave::size(xfrm);
+            ecx = xsave::size(xfrm);
+            true
+        }
+
+        LEAF_XSTATE if subleaf >= 2 => match xsave::COMPONENTS.get(subleaf as usize) {
+            Some((size, offset)) if xfrm & (1 << subleaf) != 0 => eax == *size && ebx == *offset,
+
+            _ => {
+                eax = 0;
+                ebx = 0;
+                ecx = 0;
+                edx = 0;
+                true
+            }
+        },
+
+        LEAF_SGX if subleaf == 0 => eax & SGX_EAX == SGX_EAX,
+
+        0x8000_0000 => MAX_EXTENDED_LEAF.contains(&eax),
+
+        0x8000_0001 => edx & EXT1_EDX == EXT1_EDX,
+
+        _ => true,
+    };
+
+    match consistent {
+        true => Some([eax, ebx, ecx, edx]),
+        false => None,
+    }
+}
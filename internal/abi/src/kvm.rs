@@ -0,0 +1,21 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The interface between the KVM backend and the SEV shim, besides the sallyport block
+
+/// The port, relative to `sallyport::KVM_SYSCALL_TRIGGER_PORT`, which blocks
+/// the vCPU until the host has serviced a block of the hostcall ring
+pub const RING_DOORBELL_PORT_OFFSET: u16 = 2;
+
+/// The port, relative to `sallyport::KVM_SYSCALL_TRIGGER_PORT`, which the
+/// shim writes to fetch a pending host signal
+///
+/// The host passes the signal in `rdi` on the way back, like it does for
+/// the other ports.
+pub const SIGNAL_PORT_OFFSET: u16 = 4;
+
+/// The interrupt vector, which the host injects into the running payload,
+/// if a host signal is pending
+///
+/// The host only injects it, when the payload can take interrupts, so a
+/// payload, which never makes a hostcall, still gets the signal.
+pub const SIGNAL_VECTOR: u8 = 0x20;
@@ -0,0 +1,15 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The interface between the loader and the shims, which `sallyport` does not define
+//!
+//! Both shims and the loader use this crate, so the ELF notes the loader
+//! patches and the hostcalls it serves are defined once.
+
+#![no_std]
+#![deny(clippy::all)]
+#![deny(missing_docs)]
+
+pub mod cpuid;
+pub mod kvm;
+pub mod note;
+pub mod syscall;
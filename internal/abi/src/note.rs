@@ -0,0 +1,78 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The ELF notes of the shims, which the loader reads and patches
+//!
+//! The shims declare the notes with a zero descriptor in their `.note`
+//! section. The loader patches the descriptors before it loads the shim,
+//! so the notes are part of the measurement of the keep.
+
+use core::mem::size_of;
+use core::ptr::read_volatile;
+
+/// The ELF note name of the notes, padded to 4 bytes
+pub const NAME: [u8; 8] = *b"enarx\0\0\0";
+
+/// The ELF note type of the number of usable sallyport blocks
+pub const SALLYPORT_BLOCKS: u32 = 1;
+
+/// The ELF note type of the hostcall ring switch
+pub const SALLYPORT_RING: u32 = 2;
+
+/// The ELF note type of the debug output switch
+pub const DEBUG: u32 = 3;
+
+/// The ELF note type of the prefix of encrypted files
+pub const ENCRYPT: u32 = 4;
+
+/// The ELF note type of the port to terminate TLS on
+pub const TLS: u32 = 5;
+
+/// The flag of the `TLS` descriptor to refuse TLS without attestation evidence
+pub const TLS_ATTEST: u32 = 1 << 16;
+
+/// The ELF note type of the strict time switch
+pub const TIME: u32 = 6;
+
+/// The ELF note type of the EDMM switch
+pub const EDMM: u32 = 7;
+
+/// The ELF note type of the size of the heap in pages
+pub const HEAP: u32 = 8;
+
+/// The ELF note type of the size of the data in bytes
+pub const DATA: u32 = 9;
+
+/// The maximum length of a path descriptor, including the NUL
+pub const PATH_MAX: usize = 64;
+
+/// An ELF note with the name `enarx` and a descriptor, which the loader might patch
+#[repr(C, align(4))]
+pub struct Note<T> {
+    namesz: u32,
+    descsz: u32,
+    kind: u32,
+    name: [u8; 8],
+    desc: T,
+}
+
+impl<T> Note<T> {
+    /// Create a note of type `kind` with the descriptor `desc`
+    pub const fn new(kind: u32, desc: T) -> Self {
+        Self {
+            namesz: 6,
+            descsz: size_of::<T>() as _,
+            kind,
+            name: NAME,
+            desc,
+        }
+    }
+}
+
+impl<T: Copy> Note<T> {
+    /// Read the descriptor
+    ///
+    /// The loader might have patched the descriptor, so it has to be read volatile.
+    pub fn desc(&self) -> T {
+        unsafe { read_volatile(&self.desc) }
+    }
+}
@@ -0,0 +1,18 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The Enarx syscall numbers, which `sallyport` does not define
+//!
+//! The numbers start at 0xEA10, above the ones of `sallyport`, so they never
+//! collide with a syscall the host serves. New numbers are only added here.
+
+/// Get a seal key of the enclave
+///
+/// Handled in the SGX shim. Arguments: the key policy, a pointer to a
+/// 32 byte key ID or NULL, and the buffer for the 16 byte key with its length.
+pub const SYS_ENARX_GETKEY: i64 = 0xEA10;
+
+/// Derive the key from MRENCLAVE, so only the same enclave gets it
+pub const KEYPOLICY_MRENCLAVE: u16 = 1 << 0;
+
+/// Derive the key from MRSIGNER, so all enclaves of the signer get it
+pub const KEYPOLICY_MRSIGNER: u16 = 1 << 1;
@@ -0,0 +1,120 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use abi::cpuid::{verify, VERIFIED};
+
+/// The XFRM of an enclave with x87, SSE and AVX state
+const XFRM_AVX: u64 = 0x7;
+
+/// The XFRM of an enclave with x87, SSE, AVX and AVX-512 state
+const XFRM_AVX512: u64 = 0xe7;
+
+/// The answers of a CPU with SGX, AVX and AVX-512
+fn answer(leaf: u64, subleaf: u64) -> [u64; 4] {
+    match (leaf, subleaf) {
+        (0x0, _) => [0x1b, 0x756e_6547, 0x6c65_746e, 0x4965_6e69],
+        (0x1, _) => [0x0005_0654, 0x0010_0800, 0x7ffe_fbff, 0xbfeb_fbff],
+        (0x7, 0) => [0, 0xd39f_47bf, 0x0000_0818, 0xbc00_0400],
+        (0xd, 0) => [0x2e7, 0xa88, 0xa88, 0],
+        (0xd, 2) => [256, 576, 0, 0],
+        (0xd, 3) => [64, 960, 0, 0],
+        (0xd, 4) => [64, 1024, 0, 0],
+        (0xd, 5) => [64, 1088, 0, 0],
+        (0xd, 6) => [512, 1152, 0, 0],
+        (0xd, 7) => [1024, 1664, 0, 0],
+        (0xd, 9) => [8, 2688, 0, 0],
+        (0x12, 0) => [0x3, 0, 0, 0x241f],
+        (0x8000_0000, _) => [0x8000_0008, 0, 0, 0],
+        (0x8000_0001, _) => [0, 0, 0x121, 0x2c10_0800],
+        _ => [0; 4],
+    }
+}
+
+/// Whether all verified answers of `answer` are consistent for an enclave with `xfrm`
+fn consistent(xfrm: u64, answer: impl Fn(u64, u64) -> [u64; 4]) -> bool {
+    VERIFIED
+        .iter()
+        .all(|&(leaf, subleaf)| verify(leaf, subleaf, answer(leaf, subleaf), xfrm).is_some())
+}
+
+#[test]
+fn host() {
+    assert!(consistent(XFRM_AVX512, answer));
+    assert!(consistent(XFRM_AVX, answer));
+    assert!(consistent(0x3, answer));
+}
+
+#[test]
+fn wide_registers() {
+    let mut regs = answer(0x6, 0);
+    regs[2] = 1 << 32;
+    assert_eq!(verify(0x6, 0, regs, XFRM_AVX), None);
+}
+
+#[test]
+fn max_leaf() {
+    for max in [0xd, 0x11, 0x100] {
+        let mut regs = answer(0x0, 0);
+        regs[0] = max;
+        assert_eq!(verify(0x0, 0, regs, XFRM_AVX), None, "{:#x}", max);
+    }
+
+    let mut regs = answer(0x8000_0000, 0);
+    regs[0] = 0x8000_0000;
+    assert_eq!(verify(0x8000_0000, 0, regs, XFRM_AVX), None);
+}
+
+#[test]
+fn required_features() {
+    // XSAVE
+    let mut regs = answer(0x1, 0);
+    regs[2] &= !(1 << 26);
+    assert_eq!(verify(0x1, 0, regs, XFRM_AVX), None);
+
+    // SSE2
+    let mut regs = answer(0x1, 0);
+    regs[3] &= !(1 << 26);
+    assert_eq!(verify(0x1, 0, regs, XFRM_AVX), None);
+
+    // SGX1
+    assert_eq!(verify(0x12, 0, [0; 4], XFRM_AVX), None);
+
+    // Long mode
+    assert_eq!(verify(0x8000_0001, 0, [0; 4], XFRM_AVX), None);
+}
+
+#[test]
+fn hidden_features() {
+    // AVX without AVX state
+    let [_, _, ecx, _] = verify(0x1, 0, answer(0x1, 0), 0x3).unwrap();
+    assert_eq!(ecx & 1 << 28, 0);
+
+    // AVX-512F without AVX-512 state, AVX2 with AVX state
+    let [_, ebx, _, _] = verify(0x7, 0, answer(0x7, 0), XFRM_AVX).unwrap();
+    assert_eq!(ebx & 1 << 16, 0);
+    assert_ne!(ebx & 1 << 5, 0);
+
+    let [_, ebx, _, _] = verify(0x7, 0, answer(0x7, 0), XFRM_AVX512).unwrap();
+    assert_ne!(ebx & 1 << 16, 0);
+}
+
+#[test]
+fn extended_state() {
+    // The enclave has the state of the XFRM, not the one of the host.
+    assert_eq!(
+        verify(0xd, 0, answer(0xd, 0), XFRM_AVX),
+        Some([0x7, 832, 832, 0])
+    );
+
+    // Components outside of the XFRM read as zero.
+    assert_eq!(verify(0xd, 5, answer(0xd, 5), XFRM_AVX), Some([0; 4]));
+
+    // Components of the XFRM have their architectural size and offset.
+    assert_eq!(verify(0xd, 2, [0; 4], XFRM_AVX), None);
+    assert_eq!(verify(0xd, 2, [256, 512, 0, 0], XFRM_AVX), None);
+    assert!(!consistent(XFRM_AVX, |leaf, subleaf| {
+        match (leaf, subleaf) {
+            (0xd, 2) => [0; 4],
+            _ => answer(leaf, subleaf),
+        }
+    }));
+}
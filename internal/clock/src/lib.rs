@@ -0,0 +1,272 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The clocks of the payload, which are served by the shims
+//!
+//! The monotonic clocks count the TSC, which is calibrated once against
+//! the monotonic clock of the host. Without a usable TSC, they follow the
+//! monotonic clock of the host, but they never go backwards. The host only
+//! provides hints for the wall clock, which are taken every second.
+//!
+//! In `Mode::Permissive`, the wall clock follows the hints, even backwards.
+//! In `Mode::Strict`, the hints can only move it forwards.
+//!
+//! The shims keep the clocks in `Clock` and read the TSC and the host
+//! clocks through `Host`.
+
+#![no_std]
+#![deny(clippy::all)]
+#![deny(missing_docs)]
+
+use core::convert::TryFrom;
+use core::ops::RangeInclusive;
+
+/// Nanoseconds per second
+pub const NSEC_PER_SEC: u64 = 1_000_000_000;
+
+/// The host time the TSC is calibrated against
+const CALIBRATION_NS: u64 = 10_000_000;
+
+/// The interval of the host hints for the wall clock
+const SYNC_NS: u64 = NSEC_PER_SEC;
+
+/// The plausible TSC frequencies, the TSC is not used otherwise
+const TSC_HZ: RangeInclusive<u64> = 100_000_000..=10_000_000_000;
+
+/// The TSC and the clocks of the host
+pub trait Host {
+    /// Whether the TSC can be read, asked once before the calibration
+    fn has_tsc(&mut self) -> bool;
+
+    /// Read the TSC
+    fn tsc(&mut self) -> u64;
+
+    /// Read the clock `clockid` of the host in nanoseconds
+    fn now(&mut self, clockid: libc::clockid_t) -> Result<u64, libc::c_int>;
+
+    /// Sleep on the host for `ns` nanoseconds
+    fn sleep(&mut self, ns: u64) -> Result<(), libc::c_int>;
+
+    /// Publish the calibration after each host hint, like to a vDSO
+    fn publish(&mut self, _calibration: &Calibration) {}
+}
+
+/// How the host hints move the wall clock
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Mode {
+    /// The wall clock follows the hints, even backwards
+    Permissive,
+
+    /// The hints can only move the wall clock forwards
+    Strict,
+}
+
+/// The calibrated TSC and the last host hint for the wall clock
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub struct Calibration {
+    /// The TSC at `base_ns`
+    pub tsc_base: u64,
+
+    /// The monotonic time at `tsc_base`
+    pub base_ns: u64,
+
+    /// Nanoseconds per tick in 32.32 fixed point
+    pub mult: u64,
+
+    /// The monotonic time of the last host hint for the wall clock
+    pub wall_mono: u64,
+
+    /// The wall clock time of the last host hint
+    pub wall_real: u64,
+
+    /// The monotonic time of the next host hint
+    pub next_sync: u64,
+}
+
+/// The nanoseconds per tick in 32.32 fixed point, if the frequency is plausible
+fn mult(ticks: u64, ns: u64) -> Option<u64> {
+    if ns == 0 {
+        return None;
+    }
+
+    let hz = u128::from(ticks) * u128::from(NSEC_PER_SEC) / u128::from(ns);
+    let hz = u64::try_from(hz).ok().filter(|hz| TSC_HZ.contains(hz))?;
+    u64::try_from((u128::from(NSEC_PER_SEC) << 32) / u128::from(hz)).ok()
+}
+
+/// The TSC scaled to nanoseconds
+#[derive(Copy, Clone)]
+struct Tsc {
+    base: u64,
+    base_ns: u64,
+
+    /// Nanoseconds per tick in 32.32 fixed point
+    mult: u64,
+}
+
+impl Tsc {
+    /// Calibrate the TSC against the monotonic clock of the host
+    fn calibrate(host: &mut impl Host) -> Result<Option<Self>, libc::c_int> {
+        let start = host.tsc();
+        let start_ns = host.now(libc::CLOCK_MONOTONIC)?;
+
+        // The TSC is calibrated over the elapsed host time, not the requested one.
+        let _ = host.sleep(CALIBRATION_NS);
+
+        let end = host.tsc();
+        let end_ns = host.now(libc::CLOCK_MONOTONIC)?;
+
+        let mult = mult(end.saturating_sub(start), end_ns.saturating_sub(start_ns));
+
+        Ok(mult.map(|mult| Self {
+            base: end,
+            base_ns: end_ns,
+            mult,
+        }))
+    }
+
+    fn now(&self, tsc: u64) -> u64 {
+        let ticks = u128::from(tsc.wrapping_sub(self.base));
+        let ns = u64::try_from((ticks * u128::from(self.mult)) >> 32).unwrap_or(u64::MAX);
+        self.base_ns.saturating_add(ns)
+    }
+}
+
+/// The clocks of the keep
+pub struct Clock {
+    calibrated: bool,
+
+    /// The TSC, if it is usable and could be calibrated
+    tsc: Option<Tsc>,
+
+    /// The last monotonic time
+    last: u64,
+
+    /// The monotonic and the wall clock time of the last host hint
+    wall: (u64, u64),
+
+    /// The monotonic time of the next host hint
+    next_sync: u64,
+}
+
+impl Default for Clock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock {
+    /// The clocks before the calibration, which happens on the first read
+    pub const fn new() -> Self {
+        Self {
+            calibrated: false,
+            tsc: None,
+            last: 0,
+            wall: (0, 0),
+            next_sync: 0,
+        }
+    }
+
+    /// Read the clock `clockid` in nanoseconds
+    ///
+    /// Returns `None` for the clocks, which are not served by the shims.
+    pub fn now(
+        &mut self,
+        host: &mut impl Host,
+        mode: Mode,
+        clockid: libc::clockid_t,
+    ) -> Option<Result<u64, libc::c_int>> {
+        match clockid {
+            libc::CLOCK_MONOTONIC
+            | libc::CLOCK_MONOTONIC_RAW
+            | libc::CLOCK_MONOTONIC_COARSE
+            | libc::CLOCK_BOOTTIME => Some(self.monotonic(host, mode)),
+
+            libc::CLOCK_REALTIME | libc::CLOCK_REALTIME_COARSE => Some(
+                self.monotonic(host, mode)
+                    .map(|monotonic| self.realtime_at(monotonic)),
+            ),
+
+            _ => None,
+        }
+    }
+
+    fn calibrate(&mut self, host: &mut impl Host, mode: Mode) -> Result<(), libc::c_int> {
+        self.tsc = match host.has_tsc() {
+            true => Tsc::calibrate(host)?,
+            false => None,
+        };
+
+        self.last = match self.tsc {
+            Some(tsc) => tsc.base_ns,
+            None => host.now(libc::CLOCK_MONOTONIC)?,
+        };
+
+        self.sync(host, mode)?;
+        self.calibrated = true;
+        Ok(())
+    }
+
+    /// Take a host hint for the wall clock
+    fn sync(&mut self, host: &mut impl Host, mode: Mode) -> Result<(), libc::c_int> {
+        let mut real = host.now(libc::CLOCK_REALTIME)?;
+        if self.calibrated && mode == Mode::Strict {
+            real = real.max(self.realtime_at(self.last));
+        }
+
+        self.wall = (self.last, real);
+        self.next_sync = self.last.saturating_add(SYNC_NS);
+
+        if let Some(tsc) = self.tsc {
+            host.publish(&Calibration {
+                tsc_base: tsc.base,
+                base_ns: tsc.base_ns,
+                mult: tsc.mult,
+                wall_mono: self.wall.0,
+                wall_real: self.wall.1,
+                next_sync: self.next_sync,
+            });
+        }
+
+        Ok(())
+    }
+
+    fn monotonic(&mut self, host: &mut impl Host, mode: Mode) -> Result<u64, libc::c_int> {
+        if !self.calibrated {
+            self.calibrate(host, mode)?;
+        }
+
+        // Without a TSC, the host clock is only prevented from going backwards.
+        let now = match self.tsc {
+            Some(tsc) => tsc.now(host.tsc()),
+            None => host.now(libc::CLOCK_MONOTONIC)?,
+        };
+
+        self.last = self.last.max(now);
+        if self.last >= self.next_sync {
+            self.sync(host, mode)?;
+        }
+
+        Ok(self.last)
+    }
+
+    fn realtime_at(&self, monotonic: u64) -> u64 {
+        let (base, real) = self.wall;
+        real.saturating_add(monotonic.saturating_sub(base))
+    }
+}
+
+/// Convert nanoseconds to a `timespec`
+pub fn timespec(ns: u64) -> libc::timespec {
+    libc::timespec {
+        tv_sec: (ns / NSEC_PER_SEC) as _,
+        tv_nsec: (ns % NSEC_PER_SEC) as _,
+    }
+}
+
+/// Convert nanoseconds to a `timeval`
+pub fn timeval(ns: u64) -> libc::timeval {
+    libc::timeval {
+        tv_sec: (ns / NSEC_PER_SEC) as _,
+        tv_usec: (ns % NSEC_PER_SEC / 1000) as _,
+    }
+}
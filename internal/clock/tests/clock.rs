@@ -0,0 +1,161 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use clock::{timespec, timeval, Calibration, Clock, Host, Mode, NSEC_PER_SEC};
+
+/// A host with a TSC running at `hz`, whose clocks only move when told to
+struct Fake {
+    has_tsc: bool,
+    hz: u64,
+    tsc: u64,
+    monotonic: u64,
+    realtime: u64,
+    calls: usize,
+    published: Option<Calibration>,
+}
+
+impl Fake {
+    fn new(has_tsc: bool, hz: u64) -> Self {
+        Self {
+            has_tsc,
+            hz,
+            tsc: 1000,
+            monotonic: 5 * NSEC_PER_SEC,
+            realtime: 1_600_000_000 * NSEC_PER_SEC,
+            calls: 0,
+            published: None,
+        }
+    }
+
+    /// Let `ns` nanoseconds pass on the host
+    fn pass(&mut self, ns: u64) {
+        self.tsc += (u128::from(ns) * u128::from(self.hz) / u128::from(NSEC_PER_SEC)) as u64;
+        self.monotonic += ns;
+        self.realtime += ns;
+    }
+}
+
+impl Host for Fake {
+    fn has_tsc(&mut self) -> bool {
+        self.has_tsc
+    }
+
+    fn tsc(&mut self) -> u64 {
+        self.tsc
+    }
+
+    fn now(&mut self, clockid: libc::clockid_t) -> Result<u64, libc::c_int> {
+        self.calls += 1;
+
+        match clockid {
+            libc::CLOCK_MONOTONIC => Ok(self.monotonic),
+            libc::CLOCK_REALTIME => Ok(self.realtime),
+            _ => Err(libc::EINVAL),
+        }
+    }
+
+    fn sleep(&mut self, ns: u64) -> Result<(), libc::c_int> {
+        self.pass(ns);
+        Ok(())
+    }
+
+    fn publish(&mut self, calibration: &Calibration) {
+        self.published = Some(*calibration);
+    }
+}
+
+fn now(clock: &mut Clock, host: &mut Fake, mode: Mode, clockid: libc::clockid_t) -> u64 {
+    clock.now(host, mode, clockid).unwrap().unwrap()
+}
+
+fn monotonic(clock: &mut Clock, host: &mut Fake) -> u64 {
+    now(clock, host, Mode::Permissive, libc::CLOCK_MONOTONIC)
+}
+
+#[test]
+fn tsc() {
+    let mut host = Fake::new(true, 2_000_000_000);
+    let mut clock = Clock::new();
+
+    let start = monotonic(&mut clock, &mut host);
+    assert_eq!(start, host.monotonic);
+    assert!(host.published.is_some());
+
+    // Reading the TSC doesn't ask the host.
+    let calls = host.calls;
+    host.pass(NSEC_PER_SEC / 2);
+    let later = monotonic(&mut clock, &mut host);
+    assert_eq!(host.calls, calls);
+    assert!((later - start).abs_diff(NSEC_PER_SEC / 2) < 1000);
+
+    // The wall clock is synchronized every second.
+    host.pass(NSEC_PER_SEC);
+    monotonic(&mut clock, &mut host);
+    assert_eq!(host.calls, calls + 1);
+}
+
+#[test]
+fn implausible_tsc() {
+    let mut host = Fake::new(true, 1000);
+    let mut clock = Clock::new();
+
+    monotonic(&mut clock, &mut host);
+    assert!(host.published.is_none());
+
+    let calls = host.calls;
+    monotonic(&mut clock, &mut host);
+    assert_eq!(host.calls, calls + 1);
+}
+
+#[test]
+fn monotonic_without_tsc() {
+    let mut host = Fake::new(false, 0);
+    let mut clock = Clock::new();
+
+    let start = monotonic(&mut clock, &mut host);
+    assert_eq!(start, host.monotonic);
+
+    // The host can't move the monotonic clocks backwards.
+    host.monotonic -= NSEC_PER_SEC / 2;
+    let later = monotonic(&mut clock, &mut host);
+    assert_eq!(later, start);
+}
+
+#[test]
+fn realtime() {
+    for (mode, backwards) in [(Mode::Permissive, true), (Mode::Strict, false)] {
+        let mut host = Fake::new(false, 0);
+        let mut clock = Clock::new();
+
+        let start = now(&mut clock, &mut host, mode, libc::CLOCK_REALTIME);
+        assert_eq!(start, host.realtime);
+
+        // The next host hint is an hour in the past.
+        host.pass(2 * NSEC_PER_SEC);
+        host.realtime -= 3600 * NSEC_PER_SEC;
+        now(&mut clock, &mut host, mode, libc::CLOCK_MONOTONIC);
+
+        let later = now(&mut clock, &mut host, mode, libc::CLOCK_REALTIME);
+        assert_eq!(later < start, backwards);
+    }
+}
+
+#[test]
+fn unserved() {
+    let mut host = Fake::new(false, 0);
+    let mut clock = Clock::new();
+
+    let clockid = libc::CLOCK_PROCESS_CPUTIME_ID;
+    assert!(clock.now(&mut host, Mode::Strict, clockid).is_none());
+    assert_eq!(host.calls, 0);
+}
+
+#[test]
+fn conversions() {
+    let ns = 3 * NSEC_PER_SEC + 123_456_789;
+
+    let ts = timespec(ns);
+    assert_eq!((ts.tv_sec, ts.tv_nsec), (3, 123_456_789));
+
+    let tv = timeval(ns);
+    assert_eq!((tv.tv_sec, tv.tv_usec), (3, 123_456));
+}
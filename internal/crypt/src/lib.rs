@@ -0,0 +1,587 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transparent encryption of files below a configured prefix, shared by the shims
+//!
+//! The loader sets the prefix with `exec --encrypt`. The files opened below
+//! it are stored on the host as a sequence of blocks, each encrypted and
+//! authenticated with AES-256-GCM:
+//!
+//! ```text
+//! | nonce (12) | ciphertext (up to 1024) | tag (16) | nonce (12) | ...
+//! ```
+//!
+//! Only the last block may be shorter. The associated data of a block is the
+//! SHA-256 hash of the normalized path, the block index and whether it is the
+//! last block, so the host can't move blocks within or between files, and it
+//! can't cut a file after a block, which never was the last one.
+//!
+//! The blocks are not bound to each other or to a version of the file,
+//! though. The host can replace any block with an older version of the same
+//! block, so it can roll back single blocks, the whole file, or its end to a
+//! length the file had before. An empty file can't be told apart from a file,
+//! whose blocks were all removed.
+//!
+//! The shims keep the open encrypted files in `Crypt`, derive its key from
+//! their own key source and do the I/O on the host files through `Host`.
+
+#![no_std]
+#![deny(clippy::all)]
+#![deny(missing_docs)]
+
+use aes_gcm::aead::{AeadInPlace, NewAead};
+use aes_gcm::{Aes256Gcm, Key, Nonce, Tag};
+use hkdf::Hkdf;
+use sha2::{Digest, Sha256};
+
+/// The size of the plaintext of a block
+const BLOCK: usize = 1024;
+
+/// The size of the nonce of a block
+const NONCE: usize = 12;
+
+/// The size of the tag of a block
+const TAG: usize = 16;
+
+/// The size of a block on the host
+const STORED: usize = NONCE + BLOCK + TAG;
+
+/// The maximum number of encrypted files open at the same time
+const MAX_FILES: usize = 16;
+
+/// The maximum size of a path
+const PATH_MAX: usize = libc::PATH_MAX as usize;
+
+/// The HKDF info of the file encryption key
+const INFO: &[u8] = b"enarx file encryption";
+
+/// The host file backing an encrypted file
+pub trait Host {
+    /// Read from the host file at `offset`
+    fn pread(&mut self, fd: libc::c_int, buf: &mut [u8], offset: u64)
+        -> Result<usize, libc::c_int>;
+
+    /// Write to the host file at `offset`
+    fn pwrite(&mut self, fd: libc::c_int, buf: &[u8], offset: u64) -> Result<usize, libc::c_int>;
+
+    /// The size of the host file
+    fn size(&mut self, fd: libc::c_int) -> Result<u64, libc::c_int>;
+
+    /// A random number for the nonce of a block
+    fn random(&mut self) -> u64;
+}
+
+/// An open encrypted file
+#[derive(Copy, Clone)]
+struct File {
+    fd: libc::c_int,
+    pos: u64,
+    append: bool,
+    id: [u8; 32],
+}
+
+/// Copy `path` with `.`, `..` and duplicate slashes removed
+///
+/// Relative paths are resolved against `/`, the working directory of the keep.
+fn normalize<'p>(path: &[u8], out: &'p mut [u8; PATH_MAX]) -> &'p [u8] {
+    let mut len = 0;
+
+    for component in path.split(|b| *b == b'/') {
+        match component {
+            b"" | b"." => {}
+            b".." => {
+                while len > 0 && out[len - 1] != b'/' {
+                    len -= 1;
+                }
+                len = len.saturating_sub(1);
+            }
+            _ => {
+                out[len] = b'/';
+                out[len + 1..][..component.len()].copy_from_slice(component);
+                len += 1 + component.len();
+            }
+        }
+    }
+
+    match len {
+        0 => b"/",
+        _ => &out[..len],
+    }
+}
+
+/// Whether the file at `path` relative to `dirfd` is below `prefix`
+///
+/// An empty prefix disables encryption. Paths relative to a directory file
+/// descriptor can't be told apart, so they are rejected, if encryption is
+/// enabled.
+pub fn is_encrypted(prefix: &[u8], dirfd: libc::c_int, path: &[u8]) -> Result<bool, libc::c_int> {
+    if prefix.is_empty() {
+        return Ok(false);
+    }
+
+    if !path.starts_with(b"/") && dirfd != libc::AT_FDCWD {
+        return Err(libc::EACCES);
+    }
+
+    let mut norm = [0u8; PATH_MAX];
+    let path = normalize(path, &mut norm);
+
+    Ok(match path.strip_prefix(prefix) {
+        None => false,
+        Some(rest) => prefix == b"/" || rest.is_empty() || rest[0] == b'/',
+    })
+}
+
+/// The file descriptor arguments of the syscall `nr` with the arguments `args`
+///
+/// The shims refuse the syscalls on encrypted files, which they don't
+/// handle, so the syscalls taking file descriptors must all be listed.
+/// Directory file descriptors count, because `AT_EMPTY_PATH` turns them
+/// into the file itself.
+pub fn fd_args(nr: libc::c_long, args: [usize; 6]) -> [Option<libc::c_int>; 2] {
+    let fd = |i: usize| Some(args[i] as libc::c_int);
+
+    match nr {
+        libc::SYS_read
+        | libc::SYS_write
+        | libc::SYS_close
+        | libc::SYS_fstat
+        | libc::SYS_lseek
+        | libc::SYS_ioctl
+        | libc::SYS_pread64
+        | libc::SYS_pwrite64
+        | libc::SYS_readv
+        | libc::SYS_writev
+        | libc::SYS_preadv
+        | libc::SYS_pwritev
+        | libc::SYS_preadv2
+        | libc::SYS_pwritev2
+        | libc::SYS_dup
+        | libc::SYS_fcntl
+        | libc::SYS_flock
+        | libc::SYS_fsync
+        | libc::SYS_fdatasync
+        | libc::SYS_syncfs
+        | libc::SYS_sync_file_range
+        | libc::SYS_ftruncate
+        | libc::SYS_fallocate
+        | libc::SYS_fadvise64
+        | libc::SYS_readahead
+        | libc::SYS_getdents
+        | libc::SYS_getdents64
+        | libc::SYS_fchdir
+        | libc::SYS_fchmod
+        | libc::SYS_fchown
+        | libc::SYS_fstatfs
+        | libc::SYS_fsetxattr
+        | libc::SYS_fgetxattr
+        | libc::SYS_flistxattr
+        | libc::SYS_fremovexattr
+        | libc::SYS_vmsplice
+        | libc::SYS_newfstatat
+        | libc::SYS_statx
+        | libc::SYS_fchownat
+        | libc::SYS_linkat
+        | libc::SYS_name_to_handle_at => [fd(0), None],
+
+        libc::SYS_dup2 | libc::SYS_dup3 | libc::SYS_sendfile | libc::SYS_tee => [fd(0), fd(1)],
+
+        libc::SYS_splice | libc::SYS_copy_file_range => [fd(0), fd(2)],
+
+        libc::SYS_mmap if args[3] as libc::c_int & libc::MAP_ANONYMOUS == 0 => [fd(4), None],
+
+        _ => [None, None],
+    }
+}
+
+/// The encryption state of the keep
+pub struct Crypt {
+    cipher: Option<Aes256Gcm>,
+    files: [Option<File>; MAX_FILES],
+}
+
+impl Default for Crypt {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Crypt {
+    /// The encryption state without a key and without open files
+    pub const fn new() -> Self {
+        Self {
+            cipher: None,
+            files: [None; MAX_FILES],
+        }
+    }
+
+    /// Whether the file key was derived already
+    pub fn has_key(&self) -> bool {
+        self.cipher.is_some()
+    }
+
+    /// Derive the file key from the key material `ikm` of the keep
+    pub fn set_key(&mut self, ikm: &[u8]) -> Result<(), libc::c_int> {
+        let mut okm = [0u8; 32];
+        Hkdf::<Sha256>::new(None, ikm)
+            .expand(INFO, &mut okm)
+            .or(Err(libc::ENOKEY))?;
+
+        self.cipher = Some(Aes256Gcm::new(Key::from_slice(&okm)));
+        Ok(())
+    }
+
+    /// Track a newly opened encrypted file
+    pub fn open(
+        &mut self,
+        fd: libc::c_int,
+        path: &[u8],
+        flags: libc::c_int,
+    ) -> Result<(), libc::c_int> {
+        if self.cipher.is_none() {
+            return Err(libc::ENOKEY);
+        }
+
+        let mut buf = [0u8; PATH_MAX];
+        let mut id = [0u8; 32];
+        id.copy_from_slice(&Sha256::digest(normalize(path, &mut buf)));
+
+        let slot = self
+            .files
+            .iter_mut()
+            .find(|f| f.is_none())
+            .ok_or(libc::EMFILE)?;
+
+        *slot = Some(File {
+            fd,
+            pos: 0,
+            append: flags & libc::O_APPEND != 0,
+            id,
+        });
+
+        Ok(())
+    }
+
+    /// Stop tracking `fd`, returning whether it was an encrypted file
+    pub fn close(&mut self, fd: libc::c_int) -> bool {
+        match self
+            .files
+            .iter_mut()
+            .find(|f| matches!(f, Some(f) if f.fd == fd))
+        {
+            Some(slot) => {
+                *slot = None;
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Whether `fd` is an encrypted file
+    pub fn is_open(&self, fd: libc::c_int) -> bool {
+        self.file(fd).is_some()
+    }
+
+    /// Get the encrypted file `fd`, if any
+    fn file(&self, fd: libc::c_int) -> Option<File> {
+        self.files.iter().flatten().find(|f| f.fd == fd).copied()
+    }
+
+    /// Update the encrypted file `file.fd`
+    fn update(&mut self, file: File) {
+        if let Some(slot) = self.files.iter_mut().flatten().find(|f| f.fd == file.fd) {
+            *slot = file;
+        }
+    }
+
+    /// The cipher of the file key
+    fn cipher(&self) -> Result<&Aes256Gcm, libc::c_int> {
+        self.cipher.as_ref().ok_or(libc::ENOKEY)
+    }
+
+    /// Read and decrypt block `index`, returning the size of its plaintext
+    fn read_block(
+        &self,
+        host: &mut impl Host,
+        file: &File,
+        index: u64,
+        block: &mut [u8; BLOCK],
+    ) -> Result<usize, libc::c_int> {
+        let mut stored = [0u8; STORED];
+        let mut len = 0;
+
+        while len < STORED {
+            let offset = index * STORED as u64 + len as u64;
+            match host.pread(file.fd, &mut stored[len..], offset)? {
+                0 => break,
+                read if read > STORED - len => return Err(libc::EIO),
+                read => len += read,
+            }
+        }
+
+        if len == 0 {
+            return Ok(0);
+        }
+
+        if len <= NONCE + TAG {
+            return Err(libc::EIO);
+        }
+
+        // The block was written as the last one, if nothing follows it.
+        let end = index * STORED as u64 + len as u64;
+        let last = host.size(file.fd)? <= end;
+
+        let (nonce, rest) = stored[..len].split_at_mut(NONCE);
+        let (data, tag) = rest.split_at_mut(len - NONCE - TAG);
+
+        self.cipher()?
+            .decrypt_in_place_detached(
+                Nonce::from_slice(nonce),
+                &aad(file, index, last),
+                data,
+                Tag::from_slice(tag),
+            )
+            .or(Err(libc::EIO))?;
+
+        block[..data.len()].copy_from_slice(data);
+        Ok(data.len())
+    }
+
+    /// Encrypt and write `data` as block `index`, which is the `last` one or not
+    fn write_block(
+        &self,
+        host: &mut impl Host,
+        file: &File,
+        index: u64,
+        data: &[u8],
+        last: bool,
+    ) -> Result<(), libc::c_int> {
+        let mut stored = [0u8; STORED];
+        let len = NONCE + data.len() + TAG;
+
+        for chunk in stored[..NONCE].chunks_mut(8) {
+            chunk.copy_from_slice(&host.random().to_ne_bytes()[..chunk.len()]);
+        }
+
+        let (nonce, rest) = stored[..len].split_at_mut(NONCE);
+        let (buf, tag) = rest.split_at_mut(data.len());
+        buf.copy_from_slice(data);
+
+        let t = self
+            .cipher()?
+            .encrypt_in_place_detached(Nonce::from_slice(nonce), &aad(file, index, last), buf)
+            .or(Err(libc::EIO))?;
+        tag.copy_from_slice(&t);
+
+        let mut done = 0;
+        while done < len {
+            let offset = index * STORED as u64 + done as u64;
+            match host.pwrite(file.fd, &stored[done..len], offset)? {
+                0 => return Err(libc::EIO),
+                written if written > len - done => return Err(libc::EIO),
+                written => done += written,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Encrypt `data`, which does not cross a block boundary, at `pos`
+    fn write_in_block(
+        &self,
+        host: &mut impl Host,
+        file: &File,
+        data: &[u8],
+        pos: u64,
+    ) -> Result<(), libc::c_int> {
+        let index = pos / BLOCK as u64;
+        let inner = (pos % BLOCK as u64) as usize;
+        let blocks = blocks(host, file)?;
+
+        // The current last block is followed by the new one from now on.
+        if index == blocks && index > 0 {
+            let mut block = [0u8; BLOCK];
+            let len = self.read_block(host, file, index - 1, &mut block)?;
+            self.write_block(host, file, index - 1, &block[..len], false)?;
+        }
+
+        let mut block = [0u8; BLOCK];
+        let len = match inner == 0 && data.len() == BLOCK {
+            true => 0,
+            false => self.read_block(host, file, index, &mut block)?,
+        };
+
+        block[inner..][..data.len()].copy_from_slice(data);
+        let len = len.max(inner + data.len());
+
+        self.write_block(host, file, index, &block[..len], index + 1 >= blocks)
+    }
+
+    /// Decrypt up to `buf.len()` bytes at `offset`
+    fn read_at(
+        &self,
+        host: &mut impl Host,
+        file: &File,
+        buf: &mut [u8],
+        offset: u64,
+    ) -> Result<usize, libc::c_int> {
+        let mut block = [0u8; BLOCK];
+        let mut done = 0;
+
+        while done < buf.len() {
+            let pos = offset.checked_add(done as u64).ok_or(libc::EINVAL)?;
+            let index = pos / BLOCK as u64;
+            let inner = (pos % BLOCK as u64) as usize;
+
+            let len = self.read_block(host, file, index, &mut block)?;
+            if len <= inner {
+                break;
+            }
+
+            let n = (len - inner).min(buf.len() - done);
+            buf[done..][..n].copy_from_slice(&block[inner..][..n]);
+            done += n;
+
+            if len < BLOCK {
+                break;
+            }
+        }
+
+        Ok(done)
+    }
+
+    /// Encrypt all of `data` at `offset`, filling any gap after the end with zeros
+    fn write_at(
+        &self,
+        host: &mut impl Host,
+        file: &File,
+        data: &[u8],
+        offset: u64,
+    ) -> Result<(), libc::c_int> {
+        let zeros = [0u8; BLOCK];
+
+        let mut pos = size(host, file)?;
+        while pos < offset {
+            let n = (offset - pos).min(BLOCK as u64 - pos % BLOCK as u64) as usize;
+            self.write_in_block(host, file, &zeros[..n], pos)?;
+            pos += n as u64;
+        }
+
+        let mut done = 0;
+        while done < data.len() {
+            let pos = offset.checked_add(done as u64).ok_or(libc::EINVAL)?;
+            let n = (data.len() - done).min(BLOCK - (pos % BLOCK as u64) as usize);
+            self.write_in_block(host, file, &data[done..][..n], pos)?;
+            done += n;
+        }
+
+        Ok(())
+    }
+
+    /// Read from the encrypted file `fd` at `offset` or its file position
+    pub fn read(
+        &mut self,
+        host: &mut impl Host,
+        fd: libc::c_int,
+        buf: &mut [u8],
+        offset: Option<u64>,
+    ) -> Result<usize, libc::c_int> {
+        let mut file = self.file(fd).ok_or(libc::EBADF)?;
+        let read = self.read_at(host, &file, buf, offset.unwrap_or(file.pos))?;
+
+        if offset.is_none() {
+            file.pos = file.pos.checked_add(read as u64).ok_or(libc::EINVAL)?;
+            self.update(file);
+        }
+
+        Ok(read)
+    }
+
+    /// Write to the encrypted file `fd` at `offset` or its file position
+    ///
+    /// Without an offset, files opened with `O_APPEND` are written at their end.
+    pub fn write(
+        &mut self,
+        host: &mut impl Host,
+        fd: libc::c_int,
+        buf: &[u8],
+        offset: Option<u64>,
+    ) -> Result<usize, libc::c_int> {
+        let mut file = self.file(fd).ok_or(libc::EBADF)?;
+
+        let pos = match (offset, file.append) {
+            (Some(offset), _) => offset,
+            (None, true) => size(host, &file)?,
+            (None, false) => file.pos,
+        };
+
+        self.write_at(host, &file, buf, pos)?;
+
+        if offset.is_none() {
+            file.pos = pos.checked_add(buf.len() as u64).ok_or(libc::EINVAL)?;
+            self.update(file);
+        }
+
+        Ok(buf.len())
+    }
+
+    /// The size of the plaintext of the encrypted file `fd`
+    ///
+    /// The host only knows the size of the ciphertext.
+    pub fn size(&self, host: &mut impl Host, fd: libc::c_int) -> Result<u64, libc::c_int> {
+        size(host, &self.file(fd).ok_or(libc::EBADF)?)
+    }
+
+    /// Move the file position of the encrypted file `fd`
+    pub fn seek(
+        &mut self,
+        host: &mut impl Host,
+        fd: libc::c_int,
+        offset: i64,
+        whence: libc::c_int,
+    ) -> Result<u64, libc::c_int> {
+        let mut file = self.file(fd).ok_or(libc::EBADF)?;
+
+        let base = match whence {
+            libc::SEEK_SET => 0,
+            libc::SEEK_CUR => file.pos,
+            libc::SEEK_END => size(host, &file)?,
+            _ => return Err(libc::EINVAL),
+        };
+
+        file.pos = match offset < 0 {
+            true => base.checked_sub(offset.unsigned_abs()),
+            false => base.checked_add(offset as u64),
+        }
+        .ok_or(libc::EINVAL)?;
+
+        self.update(file);
+        Ok(file.pos)
+    }
+}
+
+/// The associated data of block `index` of `file`, which is the `last` one or not
+fn aad(file: &File, index: u64, last: bool) -> [u8; 41] {
+    let mut aad = [0u8; 41];
+    aad[..32].copy_from_slice(&file.id);
+    aad[32..40].copy_from_slice(&index.to_le_bytes());
+    aad[40] = last as u8;
+    aad
+}
+
+/// The number of blocks of the file on the host
+fn blocks(host: &mut impl Host, file: &File) -> Result<u64, libc::c_int> {
+    let size = host.size(file.fd)?;
+    Ok(size / STORED as u64 + (size % STORED as u64 != 0) as u64)
+}
+
+/// The size of the plaintext of the file
+fn size(host: &mut impl Host, file: &File) -> Result<u64, libc::c_int> {
+    let size = host.size(file.fd)?;
+
+    let full = size / STORED as u64;
+    match (size % STORED as u64) as usize {
+        0 => Ok(full * BLOCK as u64),
+        rem if rem > NONCE + TAG => Ok(full * BLOCK as u64 + (rem - NONCE - TAG) as u64),
+        _ => Err(libc::EIO),
+    }
+}
@@ -0,0 +1,172 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use crypt::{fd_args, is_encrypted, Crypt, Host};
+
+/// A host file in memory
+#[derive(Default)]
+struct Memory(Vec<u8>, u64);
+
+impl Host for Memory {
+    fn pread(&mut self, _fd: i32, buf: &mut [u8], offset: u64) -> Result<usize, i32> {
+        let data = self.0.get(offset as usize..).unwrap_or(&[]);
+        let n = data.len().min(buf.len());
+        buf[..n].copy_from_slice(&data[..n]);
+        Ok(n)
+    }
+
+    fn pwrite(&mut self, _fd: i32, buf: &[u8], offset: u64) -> Result<usize, i32> {
+        let end = offset as usize + buf.len();
+        if self.0.len() < end {
+            self.0.resize(end, 0);
+        }
+
+        self.0[offset as usize..end].copy_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn size(&mut self, _fd: i32) -> Result<u64, i32> {
+        Ok(self.0.len() as u64)
+    }
+
+    fn random(&mut self) -> u64 {
+        self.1 += 1;
+        self.1
+    }
+}
+
+fn open(path: &[u8]) -> Crypt {
+    let mut crypt = Crypt::new();
+    crypt.set_key(b"key material").unwrap();
+    crypt.open(3, path, libc::O_RDWR).unwrap();
+    crypt
+}
+
+#[test]
+fn prefix() {
+    assert_eq!(is_encrypted(b"", libc::AT_FDCWD, b"/data/a"), Ok(false));
+    assert_eq!(is_encrypted(b"/data", libc::AT_FDCWD, b"/data/a"), Ok(true));
+    assert_eq!(
+        is_encrypted(b"/data", libc::AT_FDCWD, b"data/./a"),
+        Ok(true)
+    );
+    assert_eq!(
+        is_encrypted(b"/data", libc::AT_FDCWD, b"/data/../a"),
+        Ok(false)
+    );
+    assert_eq!(
+        is_encrypted(b"/data", libc::AT_FDCWD, b"/database"),
+        Ok(false)
+    );
+    assert_eq!(is_encrypted(b"/data", 4, b"a"), Err(libc::EACCES));
+}
+
+#[test]
+fn no_key() {
+    let mut crypt = Crypt::new();
+    assert_eq!(crypt.open(3, b"/data/a", 0), Err(libc::ENOKEY));
+}
+
+#[test]
+fn round_trip() {
+    let mut host = Memory::default();
+    let mut crypt = open(b"/data/a");
+
+    // Three blocks, the last one partial
+    let data: Vec<u8> = (0..2500u32).map(|i| i as u8).collect();
+    assert_eq!(crypt.write(&mut host, 3, &data, None), Ok(data.len()));
+    assert_eq!(host.0.len(), 2500 + 3 * (12 + 16));
+    assert!(!host.0.windows(64).any(|w| w == &data[..64]));
+
+    // The file position and size are those of the plaintext
+    assert_eq!(crypt.seek(&mut host, 3, 0, libc::SEEK_CUR), Ok(2500));
+    assert_eq!(crypt.seek(&mut host, 3, 0, libc::SEEK_END), Ok(2500));
+    assert_eq!(crypt.size(&mut host, 3), Ok(2500));
+    assert_eq!(crypt.size(&mut host, 4), Err(libc::EBADF));
+
+    let mut buf = vec![0u8; 3000];
+    assert_eq!(crypt.read(&mut host, 3, &mut buf, Some(0)), Ok(2500));
+    assert_eq!(&buf[..2500], &data[..]);
+
+    // A write across a block boundary
+    assert_eq!(crypt.write(&mut host, 3, b"xyz", Some(1023)), Ok(3));
+    assert_eq!(crypt.read(&mut host, 3, &mut buf[..5], Some(1022)), Ok(5));
+    assert_eq!(&buf[..5], &[data[1022], b'x', b'y', b'z', data[1026]]);
+}
+
+#[test]
+fn tampered() {
+    let mut host = Memory::default();
+    let mut crypt = open(b"/data/a");
+    crypt.write(&mut host, 3, b"top secret\n", None).unwrap();
+
+    let mut buf = [0u8; 16];
+    host.0[20] ^= 1;
+    assert_eq!(crypt.read(&mut host, 3, &mut buf, Some(0)), Err(libc::EIO));
+}
+
+#[test]
+fn truncated() {
+    let mut host = Memory::default();
+    let mut crypt = open(b"/data/a");
+
+    // Appending to a full block
+    let data = [7u8; 2500];
+    crypt.write(&mut host, 3, &data[..1024], None).unwrap();
+    crypt.write(&mut host, 3, &data[1024..], None).unwrap();
+
+    let mut buf = [0u8; 3000];
+    assert_eq!(crypt.read(&mut host, 3, &mut buf, Some(0)), Ok(2500));
+
+    // The host can't cut the file after a block, which isn't the last one.
+    let stored = host.0.clone();
+    host.0.truncate(2 * (12 + 1024 + 16));
+    assert_eq!(crypt.read(&mut host, 3, &mut buf, Some(0)), Err(libc::EIO));
+
+    // That includes the first block, even though it was the last one once.
+    host.0.truncate(12 + 1024 + 16);
+    assert_eq!(crypt.read(&mut host, 3, &mut buf, Some(0)), Err(libc::EIO));
+
+    host.0 = stored;
+    assert_eq!(crypt.read(&mut host, 3, &mut buf, Some(0)), Ok(2500));
+}
+
+#[test]
+fn moved() {
+    let mut host = Memory::default();
+    open(b"/data/a")
+        .write(&mut host, 3, b"top secret\n", None)
+        .unwrap();
+
+    // The blocks are bound to the path of the file.
+    let mut buf = [0u8; 16];
+    let mut other = open(b"/data/b");
+    assert_eq!(other.read(&mut host, 3, &mut buf, Some(0)), Err(libc::EIO));
+}
+
+#[test]
+fn fds() {
+    let args = |a: usize, b: usize, c: usize| [a, b, c, 0, 0, 0];
+
+    assert_eq!(fd_args(libc::SYS_fstat, args(3, 0, 0)), [Some(3), None]);
+    assert_eq!(fd_args(libc::SYS_preadv2, args(3, 0, 0)), [Some(3), None]);
+    assert_eq!(
+        fd_args(libc::SYS_sendfile, args(4, 3, 0)),
+        [Some(4), Some(3)]
+    );
+    assert_eq!(fd_args(libc::SYS_splice, args(3, 0, 4)), [Some(3), Some(4)]);
+    assert_eq!(fd_args(libc::SYS_getpid, args(3, 0, 0)), [None, None]);
+
+    // Only file mappings have a file descriptor.
+    let mmap = |flags: libc::c_int| [0, 4096, 1, flags as usize, 3, 0];
+    assert_eq!(
+        fd_args(libc::SYS_mmap, mmap(libc::MAP_SHARED)),
+        [Some(3), None]
+    );
+    assert_eq!(
+        fd_args(
+            libc::SYS_mmap,
+            mmap(libc::MAP_PRIVATE | libc::MAP_ANONYMOUS)
+        ),
+        [None, None]
+    );
+}
@@ -0,0 +1,481 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Marshalling of `poll`, `select` and `epoll`, shared by the shims
+//!
+//! The events returned by the host are checked, before they are copied
+//! back to the payload: the host can't report events, which were not asked
+//! for, on file descriptors or `epoll` user data, which the payload did not
+//! register.
+//!
+//! A registration belongs to the open file description on the host, so it
+//! outlives the file descriptor, if the description is shared. File
+//! descriptors and `epoll` instances with registrations can't be
+//! duplicated, and duplicated ones can't be registered: both fail with
+//! `EOPNOTSUPP`. The sharing is tracked for the first `FD_SETSIZE` file
+//! descriptors, duplicating beyond fails with `EMFILE`.
+//!
+//! `poll` and `select` are executed with `ppoll` on the host. Their signal
+//! masks are not applied, signals are delivered by the shim once the
+//! syscall returns. `select` doesn't write back the remaining time.
+//!
+//! The shims keep the registrations and the sharing in `Poll`, validate the
+//! arrays of the payload and do the syscalls on the host through `Host`.
+
+#![no_std]
+#![deny(clippy::all)]
+#![deny(missing_docs)]
+
+/// The maximum number of file descriptors polled at once
+const MAX_POLL: usize = 256;
+
+/// The maximum number of events returned by a single `epoll_wait`
+const MAX_EVENTS: usize = 128;
+
+/// The maximum number of file descriptors registered with all `epoll` instances
+const MAX_WATCHES: usize = 1024;
+
+/// The number of file descriptors, whose sharing is tracked
+const MAX_FDS: usize = libc::FD_SETSIZE;
+
+/// The events `poll` reports without being asked for
+const POLL_ALWAYS: libc::c_short = libc::POLLERR | libc::POLLHUP | libc::POLLNVAL;
+
+/// The events `epoll_wait` reports without being asked for
+const EPOLL_ALWAYS: u32 = (libc::EPOLLERR | libc::EPOLLHUP) as u32;
+
+/// The `poll` events of the read, write and exception sets of `select`
+const SELECT_EVENTS: [libc::c_short; 3] = [libc::POLLIN, libc::POLLOUT, libc::POLLPRI];
+
+/// The `poll` events, which mark a file descriptor ready in the sets of `select`
+const SELECT_READY: [libc::c_short; 3] = [
+    libc::POLLIN | libc::POLLHUP | libc::POLLERR,
+    libc::POLLOUT | libc::POLLERR,
+    libc::POLLPRI,
+];
+
+const UNUSED_POLLFD: libc::pollfd = libc::pollfd {
+    fd: -1,
+    events: 0,
+    revents: 0,
+};
+
+const UNUSED_EVENT: libc::epoll_event = libc::epoll_event { events: 0, u64: 0 };
+
+/// The syscalls on the host
+pub trait Host {
+    /// Execute `ppoll` on the host without a signal mask
+    ///
+    /// Writes the array returned by the host to `returned`, which is as
+    /// long as `fds`, and returns the number of ready file descriptors.
+    fn ppoll(
+        &mut self,
+        fds: &[libc::pollfd],
+        timeout: Option<&libc::timespec>,
+        returned: &mut [libc::pollfd],
+    ) -> Result<usize, libc::c_int>;
+
+    /// Execute `epoll_ctl` on the host
+    fn epoll_ctl(
+        &mut self,
+        epfd: libc::c_int,
+        op: libc::c_int,
+        fd: libc::c_int,
+        event: Option<&libc::epoll_event>,
+    ) -> Result<(), libc::c_int>;
+
+    /// Execute `epoll_wait` on the host for up to `events.len()` events
+    ///
+    /// Writes the returned events to `events` and returns the number of
+    /// events reported by the host.
+    fn epoll_wait(
+        &mut self,
+        epfd: libc::c_int,
+        events: &mut [libc::epoll_event],
+        timeout: libc::c_int,
+    ) -> Result<usize, libc::c_int>;
+
+    /// Close `fd` on the host
+    fn close(&mut self, fd: libc::c_int);
+
+    /// Terminate the keep, because the host contradicted itself
+    fn attacked(&mut self) -> !;
+}
+
+/// A file descriptor registered with an `epoll` instance
+#[derive(Copy, Clone)]
+struct Watch {
+    epfd: libc::c_int,
+    fd: libc::c_int,
+    events: u32,
+    data: u64,
+}
+
+/// The `epoll` registrations and the shared file descriptors of the keep
+pub struct Poll {
+    /// The file descriptors registered with all `epoll` instances
+    watches: [Option<Watch>; MAX_WATCHES],
+
+    /// The groups of file descriptors sharing an open file description
+    ///
+    /// A group is numbered after its first file descriptor plus one, 0
+    /// marks a file descriptor, which shares its description with no other
+    /// one.
+    shared: [usize; MAX_FDS],
+}
+
+impl Default for Poll {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Poll {
+    /// The state without registrations and shared file descriptors
+    pub const fn new() -> Self {
+        Self {
+            watches: [None; MAX_WATCHES],
+            shared: [0; MAX_FDS],
+        }
+    }
+
+    /// Forget the registrations and the sharing of `fd`, which is closed or new
+    ///
+    /// Closing an `epoll` instance forgets all file descriptors registered
+    /// with it. A new file descriptor may reuse the number of one, which
+    /// the host closed behind the back of the shim.
+    pub fn forget(&mut self, fd: libc::c_int) {
+        for watch in self.watches.iter_mut() {
+            if matches!(watch, Some(w) if w.epfd == fd || w.fd == fd) {
+                *watch = None;
+            }
+        }
+
+        self.unshare(fd);
+    }
+
+    /// Whether `fd` is registered with an `epoll` instance or is one with registrations
+    fn is_watched(&self, fd: libc::c_int) -> bool {
+        self.watches
+            .iter()
+            .flatten()
+            .any(|w| w.epfd == fd || w.fd == fd)
+    }
+
+    /// Whether `fd` shares its open file description with another file descriptor
+    fn is_shared(&self, fd: libc::c_int) -> bool {
+        matches!(self.shared.get(fd as usize), Some(group) if *group != 0)
+    }
+
+    /// Record that `new` was duplicated from `old`, both below `MAX_FDS`
+    fn share(&mut self, old: libc::c_int, new: libc::c_int) {
+        if self.shared[old as usize] == 0 {
+            self.shared[old as usize] = old as usize + 1;
+        }
+
+        self.shared[new as usize] = self.shared[old as usize];
+    }
+
+    /// Forget that `fd` shares its open file description
+    ///
+    /// The last file descriptor left in a group doesn't share it anymore.
+    fn unshare(&mut self, fd: libc::c_int) {
+        let group = match self.shared.get_mut(fd as usize) {
+            Some(group) => core::mem::replace(group, 0),
+            None => return,
+        };
+
+        if group != 0 {
+            let mut rest = self.shared.iter_mut().filter(|g| **g == group);
+            if let (Some(last), None) = (rest.next(), rest.next()) {
+                *last = 0;
+            }
+        }
+    }
+
+    /// Poll `fds` on the host and set their returned events
+    ///
+    /// Returns the number of file descriptors with events.
+    pub fn poll(
+        &mut self,
+        host: &mut impl Host,
+        fds: &mut [libc::pollfd],
+        timeout: Option<libc::timespec>,
+    ) -> Result<usize, libc::c_int> {
+        let nfds = fds.len();
+        if nfds > MAX_POLL {
+            return Err(libc::EINVAL);
+        }
+
+        let mut trusted = [UNUSED_POLLFD; MAX_POLL];
+        trusted[..nfds].copy_from_slice(fds);
+
+        let ready = ppoll(host, &mut trusted[..nfds], timeout)?;
+
+        for (fd, trusted) in fds.iter_mut().zip(&trusted[..nfds]) {
+            fd.revents = trusted.revents;
+        }
+
+        Ok(ready)
+    }
+
+    /// Wait for the file descriptors in the `fd_set` words `sets` below `nfds`
+    ///
+    /// The read, write and exception sets are replaced with the ready file
+    /// descriptors. Returns their total number.
+    pub fn select(
+        &mut self,
+        host: &mut impl Host,
+        nfds: libc::c_int,
+        mut sets: [Option<&mut [u64]>; 3],
+        timeout: Option<libc::timespec>,
+    ) -> Result<usize, libc::c_int> {
+        let words = set_words(nfds)?;
+        if sets.iter().flatten().any(|set| set.len() != words) {
+            return Err(libc::EFAULT);
+        }
+
+        let mut fds = [UNUSED_POLLFD; MAX_POLL];
+        let mut len = 0;
+        for fd in 0..nfds as usize {
+            let mut events = 0;
+            for (set, event) in sets.iter().zip(&SELECT_EVENTS) {
+                if matches!(set, Some(set) if set[fd / 64] & 1 << (fd % 64) != 0) {
+                    events |= event;
+                }
+            }
+
+            if events != 0 {
+                let pollfd = fds.get_mut(len).ok_or(libc::ENOMEM)?;
+                pollfd.fd = fd as _;
+                pollfd.events = events;
+                len += 1;
+            }
+        }
+
+        ppoll(host, &mut fds[..len], timeout)?;
+
+        if fds[..len].iter().any(|fd| fd.revents & libc::POLLNVAL != 0) {
+            return Err(libc::EBADF);
+        }
+
+        let mut ready = 0;
+        for (i, set) in sets.iter_mut().enumerate() {
+            if let Some(set) = set {
+                set.iter_mut().for_each(|word| *word = 0);
+
+                for fd in fds[..len].iter() {
+                    if fd.events & SELECT_EVENTS[i] != 0 && fd.revents & SELECT_READY[i] != 0 {
+                        let fd = fd.fd as usize;
+                        set[fd / 64] |= 1 << (fd % 64);
+                        ready += 1;
+                    }
+                }
+            }
+        }
+
+        Ok(ready)
+    }
+
+    /// Register, modify or remove `fd` with the `epoll` instance `epfd`
+    ///
+    /// `event` is read from the payload for all operations but `EPOLL_CTL_DEL`.
+    pub fn epoll_ctl(
+        &mut self,
+        host: &mut impl Host,
+        epfd: libc::c_int,
+        op: libc::c_int,
+        fd: libc::c_int,
+        event: Option<libc::epoll_event>,
+    ) -> Result<(), libc::c_int> {
+        let event = match (op, event) {
+            (libc::EPOLL_CTL_DEL, _) => None,
+            (libc::EPOLL_CTL_ADD | libc::EPOLL_CTL_MOD, Some(event)) => Some(event),
+            (libc::EPOLL_CTL_ADD | libc::EPOLL_CTL_MOD, None) => return Err(libc::EFAULT),
+            _ => return Err(libc::EINVAL),
+        };
+
+        if op == libc::EPOLL_CTL_ADD && (self.is_shared(epfd) || self.is_shared(fd)) {
+            return Err(libc::EOPNOTSUPP);
+        }
+
+        let index = self
+            .watches
+            .iter()
+            .position(|w| matches!(w, Some(w) if w.epfd == epfd && w.fd == fd))
+            .or_else(|| self.watches.iter().position(Option::is_none));
+
+        if index.is_none() && op == libc::EPOLL_CTL_ADD {
+            return Err(libc::ENOSPC);
+        }
+
+        host.epoll_ctl(epfd, op, fd, event.as_ref())?;
+
+        if let Some(index) = index {
+            self.watches[index] = event.map(|event| Watch {
+                epfd,
+                fd,
+                events: event.events,
+                data: event.u64,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Wait for events of the `epoll` instance `epfd`
+    ///
+    /// Returns the number of events written to `events`.
+    pub fn epoll_wait(
+        &mut self,
+        host: &mut impl Host,
+        epfd: libc::c_int,
+        events: &mut [libc::epoll_event],
+        timeout: libc::c_int,
+    ) -> Result<usize, libc::c_int> {
+        if events.is_empty() {
+            return Err(libc::EINVAL);
+        }
+
+        let len = events.len().min(MAX_EVENTS);
+        let mut returned = [UNUSED_EVENT; MAX_EVENTS];
+
+        let count = host.epoll_wait(epfd, &mut returned[..len], timeout)?;
+        if count > len {
+            host.attacked();
+        }
+
+        for (event, returned) in events.iter_mut().zip(&returned[..count]) {
+            let (revents, data) = (returned.events, returned.u64);
+
+            let registered = self.watches.iter().flatten().any(|w| {
+                w.epfd == epfd && w.data == data && revents & !(w.events | EPOLL_ALWAYS) == 0
+            });
+
+            if !registered {
+                host.attacked();
+            }
+
+            *event = *returned;
+        }
+
+        Ok(count)
+    }
+
+    /// Duplicate `fd` to `new`, or to the lowest free number from `new` on for `dup` and `F_DUPFD`
+    ///
+    /// `duplicate` executes the syscall of the payload on the host. Returns
+    /// the duplicate.
+    pub fn dup<H: Host>(
+        &mut self,
+        host: &mut H,
+        fd: libc::c_int,
+        new: libc::c_int,
+        duplicate: impl FnOnce(&mut H) -> Result<libc::c_int, libc::c_int>,
+    ) -> Result<libc::c_int, libc::c_int> {
+        if self.is_watched(fd) {
+            return Err(libc::EOPNOTSUPP);
+        }
+
+        // Negative numbers are rejected by the host.
+        if fd >= 0 && new >= 0 && (fd as usize >= MAX_FDS || new as usize >= MAX_FDS) {
+            return Err(libc::EMFILE);
+        }
+
+        let dup = duplicate(host)?;
+        if dup == fd {
+            return Ok(dup);
+        }
+
+        if dup as usize >= MAX_FDS {
+            host.close(dup);
+            return Err(libc::EMFILE);
+        }
+
+        // `dup2` and `dup3` close the file descriptor they replace.
+        self.forget(dup);
+        self.share(fd, dup);
+
+        Ok(dup)
+    }
+}
+
+/// Poll `fds` on the host and set their returned events
+///
+/// Returns the number of file descriptors with events.
+fn ppoll(
+    host: &mut impl Host,
+    fds: &mut [libc::pollfd],
+    timeout: Option<libc::timespec>,
+) -> Result<usize, libc::c_int> {
+    let nfds = fds.len();
+    let mut returned = [UNUSED_POLLFD; MAX_POLL];
+
+    let ready = host.ppoll(fds, timeout.as_ref(), &mut returned[..nfds])?;
+    if ready > nfds {
+        host.attacked();
+    }
+
+    let mut count = 0;
+    for (fd, returned) in fds.iter_mut().zip(&returned[..nfds]) {
+        let allowed = match fd.fd {
+            n if n < 0 => 0,
+            _ => fd.events | POLL_ALWAYS,
+        };
+
+        if returned.revents & !allowed != 0 {
+            host.attacked();
+        }
+
+        if returned.revents != 0 {
+            count += 1;
+        }
+
+        fd.revents = returned.revents;
+    }
+
+    if count != ready {
+        host.attacked();
+    }
+
+    Ok(ready)
+}
+
+/// Convert a `poll` timeout in milliseconds, negative meaning infinite
+pub fn poll_timeout(timeout: libc::c_int) -> Option<libc::timespec> {
+    match timeout {
+        t if t < 0 => None,
+        t => Some(libc::timespec {
+            tv_sec: (t / 1000).into(),
+            tv_nsec: (t % 1000 * 1_000_000).into(),
+        }),
+    }
+}
+
+/// Check a `timespec` timeout of `ppoll` or `pselect6`
+pub fn timespec_timeout(timeout: &libc::timespec) -> Result<libc::timespec, libc::c_int> {
+    match timeout.tv_sec < 0 || !(0..1_000_000_000).contains(&timeout.tv_nsec) {
+        true => Err(libc::EINVAL),
+        false => Ok(*timeout),
+    }
+}
+
+/// Convert a `timeval` timeout of `select`
+pub fn timeval_timeout(timeout: &libc::timeval) -> Result<libc::timespec, libc::c_int> {
+    match timeout.tv_sec < 0 || !(0..1_000_000).contains(&timeout.tv_usec) {
+        true => Err(libc::EINVAL),
+        false => Ok(libc::timespec {
+            tv_sec: timeout.tv_sec,
+            tv_nsec: timeout.tv_usec * 1000,
+        }),
+    }
+}
+
+/// The number of words of the `fd_set` of `select` for the first `nfds` file descriptors
+pub fn set_words(nfds: libc::c_int) -> Result<usize, libc::c_int> {
+    if nfds < 0 || nfds as usize > libc::FD_SETSIZE {
+        return Err(libc::EINVAL);
+    }
+
+    let nfds = nfds as usize;
+    Ok(nfds / 64 + (nfds % 64 != 0) as usize)
+}
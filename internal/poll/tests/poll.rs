@@ -0,0 +1,298 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use poll::{poll_timeout, set_words, timeval_timeout, Host, Poll};
+
+/// A host returning the prepared events
+#[derive(Default)]
+struct Mock {
+    revents: Vec<libc::c_short>,
+    ready: Option<usize>,
+    events: Vec<libc::epoll_event>,
+    closed: Vec<libc::c_int>,
+}
+
+impl Host for Mock {
+    fn ppoll(
+        &mut self,
+        fds: &[libc::pollfd],
+        _timeout: Option<&libc::timespec>,
+        returned: &mut [libc::pollfd],
+    ) -> Result<usize, libc::c_int> {
+        for ((returned, fd), revents) in returned.iter_mut().zip(fds).zip(&self.revents) {
+            *returned = *fd;
+            returned.revents = *revents;
+        }
+
+        let ready = self.revents.iter().filter(|r| **r != 0).count();
+        Ok(self.ready.unwrap_or(ready))
+    }
+
+    fn epoll_ctl(
+        &mut self,
+        _epfd: libc::c_int,
+        _op: libc::c_int,
+        _fd: libc::c_int,
+        _event: Option<&libc::epoll_event>,
+    ) -> Result<(), libc::c_int> {
+        Ok(())
+    }
+
+    fn epoll_wait(
+        &mut self,
+        _epfd: libc::c_int,
+        events: &mut [libc::epoll_event],
+        _timeout: libc::c_int,
+    ) -> Result<usize, libc::c_int> {
+        for (event, returned) in events.iter_mut().zip(&self.events) {
+            *event = *returned;
+        }
+
+        Ok(self.events.len())
+    }
+
+    fn close(&mut self, fd: libc::c_int) {
+        self.closed.push(fd);
+    }
+
+    fn attacked(&mut self) -> ! {
+        panic!("attacked")
+    }
+}
+
+fn pollfd(fd: libc::c_int, events: libc::c_short) -> libc::pollfd {
+    libc::pollfd {
+        fd,
+        events,
+        revents: 0,
+    }
+}
+
+fn event(events: libc::c_int, data: u64) -> libc::epoll_event {
+    libc::epoll_event {
+        events: events as u32,
+        u64: data,
+    }
+}
+
+#[test]
+fn poll() {
+    let mut host = Mock {
+        revents: vec![libc::POLLIN, 0, libc::POLLHUP],
+        ..Mock::default()
+    };
+
+    let mut fds = [
+        pollfd(3, libc::POLLIN),
+        pollfd(4, libc::POLLOUT),
+        pollfd(5, libc::POLLOUT),
+    ];
+
+    assert_eq!(Poll::new().poll(&mut host, &mut fds, None), Ok(2));
+    assert_eq!(fds[0].revents, libc::POLLIN);
+    assert_eq!(fds[1].revents, 0);
+    assert_eq!(fds[2].revents, libc::POLLHUP);
+
+    let mut fds = [pollfd(3, libc::POLLIN); 257];
+    assert_eq!(
+        Poll::new().poll(&mut host, &mut fds, None),
+        Err(libc::EINVAL)
+    );
+}
+
+#[test]
+#[should_panic(expected = "attacked")]
+fn poll_unrequested() {
+    let mut host = Mock {
+        revents: vec![libc::POLLOUT],
+        ..Mock::default()
+    };
+
+    let mut fds = [pollfd(3, libc::POLLIN)];
+    let _ = Poll::new().poll(&mut host, &mut fds, None);
+}
+
+#[test]
+#[should_panic(expected = "attacked")]
+fn poll_ignored() {
+    let mut host = Mock {
+        revents: vec![libc::POLLHUP],
+        ..Mock::default()
+    };
+
+    let mut fds = [pollfd(-1, libc::POLLIN)];
+    let _ = Poll::new().poll(&mut host, &mut fds, None);
+}
+
+#[test]
+#[should_panic(expected = "attacked")]
+fn poll_miscounted() {
+    let mut host = Mock {
+        revents: vec![libc::POLLIN, 0],
+        ready: Some(2),
+        ..Mock::default()
+    };
+
+    let mut fds = [pollfd(3, libc::POLLIN), pollfd(4, libc::POLLIN)];
+    let _ = Poll::new().poll(&mut host, &mut fds, None);
+}
+
+#[test]
+fn select() {
+    let mut host = Mock {
+        revents: vec![libc::POLLIN, libc::POLLIN | libc::POLLOUT],
+        ..Mock::default()
+    };
+
+    let mut read = [1 << 3 | 1 << 5];
+    let mut write = [1 << 5];
+    let sets = [Some(&mut read[..]), Some(&mut write[..]), None];
+
+    assert_eq!(Poll::new().select(&mut host, 6, sets, None), Ok(3));
+    assert_eq!(read, [1 << 3 | 1 << 5]);
+    assert_eq!(write, [1 << 5]);
+
+    let mut host = Mock {
+        revents: vec![libc::POLLNVAL],
+        ..Mock::default()
+    };
+
+    let mut read = [1 << 3];
+    let sets = [Some(&mut read[..]), None, None];
+    assert_eq!(
+        Poll::new().select(&mut host, 4, sets, None),
+        Err(libc::EBADF)
+    );
+
+    let sets = [None, None, None];
+    assert_eq!(
+        Poll::new().select(&mut host, -1, sets, None),
+        Err(libc::EINVAL)
+    );
+}
+
+#[test]
+fn epoll() {
+    let mut poll = Poll::new();
+    let mut host = Mock {
+        events: vec![event(libc::EPOLLIN, 7), event(libc::EPOLLHUP, 7)],
+        ..Mock::default()
+    };
+
+    let added = event(libc::EPOLLIN, 7);
+    assert_eq!(
+        poll.epoll_ctl(&mut host, 3, libc::EPOLL_CTL_ADD, 4, Some(added)),
+        Ok(())
+    );
+
+    let mut events = [event(0, 0); 4];
+    assert_eq!(poll.epoll_wait(&mut host, 3, &mut events, 0), Ok(2));
+    assert_eq!({ events[1].events }, libc::EPOLLHUP as u32);
+
+    assert_eq!(
+        poll.epoll_ctl(&mut host, 3, libc::EPOLL_CTL_ADD, 4, None),
+        Err(libc::EFAULT)
+    );
+    assert_eq!(
+        poll.epoll_ctl(&mut host, 3, 42, 4, Some(added)),
+        Err(libc::EINVAL)
+    );
+    assert_eq!(poll.epoll_wait(&mut host, 3, &mut [], 0), Err(libc::EINVAL));
+}
+
+#[test]
+#[should_panic(expected = "attacked")]
+fn epoll_unregistered() {
+    let mut poll = Poll::new();
+    let mut host = Mock {
+        events: vec![event(libc::EPOLLIN, 8)],
+        ..Mock::default()
+    };
+
+    let added = event(libc::EPOLLIN, 7);
+    poll.epoll_ctl(&mut host, 3, libc::EPOLL_CTL_ADD, 4, Some(added))
+        .unwrap();
+
+    let _ = poll.epoll_wait(&mut host, 3, &mut [event(0, 0)], 0);
+}
+
+#[test]
+#[should_panic(expected = "attacked")]
+fn epoll_removed() {
+    let mut poll = Poll::new();
+    let mut host = Mock {
+        events: vec![event(libc::EPOLLIN, 7)],
+        ..Mock::default()
+    };
+
+    let added = event(libc::EPOLLIN, 7);
+    poll.epoll_ctl(&mut host, 3, libc::EPOLL_CTL_ADD, 4, Some(added))
+        .unwrap();
+    poll.forget(3);
+
+    let _ = poll.epoll_wait(&mut host, 3, &mut [event(0, 0)], 0);
+}
+
+#[test]
+fn dup() {
+    let mut poll = Poll::new();
+    let mut host = Mock::default();
+
+    let added = event(libc::EPOLLIN, 7);
+    assert_eq!(poll.dup(&mut host, 4, 0, |_| Ok(5)), Ok(5));
+    assert_eq!(
+        poll.epoll_ctl(&mut host, 3, libc::EPOLL_CTL_ADD, 4, Some(added)),
+        Err(libc::EOPNOTSUPP)
+    );
+
+    // Once the duplicate is closed, the description isn't shared anymore.
+    poll.forget(5);
+    assert_eq!(
+        poll.epoll_ctl(&mut host, 3, libc::EPOLL_CTL_ADD, 4, Some(added)),
+        Ok(())
+    );
+    assert_eq!(poll.dup(&mut host, 4, 0, |_| Ok(5)), Err(libc::EOPNOTSUPP));
+    assert_eq!(poll.dup(&mut host, 3, 0, |_| Ok(5)), Err(libc::EOPNOTSUPP));
+}
+
+#[test]
+fn dup_beyond() {
+    let mut poll = Poll::new();
+    let mut host = Mock::default();
+    let beyond = |_: &mut Mock| Ok(libc::FD_SETSIZE as _);
+
+    assert_eq!(
+        poll.dup(&mut host, 4, libc::FD_SETSIZE as _, beyond),
+        Err(libc::EMFILE)
+    );
+    assert!(host.closed.is_empty());
+
+    assert_eq!(poll.dup(&mut host, 4, 0, beyond), Err(libc::EMFILE));
+    assert_eq!(host.closed, [libc::FD_SETSIZE as libc::c_int]);
+}
+
+#[test]
+fn timeouts() {
+    let timeout = poll_timeout(1500).unwrap();
+    assert_eq!((timeout.tv_sec, timeout.tv_nsec), (1, 500_000_000));
+    assert!(poll_timeout(-1).is_none());
+
+    let timeval = libc::timeval {
+        tv_sec: 2,
+        tv_usec: 3,
+    };
+    let timeout = timeval_timeout(&timeval).unwrap();
+    assert_eq!((timeout.tv_sec, timeout.tv_nsec), (2, 3000));
+
+    let timeval = libc::timeval {
+        tv_sec: 0,
+        tv_usec: 1_000_000,
+    };
+    assert_eq!(timeval_timeout(&timeval).err(), Some(libc::EINVAL));
+
+    assert_eq!(set_words(0), Ok(0));
+    assert_eq!(set_words(65), Ok(2));
+    assert_eq!(
+        set_words(libc::FD_SETSIZE as libc::c_int + 1),
+        Err(libc::EINVAL)
+    );
+}
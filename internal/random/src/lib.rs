@@ -0,0 +1,229 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Randomness for the payload, which never comes from the host, shared by the shims
+//!
+//! `getrandom` and reads of `/dev/urandom` and `/dev/random` are served by
+//! an HMAC-DRBG (NIST SP 800-90A) with SHA-256, which is seeded and
+//! periodically reseeded with `rdrand`. If `rdrand` keeps failing, they
+//! fail with `EIO` instead of returning weak random bytes.
+//!
+//! The shims keep the DRBG and the open random devices in `Random` and
+//! hand it their entropy source, which is `rdrand` in a keep.
+
+#![no_std]
+#![deny(clippy::all)]
+#![deny(missing_docs)]
+
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+/// The number of `rdrand` attempts, before giving up
+const RETRIES: usize = 1024;
+
+/// The number of requests served, before the DRBG is reseeded
+const RESEED_INTERVAL: u32 = 1024;
+
+/// The maximum number of bytes generated per request
+const MAX_REQUEST: usize = 65536;
+
+/// The maximum number of open `/dev/urandom` and `/dev/random` files
+const MAX_FILES: usize = 16;
+
+/// The maximum number of bytes returned by a single read, like Linux
+pub const MAX_READ: usize = 33_554_431;
+
+/// Don't block for the initialization of the pool, missing in `libc`
+pub const GRND_INSECURE: libc::c_uint = 0x0004;
+
+/// Only set `FD_CLOEXEC` on the range of `close_range`, missing in `libc`
+pub const CLOSE_RANGE_CLOEXEC: libc::c_uint = 1 << 2;
+
+/// Get a random number from the CPU, if it has one within a number of attempts
+pub fn rdrand() -> Option<u64> {
+    let mut r: u64 = 0;
+
+    for _ in 0..RETRIES {
+        if unsafe { core::arch::x86_64::_rdrand64_step(&mut r) } == 1 {
+            return Some(r);
+        }
+    }
+
+    None
+}
+
+/// Whether `path` is a random device served by the shim
+pub fn is_device(path: &[u8]) -> bool {
+    path == b"/dev/urandom" || path == b"/dev/random"
+}
+
+/// Check the `flags` of `getrandom`
+///
+/// They don't change the result, as the DRBG never blocks.
+pub fn check_flags(flags: libc::c_uint) -> Result<(), libc::c_int> {
+    if flags & !(libc::GRND_NONBLOCK | libc::GRND_RANDOM | GRND_INSECURE) != 0 {
+        return Err(libc::EINVAL);
+    }
+
+    if flags & (libc::GRND_RANDOM | GRND_INSECURE) == libc::GRND_RANDOM | GRND_INSECURE {
+        return Err(libc::EINVAL);
+    }
+
+    Ok(())
+}
+
+fn hmac(key: &[u8; 32], data: &[&[u8]]) -> [u8; 32] {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).unwrap();
+    for data in data {
+        mac.update(data);
+    }
+
+    mac.finalize().into_bytes().into()
+}
+
+/// An HMAC-DRBG with SHA-256
+struct Drbg {
+    key: [u8; 32],
+    value: [u8; 32],
+    requests: u32,
+}
+
+impl Drbg {
+    /// Instantiate the DRBG from `entropy`
+    fn new(entropy: &mut impl FnMut() -> Option<u64>) -> Result<Self, libc::c_int> {
+        let mut drbg = Self {
+            key: [0; 32],
+            value: [1; 32],
+            requests: 0,
+        };
+
+        drbg.reseed(entropy)?;
+        Ok(drbg)
+    }
+
+    fn update(&mut self, data: &[u8]) {
+        self.key = hmac(&self.key, &[&self.value, &[0], data]);
+        self.value = hmac(&self.key, &[&self.value]);
+
+        if !data.is_empty() {
+            self.key = hmac(&self.key, &[&self.value, &[1], data]);
+            self.value = hmac(&self.key, &[&self.value]);
+        }
+    }
+
+    /// Mix 384 bits of fresh entropy into the state
+    fn reseed(&mut self, entropy: &mut impl FnMut() -> Option<u64>) -> Result<(), libc::c_int> {
+        let mut seed = [0u8; 48];
+        for chunk in seed.chunks_mut(8) {
+            chunk.copy_from_slice(&entropy().ok_or(libc::EIO)?.to_ne_bytes());
+        }
+
+        self.update(&seed);
+        self.requests = 0;
+        Ok(())
+    }
+
+    fn generate(
+        &mut self,
+        entropy: &mut impl FnMut() -> Option<u64>,
+        buf: &mut [u8],
+    ) -> Result<(), libc::c_int> {
+        for request in buf.chunks_mut(MAX_REQUEST) {
+            if self.requests >= RESEED_INTERVAL {
+                self.reseed(entropy)?;
+            }
+
+            for chunk in request.chunks_mut(32) {
+                self.value = hmac(&self.key, &[&self.value]);
+                chunk.copy_from_slice(&self.value[..chunk.len()]);
+            }
+
+            self.update(&[]);
+            self.requests = self.requests.saturating_add(1);
+        }
+
+        Ok(())
+    }
+}
+
+/// The random state of the keep
+pub struct Random {
+    drbg: Option<Drbg>,
+    files: [libc::c_int; MAX_FILES],
+}
+
+impl Default for Random {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Random {
+    /// The random state before the first request and without open devices
+    pub const fn new() -> Self {
+        Self {
+            drbg: None,
+            files: [-1; MAX_FILES],
+        }
+    }
+
+    /// Fill `buf` with random bytes, seeding the DRBG from `entropy`
+    ///
+    /// Fails with `EIO`, if `entropy` can't provide the seed.
+    pub fn fill(
+        &mut self,
+        mut entropy: impl FnMut() -> Option<u64>,
+        buf: &mut [u8],
+    ) -> Result<(), libc::c_int> {
+        if self.drbg.is_none() {
+            self.drbg = Some(Drbg::new(&mut entropy)?);
+        }
+
+        self.drbg.as_mut().unwrap().generate(&mut entropy, buf)
+    }
+
+    /// Whether another random device can be opened
+    pub fn can_open(&self) -> bool {
+        self.files.contains(&-1)
+    }
+
+    /// Track the file descriptor `fd` opened for a random device
+    pub fn open(&mut self, fd: libc::c_int) -> Result<(), libc::c_int> {
+        let file = self
+            .files
+            .iter_mut()
+            .find(|f| **f == -1)
+            .ok_or(libc::EMFILE)?;
+
+        *file = fd;
+        Ok(())
+    }
+
+    /// Whether `fd` is a random device
+    pub fn is_open(&self, fd: libc::c_int) -> bool {
+        fd >= 0 && self.files.contains(&fd)
+    }
+
+    /// Stop tracking the closed random device `fd`
+    pub fn close(&mut self, fd: libc::c_int) {
+        for file in self.files.iter_mut() {
+            if *file == fd {
+                *file = -1;
+            }
+        }
+    }
+
+    /// Stop tracking the random devices closed by `close_range` from `first` to `last`
+    ///
+    /// With `CLOSE_RANGE_CLOEXEC`, nothing is closed before `execve`.
+    pub fn close_range(&mut self, first: libc::c_uint, last: libc::c_uint, flags: libc::c_uint) {
+        if flags & CLOSE_RANGE_CLOEXEC != 0 {
+            return;
+        }
+
+        for file in self.files.iter_mut() {
+            if *file >= 0 && (first..=last).contains(&(*file as libc::c_uint)) {
+                *file = -1;
+            }
+        }
+    }
+}
@@ -0,0 +1,115 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use random::{check_flags, is_device, Random, CLOSE_RANGE_CLOEXEC, GRND_INSECURE};
+
+/// An entropy source counting up from `seed`
+fn counter(mut seed: u64) -> impl FnMut() -> Option<u64> {
+    move || {
+        seed += 1;
+        Some(seed)
+    }
+}
+
+#[test]
+fn deterministic() {
+    let (mut a, mut b) = ([0u8; 100], [0u8; 100]);
+
+    Random::new().fill(counter(0), &mut a).unwrap();
+    Random::new().fill(counter(0), &mut b).unwrap();
+    assert_eq!(a[..], b[..]);
+
+    Random::new().fill(counter(1), &mut b).unwrap();
+    assert_ne!(a[..], b[..]);
+}
+
+#[test]
+fn requests_differ() {
+    let mut random = Random::new();
+    let (mut a, mut b) = ([0u8; 32], [0u8; 32]);
+
+    random.fill(counter(0), &mut a).unwrap();
+    random.fill(counter(0), &mut b).unwrap();
+    assert_ne!(a, b);
+}
+
+#[test]
+fn fail_closed() {
+    let mut buf = [0u8; 32];
+    assert_eq!(Random::new().fill(|| None, &mut buf), Err(libc::EIO));
+    assert_eq!(buf, [0u8; 32]);
+
+    // Once seeded, the DRBG fails when it needs to be reseeded.
+    let mut random = Random::new();
+    random.fill(counter(0), &mut buf).unwrap();
+
+    let mut reseeded = false;
+    for _ in 0..2048 {
+        match random.fill(|| None, &mut buf) {
+            Ok(()) => {}
+            Err(e) => {
+                assert_eq!(e, libc::EIO);
+                reseeded = true;
+                break;
+            }
+        }
+    }
+
+    assert!(reseeded);
+}
+
+#[test]
+fn files() {
+    let mut random = Random::new();
+    assert!(!random.is_open(-1));
+
+    for fd in 3..19 {
+        assert!(random.can_open());
+        random.open(fd).unwrap();
+    }
+
+    assert!(!random.can_open());
+    assert_eq!(random.open(19), Err(libc::EMFILE));
+    assert!(random.is_open(3));
+
+    random.close(3);
+    assert!(!random.is_open(3));
+    assert!(random.can_open());
+}
+
+#[test]
+fn close_range() {
+    let mut random = Random::new();
+    for fd in 3..8 {
+        random.open(fd).unwrap();
+    }
+
+    random.close_range(4, 6, CLOSE_RANGE_CLOEXEC);
+    assert!((3..8).all(|fd| random.is_open(fd)));
+
+    random.close_range(4, 6, 0);
+    assert!(random.is_open(3));
+    assert!((4..=6).all(|fd| !random.is_open(fd)));
+    assert!(random.is_open(7));
+
+    random.close_range(0, !0, 0);
+    assert!((3..8).all(|fd| !random.is_open(fd)));
+}
+
+#[test]
+fn flags() {
+    assert!(check_flags(0).is_ok());
+    assert!(check_flags(libc::GRND_NONBLOCK | libc::GRND_RANDOM).is_ok());
+    assert!(check_flags(GRND_INSECURE).is_ok());
+    assert_eq!(
+        check_flags(libc::GRND_RANDOM | GRND_INSECURE),
+        Err(libc::EINVAL)
+    );
+    assert_eq!(check_flags(0x8), Err(libc::EINVAL));
+}
+
+#[test]
+fn devices() {
+    assert!(is_device(b"/dev/urandom"));
+    assert!(is_device(b"/dev/random"));
+    assert!(!is_device(b"/dev/urandom2"));
+}
@@ -1,5 +1,34 @@
 // SPDX-License-Identifier: Apache-2.0
 
+use std::path::PathBuf;
+use std::process::Command;
+
 fn main() {
     println!("cargo:rerun-if-changed=layout.ld");
+    println!("cargo:rerun-if-changed=vdso/vdso.c");
+    println!("cargo:rerun-if-changed=vdso/vdso.lds");
+
+    // Build the vDSO of the payload, which the shim embeds.
+    let out = PathBuf::from(std::env::var_os("OUT_DIR").unwrap()).join("vdso.so");
+
+    let status = Command::new("cc")
+        .arg("-O2")
+        .arg("-fPIC")
+        .arg("-fno-stack-protector")
+        .arg("-fno-asynchronous-unwind-tables")
+        .arg("-nostdlib")
+        .arg("-shared")
+        .arg("-Wl,-T,vdso/vdso.lds")
+        .arg("-Wl,--hash-style=both")
+        .arg("-Wl,--build-id=none")
+        .arg("-Wl,--no-undefined")
+        .arg("-Wl,-soname=linux-vdso.so.1")
+        .arg("-Wl,-z,max-page-size=4096")
+        .arg("-o")
+        .arg(&out)
+        .arg("vdso/vdso.c")
+        .status()
+        .expect("failed to compile the vDSO");
+
+    assert!(status.success(), "Failed to compile the vDSO");
 }
@@ -539,8 +539,9 @@ unsafe impl paging::FrameAllocator<Size1GiB> for EnarxAllocator {
     }
 }
 
+/// Translate a shim virtual address to the physical address with the C-bit set
 #[inline]
-fn shim_virt_to_enc_phys<T>(p: *mut T) -> PhysAddr {
+pub fn shim_virt_to_enc_phys<T>(p: *mut T) -> PhysAddr {
     let addr = Address::<u64, _>::from(p);
     let virt = ShimVirtAddr::try_from(addr).unwrap();
     let phys = ShimPhysAddr::try_from(virt).unwrap();
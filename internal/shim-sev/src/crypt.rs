@@ -0,0 +1,95 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transparent encryption of files below a configured prefix
+//!
+//! The `crypt` crate encrypts the files. The key is derived from the secret
+//! injected by the SEV guest owner, or by the loader of a plain KVM keep.
+
+use crate::spin::Locked;
+use crate::SEV_SECRET;
+
+use ::crypt::Crypt;
+use abi::note::{self, Note, PATH_MAX as PREFIX_MAX};
+
+pub use ::crypt::{fd_args, Host};
+
+/// The prefix of the files, which are encrypted transparently
+///
+/// An empty prefix disables encryption. Like the debug switch, the prefix
+/// is part of the measurement of the keep.
+#[used]
+#[link_section = ".note"]
+static NOTE_ENARX_ENCRYPT: Note<[u8; PREFIX_MAX]> = Note::new(note::ENCRYPT, [0; PREFIX_MAX]);
+
+/// The encryption state of the keep
+static CRYPT: Locked<Crypt> = Locked::new(Crypt::new());
+
+/// Whether the file at `path` relative to `dirfd` is encrypted
+pub fn is_encrypted(dirfd: libc::c_int, path: &[u8]) -> Result<bool, libc::c_int> {
+    let desc = NOTE_ENARX_ENCRYPT.desc();
+    let len = desc.iter().position(|b| *b == 0).unwrap_or(PREFIX_MAX);
+
+    ::crypt::is_encrypted(&desc[..len], dirfd, path)
+}
+
+/// Track a newly opened encrypted file
+pub fn open(fd: libc::c_int, path: &[u8], flags: libc::c_int) -> Result<(), libc::c_int> {
+    let mut crypt = CRYPT.lock();
+
+    if !crypt.has_key() {
+        let secret = SEV_SECRET.read();
+        let ikm = secret
+            .as_ref()
+            .and_then(|s| s.try_as_slice())
+            .ok_or(libc::ENOKEY)?;
+
+        crypt.set_key(ikm)?;
+    }
+
+    crypt.open(fd, path, flags)
+}
+
+/// Stop tracking `fd`, returning whether it was an encrypted file
+pub fn close(fd: libc::c_int) -> bool {
+    CRYPT.lock().close(fd)
+}
+
+/// Whether `fd` is an encrypted file
+pub fn is_open(fd: libc::c_int) -> bool {
+    CRYPT.lock().is_open(fd)
+}
+
+/// Read from the encrypted file `fd` at `offset` or its file position
+pub fn read(
+    host: &mut impl Host,
+    fd: libc::c_int,
+    buf: &mut [u8],
+    offset: Option<u64>,
+) -> Result<usize, libc::c_int> {
+    CRYPT.lock().read(host, fd, buf, offset)
+}
+
+/// Write to the encrypted file `fd` at `offset` or its file position
+pub fn write(
+    host: &mut impl Host,
+    fd: libc::c_int,
+    buf: &[u8],
+    offset: Option<u64>,
+) -> Result<usize, libc::c_int> {
+    CRYPT.lock().write(host, fd, buf, offset)
+}
+
+/// The size of the plaintext of the encrypted file `fd`
+pub fn size(host: &mut impl Host, fd: libc::c_int) -> Result<u64, libc::c_int> {
+    CRYPT.lock().size(host, fd)
+}
+
+/// Move the file position of the encrypted file `fd`
+pub fn seek(
+    host: &mut impl Host,
+    fd: libc::c_int,
+    offset: i64,
+    whence: libc::c_int,
+) -> Result<u64, libc::c_int> {
+    CRYPT.lock().seek(host, fd, offset, whence)
+}
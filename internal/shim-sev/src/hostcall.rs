@@ -4,17 +4,17 @@
 
 use crate::addr::{HostVirtAddr, ShimPhysUnencryptedAddr, ShimVirtAddr};
 use crate::asm::_enarx_asm_triple_fault;
-use crate::spin::RwLocked;
-use array_const_fn_init::array_const_fn_init;
+use crate::spin::{Locked, RwLocked};
+use abi::note::{self, Note};
 use core::convert::TryFrom;
 use core::mem::size_of;
+use core::sync::atomic::{AtomicU32, Ordering};
 use primordial::{Address, Register};
 use sallyport::syscall::enarx::MemInfo;
 use sallyport::syscall::{SYS_ENARX_BALLOON_MEMORY, SYS_ENARX_MEM_INFO};
 use sallyport::KVM_SYSCALL_TRIGGER_PORT;
 use sallyport::{request, Block};
 use spinning::Lazy;
-use x86_64::instructions::port::Port;
 use x86_64::PhysAddr;
 
 /// Host file descriptor
@@ -46,12 +46,144 @@ impl HostFd {
     }
 }
 
-const MAX_BLOCK_NR: usize = 512;
+/// The port to block the vCPU on, until the host has serviced a block of the ring
+const KVM_RING_DOORBELL_PORT: u16 =
+    KVM_SYSCALL_TRIGGER_PORT.wrapping_add(abi::kvm::RING_DOORBELL_PORT_OFFSET);
 
-fn return_empty_option(_i: usize) -> Option<&'static mut Block> {
-    None
+/// The port to fetch a pending host signal from
+const KVM_SIGNAL_PORT: u16 = KVM_SYSCALL_TRIGGER_PORT.wrapping_add(abi::kvm::SIGNAL_PORT_OFFSET);
+
+/// The number of entries of the hostcall ring
+const RING_SIZE: usize = 32;
+
+/// The number of polls for a ring request to be done, before blocking the vCPU
+const RING_SPIN_LIMIT: usize = 1 << 10;
+
+/// The number of sallyport blocks the host provides
+///
+/// The loader patches the descriptor with the number of blocks it has
+/// mapped into the sallyport segment, so it has to be read volatile.
+#[used]
+#[link_section = ".note"]
+static NOTE_ENARX_SALLYPORT_BLOCKS: Note<u32> = Note::new(note::SALLYPORT_BLOCKS, 0);
+
+/// Whether the host services hostcalls via the hostcall ring
+///
+/// The loader sets the descriptor to a non-zero value, if it runs
+/// the ring service, so it has to be read volatile.
+#[used]
+#[link_section = ".note"]
+static NOTE_ENARX_SALLYPORT_RING: Note<u32> = Note::new(note::SALLYPORT_RING, 0);
+
+/// The hostcall ring in the last page of the sallyport segment
+///
+/// Has to match the layout in `src/backend/kvm/ring.rs` of the loader.
+#[repr(C)]
+struct Ring {
+    /// Written by the shim, the next free entry
+    head: AtomicU32,
+
+    /// Written by the host, the next entry to service
+    tail: AtomicU32,
+
+    /// Non-zero, if the host service thread is sleeping
+    idle: AtomicU32,
+
+    /// Indices of the blocks to service
+    entries: [AtomicU32; RING_SIZE],
+
+    /// Non-zero, if the request in the block with the same index is done
+    done: [AtomicU32; RING_SIZE],
+}
+
+impl Ring {
+    /// Push a block index to the ring
+    ///
+    /// Returns `true`, if the host service thread has to be woken up.
+    fn push(&self, index: u16) -> bool {
+        let _lock = HOST_CALL_RING_LOCK.lock();
+
+        let head = self.head.load(Ordering::Relaxed);
+        self.entries[(head as usize).wrapping_rem(RING_SIZE)]
+            .store(index.into(), Ordering::Relaxed);
+        self.head.store(head.wrapping_add(1), Ordering::SeqCst);
+
+        self.idle.load(Ordering::SeqCst) != 0
+    }
+
+    /// Let the host service the block `index` and wait until it is done
+    ///
+    /// Polls for the request to be done for a while, before blocking the vCPU.
+    fn call(&self, index: u16) {
+        let done = &self.done[usize::from(index)];
+        done.store(0, Ordering::Relaxed);
+
+        if !self.push(index) {
+            for _ in 0..RING_SPIN_LIMIT {
+                if done.load(Ordering::Acquire) != 0 {
+                    return;
+                }
+                core::hint::spin_loop();
+            }
+        }
+
+        while done.load(Ordering::Acquire) == 0 {
+            unsafe { trigger(KVM_RING_DOORBELL_PORT, index) };
+        }
+    }
 }
 
+/// Exit to the host by writing the block `index` to `port`
+///
+/// The host passes a pending host signal in `rdi` on the way back.
+///
+/// # Safety
+///
+/// The host services the block `index`, so it must be set up for the request.
+#[inline(always)]
+unsafe fn trigger(port: u16, index: u16) {
+    let signo: u64;
+
+    asm!(
+        "out dx, ax",
+        in("dx") port,
+        in("ax") index,
+        inout("rdi") 0u64 => signo,
+        options(nostack, preserves_flags)
+    );
+
+    if signo != 0 {
+        crate::signal::host(signo);
+    }
+}
+
+/// Fetch the pending host signal, after the host injected `abi::kvm::SIGNAL_VECTOR`
+pub fn fetch_signal() {
+    unsafe { trigger(KVM_SIGNAL_PORT, 0) }
+}
+
+/// Serializes pushing to the hostcall ring
+static HOST_CALL_RING_LOCK: Locked<()> = Locked::<()>::new(());
+
+/// The hostcall ring, if the host services it
+static HOST_CALL_RING: Lazy<Option<&'static Ring>> = Lazy::new(|| {
+    if NOTE_ENARX_SALLYPORT_RING.desc() == 0 {
+        return None;
+    }
+
+    let ring = unsafe {
+        let address =
+            Address::<u64, Ring>::from(&crate::_ENARX_SALLYPORT_RING as *const _ as *const Ring);
+        let shim_virt = ShimVirtAddr::<Ring>::try_from(address).unwrap();
+
+        &*(ShimPhysUnencryptedAddr::<Ring>::try_from(shim_virt)
+            .unwrap()
+            .into_mut() as *const Ring)
+    };
+
+    Some(ring)
+});
+
 /// The static HostCall Mutex
 pub static HOST_CALL_ALLOC: Lazy<RwLocked<HostCallAllocator>> = Lazy::new(|| {
     let block_mut: *mut Block = unsafe {
@@ -64,38 +196,61 @@ pub static HOST_CALL_ALLOC: Lazy<RwLocked<HostCallAllocator>> = Lazy::new(|| {
             .into_mut() as *mut _
     };
 
-    let nr_syscall_blocks = unsafe {
-        (&crate::_ENARX_SALLYPORT_END as *const _ as usize
+    // The linker script reserves room for `_ENARX_SALLYPORT_BLOCK_MAX` blocks.
+    let max_syscall_blocks = unsafe {
+        (&crate::_ENARX_SALLYPORT_RING as *const _ as usize
             - &crate::_ENARX_SALLYPORT_START as *const _ as usize)
             / size_of::<Block>()
     };
 
-    assert!(nr_syscall_blocks <= 512);
-
-    let mut hostcall_allocator = HostCallAllocator(array_const_fn_init![return_empty_option; 512]);
+    let nr_syscall_blocks = NOTE_ENARX_SALLYPORT_BLOCKS.desc() as usize;
 
-    for i in 0..nr_syscall_blocks {
-        (hostcall_allocator.0)[i].replace(unsafe { &mut *block_mut.add(i) });
+    // Without a single block, the shim can't even report the failure.
+    if nr_syscall_blocks == 0 {
+        unsafe { _enarx_asm_triple_fault() }
     }
 
-    RwLocked::<HostCallAllocator>::new(hostcall_allocator)
+    assert!(nr_syscall_blocks <= max_syscall_blocks);
+    assert!(nr_syscall_blocks <= u64::BITS as usize);
+    assert!(HOST_CALL_RING.is_none() || nr_syscall_blocks <= RING_SIZE);
+
+    let free = u64::MAX
+        .checked_shr(u64::BITS.wrapping_sub(nr_syscall_blocks as u32))
+        .unwrap_or(0);
+
+    RwLocked::<HostCallAllocator>::new(HostCallAllocator {
+        blocks: block_mut as usize,
+        free,
+    })
 });
 
 /// Allocator for all `sallyport::Block`
-pub struct HostCallAllocator([Option<&'static mut Block>; MAX_BLOCK_NR]);
+pub struct HostCallAllocator {
+    /// The address of the first block
+    blocks: usize,
+
+    /// A set bit for every free block
+    free: u64,
+}
 
 impl RwLocked<HostCallAllocator> {
     /// Try to allocate a `HostCall` object to use a `sallyport::Block`
     pub fn try_alloc(&self) -> Option<HostCall> {
         let mut this = self.write();
-        this.0
-            .iter_mut()
-            .enumerate()
-            .find(|(_i, x)| x.is_some())
-            .map(|(i, ele)| HostCall {
-                block_index: i as _,
-                block: ele.take(),
-            })
+
+        if this.free == 0 {
+            return None;
+        }
+
+        let index = this.free.trailing_zeros();
+        this.free &= !1u64.checked_shl(index)?;
+
+        let block = unsafe { &mut *(this.blocks as *mut Block).add(index as usize) };
+
+        Some(HostCall {
+            block_index: index as _,
+            block: Some(block),
+        })
     }
 }
 
@@ -107,12 +262,16 @@ pub struct HostCall {
 
 impl Drop for HostCall {
     fn drop(&mut self) {
-        HOST_CALL_ALLOC.write().0[self.block_index as usize] = self.block.take();
+        self.block.take();
+        HOST_CALL_ALLOC.write().free |= 1u64.checked_shl(self.block_index.into()).unwrap();
     }
 }
 
 impl HostCall {
-    /// Causes a `#VMEXIT` for the host to process the data in the shared memory
+    /// Lets the host process the data in the shared memory
+    ///
+    /// Causes a `#VMEXIT`, unless the host services the hostcall ring and
+    /// the request does not need the vCPU.
     ///
     /// Returns the contents of the shared memory reply status, the host might have
     /// written.
@@ -122,12 +281,13 @@ impl HostCall {
     /// The parameters returned can't be trusted.
     #[inline(always)]
     pub unsafe fn hostcall(&mut self) -> sallyport::Result {
-        let mut port = Port::<u16>::new(KVM_SYSCALL_TRIGGER_PORT);
-
         // prevent earlier writes from being moved beyond this point
         core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::Release);
 
-        port.write(self.block_index);
+        match *HOST_CALL_RING {
+            Some(ring) if self.is_ringable() => ring.call(self.block_index),
+            _ => trigger(KVM_SYSCALL_TRIGGER_PORT, self.block_index),
+        }
 
         // prevent later reads from being moved before this point
         core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::Acquire);
@@ -135,6 +295,18 @@ impl HostCall {
         self.block.as_mut().unwrap().msg.rep.into()
     }
 
+    /// Whether the request can be serviced via the hostcall ring
+    ///
+    /// Requests which need the vCPU or end it have to exit the keep.
+    fn is_ringable(&self) -> bool {
+        let req = unsafe { self.block.as_ref().unwrap().msg.req };
+
+        !matches!(
+            i64::from(req.num),
+            SYS_ENARX_BALLOON_MEMORY | SYS_ENARX_MEM_INFO | libc::SYS_exit | libc::SYS_exit_group
+        )
+    }
+
     /// Return reference to the inner `Block`
     pub fn as_block(&self) -> &Block {
         self.block.as_ref().unwrap()
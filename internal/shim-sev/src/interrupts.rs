@@ -0,0 +1,247 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Interrupt Descriptor Table
+//!
+//! Exceptions of the payload are turned into signals. Exceptions of the
+//! shim itself shut down the keep. The host interrupts the payload with
+//! `SIGNAL_VECTOR` to hand it a host signal.
+
+use crate::asm::_enarx_asm_triple_fault;
+use crate::eprintln;
+use crate::hostcall::fetch_signal;
+use crate::signal::{self, Context, Info};
+use crate::usermode::resume;
+use abi::kvm::SIGNAL_VECTOR;
+use signals::{
+    BUS_ADRALN, FPE_INTDIV, ILL_ILLOPN, SEGV_ACCERR, SEGV_MAPERR, SI_KERNEL, TRAP_BRKPT,
+};
+use spinning::Lazy;
+use x86_64::registers::control::Cr2;
+use x86_64::structures::idt::InterruptDescriptorTable;
+use x86_64::VirtAddr;
+
+/// The registers saved by the exception entry
+#[repr(C)]
+struct ExceptionFrame {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    r11: u64,
+    r10: u64,
+    r9: u64,
+    r8: u64,
+    rbp: u64,
+    rdi: u64,
+    rsi: u64,
+    rdx: u64,
+    rcx: u64,
+    rbx: u64,
+    rax: u64,
+    vector: u64,
+    error_code: u64,
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+impl ExceptionFrame {
+    fn context(&self) -> Context {
+        Context {
+            r8: self.r8,
+            r9: self.r9,
+            r10: self.r10,
+            r11: self.r11,
+            r12: self.r12,
+            r13: self.r13,
+            r14: self.r14,
+            r15: self.r15,
+            rdi: self.rdi,
+            rsi: self.rsi,
+            rbp: self.rbp,
+            rbx: self.rbx,
+            rdx: self.rdx,
+            rax: self.rax,
+            rcx: self.rcx,
+            rsp: self.rsp,
+            rip: self.rip,
+            rflags: self.rflags,
+        }
+    }
+}
+
+/// Define an exception entry, which pushes a zero error code, if the CPU doesn't
+macro_rules! exception {
+    ($name:ident, $vector:literal) => {
+        #[naked]
+        unsafe extern "sysv64" fn $name() -> ! {
+            asm!(
+                "push 0",
+                "push {VECTOR}",
+                "jmp {COMMON}",
+                VECTOR = const $vector,
+                COMMON = sym exception_common,
+                options(noreturn)
+            )
+        }
+    };
+    ($name:ident, $vector:literal, error_code) => {
+        #[naked]
+        unsafe extern "sysv64" fn $name() -> ! {
+            asm!(
+                "push {VECTOR}",
+                "jmp {COMMON}",
+                VECTOR = const $vector,
+                COMMON = sym exception_common,
+                options(noreturn)
+            )
+        }
+    };
+}
+
+exception!(divide_error, 0);
+exception!(debug, 1);
+exception!(breakpoint, 3);
+exception!(bound_range_exceeded, 5);
+exception!(invalid_opcode, 6);
+exception!(segment_not_present, 11, error_code);
+exception!(stack_segment_fault, 12, error_code);
+exception!(general_protection_fault, 13, error_code);
+exception!(page_fault, 14, error_code);
+exception!(x87_floating_point, 16);
+exception!(alignment_check, 17, error_code);
+exception!(simd_floating_point, 19);
+exception!(host_signal, 0x20);
+
+/// The common part of all exception entries
+///
+/// Saves all registers in an `ExceptionFrame` and calls `exception_rust`.
+#[naked]
+unsafe extern "sysv64" fn exception_common() -> ! {
+    asm!("
+    # switch to the shim gs base, if the exception happened in the payload
+    test   QWORD PTR [rsp + 0x18],  0x3
+    jz     2f
+    swapgs
+2:
+    push   rax
+    push   rbx
+    push   rcx
+    push   rdx
+    push   rsi
+    push   rdi
+    push   rbp
+    push   r8
+    push   r9
+    push   r10
+    push   r11
+    push   r12
+    push   r13
+    push   r14
+    push   r15
+
+    mov    rdi,                     rsp
+    call   {exception_rust}
+    ud2
+    ",
+    exception_rust = sym exception_rust,
+    options(noreturn)
+    )
+}
+
+/// Handle an exception in rust
+extern "sysv64" fn exception_rust(frame: &ExceptionFrame) -> ! {
+    if frame.cs & 0x3 == 0 {
+        eprintln!(
+            "shim exception {} at {:#x}, error code {:#x}",
+            frame.vector, frame.rip, frame.error_code
+        );
+
+        // provoke triple fault, causing a VM shutdown
+        unsafe { _enarx_asm_triple_fault() }
+    }
+
+    // The host only injects the vector into the payload, which resumes
+    // right away, unless a signal has to be delivered.
+    if frame.vector == u64::from(SIGNAL_VECTOR) {
+        let ctx = frame.context();
+        fetch_signal();
+        signal::deliver(ctx);
+        unsafe { resume(&ctx) }
+    }
+
+    let mut cr2 = 0;
+
+    let (signo, code, value) = match frame.vector {
+        0 => (libc::SIGFPE, FPE_INTDIV, frame.rip),
+        16 | 19 => (libc::SIGFPE, 0, frame.rip),
+        1 | 3 => (libc::SIGTRAP, TRAP_BRKPT, frame.rip),
+        6 => (libc::SIGILL, ILL_ILLOPN, frame.rip),
+        17 => (libc::SIGBUS, BUS_ADRALN, 0),
+        14 => {
+            cr2 = Cr2::read().as_u64();
+            let code = match frame.error_code & 0x1 {
+                0 => SEGV_MAPERR,
+                _ => SEGV_ACCERR,
+            };
+            (libc::SIGSEGV, code, cr2)
+        }
+        _ => (libc::SIGSEGV, SI_KERNEL, 0),
+    };
+
+    let info = Info {
+        signo,
+        code,
+        value,
+        trapno: frame.vector,
+        err: frame.error_code,
+    };
+
+    signal::fault(frame.context(), info, cr2)
+}
+
+/// The global IDT
+static IDT: Lazy<InterruptDescriptorTable> = Lazy::new(|| {
+    let mut idt = InterruptDescriptorTable::new();
+
+    let addr = |f: unsafe extern "sysv64" fn() -> !| VirtAddr::new(f as usize as u64);
+
+    unsafe {
+        idt.divide_error.set_handler_addr(addr(divide_error));
+        idt.debug.set_handler_addr(addr(debug));
+        idt.breakpoint.set_handler_addr(addr(breakpoint));
+        idt.bound_range_exceeded
+            .set_handler_addr(addr(bound_range_exceeded));
+        idt.invalid_opcode.set_handler_addr(addr(invalid_opcode));
+        idt.segment_not_present
+            .set_handler_addr(addr(segment_not_present));
+        idt.stack_segment_fault
+            .set_handler_addr(addr(stack_segment_fault));
+        idt.general_protection_fault
+            .set_handler_addr(addr(general_protection_fault));
+        idt.page_fault.set_handler_addr(addr(page_fault));
+        idt.x87_floating_point
+            .set_handler_addr(addr(x87_floating_point));
+        idt.alignment_check.set_handler_addr(addr(alignment_check));
+        idt.simd_floating_point
+            .set_handler_addr(addr(simd_floating_point));
+        idt[usize::from(SIGNAL_VECTOR)].set_handler_addr(addr(host_signal));
+    }
+
+    idt
+});
+
+/// Load the IDT
+///
+/// # Safety
+///
+/// `unsafe` because the caller has to ensure it is only called once
+/// and in a single-threaded context.
+pub unsafe fn init() {
+    #[cfg(debug_assertions)]
+    crate::eprintln!("init_idt");
+
+    IDT.load();
+}
@@ -22,19 +22,26 @@ pub mod addr;
 pub mod allocator;
 pub mod asm;
 pub mod attestation;
+pub mod crypt;
 pub mod gdt;
 pub mod hostcall;
 pub mod hostmap;
+pub mod interrupts;
 pub mod no_std;
 pub mod pagetables;
 pub mod paging;
 pub mod payload;
+pub mod poll;
 pub mod random;
 pub mod shim_stack;
+pub mod signal;
 pub mod spin;
 mod start;
 pub mod syscall;
+pub mod time;
+pub mod tls;
 pub mod usermode;
+pub mod vdso;
 
 use crate::attestation::SevSecret;
 use crate::pagetables::switch_sallyport_to_unencrypted;
@@ -68,6 +75,8 @@ extern "C" {
     /// Extern
     pub static _ENARX_SALLYPORT_END: Page4KiB;
     /// Extern
+    pub static _ENARX_SALLYPORT_RING: Page4KiB;
+    /// Extern
     pub static _ENARX_MEM_START: Page4KiB;
     /// Extern
     pub static _ENARX_SHIM_START: Page4KiB;
@@ -127,7 +136,10 @@ pub unsafe extern "sysv64" fn _start_main(c_bit_mask: u64) -> ! {
 
 /// The entry point for the shim
 extern "C" fn shim_main() -> ! {
-    unsafe { gdt::init() };
+    unsafe {
+        gdt::init();
+        interrupts::init();
+    }
     payload::execute_payload()
 }
 
@@ -7,6 +7,7 @@ use crate::paging::SHIM_PAGETABLE;
 use crate::random::random;
 use crate::shim_stack::init_stack_with_guard;
 use crate::usermode::usermode;
+use crate::vdso;
 use crate::{get_cbit_mask, PAYLOAD_READY};
 
 use core::convert::TryFrom;
@@ -31,10 +32,33 @@ const PAYLOAD_BRK_VIRT_ADDR_BASE: VirtAddr = VirtAddr::new_truncate(0x5555_0000_
 /// Payload stack virtual address
 const PAYLOAD_STACK_VIRT_ADDR_BASE: VirtAddr = VirtAddr::new_truncate(0x7ff0_0000_0000);
 
+/// The vDSO virtual address, minus the vvar page, plus a random offset
+const PAYLOAD_VDSO_VIRT_ADDR_BASE: VirtAddr = VirtAddr::new_truncate(0x7fe0_0000_0000);
+
 /// Initial payload stack size
 #[allow(clippy::integer_arithmetic)]
 const PAYLOAD_STACK_SIZE: u64 = bytes![8; MiB];
 
+/// The `AT_HWCAP2` bit of the FSGSBASE instructions, which the shim enables
+const HWCAP2_FSGSBASE: usize = 2;
+
+/// The hardware capabilities of the payload for `AT_HWCAP` and `AT_HWCAP2`
+///
+/// Like Linux, `AT_HWCAP` is `edx` of CPUID leaf 1. The CPUID of the vCPU
+/// is the one filtered by the CPUID policy of the loader.
+fn hwcap() -> (usize, usize) {
+    use core::arch::x86_64::{__cpuid, __cpuid_count};
+
+    let max = unsafe { __cpuid(0) }.eax;
+    let hwcap = unsafe { __cpuid(1) }.edx as usize;
+    let fsgsbase = max >= 7 && unsafe { __cpuid_count(7, 0) }.ebx & 1 != 0;
+
+    match fsgsbase {
+        true => (hwcap, HWCAP2_FSGSBASE),
+        false => (hwcap, 0),
+    }
+}
+
 /// The randomized virtual address of the payload
 pub static PAYLOAD_VIRT_ADDR: Lazy<RwLock<VirtAddr>> = Lazy::new(|| {
     RwLock::<VirtAddr>::const_new(
@@ -127,6 +151,7 @@ fn crt0setup(
     app_virt_start: VirtAddr,
     stack_slice: &'static mut [u8],
     header: &Header,
+    vdso: VirtAddr,
 ) -> (VirtAddr, u64) {
     let mut builder = Builder::new(stack_slice);
     builder.push("/init").unwrap();
@@ -137,11 +162,11 @@ fn crt0setup(
     let ph_header = app_virt_start + header.e_phoff;
     let ph_entry = app_virt_start + header.e_entry;
 
-    let hwcap = unsafe { core::arch::x86_64::__cpuid(1) }.edx;
+    let (hwcap, hwcap2) = hwcap();
     let rand = unsafe { core::mem::transmute([random(), random()]) };
 
     for aux in &[
-        //Entry::SysInfoEHdr(0x7FD735C0E000),
+        Entry::SysInfoEHdr(vdso.as_u64() as _),
         Entry::ExecFilename("/init"),
         Entry::Platform("x86_64"),
         Entry::Uid(1000),
@@ -156,8 +181,8 @@ fn crt0setup(
         Entry::PHdr(ph_header.as_u64() as _),
         Entry::PHent(header.e_phentsize as _),
         Entry::PHnum(header.e_phnum as _),
-        Entry::HwCap(hwcap as _),
-        Entry::HwCap2(0),
+        Entry::HwCap(hwcap),
+        Entry::HwCap2(hwcap2),
         Entry::Random(rand),
         Entry::Entry(ph_entry.as_u64() as _),
     ] {
@@ -179,7 +204,9 @@ pub fn execute_payload() -> ! {
         PageTableFlags::USER_ACCESSIBLE,
     );
 
-    let (entry, sp_handle) = crt0setup(*PAYLOAD_VIRT_ADDR.read(), stack.slice, header);
+    let vdso = vdso::map(PAYLOAD_VDSO_VIRT_ADDR_BASE + (random() & 0xFFFF_F000));
+
+    let (entry, sp_handle) = crt0setup(*PAYLOAD_VIRT_ADDR.read(), stack.slice, header, vdso);
 
     unsafe {
         PAYLOAD_READY.store(true, Ordering::Relaxed);
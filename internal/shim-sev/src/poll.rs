@@ -0,0 +1,70 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The state of `poll`, `select` and `epoll` marshalling
+//!
+//! The `poll` crate checks the events returned by the host and tracks the
+//! `epoll` registrations and the shared file descriptors.
+
+use crate::spin::Locked;
+
+use ::poll::Poll;
+
+pub use ::poll::{poll_timeout, set_words, timespec_timeout, timeval_timeout, Host};
+
+/// The `epoll` registrations and the shared file descriptors of the keep
+static POLL: Locked<Poll> = Locked::new(Poll::new());
+
+/// Forget the registrations and the sharing of `fd`, which is closed or new
+pub fn forget(fd: libc::c_int) {
+    POLL.lock().forget(fd)
+}
+
+/// Poll `fds` on the host and set their returned events
+pub fn poll(
+    host: &mut impl Host,
+    fds: &mut [libc::pollfd],
+    timeout: Option<libc::timespec>,
+) -> Result<usize, libc::c_int> {
+    POLL.lock().poll(host, fds, timeout)
+}
+
+/// Wait for the file descriptors in the `fd_set` words `sets` below `nfds`
+pub fn select(
+    host: &mut impl Host,
+    nfds: libc::c_int,
+    sets: [Option<&mut [u64]>; 3],
+    timeout: Option<libc::timespec>,
+) -> Result<usize, libc::c_int> {
+    POLL.lock().select(host, nfds, sets, timeout)
+}
+
+/// Register, modify or remove `fd` with the `epoll` instance `epfd`
+pub fn epoll_ctl(
+    host: &mut impl Host,
+    epfd: libc::c_int,
+    op: libc::c_int,
+    fd: libc::c_int,
+    event: Option<libc::epoll_event>,
+) -> Result<(), libc::c_int> {
+    POLL.lock().epoll_ctl(host, epfd, op, fd, event)
+}
+
+/// Wait for events of the `epoll` instance `epfd`
+pub fn epoll_wait(
+    host: &mut impl Host,
+    epfd: libc::c_int,
+    events: &mut [libc::epoll_event],
+    timeout: libc::c_int,
+) -> Result<usize, libc::c_int> {
+    POLL.lock().epoll_wait(host, epfd, events, timeout)
+}
+
+/// Duplicate `fd` with the syscall of the payload and track the duplicate
+pub fn dup<H: Host>(
+    host: &mut H,
+    fd: libc::c_int,
+    new: libc::c_int,
+    duplicate: impl FnOnce(&mut H) -> Result<libc::c_int, libc::c_int>,
+) -> Result<libc::c_int, libc::c_int> {
+    POLL.lock().dup(host, fd, new, duplicate)
+}
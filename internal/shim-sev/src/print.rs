@@ -6,12 +6,24 @@ use crate::hostcall::{self, HostFd};
 
 struct HostWrite(HostFd);
 
+use abi::note::{self, Note};
 use core::fmt;
 use core::sync::atomic::{AtomicUsize, Ordering};
 
-// FIXME: remove, if https://github.com/enarx/enarx/issues/831 is fleshed out
-/// Global flag allowing debug output.
-pub const TRACE: bool = false;
+/// Whether debug output is enabled
+///
+/// The loader sets the descriptor to a non-zero value with `exec --debug`.
+/// The note is part of the loaded and measured shim, so enabling debug
+/// output changes the measurement of the keep.
+#[used]
+#[link_section = ".note"]
+static NOTE_ENARX_DEBUG: Note<u32> = Note::new(note::DEBUG, 0);
+
+/// Returns true, if the loader enabled debug output
+#[inline(always)]
+pub fn is_trace_enabled() -> bool {
+    NOTE_ENARX_DEBUG.desc() != 0
+}
 
 /// start with printing disabled
 static mut PRINT_INHIBITOR: AtomicUsize = AtomicUsize::new(1);
@@ -107,7 +119,7 @@ pub fn _eprint(args: fmt::Arguments) {
 #[macro_export]
 macro_rules! print {
     ($($arg:tt)*) => {
-       if $crate::print::TRACE { $crate::print::_print(format_args!($($arg)*)); }
+       if $crate::print::is_trace_enabled() { $crate::print::_print(format_args!($($arg)*)); }
     };
 }
 
@@ -147,7 +159,7 @@ macro_rules! println {
 #[macro_export]
 macro_rules! eprint {
     ($($arg:tt)*) => {
-        if $crate::print::TRACE { $crate::print::_eprint(format_args!($($arg)*)) };
+        if $crate::print::is_trace_enabled() { $crate::print::_eprint(format_args!($($arg)*)) };
     };
 }
 
@@ -1,16 +1,56 @@
 // SPDX-License-Identifier: Apache-2.0
 
 //! Random functions
+//!
+//! The randomness of the payload never comes from the host: the `random`
+//! crate serves `getrandom` and reads of `/dev/urandom` and `/dev/random`
+//! from a DRBG seeded with `rdrand`.
+
+use crate::spin::Locked;
+
+use ::random::{rdrand, Random};
+
+pub use ::random::{check_flags, is_device, MAX_READ};
+
+/// The random state of the keep
+static RANDOM: Locked<Random> = Locked::new(Random::new());
 
 /// Get a random number
 pub fn random() -> u64 {
-    let mut r: u64 = 0;
-
-    for _ in 0..1024 {
-        if unsafe { core::arch::x86_64::_rdrand64_step(&mut r) } == 1 {
-            return r;
-        }
+    match rdrand() {
+        Some(r) => r,
+        None => panic!("Could not get random!"),
     }
+}
+
+/// Fill `buf` with random bytes
+///
+/// Fails with `EIO`, if the CPU can't provide the entropy to seed the DRBG.
+pub fn fill(buf: &mut [u8]) -> Result<(), libc::c_int> {
+    RANDOM.lock().fill(rdrand, buf)
+}
+
+/// Whether another random device can be opened
+pub fn can_open() -> bool {
+    RANDOM.lock().can_open()
+}
+
+/// Track the file descriptor `fd` opened for a random device
+pub fn open(fd: libc::c_int) -> Result<(), libc::c_int> {
+    RANDOM.lock().open(fd)
+}
+
+/// Whether `fd` is a random device
+pub fn is_open(fd: libc::c_int) -> bool {
+    RANDOM.lock().is_open(fd)
+}
+
+/// Stop tracking the closed random device `fd`
+pub fn close(fd: libc::c_int) {
+    RANDOM.lock().close(fd)
+}
 
-    panic!("Could not get random!")
+/// Stop tracking the random devices closed by `close_range` from `first` to `last`
+pub fn close_range(first: libc::c_uint, last: libc::c_uint, flags: libc::c_uint) {
+    RANDOM.lock().close_range(first, last, flags)
 }
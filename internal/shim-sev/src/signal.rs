@@ -0,0 +1,179 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! POSIX signals of the payload
+//!
+//! The shim keeps the signal state of the payload in `SIGNALS` and delivers
+//! a signal on the way back to the payload, by building the signal frame on
+//! the payload stack and resuming the payload in the signal handler.
+//! `rt_sigreturn` restores the registers and the extended state saved in the
+//! frame. The shim and the payload share the extended state, which is saved
+//! with `xsave64` and restored with `xrstor64`.
+
+use crate::eprintln;
+use crate::hostcall::shim_exit;
+use crate::paging::SHIM_PAGETABLE;
+use crate::spin::Locked;
+use crate::usermode::resume;
+use core::sync::atomic::{AtomicU64, Ordering};
+use signals::frame::{self, Payload};
+use signals::{bit, Delivery, Signals, USER_FLAGS};
+use x86_64::registers::rflags::RFlags;
+use x86_64::registers::xcontrol::XCr0;
+use x86_64::structures::paging::Translate;
+use x86_64::VirtAddr;
+
+pub use signals::{Context, Info, SigAction, SI_TKILL, SI_USER};
+
+/// The end of the lower half of the address space, where the payload lives
+const USER_END: u64 = 0x0000_8000_0000_0000;
+
+/// The signal state of the payload
+pub static SIGNALS: Locked<Signals> = Locked::new(Signals::new());
+
+/// The signals forwarded by the host, which are not pending yet
+///
+/// The host passes them on the way back from a hostcall, which may be made
+/// while `SIGNALS` is locked.
+static HOST: AtomicU64 = AtomicU64::new(0);
+
+/// Mark a signal forwarded by the host as pending
+///
+/// Invalid signal numbers are ignored, because the host can't be trusted.
+pub fn host(signo: u64) {
+    if let 1..=64 = signo {
+        HOST.fetch_or(bit(signo as libc::c_int), Ordering::SeqCst);
+    }
+}
+
+/// The payload in the lower half of the address space of the shim
+struct User;
+
+impl Payload for User {
+    fn is_payload(&self, addr: u64, len: u64) -> bool {
+        is_user(addr, len)
+    }
+
+    fn xfeatures(&self) -> u64 {
+        XCr0::read_raw()
+    }
+
+    unsafe fn xsave(&mut self, area: *mut u8) {
+        let mask = self.xfeatures() & signals::xsave::KNOWN;
+
+        asm!(
+            "xsave64 [{}]",
+            in(reg) area,
+            in("eax") mask as u32,
+            in("edx") mask.wrapping_shr(32) as u32,
+            options(nostack)
+        );
+    }
+
+    unsafe fn xrstor(&mut self, area: *const u8) {
+        let mask = self.xfeatures() & signals::xsave::KNOWN;
+
+        asm!(
+            "xrstor64 [{}]",
+            in(reg) area,
+            in("eax") mask as u32,
+            in("edx") mask.wrapping_shr(32) as u32,
+            options(nostack)
+        );
+    }
+}
+
+/// Check, if `len` bytes at `addr` are mapped payload memory
+fn is_user(addr: u64, len: u64) -> bool {
+    let end = match addr.checked_add(len) {
+        Some(end) if end <= USER_END && len > 0 => end,
+        _ => return false,
+    };
+
+    let table = SHIM_PAGETABLE.read();
+    let mut page = addr & !0xFFF;
+
+    while page < end {
+        if table.translate_addr(VirtAddr::new(page)).is_none() {
+            return false;
+        }
+
+        page = page.wrapping_add(0x1000);
+    }
+
+    true
+}
+
+/// Terminate the payload, like the default action of `signo` would
+fn terminate(signo: libc::c_int) -> ! {
+    eprintln!("SC> payload killed by signal {}", signo);
+    shim_exit(128i32.wrapping_add(signo))
+}
+
+/// Build the signal frame on the payload stack and run the handler
+fn run(ctx: Context, info: Info, action: SigAction, mask: u64, cr2: u64) -> ! {
+    let segments = crate::gdt::USER_CODE_SEGMENT | crate::gdt::USER_DATA_SEGMENT.wrapping_shl(48);
+
+    match frame::enter(&mut User, &ctx, &info, &action, mask, cr2, segments) {
+        Some(regs) => unsafe { resume(&regs) },
+        None => terminate(libc::SIGSEGV),
+    }
+}
+
+/// Take the next pending signal, including the signals forwarded by the host
+fn take() -> Option<(Info, Delivery)> {
+    let mut signals = SIGNALS.lock();
+
+    let mut host = HOST.swap(0, Ordering::SeqCst);
+    while host != 0 {
+        let signo = host.trailing_zeros().wrapping_add(1) as libc::c_int;
+        let _ = signals.raise(signo, SI_USER);
+        host &= host.wrapping_sub(1);
+    }
+
+    signals.take()
+}
+
+/// Deliver the next pending signal to the payload, which would resume with `ctx`
+///
+/// Returns, if there is no signal to deliver.
+pub fn deliver(ctx: Context) {
+    match take() {
+        None => {}
+        Some((info, Delivery::Terminate)) => terminate(info.signo),
+        Some((info, Delivery::Handler(action, mask))) => run(ctx, info, action, mask, 0),
+    }
+}
+
+/// Deliver a synchronous fault of the payload
+pub fn fault(ctx: Context, info: Info, cr2: u64) -> ! {
+    let delivery = SIGNALS.lock().fault(info.signo);
+
+    match delivery {
+        Delivery::Terminate => terminate(info.signo),
+        Delivery::Handler(action, mask) => run(ctx, info, action, mask, cr2),
+    }
+}
+
+/// Return from a signal handler
+///
+/// `ctx` is the context of the `rt_sigreturn` syscall, which is called by
+/// the restorer, after the handler returned and popped the restorer address.
+pub fn sigreturn(ctx: Context) -> ! {
+    let (mut regs, mask) = match frame::sigreturn(&mut User, ctx.rsp) {
+        Some(saved) => saved,
+        None => terminate(libc::SIGSEGV),
+    };
+
+    regs.rflags = (regs.rflags & USER_FLAGS) | RFlags::INTERRUPT_FLAG.bits();
+
+    if regs.rip >= USER_END || regs.rsp >= USER_END {
+        terminate(libc::SIGSEGV)
+    }
+
+    SIGNALS.lock().restore(mask);
+
+    // Signals unblocked by the restored mask are delivered right away.
+    deliver(regs);
+
+    unsafe { resume(&regs) }
+}
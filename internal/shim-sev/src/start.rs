@@ -171,6 +171,13 @@ define_addr code32_start 30f
     or      eax,    0x50620
     mov     cr4,    eax
 
+    // setup XCR0
+    // XCR0 |= X87 | SSE, so XSAVE saves the SSE state of the payload, too
+    xor     ecx,    ecx
+    xgetbv
+    or      eax,    0x3
+    xsetbv
+
     // setup EFER
     // EFER |= LONG_MODE_ACTIVE | LONG_MODE_ENABLE | NO_EXECUTE_ENABLE | SYSTEM_CALL_EXTENSIONS
     // FIXME: what about already set bits?
@@ -5,13 +5,20 @@
 use crate::addr::{HostVirtAddr, ShimPhysUnencryptedAddr};
 use crate::allocator::ALLOCATOR;
 use crate::asm::_enarx_asm_triple_fault;
+use crate::crypt::{self, Host};
 use crate::hostcall::{HostCall, HOST_CALL_ALLOC};
 use crate::paging::SHIM_PAGETABLE;
 use crate::payload::{NEXT_BRK_RWLOCK, NEXT_MMAP_RWLOCK};
+use crate::poll;
+use crate::random;
+use crate::signal::{self, Context, SigAction};
+use crate::time;
+use crate::tls;
 use crate::{eprintln, C_BIT_MASK, SEV_SECRET};
 use core::convert::TryFrom;
 use core::mem::size_of;
 use core::ops::{Deref, DerefMut};
+use core::ptr::null_mut;
 use core::sync::atomic::Ordering;
 use primordial::{Address, Register};
 use sallyport::syscall::{
@@ -22,18 +29,70 @@ use sallyport::syscall::{
 use sallyport::untrusted::{
     AddressValidator, UntrustedRef, UntrustedRefMut, Validate, ValidateSlice,
 };
-use sallyport::{Cursor, Request};
+use sallyport::{request, Cursor, Request};
 use x86_64::instructions::segmentation::{Segment64, FS, GS};
 use x86_64::instructions::tlb::flush_all;
 use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
 use x86_64::{align_up, VirtAddr};
 
+/// The maximum size of the directory entries read at once
+const DENTS_MAX: usize = 4096;
+
 #[repr(C)]
 struct X8664DoubleReturn {
     rax: u64,
     rdx: u64,
 }
 
+/// The registers of the payload saved by `_syscall_enter`
+#[repr(C)]
+pub struct SyscallFrame {
+    r15: u64,
+    r14: u64,
+    r13: u64,
+    r12: u64,
+    rbp: u64,
+    r9: u64,
+    r8: u64,
+    r10: u64,
+    r11: u64,
+    rdx: u64,
+    rsi: u64,
+    rdi: u64,
+    rbx: u64,
+    rip: u64,
+    cs: u64,
+    rflags: u64,
+    rsp: u64,
+    ss: u64,
+}
+
+impl SyscallFrame {
+    /// The context of the payload returning `rax` and `rdx` from the syscall
+    fn context(&self, rax: u64, rdx: u64) -> Context {
+        Context {
+            r8: self.r8,
+            r9: self.r9,
+            r10: self.r10,
+            r11: 0,
+            r12: self.r12,
+            r13: self.r13,
+            r14: self.r14,
+            r15: self.r15,
+            rdi: self.rdi,
+            rsi: self.rsi,
+            rbp: self.rbp,
+            rbx: self.rbx,
+            rdx,
+            rax,
+            rcx: 0,
+            rsp: self.rsp,
+            rip: self.rip,
+            rflags: self.rflags,
+        }
+    }
+}
+
 /// syscall service routine
 ///
 /// # Safety
@@ -79,15 +138,31 @@ pub unsafe extern "sysv64" fn _syscall_enter() -> ! {
     push   r8
     push   r9
 
+    # save the callee saved registers for signal frames
+    push   rbp
+    push   r12
+    push   r13
+    push   r14
+    push   r15
+
+    # pointer to the saved registers on the stack as the eighth argument
+    push   rsp
+
     # syscall number on the stack as the seventh argument
     push   rax
 
     call   {syscall_rust}
 
     # skip %rax pop, as it is the return value
-    add    rsp,                     0x8
+    # and the pointer to the saved registers
+    add    rsp,                     0x10
 
     # restore registers
+    pop    r15
+    pop    r14
+    pop    r13
+    pop    r12
+    pop    rbp
     pop    r9
     pop    r8
     pop    r10
@@ -116,6 +191,7 @@ pub unsafe extern "sysv64" fn _syscall_enter() -> ! {
 
 /// Handle a syscall in rust
 #[allow(clippy::many_single_char_names)]
+#[allow(clippy::too_many_arguments)]
 extern "sysv64" fn syscall_rust(
     a: Register<usize>,
     b: Register<usize>,
@@ -124,7 +200,12 @@ extern "sysv64" fn syscall_rust(
     e: Register<usize>,
     f: Register<usize>,
     nr: usize,
+    frame: &mut SyscallFrame,
 ) -> X8664DoubleReturn {
+    if nr == libc::SYS_rt_sigreturn as usize {
+        signal::sigreturn(frame.context(0, 0))
+    }
+
     let orig_rdx: usize = c.into();
 
     let mut h = Handler {
@@ -132,9 +213,24 @@ extern "sysv64" fn syscall_rust(
         argv: [a.into(), b.into(), c.into(), d.into(), e.into(), f.into()],
     };
 
-    let ret = h.syscall(a, b, c, d, e, f, nr);
+    let ret = match h
+        .signal_syscall(nr)
+        .or_else(|| h.poll_syscall(nr))
+        .or_else(|| h.time_syscall(nr))
+        .or_else(|| h.random_syscall(nr))
+        .or_else(|| h.open_syscall(nr))
+        .or_else(|| h.crypt_syscall(nr))
+        .or_else(|| h.tls_syscall(nr))
+        .or_else(|| h.dup_syscall(nr))
+    {
+        Some(ret) => ret,
+        None => h.syscall(a, b, c, d, e, f, nr),
+    };
 
-    match ret {
+    // Free the sallyport block, before a signal handler might be entered.
+    drop(h);
+
+    let ret = match ret {
         Err(e) => X8664DoubleReturn {
             rax: e.checked_neg().unwrap() as _,
             // Preserve `rdx` as it is normally not clobbered with a syscall
@@ -144,7 +240,11 @@ extern "sysv64" fn syscall_rust(
             rax: rax.into(),
             rdx: rdx.into(),
         },
-    }
+    };
+
+    signal::deliver(frame.context(ret.rax, ret.rdx));
+
+    ret
 }
 
 /// The syscall Handler
@@ -153,6 +253,1267 @@ struct Handler {
     argv: [usize; 6],
 }
 
+impl Handler {
+    /// Handle the signal syscalls, which are emulated by the shim
+    ///
+    /// Returns `None` for all other syscalls.
+    fn signal_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        let [a, b, c, d, ..] = self.argv;
+
+        Some(match nr as libc::c_long {
+            libc::SYS_rt_sigaction => {
+                self.trace("rt_sigaction", 4);
+                self.rt_sigaction(a as _, b as _, c as _, d)
+            }
+
+            libc::SYS_rt_sigprocmask => {
+                self.trace("rt_sigprocmask", 4);
+                self.rt_sigprocmask(a as _, b as _, c as _, d)
+            }
+
+            libc::SYS_kill => {
+                self.trace("kill", 2);
+                self.kill(libc::SYS_getpid, a, b as _, signal::SI_USER)
+            }
+
+            libc::SYS_tkill => {
+                self.trace("tkill", 2);
+                self.kill(libc::SYS_gettid, a, b as _, signal::SI_TKILL)
+            }
+
+            libc::SYS_tgkill => {
+                self.trace("tgkill", 3);
+                self.kill(libc::SYS_gettid, b, c as _, signal::SI_TKILL)
+            }
+
+            _ => return None,
+        })
+    }
+
+    fn rt_sigaction(
+        &mut self,
+        signo: libc::c_int,
+        act: *const SigAction,
+        oldact: *mut SigAction,
+        size: usize,
+    ) -> sallyport::Result {
+        if size != size_of::<u64>() {
+            return Err(libc::EINVAL);
+        }
+
+        let act = match act.is_null() {
+            true => None,
+            false => Some(*UntrustedRef::from(act).validate(self).ok_or(libc::EFAULT)?),
+        };
+
+        let old = signal::SIGNALS.lock().action(signo, act)?;
+
+        if !oldact.is_null() {
+            *UntrustedRefMut::from(oldact)
+                .validate(self)
+                .ok_or(libc::EFAULT)? = old;
+        }
+
+        Ok(Default::default())
+    }
+
+    fn rt_sigprocmask(
+        &mut self,
+        how: libc::c_int,
+        set: *const u64,
+        oldset: *mut u64,
+        size: usize,
+    ) -> sallyport::Result {
+        if size != size_of::<u64>() {
+            return Err(libc::EINVAL);
+        }
+
+        let set = match set.is_null() {
+            true => None,
+            false => Some(*UntrustedRef::from(set).validate(self).ok_or(libc::EFAULT)?),
+        };
+
+        let old = signal::SIGNALS.lock().mask(how, set)?;
+
+        if !oldset.is_null() {
+            *UntrustedRefMut::from(oldset)
+                .validate(self)
+                .ok_or(libc::EFAULT)? = old;
+        }
+
+        Ok(Default::default())
+    }
+
+    /// Send a signal to the payload itself
+    ///
+    /// The payload can't signal any other process of the host.
+    fn kill(
+        &mut self,
+        id_nr: libc::c_long,
+        id: usize,
+        signo: libc::c_int,
+        code: libc::c_int,
+    ) -> sallyport::Result {
+        let own: usize = unsafe { self.proxy(request!(id_nr)) }?[0].into();
+
+        if id != own && !(id_nr == libc::SYS_getpid && id == 0) {
+            return Err(libc::EPERM);
+        }
+
+        signal::SIGNALS.lock().raise(signo, code)?;
+        Ok(Default::default())
+    }
+
+    /// Proxy `open` and `openat`, which the loader resolves beneath the granted
+    /// directories, and `getdents64` to list the directories
+    ///
+    /// Returns `None` for all other syscalls.
+    fn open_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        let [a, b, c, d, ..] = self.argv;
+
+        Some(match nr as libc::c_long {
+            libc::SYS_open => {
+                self.trace("open", 3);
+                self.openat(libc::AT_FDCWD, a as _, b as _, c as _)
+            }
+
+            libc::SYS_openat => {
+                self.trace("openat", 4);
+                self.openat(a as _, b as _, c as _, d as _)
+            }
+
+            libc::SYS_getdents64 => {
+                self.trace("getdents64", 3);
+                self.getdents64(a as _, b as _, c as _)
+            }
+
+            _ => return None,
+        })
+    }
+
+    /// Read directory entries, at most `DENTS_MAX` bytes at once
+    fn getdents64(&mut self, fd: libc::c_int, dirp: *mut u8, count: usize) -> sallyport::Result {
+        let buf = UntrustedRefMut::from(dirp)
+            .validate_slice(count, self)
+            .ok_or(libc::EFAULT)?;
+
+        let len = count.min(DENTS_MAX);
+        let c = self.new_cursor();
+        let (_, untrusted) = c.alloc::<u8>(len).or(Err(libc::EMSGSIZE))?;
+        let host_virt = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        let ret = unsafe { self.proxy(request!(libc::SYS_getdents64 => fd, host_virt, len))? };
+        let read: usize = ret[0].into();
+        if read > len {
+            self.attacked();
+        }
+
+        let c = self.new_cursor();
+        unsafe { c.copy_into_raw_parts(len, buf.as_mut_ptr(), read) }.or(Err(libc::EMSGSIZE))?;
+
+        Ok(ret)
+    }
+
+    fn openat(
+        &mut self,
+        dirfd: libc::c_int,
+        path: *const u8,
+        flags: libc::c_int,
+        mode: libc::mode_t,
+    ) -> sallyport::Result {
+        let mut len = 0usize;
+        loop {
+            if len == libc::PATH_MAX as usize {
+                return Err(libc::ENAMETOOLONG);
+            }
+
+            let byte = UntrustedRef::from(path.wrapping_add(len))
+                .validate(self)
+                .ok_or(libc::EFAULT)?;
+
+            if *byte == 0 {
+                break;
+            }
+
+            len = len.wrapping_add(1);
+        }
+
+        // Include the terminating NUL.
+        let path = UntrustedRef::from(path)
+            .validate_slice(len.wrapping_add(1), self)
+            .ok_or(libc::EFAULT)?;
+
+        if random::is_device(&path[..len]) {
+            return self.open_random(flags);
+        }
+
+        let encrypted = crypt::is_encrypted(dirfd, &path[..len])?;
+
+        let c = self.new_cursor();
+        let (_, untrusted) = c.copy_from_slice(path).or(Err(libc::ENAMETOOLONG))?;
+        let host_virt = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        let ret =
+            unsafe { self.proxy(request!(libc::SYS_openat => dirfd, host_virt, flags, mode))? };
+
+        if encrypted {
+            let fd: usize = ret[0].into();
+            if let Err(e) = crypt::open(fd as _, &path[..len], flags) {
+                let _ = unsafe { self.proxy(request!(libc::SYS_close => fd)) };
+                return Err(e);
+            }
+        }
+
+        Ok(ret)
+    }
+
+    /// Open a random device served by the shim
+    ///
+    /// The host only provides the file descriptor, with an `eventfd`.
+    fn open_random(&mut self, flags: libc::c_int) -> sallyport::Result {
+        if !random::can_open() {
+            return Err(libc::EMFILE);
+        }
+
+        let flags = flags & (libc::O_CLOEXEC | libc::O_NONBLOCK);
+        let ret = unsafe { self.proxy(request!(libc::SYS_eventfd2 => 0, flags))? };
+
+        random::open(usize::from(ret[0]) as _)?;
+        Ok(ret)
+    }
+
+    /// Handle the syscalls on encrypted files
+    ///
+    /// Returns `None` for all other syscalls and file descriptors. The
+    /// syscalls on encrypted files, which are not handled, are refused, so
+    /// neither plaintext nor the size of the ciphertext reaches the payload
+    /// or the host.
+    fn crypt_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        let [a, b, c, d, ..] = self.argv;
+        let fd = a as libc::c_int;
+
+        let fds = crypt::fd_args(nr as _, self.argv);
+        if !fds.iter().flatten().any(|fd| crypt::is_open(*fd)) {
+            return None;
+        }
+
+        Some(match nr as libc::c_long {
+            libc::SYS_read | libc::SYS_pread64 => {
+                let offset = match nr as libc::c_long {
+                    libc::SYS_read => None,
+                    _ => Some(d as u64),
+                };
+
+                self.trace("read", 3);
+                UntrustedRefMut::from(b as *mut u8)
+                    .validate_slice(c, self)
+                    .ok_or(libc::EFAULT)
+                    .and_then(|buf| crypt::read(self, fd, buf, offset))
+                    .map(|read| [read.into(), 0.into()])
+            }
+
+            libc::SYS_write | libc::SYS_pwrite64 => {
+                let offset = match nr as libc::c_long {
+                    libc::SYS_write => None,
+                    _ => Some(d as u64),
+                };
+
+                self.trace("write", 3);
+                UntrustedRef::from(b as *const u8)
+                    .validate_slice(c, self)
+                    .ok_or(libc::EFAULT)
+                    .and_then(|buf| crypt::write(self, fd, buf, offset))
+                    .map(|written| [written.into(), 0.into()])
+            }
+
+            libc::SYS_readv | libc::SYS_writev => {
+                self.trace("readv", 3);
+                self.crypt_vectored(nr as _, fd, b as _, c as _)
+            }
+
+            libc::SYS_lseek => {
+                self.trace("lseek", 3);
+                crypt::seek(self, fd, b as _, c as _).map(|pos| [(pos as usize).into(), 0.into()])
+            }
+
+            libc::SYS_fstat => self.crypt_fstat(fd, b as _),
+
+            libc::SYS_close => {
+                crypt::close(fd);
+                return None;
+            }
+
+            // Neither the data nor its size is involved.
+            libc::SYS_fsync | libc::SYS_fdatasync | libc::SYS_flock | libc::SYS_fadvise64 => {
+                return None
+            }
+
+            // Duplicates would not share the file position, and the shim
+            // tracks `O_APPEND`.
+            libc::SYS_fcntl => match b as libc::c_int {
+                libc::F_GETFD | libc::F_SETFD | libc::F_GETFL => return None,
+                _ => Err(libc::EOPNOTSUPP),
+            },
+
+            _ => Err(libc::EOPNOTSUPP),
+        })
+    }
+
+    /// Do `fstat` on the host and replace the size of the ciphertext
+    fn crypt_fstat(&mut self, fd: libc::c_int, statbuf: *mut libc::stat) -> sallyport::Result {
+        let [a, b, c, d, e, f] = [fd as usize, statbuf as usize, 0, 0, 0, 0].map(Register::from);
+        let ret = self.syscall(a, b, c, d, e, f, libc::SYS_fstat as _)?;
+        let size = crypt::size(self, fd)?;
+
+        let stat = UntrustedRefMut::from(statbuf)
+            .validate(self)
+            .ok_or(libc::EFAULT)?;
+        stat.st_size = size as _;
+        stat.st_blocks = ((size + 511) / 512) as _;
+
+        Ok(ret)
+    }
+
+    /// Handle `readv` and `writev` on an encrypted file
+    fn crypt_vectored(
+        &mut self,
+        nr: libc::c_long,
+        fd: libc::c_int,
+        iovec: *const libc::iovec,
+        iovcnt: usize,
+    ) -> sallyport::Result {
+        let iovec = UntrustedRef::from(iovec)
+            .validate_slice(iovcnt, self)
+            .ok_or(libc::EFAULT)?;
+
+        let mut total = 0usize;
+        for iov in iovec {
+            let done = match nr {
+                libc::SYS_readv => {
+                    let buf = UntrustedRefMut::from(iov.iov_base as *mut u8)
+                        .validate_slice(iov.iov_len, self)
+                        .ok_or(libc::EFAULT)?;
+                    crypt::read(self, fd, buf, None)?
+                }
+                _ => {
+                    let buf = UntrustedRef::from(iov.iov_base as *const u8)
+                        .validate_slice(iov.iov_len, self)
+                        .ok_or(libc::EFAULT)?;
+                    crypt::write(self, fd, buf, None)?
+                }
+            };
+
+            total = total.checked_add(done).ok_or(libc::EINVAL)?;
+            if done < iov.iov_len {
+                break;
+            }
+        }
+
+        Ok([total.into(), 0.into()])
+    }
+
+    /// Handle `getrandom` and the syscalls on random devices
+    ///
+    /// Returns `None` for all other syscalls and file descriptors.
+    fn random_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        let [a, b, c, ..] = self.argv;
+        let fd = a as libc::c_int;
+
+        if nr as libc::c_long == libc::SYS_getrandom {
+            self.trace("getrandom", 3);
+            return Some(self.getrandom(a as _, b, c as _));
+        }
+
+        if nr as libc::c_long == libc::SYS_close_range {
+            self.trace("close_range", 3);
+            return Some(self.close_range(a as _, b as _, c as _));
+        }
+
+        if !random::is_open(fd) {
+            return None;
+        }
+
+        Some(match nr as libc::c_long {
+            libc::SYS_read | libc::SYS_pread64 => {
+                self.trace("read", 3);
+                self.getrandom(b as _, c, 0)
+            }
+
+            libc::SYS_readv => {
+                self.trace("readv", 3);
+                self.random_readv(b as _, c)
+            }
+
+            // Like Linux, writes don't add entropy without privileges.
+            libc::SYS_write | libc::SYS_pwrite64 => {
+                self.trace("write", 3);
+                UntrustedRef::from(b as *const u8)
+                    .validate_slice(c, self)
+                    .ok_or(libc::EFAULT)
+                    .map(|buf| [buf.len().into(), 0.into()])
+            }
+
+            libc::SYS_close => {
+                random::close(fd);
+                return None;
+            }
+
+            libc::SYS_dup | libc::SYS_dup2 | libc::SYS_dup3 => Err(libc::EOPNOTSUPP),
+
+            libc::SYS_fcntl => match b as libc::c_int {
+                libc::F_DUPFD | libc::F_DUPFD_CLOEXEC => Err(libc::EOPNOTSUPP),
+                _ => return None,
+            },
+
+            _ => return None,
+        })
+    }
+
+    /// Do `close_range` on the host and stop tracking the random devices it closed
+    fn close_range(
+        &mut self,
+        first: libc::c_uint,
+        last: libc::c_uint,
+        flags: libc::c_uint,
+    ) -> sallyport::Result {
+        let req = request!(libc::SYS_close_range => first as usize, last as usize, flags as usize);
+        let ret = unsafe { self.proxy(req)? };
+
+        random::close_range(first, last, flags);
+        Ok(ret)
+    }
+
+    fn getrandom(&mut self, buf: *mut u8, len: usize, flags: libc::c_uint) -> sallyport::Result {
+        random::check_flags(flags)?;
+
+        let len = len.min(random::MAX_READ);
+        let buf = UntrustedRefMut::from(buf)
+            .validate_slice(len, self)
+            .ok_or(libc::EFAULT)?;
+
+        random::fill(buf)?;
+        Ok([len.into(), 0.into()])
+    }
+
+    fn random_readv(&mut self, iovec: *const libc::iovec, iovcnt: usize) -> sallyport::Result {
+        let iovec = UntrustedRef::from(iovec)
+            .validate_slice(iovcnt, self)
+            .ok_or(libc::EFAULT)?;
+
+        let mut total = 0usize;
+        for iov in iovec {
+            let len = iov.iov_len.min(random::MAX_READ.saturating_sub(total));
+            let done: usize = self.getrandom(iov.iov_base as _, len, 0)?[0].into();
+            total = total.checked_add(done).ok_or(libc::EINVAL)?;
+        }
+
+        Ok([total.into(), 0.into()])
+    }
+}
+
+impl Handler {
+    /// Handle the time syscalls on the clocks served by the shim
+    ///
+    /// Returns `None` for all other syscalls and clocks.
+    fn time_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        let [a, b, ..] = self.argv;
+
+        Some(match nr as libc::c_long {
+            libc::SYS_clock_gettime => {
+                let ns = time::now(self, a as _)?;
+                self.trace("clock_gettime", 2);
+                ns.and_then(|ns| self.get_clock(ns, b as _))
+            }
+
+            libc::SYS_gettimeofday => {
+                self.trace("gettimeofday", 2);
+                self.get_timeofday(a as _, b as _)
+            }
+
+            libc::SYS_time => {
+                self.trace("time", 1);
+                self.get_time(a as _)
+            }
+
+            _ => return None,
+        })
+    }
+
+    fn get_clock(&mut self, ns: u64, tp: *mut libc::timespec) -> sallyport::Result {
+        *UntrustedRefMut::from(tp)
+            .validate(self)
+            .ok_or(libc::EFAULT)? = time::timespec(ns);
+
+        Ok(Default::default())
+    }
+
+    fn get_timeofday(&mut self, tv: *mut libc::timeval, tz: *mut libc::c_int) -> sallyport::Result {
+        if !tv.is_null() {
+            let tv = UntrustedRefMut::from(tv)
+                .validate(self)
+                .ok_or(libc::EFAULT)?;
+
+            let ns = time::now(self, libc::CLOCK_REALTIME).unwrap()?;
+            *tv = time::timeval(ns);
+        }
+
+        // The keep is always in UTC, without daylight saving time.
+        if !tz.is_null() {
+            UntrustedRefMut::from(tz)
+                .validate_slice(2, self)
+                .ok_or(libc::EFAULT)?
+                .fill(0);
+        }
+
+        Ok(Default::default())
+    }
+
+    fn get_time(&mut self, tloc: *mut libc::time_t) -> sallyport::Result {
+        let ns = time::now(self, libc::CLOCK_REALTIME).unwrap()?;
+        let sec = time::timespec(ns).tv_sec;
+
+        if !tloc.is_null() {
+            *UntrustedRefMut::from(tloc)
+                .validate(self)
+                .ok_or(libc::EFAULT)? = sec;
+        }
+
+        Ok([(sec as usize).into(), 0.into()])
+    }
+}
+
+impl Handler {
+    /// Handle the syscalls on TLS listeners and sessions
+    ///
+    /// Returns `None` for all other syscalls and file descriptors.
+    fn tls_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        if tls::port() == 0 {
+            return None;
+        }
+
+        let [a, b, c, d, e, f] = self.argv;
+        let fd = a as libc::c_int;
+
+        if nr as libc::c_long == libc::SYS_bind {
+            return Some(self.tls_bind(fd, b as _, c as _));
+        }
+
+        if tls::is_listener(fd) {
+            return self.tls_listener(nr, fd);
+        }
+
+        if !tls::is_open(fd) {
+            return None;
+        }
+
+        Some(match nr as libc::c_long {
+            libc::SYS_read => {
+                self.trace("read", 3);
+                self.tls_read(fd, b as _, c, null_mut(), null_mut())
+            }
+
+            libc::SYS_recvfrom => {
+                self.trace("recvfrom", 6);
+                match d {
+                    0 => self.tls_read(fd, b as _, c, e as _, f as _),
+                    _ => Err(libc::EOPNOTSUPP),
+                }
+            }
+
+            libc::SYS_write => {
+                self.trace("write", 3);
+                self.tls_write(fd, b as _, c)
+            }
+
+            libc::SYS_sendto => {
+                self.trace("sendto", 6);
+                match (d as libc::c_int & !libc::MSG_NOSIGNAL, e) {
+                    (0, 0) => self.tls_write(fd, b as _, c),
+                    _ => Err(libc::EOPNOTSUPP),
+                }
+            }
+
+            libc::SYS_readv | libc::SYS_writev => {
+                self.trace("readv", 3);
+                self.tls_vectored(nr as _, fd, b as _, c as _)
+            }
+
+            libc::SYS_shutdown => {
+                if b as libc::c_int != libc::SHUT_RD {
+                    tls::shutdown(&mut Socket { handler: self, fd }, fd);
+                }
+                return None;
+            }
+
+            libc::SYS_close => {
+                tls::close(&mut Socket { handler: self, fd }, fd);
+                return None;
+            }
+
+            // The session can't be shared or used without blocking.
+            libc::SYS_dup
+            | libc::SYS_dup2
+            | libc::SYS_dup3
+            | libc::SYS_recvmsg
+            | libc::SYS_sendmsg
+            | libc::SYS_sendfile
+            | libc::SYS_splice => Err(libc::EOPNOTSUPP),
+
+            libc::SYS_fcntl => match b as libc::c_int {
+                libc::F_DUPFD | libc::F_DUPFD_CLOEXEC => Err(libc::EOPNOTSUPP),
+                libc::F_SETFL if c as libc::c_int & libc::O_NONBLOCK != 0 => Err(libc::EOPNOTSUPP),
+                _ => return None,
+            },
+
+            libc::SYS_ioctl if b == libc::FIONBIO as usize => Err(libc::EOPNOTSUPP),
+
+            _ => return None,
+        })
+    }
+
+    /// Handle the syscalls on a TLS listener
+    fn tls_listener(&mut self, nr: usize, fd: libc::c_int) -> Option<sallyport::Result> {
+        let [a, b, c, d, e, f] = self.argv;
+
+        Some(match nr as libc::c_long {
+            libc::SYS_accept | libc::SYS_accept4 => {
+                // The accepted socket must block for the handshake.
+                let flags = match nr as libc::c_long {
+                    libc::SYS_accept => 0,
+                    _ => d & !(libc::SOCK_NONBLOCK as usize),
+                };
+
+                let ret = self.syscall(
+                    a.into(),
+                    b.into(),
+                    c.into(),
+                    flags.into(),
+                    e.into(),
+                    f.into(),
+                    libc::SYS_accept4 as _,
+                );
+
+                match ret {
+                    Ok(ret) => self.tls_accept(usize::from(ret[0]) as _).map(|_| ret),
+                    Err(e) => Err(e),
+                }
+            }
+
+            libc::SYS_close => {
+                tls::unlisten(fd);
+                return None;
+            }
+
+            libc::SYS_dup | libc::SYS_dup2 | libc::SYS_dup3 => Err(libc::EOPNOTSUPP),
+
+            libc::SYS_fcntl => match b as libc::c_int {
+                libc::F_DUPFD | libc::F_DUPFD_CLOEXEC => Err(libc::EOPNOTSUPP),
+                _ => return None,
+            },
+
+            _ => return None,
+        })
+    }
+
+    /// Bind a socket, which becomes a TLS listener for the configured port
+    fn tls_bind(
+        &mut self,
+        fd: libc::c_int,
+        addr: *const u8,
+        addrlen: libc::socklen_t,
+    ) -> sallyport::Result {
+        let mut bound = None;
+
+        if !addr.is_null() && addrlen >= 4 {
+            let addr = UntrustedRef::from(addr)
+                .validate_slice(4, self)
+                .ok_or(libc::EFAULT)?;
+
+            // The port follows the family in `sockaddr_in` and `sockaddr_in6`.
+            let family = u16::from_ne_bytes([addr[0], addr[1]]) as libc::c_int;
+            if family == libc::AF_INET || family == libc::AF_INET6 {
+                bound = Some(u16::from_be_bytes([addr[2], addr[3]]));
+            }
+        }
+
+        let listener = bound == Some(tls::port());
+        if listener && !tls::can_listen() {
+            return Err(libc::ENOBUFS);
+        }
+
+        let [a, b, c, d, e, f] = self.argv;
+        let ret = self.syscall(
+            a.into(),
+            b.into(),
+            c.into(),
+            d.into(),
+            e.into(),
+            f.into(),
+            libc::SYS_bind as _,
+        )?;
+
+        if listener {
+            tls::listen(fd)?;
+        }
+
+        Ok(ret)
+    }
+
+    /// Run the handshake on an accepted socket
+    ///
+    /// On failure, the socket is closed and the payload sees an aborted
+    /// connection.
+    fn tls_accept(&mut self, fd: libc::c_int) -> Result<(), libc::c_int> {
+        let ret = tls::accept(&mut Socket { handler: self, fd }, fd);
+
+        if ret.is_err() {
+            let req = request!(libc::SYS_close => fd);
+            let _ = unsafe { self.proxy(req) };
+        }
+
+        ret
+    }
+
+    fn tls_read(
+        &mut self,
+        fd: libc::c_int,
+        buf: *mut u8,
+        count: usize,
+        addr: *mut u8,
+        addrlen: *mut libc::socklen_t,
+    ) -> sallyport::Result {
+        let buf = UntrustedRefMut::from(buf)
+            .validate_slice(count, self)
+            .ok_or(libc::EFAULT)?;
+        let read = tls::read(&mut Socket { handler: self, fd }, fd, buf)?;
+
+        // A connected stream socket reports no address, like Linux.
+        if !addr.is_null() && !addrlen.is_null() {
+            let addrlen = UntrustedRefMut::from(addrlen)
+                .validate(self)
+                .ok_or(libc::EFAULT)?;
+            *addrlen = 0;
+        }
+
+        Ok([read.into(), 0.into()])
+    }
+
+    fn tls_write(&mut self, fd: libc::c_int, buf: *const u8, count: usize) -> sallyport::Result {
+        let buf = UntrustedRef::from(buf)
+            .validate_slice(count, self)
+            .ok_or(libc::EFAULT)?;
+        let written = tls::write(&mut Socket { handler: self, fd }, fd, buf)?;
+
+        Ok([written.into(), 0.into()])
+    }
+
+    /// Handle `readv` and `writev` on a TLS session
+    fn tls_vectored(
+        &mut self,
+        nr: libc::c_long,
+        fd: libc::c_int,
+        iovec: *const libc::iovec,
+        iovcnt: usize,
+    ) -> sallyport::Result {
+        let iovec = UntrustedRef::from(iovec)
+            .validate_slice(iovcnt, self)
+            .ok_or(libc::EFAULT)?;
+
+        let mut total = 0usize;
+        for iov in iovec {
+            let done: usize = match nr {
+                libc::SYS_readv => {
+                    // Only wait for more data, if nothing was read yet.
+                    if total > 0 && tls::pending(fd) == 0 {
+                        break;
+                    }
+
+                    let buf = iov.iov_base as *mut u8;
+                    self.tls_read(fd, buf, iov.iov_len, null_mut(), null_mut())?[0].into()
+                }
+                _ => self.tls_write(fd, iov.iov_base as _, iov.iov_len)?[0].into(),
+            };
+
+            total = total.checked_add(done).ok_or(libc::EINVAL)?;
+            if done < iov.iov_len {
+                break;
+            }
+        }
+
+        Ok([total.into(), 0.into()])
+    }
+}
+
+/// The maximum size of a single read or write on the host socket of a TLS session
+const TLS_IO_MAX: usize = 2048;
+
+/// The host socket of a TLS session
+struct Socket<'h> {
+    handler: &'h mut Handler,
+    fd: libc::c_int,
+}
+
+impl ::tls::Io for Socket<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, libc::c_int> {
+        let len = buf.len().min(TLS_IO_MAX);
+        let c = self.handler.new_cursor();
+        let (_, untrusted) = c.alloc::<u8>(len).or(Err(libc::EMSGSIZE))?;
+        let host_virt = Handler::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        let req = request!(libc::SYS_read => self.fd, host_virt, len);
+        let read: usize = unsafe { self.handler.proxy(req)? }[0].into();
+
+        if read > len {
+            self.handler.attacked();
+        }
+
+        let c = self.handler.new_cursor();
+        unsafe { c.copy_into_raw_parts(len, buf.as_mut_ptr(), read) }.or(Err(libc::EMSGSIZE))?;
+
+        Ok(read)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, libc::c_int> {
+        let len = buf.len().min(TLS_IO_MAX);
+        let c = self.handler.new_cursor();
+        let (_, untrusted) = c.copy_from_slice(&buf[..len]).or(Err(libc::EMSGSIZE))?;
+        let host_virt = Handler::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        let req = request!(libc::SYS_write => self.fd, host_virt, len);
+        let written: usize = unsafe { self.handler.proxy(req)? }[0].into();
+
+        if written > len {
+            self.handler.attacked();
+        }
+
+        Ok(written)
+    }
+}
+
+impl Handler {
+    /// Handle the syscalls waiting for events on file descriptors
+    ///
+    /// Returns `None` for all other syscalls.
+    fn poll_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        let [a, b, c, d, e, _] = self.argv;
+
+        Some(match nr as libc::c_long {
+            libc::SYS_poll => {
+                self.trace("poll", 3);
+                self.poll(a as _, b as _, Ok(poll::poll_timeout(c as _)))
+            }
+
+            libc::SYS_ppoll => {
+                self.trace("ppoll", 5);
+                let timeout = self.poll_timespec(c as _);
+                self.poll(a as _, b as _, timeout)
+            }
+
+            libc::SYS_select => {
+                self.trace("select", 5);
+                let timeout = self.poll_timeval(e as _);
+                self.select(a as _, [b as _, c as _, d as _], timeout)
+            }
+
+            libc::SYS_pselect6 => {
+                self.trace("pselect6", 6);
+                let timeout = self.poll_timespec(e as _);
+                self.select(a as _, [b as _, c as _, d as _], timeout)
+            }
+
+            libc::SYS_epoll_create => {
+                self.trace("epoll_create", 1);
+                self.epoll_create(libc::SYS_epoll_create, a as _)
+            }
+
+            libc::SYS_epoll_create1 => {
+                self.trace("epoll_create1", 1);
+                self.epoll_create(libc::SYS_epoll_create1, a as _)
+            }
+
+            libc::SYS_epoll_ctl => {
+                self.trace("epoll_ctl", 4);
+                self.poll_epoll_ctl(a as _, b as _, c as _, d as _)
+            }
+
+            libc::SYS_epoll_wait => {
+                self.trace("epoll_wait", 4);
+                self.poll_epoll_wait(a as _, b as _, c as _, d as _)
+            }
+
+            libc::SYS_epoll_pwait => {
+                self.trace("epoll_pwait", 6);
+                self.poll_epoll_wait(a as _, b as _, c as _, d as _)
+            }
+
+            // The file descriptor is closed by the next handler.
+            libc::SYS_close => {
+                poll::forget(a as _);
+                return None;
+            }
+
+            _ => return None,
+        })
+    }
+
+    /// Handle the syscalls duplicating file descriptors
+    ///
+    /// Comes after all other handlers, which may refuse to duplicate their
+    /// file descriptors. Returns `None` for all other syscalls.
+    fn dup_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        let [a, b, c, _, _, _] = self.argv;
+
+        Some(match nr as libc::c_long {
+            libc::SYS_dup => self.poll_dup(nr, a as _, 0),
+            libc::SYS_dup2 | libc::SYS_dup3 => self.poll_dup(nr, a as _, b as _),
+
+            libc::SYS_fcntl => match b as libc::c_int {
+                libc::F_DUPFD | libc::F_DUPFD_CLOEXEC => self.poll_dup(nr, a as _, c as _),
+                _ => return None,
+            },
+
+            _ => return None,
+        })
+    }
+
+    /// Read an optional `timespec` timeout
+    fn poll_timespec(
+        &mut self,
+        timeout: *const libc::timespec,
+    ) -> Result<Option<libc::timespec>, libc::c_int> {
+        if timeout.is_null() {
+            return Ok(None);
+        }
+
+        let timeout = UntrustedRef::from(timeout)
+            .validate(self)
+            .ok_or(libc::EFAULT)?;
+
+        poll::timespec_timeout(timeout).map(Some)
+    }
+
+    /// Read an optional `timeval` timeout
+    fn poll_timeval(
+        &mut self,
+        timeout: *const libc::timeval,
+    ) -> Result<Option<libc::timespec>, libc::c_int> {
+        if timeout.is_null() {
+            return Ok(None);
+        }
+
+        let timeout = UntrustedRef::from(timeout)
+            .validate(self)
+            .ok_or(libc::EFAULT)?;
+
+        poll::timeval_timeout(timeout).map(Some)
+    }
+
+    fn poll(
+        &mut self,
+        fds: *mut libc::pollfd,
+        nfds: libc::nfds_t,
+        timeout: Result<Option<libc::timespec>, libc::c_int>,
+    ) -> sallyport::Result {
+        let timeout = timeout?;
+
+        let fds = UntrustedRefMut::from(fds)
+            .validate_slice(nfds as usize, self)
+            .ok_or(libc::EFAULT)?;
+
+        let ready = poll::poll(self, fds, timeout)?;
+        Ok([ready.into(), 0.into()])
+    }
+
+    fn select(
+        &mut self,
+        nfds: libc::c_int,
+        sets: [*mut u64; 3],
+        timeout: Result<Option<libc::timespec>, libc::c_int>,
+    ) -> sallyport::Result {
+        let timeout = timeout?;
+
+        // The sets only span the words of the first `nfds` file descriptors.
+        let words = poll::set_words(nfds)?;
+
+        let mut untrusted: [Option<&mut [u64]>; 3] = [None, None, None];
+        for (set, ptr) in untrusted.iter_mut().zip(&sets) {
+            if !ptr.is_null() {
+                let slice = UntrustedRefMut::from(*ptr)
+                    .validate_slice(words, self)
+                    .ok_or(libc::EFAULT)?;
+                *set = Some(slice);
+            }
+        }
+
+        let ready = poll::select(self, nfds, untrusted, timeout)?;
+        Ok([ready.into(), 0.into()])
+    }
+
+    /// Duplicate `fd` to `new`, or to the lowest free number from `new` on for `dup` and `F_DUPFD`
+    fn poll_dup(&mut self, nr: usize, fd: libc::c_int, new: libc::c_int) -> sallyport::Result {
+        let dup = poll::dup(self, fd, new, |handler| {
+            let [a, b, c, d, e, f] = handler.argv;
+            let ret = handler.syscall(
+                a.into(),
+                b.into(),
+                c.into(),
+                d.into(),
+                e.into(),
+                f.into(),
+                nr,
+            )?;
+
+            Ok(usize::from(ret[0]) as _)
+        })?;
+
+        if dup != fd {
+            random::close(dup);
+        }
+
+        Ok([(dup as usize).into(), 0.into()])
+    }
+
+    fn epoll_create(&mut self, nr: libc::c_long, arg: libc::c_int) -> sallyport::Result {
+        let ret = unsafe { self.proxy(request!(nr => arg))? };
+
+        // The number may have been an `epoll` instance closed by the host.
+        poll::forget(usize::from(ret[0]) as _);
+
+        Ok(ret)
+    }
+
+    fn poll_epoll_ctl(
+        &mut self,
+        epfd: libc::c_int,
+        op: libc::c_int,
+        fd: libc::c_int,
+        event: *const libc::epoll_event,
+    ) -> sallyport::Result {
+        let event = match op {
+            libc::EPOLL_CTL_ADD | libc::EPOLL_CTL_MOD => Some(
+                *UntrustedRef::from(event)
+                    .validate(self)
+                    .ok_or(libc::EFAULT)?,
+            ),
+            _ => None,
+        };
+
+        poll::epoll_ctl(self, epfd, op, fd, event)?;
+        Ok([0.into(), 0.into()])
+    }
+
+    fn poll_epoll_wait(
+        &mut self,
+        epfd: libc::c_int,
+        events: *mut libc::epoll_event,
+        maxevents: libc::c_int,
+        timeout: libc::c_int,
+    ) -> sallyport::Result {
+        if maxevents <= 0 {
+            return Err(libc::EINVAL);
+        }
+
+        let events = UntrustedRefMut::from(events)
+            .validate_slice(maxevents, self)
+            .ok_or(libc::EFAULT)?;
+
+        let count = poll::epoll_wait(self, epfd, events, timeout)?;
+        Ok([count.into(), 0.into()])
+    }
+}
+
+impl Host for Handler {
+    fn pread(
+        &mut self,
+        fd: libc::c_int,
+        buf: &mut [u8],
+        offset: u64,
+    ) -> Result<usize, libc::c_int> {
+        let c = self.new_cursor();
+        let (_, untrusted) = c.alloc::<u8>(buf.len()).or(Err(libc::EMSGSIZE))?;
+        let host_virt = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        let req = request!(libc::SYS_pread64 => fd, host_virt, buf.len(), offset as usize);
+        let read: usize = unsafe { self.proxy(req)? }[0].into();
+
+        if read > buf.len() {
+            self.attacked();
+        }
+
+        let c = self.new_cursor();
+        unsafe { c.copy_into_raw_parts(buf.len(), buf.as_mut_ptr(), read) }
+            .or(Err(libc::EMSGSIZE))?;
+
+        Ok(read)
+    }
+
+    fn pwrite(&mut self, fd: libc::c_int, buf: &[u8], offset: u64) -> Result<usize, libc::c_int> {
+        let c = self.new_cursor();
+        let (_, untrusted) = c.copy_from_slice(buf).or(Err(libc::EMSGSIZE))?;
+        let host_virt = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        let req = request!(libc::SYS_pwrite64 => fd, host_virt, buf.len(), offset as usize);
+        let written: usize = unsafe { self.proxy(req)? }[0].into();
+
+        if written > buf.len() {
+            self.attacked();
+        }
+
+        Ok(written)
+    }
+
+    fn size(&mut self, fd: libc::c_int) -> Result<u64, libc::c_int> {
+        let req = request!(libc::SYS_lseek => fd, 0, libc::SEEK_END);
+        let size: usize = unsafe { self.proxy(req)? }[0].into();
+        Ok(size as u64)
+    }
+    fn random(&mut self) -> u64 {
+        random::random()
+    }
+}
+
+impl poll::Host for Handler {
+    fn ppoll(
+        &mut self,
+        fds: &[libc::pollfd],
+        timeout: Option<&libc::timespec>,
+        returned: &mut [libc::pollfd],
+    ) -> Result<usize, libc::c_int> {
+        let nfds = fds.len();
+
+        let c = self.new_cursor();
+        let (c, untrusted) = c.copy_from_slice(fds).or(Err(libc::EMSGSIZE))?;
+        let host_fds = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        let host_timeout = match timeout {
+            None => 0,
+            Some(timeout) => {
+                let (_, untrusted) = c.copy_from_slice(&[*timeout]).or(Err(libc::EMSGSIZE))?;
+                Self::translate_shim_to_host_addr(untrusted.as_ptr())
+            }
+        };
+
+        let req = request!(libc::SYS_ppoll => host_fds, nfds, host_timeout, 0, 0);
+        let ready: usize = unsafe { self.proxy(req)? }[0].into();
+
+        let c = self.new_cursor();
+        unsafe { c.copy_into_raw_parts(nfds, returned.as_mut_ptr(), nfds) }
+            .or(Err(libc::EMSGSIZE))?;
+
+        Ok(ready)
+    }
+
+    fn epoll_ctl(
+        &mut self,
+        epfd: libc::c_int,
+        op: libc::c_int,
+        fd: libc::c_int,
+        event: Option<&libc::epoll_event>,
+    ) -> Result<(), libc::c_int> {
+        let host_event = match event {
+            None => 0,
+            Some(event) => {
+                let c = self.new_cursor();
+                let (_, untrusted) = c.copy_from_slice(&[*event]).or(Err(libc::EMSGSIZE))?;
+                Self::translate_shim_to_host_addr(untrusted.as_ptr())
+            }
+        };
+
+        let req = request!(libc::SYS_epoll_ctl => epfd, op, fd, host_event);
+        unsafe { self.proxy(req)? };
+
+        Ok(())
+    }
+
+    fn epoll_wait(
+        &mut self,
+        epfd: libc::c_int,
+        events: &mut [libc::epoll_event],
+        timeout: libc::c_int,
+    ) -> Result<usize, libc::c_int> {
+        let len = events.len();
+
+        let c = self.new_cursor();
+        let (_, untrusted) = c.alloc::<libc::epoll_event>(len).or(Err(libc::EMSGSIZE))?;
+        let host_events = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        let req = request!(libc::SYS_epoll_wait => epfd, host_events, len, timeout);
+        let count: usize = unsafe { self.proxy(req)? }[0].into();
+
+        // The `poll` crate terminates the keep for more events than asked for.
+        if count > len {
+            return Ok(count);
+        }
+
+        let c = self.new_cursor();
+        unsafe { c.copy_into_raw_parts(len, events.as_mut_ptr(), count) }
+            .or(Err(libc::EMSGSIZE))?;
+
+        Ok(count)
+    }
+
+    fn close(&mut self, fd: libc::c_int) {
+        let _ = unsafe { self.proxy(request!(libc::SYS_close => fd)) };
+    }
+
+    fn attacked(&mut self) -> ! {
+        BaseSyscallHandler::attacked(self)
+    }
+}
+
+impl time::Host for Handler {
+    fn has_tsc(&mut self) -> bool {
+        true
+    }
+
+    fn tsc(&mut self) -> u64 {
+        time::rdtsc()
+    }
+
+    fn now(&mut self, clockid: libc::clockid_t) -> Result<u64, libc::c_int> {
+        let c = self.new_cursor();
+        let (_, untrusted) = c.alloc::<libc::timespec>(1).or(Err(libc::EMSGSIZE))?;
+        let host_virt = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        unsafe { self.proxy(request!(libc::SYS_clock_gettime => clockid, host_virt))? };
+
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+
+        let c = self.new_cursor();
+        unsafe { c.copy_into_raw_parts(1, &mut ts, 1) }.or(Err(libc::EMSGSIZE))?;
+
+        if ts.tv_sec < 0 || !(0..time::NSEC_PER_SEC as _).contains(&ts.tv_nsec) {
+            self.attacked();
+        }
+
+        match (ts.tv_sec as u64)
+            .checked_mul(time::NSEC_PER_SEC)
+            .and_then(|ns| ns.checked_add(ts.tv_nsec as u64))
+        {
+            Some(ns) => Ok(ns),
+            None => self.attacked(),
+        }
+    }
+
+    fn sleep(&mut self, ns: u64) -> Result<(), libc::c_int> {
+        let c = self.new_cursor();
+        let (_, untrusted) = c
+            .copy_from_slice(&[time::timespec(ns)])
+            .or(Err(libc::EMSGSIZE))?;
+        let host_virt = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        unsafe { self.proxy(request!(libc::SYS_nanosleep => host_virt, 0))? };
+        Ok(())
+    }
+
+    fn publish(&mut self, calibration: &time::Calibration) {
+        time::publish(calibration)
+    }
+}
+
 impl AddressValidator for Handler {
     #[inline(always)]
     fn validate_const_mem_fn(&self, _ptr: *const (), _size: usize) -> bool {
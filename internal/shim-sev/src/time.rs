@@ -0,0 +1,59 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Time functions
+//!
+//! The clocks of the payload are served by the `clock` crate, so querying
+//! the time doesn't leave the keep. The calibration of the TSC is published
+//! to the vDSO, which serves the clocks without a syscall until the next
+//! host hint for the wall clock is due.
+
+use crate::spin::Locked;
+use crate::vdso::{self, VVar};
+
+use ::clock::{Clock, Mode};
+use abi::note::{self, Note};
+
+pub use ::clock::{timespec, timeval, Calibration, Host, NSEC_PER_SEC};
+
+/// Whether the host time hints are kept from moving the wall clock backwards
+///
+/// The loader sets the descriptor to a non-zero value with
+/// `exec --strict-time`. The note is part of the measurement of the keep.
+#[used]
+#[link_section = ".note"]
+static NOTE_ENARX_TIME: Note<u32> = Note::new(note::TIME, 0);
+
+/// The clocks of the keep
+static CLOCK: Locked<Clock> = Locked::new(Clock::new());
+
+fn mode() -> Mode {
+    match NOTE_ENARX_TIME.desc() {
+        0 => Mode::Permissive,
+        _ => Mode::Strict,
+    }
+}
+
+/// Read the TSC
+pub fn rdtsc() -> u64 {
+    unsafe { core::arch::x86_64::_rdtsc() }
+}
+
+/// Publish the calibrated TSC and the last host hint to the vDSO
+pub fn publish(calibration: &Calibration) {
+    vdso::publish(VVar {
+        enabled: 1,
+        tsc_base: calibration.tsc_base,
+        base_ns: calibration.base_ns,
+        mult: calibration.mult,
+        wall_mono: calibration.wall_mono,
+        wall_real: calibration.wall_real,
+        next_sync: calibration.next_sync,
+    });
+}
+
+/// Read the clock `clockid` in nanoseconds
+///
+/// Returns `None` for the clocks, which are not served by the shim.
+pub fn now(host: &mut impl Host, clockid: libc::clockid_t) -> Option<Result<u64, libc::c_int>> {
+    CLOCK.lock().now(host, mode(), clockid)
+}
@@ -0,0 +1,214 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transparent TLS termination on a configured port
+//!
+//! The loader sets the port with `exec --tls`. Sockets bound to it become
+//! TLS listeners: the shim runs the handshake on every accepted connection,
+//! and the payload reads and writes the plaintext of the session.
+//!
+//! The key of the keep is generated on first use. If the SEV guest owner
+//! injected a secret, the certificate carries a MAC of the public key with a
+//! key derived from the secret, which only an attested keep can compute.
+//! Without a secret, the certificate carries no evidence, unless the loader
+//! set `exec --attest`, which refuses the connections instead.
+//!
+//! The handshake and all I/O on the sessions block. Non-blocking sessions
+//! are not supported.
+
+use crate::random::random;
+use crate::spin::Locked;
+use crate::SEV_SECRET;
+
+use abi::note::{self, Note};
+use hkdf::Hkdf;
+use sha2::Sha256;
+use tls::{Evidence, Identity, Io, Session};
+
+/// The maximum number of TLS listeners
+const MAX_LISTENERS: usize = 4;
+
+/// The maximum number of TLS sessions open at the same time
+const MAX_SESSIONS: usize = 4;
+
+/// The HKDF info of the evidence in the certificate
+const INFO: &[u8] = b"enarx tls evidence";
+
+/// The port of the listening sockets to terminate TLS on
+///
+/// The port 0 disables TLS termination. The `TLS_ATTEST` flag refuses
+/// connections without a secret. Like the debug switch, the descriptor is
+/// part of the measurement of the keep.
+#[used]
+#[link_section = ".note"]
+static NOTE_ENARX_TLS: Note<u32> = Note::new(note::TLS, 0);
+
+/// An open TLS session
+struct Slot {
+    fd: libc::c_int,
+    shutdown: bool,
+    session: Session,
+}
+
+/// The TLS state of the keep
+struct Tls {
+    identity: Option<Identity>,
+    listeners: [libc::c_int; MAX_LISTENERS],
+    slots: [Slot; MAX_SESSIONS],
+}
+
+const FREE: Slot = Slot {
+    fd: -1,
+    shutdown: false,
+    session: Session::new(),
+};
+
+/// The TLS state of the keep
+static TLS: Locked<Tls> = Locked::new(Tls {
+    identity: None,
+    listeners: [-1; MAX_LISTENERS],
+    slots: [FREE; MAX_SESSIONS],
+});
+
+/// The port to terminate TLS on, 0 if disabled
+pub fn port() -> u16 {
+    NOTE_ENARX_TLS.desc() as u16
+}
+
+/// Whether the certificate has to carry evidence
+fn attest() -> bool {
+    NOTE_ENARX_TLS.desc() & note::TLS_ATTEST != 0
+}
+
+/// Whether another TLS listener can be tracked
+pub fn can_listen() -> bool {
+    TLS.lock().listeners.contains(&-1)
+}
+
+/// Track a socket bound to the TLS port
+pub fn listen(fd: libc::c_int) -> Result<(), libc::c_int> {
+    let mut tls = TLS.lock();
+    let listener = tls
+        .listeners
+        .iter_mut()
+        .find(|l| **l == -1)
+        .ok_or(libc::ENOBUFS)?;
+
+    *listener = fd;
+    Ok(())
+}
+
+/// Whether `fd` is a TLS listener
+pub fn is_listener(fd: libc::c_int) -> bool {
+    fd >= 0 && TLS.lock().listeners.contains(&fd)
+}
+
+/// Stop tracking a closed TLS listener
+pub fn unlisten(fd: libc::c_int) {
+    for listener in TLS.lock().listeners.iter_mut() {
+        if *listener == fd {
+            *listener = -1;
+        }
+    }
+}
+
+/// Whether `fd` is a TLS session
+pub fn is_open(fd: libc::c_int) -> bool {
+    fd >= 0 && TLS.lock().slots.iter().any(|s| s.fd == fd)
+}
+
+/// Create the identity of the keep
+fn identity() -> Result<Identity, libc::c_int> {
+    let mut seed = [0u8; 32];
+    for chunk in seed.chunks_mut(8) {
+        chunk.copy_from_slice(&random().to_ne_bytes());
+    }
+
+    let public = Identity::public_key(&seed);
+    let mut mac = [0u8; 32];
+
+    let secret = SEV_SECRET.read();
+    let evidence = match secret.as_ref().and_then(|s| s.try_as_slice()) {
+        None if attest() => return Err(libc::ENOKEY),
+        None => Evidence::None,
+        Some(ikm) => {
+            Hkdf::<Sha256>::new(None, ikm)
+                .expand_multi_info(&[INFO, &public], &mut mac)
+                .or(Err(libc::ENOKEY))?;
+            Evidence::Sev(&mac)
+        }
+    };
+
+    Identity::new(&seed, evidence).or(Err(libc::ENOMEM))
+}
+
+/// Run the handshake on the accepted socket `fd`
+///
+/// The caller has to close the socket on failure.
+pub fn accept(io: &mut impl Io, fd: libc::c_int) -> Result<(), libc::c_int> {
+    let mut tls = TLS.lock();
+    let tls = &mut *tls;
+
+    let slot = tls
+        .slots
+        .iter_mut()
+        .find(|s| s.fd == -1)
+        .ok_or(libc::ECONNABORTED)?;
+
+    if tls.identity.is_none() {
+        tls.identity = Some(identity()?);
+    }
+
+    let mut entropy = [0u8; 64];
+    for chunk in entropy.chunks_mut(8) {
+        chunk.copy_from_slice(&random().to_ne_bytes());
+    }
+
+    let identity = tls.identity.as_ref().unwrap();
+    slot.session
+        .accept(io, identity, &entropy)
+        .or(Err(libc::ECONNABORTED))?;
+
+    slot.fd = fd;
+    slot.shutdown = false;
+    Ok(())
+}
+
+fn with_slot<T>(fd: libc::c_int, f: impl FnOnce(&mut Slot) -> T) -> T {
+    let mut tls = TLS.lock();
+    let slot = tls.slots.iter_mut().find(|s| s.fd == fd).unwrap();
+    f(slot)
+}
+
+/// Read the plaintext of the session `fd`
+pub fn read(io: &mut impl Io, fd: libc::c_int, buf: &mut [u8]) -> Result<usize, libc::c_int> {
+    with_slot(fd, |slot| slot.session.read(io, buf).map_err(|e| e.errno()))
+}
+
+/// The number of bytes a `read` of the session `fd` returns without blocking
+pub fn pending(fd: libc::c_int) -> usize {
+    with_slot(fd, |slot| slot.session.pending())
+}
+
+/// Write the plaintext of the session `fd`
+pub fn write(io: &mut impl Io, fd: libc::c_int, buf: &[u8]) -> Result<usize, libc::c_int> {
+    with_slot(fd, |slot| match slot.shutdown {
+        true => Err(libc::EPIPE),
+        false => slot.session.write(io, buf).map_err(|e| e.errno()),
+    })
+}
+
+/// Send `close_notify` once, when the payload is done writing
+pub fn shutdown(io: &mut impl Io, fd: libc::c_int) {
+    with_slot(fd, |slot| {
+        if !slot.shutdown {
+            slot.shutdown = true;
+            let _ = slot.session.close(io);
+        }
+    })
+}
+
+/// Close the session `fd`, before the socket is closed
+pub fn close(io: &mut impl Io, fd: libc::c_int) {
+    shutdown(io, fd);
+    with_slot(fd, |slot| slot.fd = -1)
+}
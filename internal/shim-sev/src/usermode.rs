@@ -3,6 +3,7 @@
 //! switch to Ring 3 aka usermode
 
 use crate::gdt::{USER_CODE_SEGMENT, USER_DATA_SEGMENT};
+use crate::signal::Context;
 use x86_64::registers::rflags::RFlags;
 
 /// Enter Ring 3
@@ -55,3 +56,45 @@ pub unsafe fn usermode(ip: u64, sp: u64) -> ! {
     options(noreturn, nomem)
     )
 }
+
+/// Return to Ring 3 with all registers of `ctx`
+///
+/// # Safety
+///
+/// The shim GS base has to be active, like in a syscall or an exception
+/// of the payload, and `ctx` has to be a payload context.
+pub unsafe fn resume(ctx: &Context) -> ! {
+    asm!("
+        push     {USER_DATA_SEGMENT}
+        push     QWORD PTR [rdi + 0x78]     # rsp
+        push     QWORD PTR [rdi + 0x88]     # rflags
+        push     {USER_CODE_SEGMENT}
+        push     QWORD PTR [rdi + 0x80]     # rip
+
+        mov      r8,                    QWORD PTR [rdi + 0x00]
+        mov      r9,                    QWORD PTR [rdi + 0x08]
+        mov      r10,                   QWORD PTR [rdi + 0x10]
+        mov      r11,                   QWORD PTR [rdi + 0x18]
+        mov      r12,                   QWORD PTR [rdi + 0x20]
+        mov      r13,                   QWORD PTR [rdi + 0x28]
+        mov      r14,                   QWORD PTR [rdi + 0x30]
+        mov      r15,                   QWORD PTR [rdi + 0x38]
+        mov      rsi,                   QWORD PTR [rdi + 0x48]
+        mov      rbp,                   QWORD PTR [rdi + 0x50]
+        mov      rbx,                   QWORD PTR [rdi + 0x58]
+        mov      rdx,                   QWORD PTR [rdi + 0x60]
+        mov      rax,                   QWORD PTR [rdi + 0x68]
+        mov      rcx,                   QWORD PTR [rdi + 0x70]
+        mov      rdi,                   QWORD PTR [rdi + 0x40]
+
+        # restore the payload gs base
+        swapgs
+
+        iretq
+          ",
+    USER_DATA_SEGMENT = const USER_DATA_SEGMENT,
+    USER_CODE_SEGMENT = const USER_CODE_SEGMENT,
+    in("rdi") ctx,
+    options(noreturn)
+    )
+}
@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The vDSO of the payload
+//!
+//! The vDSO reads the clocks from the TSC calibration, which the shim
+//! publishes in the vvar page, so querying the time doesn't even need a
+//! syscall. The vvar page is mapped read-only right before the vDSO. The
+//! sources of the vDSO are in `vdso/`, `build.rs` compiles them.
+
+use crate::allocator::{shim_virt_to_enc_phys, ALLOCATOR};
+use crate::paging::SHIM_PAGETABLE;
+use crate::spin::Locked;
+
+use core::alloc::Layout;
+use core::ops::DerefMut;
+use x86_64::structures::paging::{Page, PageTableFlags, Size4KiB};
+use x86_64::{align_up, VirtAddr};
+
+/// The vDSO image
+static IMAGE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/vdso.so"));
+
+/// The clocks published to the vDSO
+///
+/// Keep in sync with `struct vvar` in `vdso/vdso.c`.
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct VVar {
+    /// Non-zero, if the TSC is calibrated
+    pub enabled: u64,
+
+    /// The TSC at `base_ns`
+    pub tsc_base: u64,
+
+    /// The monotonic time at `tsc_base`
+    pub base_ns: u64,
+
+    /// Nanoseconds per tick in 32.32 fixed point
+    pub mult: u64,
+
+    /// The monotonic time of the last host hint for the wall clock
+    pub wall_mono: u64,
+
+    /// The wall clock time of the last host hint
+    pub wall_real: u64,
+
+    /// The monotonic time of the next host hint, the vDSO falls back to the syscall then
+    pub next_sync: u64,
+}
+
+/// The vvar page, once it is mapped
+static VVAR: Locked<Option<&'static mut VVar>> = Locked::new(None);
+
+/// Allocate zeroed pages in the shim and map them to `addr` for the payload
+fn map_pages(addr: VirtAddr, size: usize, flags: PageTableFlags) -> &'static mut [u8] {
+    let size = align_up(size as u64, Page::<Size4KiB>::SIZE) as usize;
+    let layout = Layout::from_size_align(size, Page::<Size4KiB>::SIZE as usize).unwrap();
+
+    let mut allocator = ALLOCATOR.write();
+    let ptr = allocator
+        .try_alloc(layout)
+        .expect("vDSO allocation failed")
+        .as_ptr();
+
+    let pages = unsafe { core::slice::from_raw_parts_mut(ptr, size) };
+    pages.fill(0);
+
+    allocator
+        .map_memory(
+            SHIM_PAGETABLE.write().deref_mut(),
+            shim_virt_to_enc_phys(ptr),
+            addr,
+            size,
+            PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | flags,
+            PageTableFlags::PRESENT | PageTableFlags::USER_ACCESSIBLE | PageTableFlags::WRITABLE,
+        )
+        .expect("Map vDSO failed!");
+
+    pages
+}
+
+/// Map the vvar page and the vDSO after it at `addr`
+///
+/// Returns the address of the vDSO for `AT_SYSINFO_EHDR`.
+pub fn map(addr: VirtAddr) -> VirtAddr {
+    let vvar = map_pages(
+        addr,
+        Page::<Size4KiB>::SIZE as _,
+        PageTableFlags::NO_EXECUTE,
+    );
+    let vdso = addr + Page::<Size4KiB>::SIZE;
+    map_pages(vdso, IMAGE.len(), PageTableFlags::empty())[..IMAGE.len()].copy_from_slice(IMAGE);
+
+    #[allow(clippy::cast_ptr_alignment)]
+    let vvar = unsafe { &mut *(vvar.as_mut_ptr() as *mut VVar) };
+    *VVAR.lock() = Some(vvar);
+
+    vdso
+}
+
+/// Publish the clocks to the vDSO, if it is mapped
+pub fn publish(clocks: VVar) {
+    if let Some(vvar) = VVAR.lock().as_mut() {
+        **vvar = clocks;
+    }
+}
@@ -0,0 +1,175 @@
+// SPDX-License-Identifier: Apache-2.0
+
+// The vDSO of the payload
+//
+// The clocks are computed from the TSC and the calibration the shim
+// publishes in the vvar page, which is mapped read-only right before the
+// vDSO. Before the TSC is calibrated and whenever the wall clock is due
+// for its next host hint, the functions fall back to the syscall.
+//
+// The keep has a single CPU and the shim only updates the vvar page
+// during syscalls, so it never changes while it is read.
+
+#include <stdint.h>
+#include <sys/syscall.h>
+
+#define NSEC_PER_SEC 1000000000ULL
+
+#define CLOCK_REALTIME 0
+#define CLOCK_MONOTONIC 1
+#define CLOCK_MONOTONIC_RAW 4
+#define CLOCK_REALTIME_COARSE 5
+#define CLOCK_MONOTONIC_COARSE 6
+#define CLOCK_BOOTTIME 7
+
+typedef int clockid_t;
+typedef long time_t;
+
+struct timespec {
+    time_t tv_sec;
+    long tv_nsec;
+};
+
+struct timeval {
+    time_t tv_sec;
+    long tv_usec;
+};
+
+struct timezone {
+    int tz_minuteswest;
+    int tz_dsttime;
+};
+
+// Keep in sync with `VVar` in `src/vdso.rs`.
+struct vvar {
+    uint64_t enabled;
+    uint64_t tsc_base;
+    uint64_t base_ns;
+    uint64_t mult;
+    uint64_t wall_mono;
+    uint64_t wall_real;
+    uint64_t next_sync;
+};
+
+extern const volatile struct vvar vvar_page __attribute__((visibility("hidden")));
+
+static long sys(long nr, long a, long b) {
+    long rax;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (nr), "D" (a), "S" (b)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
+
+static uint64_t rdtsc(void) {
+    uint32_t lo, hi;
+
+    asm volatile("rdtsc" : "=a" (lo), "=d" (hi));
+    return (uint64_t) hi << 32 | lo;
+}
+
+static int monotonic(uint64_t *ns) {
+    const volatile struct vvar *v = &vvar_page;
+
+    if (!v->enabled)
+        return 0;
+
+    unsigned __int128 delta = (unsigned __int128) (rdtsc() - v->tsc_base) * v->mult >> 32;
+    if (delta >= v->next_sync - v->base_ns)
+        return 0;
+
+    *ns = v->base_ns + (uint64_t) delta;
+    return 1;
+}
+
+static int now(clockid_t clock, uint64_t *ns) {
+    switch (clock) {
+    case CLOCK_MONOTONIC:
+    case CLOCK_MONOTONIC_RAW:
+    case CLOCK_MONOTONIC_COARSE:
+    case CLOCK_BOOTTIME:
+        return monotonic(ns);
+
+    case CLOCK_REALTIME:
+    case CLOCK_REALTIME_COARSE:
+        if (!monotonic(ns))
+            return 0;
+
+        *ns = vvar_page.wall_real + (*ns - vvar_page.wall_mono);
+        return 1;
+
+    default:
+        return 0;
+    }
+}
+
+int __vdso_clock_gettime(clockid_t clock, struct timespec *ts) {
+    uint64_t ns;
+
+    if (!now(clock, &ns))
+        return sys(SYS_clock_gettime, clock, (long) ts);
+
+    ts->tv_sec = ns / NSEC_PER_SEC;
+    ts->tv_nsec = ns % NSEC_PER_SEC;
+    return 0;
+}
+
+int __vdso_gettimeofday(struct timeval *tv, struct timezone *tz) {
+    uint64_t ns;
+
+    if (!now(CLOCK_REALTIME, &ns))
+        return sys(SYS_gettimeofday, (long) tv, (long) tz);
+
+    if (tv) {
+        tv->tv_sec = ns / NSEC_PER_SEC;
+        tv->tv_usec = ns % NSEC_PER_SEC / 1000;
+    }
+
+    // The keep is always in UTC, without daylight saving time.
+    if (tz) {
+        tz->tz_minuteswest = 0;
+        tz->tz_dsttime = 0;
+    }
+
+    return 0;
+}
+
+time_t __vdso_time(time_t *t) {
+    uint64_t ns;
+
+    if (!now(CLOCK_REALTIME, &ns))
+        return sys(SYS_time, (long) t, 0);
+
+    if (t)
+        *t = ns / NSEC_PER_SEC;
+
+    return ns / NSEC_PER_SEC;
+}
+
+// The keep has a single CPU on a single node.
+long __vdso_getcpu(unsigned *cpu, unsigned *node, void *unused) {
+    (void) unused;
+
+    if (cpu)
+        *cpu = 0;
+
+    if (node)
+        *node = 0;
+
+    return 0;
+}
+
+int clock_gettime(clockid_t, struct timespec *)
+    __attribute__((weak, alias("__vdso_clock_gettime")));
+
+int gettimeofday(struct timeval *, struct timezone *)
+    __attribute__((weak, alias("__vdso_gettimeofday")));
+
+time_t time(time_t *) __attribute__((weak, alias("__vdso_time")));
+
+long getcpu(unsigned *, unsigned *, void *) __attribute__((weak, alias("__vdso_getcpu")));
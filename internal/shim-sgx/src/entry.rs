@@ -1,8 +1,39 @@
 // SPDX-License-Identifier: Apache-2.0
 
+use core::fmt::Write;
+
 use crt0stack::{Builder, Entry, Handle, OutOfSpace};
 use goblin::elf::header::{header64::Header, ELFMAG};
 
+/// A buffer to format an environment variable in
+struct Var {
+    bytes: [u8; 64],
+    len: usize,
+}
+
+impl Var {
+    fn new() -> Self {
+        Self {
+            bytes: [0; 64],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or_default()
+    }
+}
+
+impl Write for Var {
+    fn write_str(&mut self, s: &str) -> core::fmt::Result {
+        let end = self.len + s.len();
+        let bytes = self.bytes.get_mut(self.len..end).ok_or(core::fmt::Error)?;
+        bytes.copy_from_slice(s.as_bytes());
+        self.len = end;
+        Ok(())
+    }
+}
+
 fn exit(code: usize) -> ! {
     loop {
         unsafe {
@@ -15,16 +46,21 @@ fn exit(code: usize) -> ! {
     }
 }
 
-fn random() -> u64 {
-    let mut r: u64 = 0;
-
-    for _ in 0..1024 {
-        if unsafe { core::arch::x86_64::_rdrand64_step(&mut r) } == 1 {
-            return r;
-        }
+pub(crate) fn random() -> u64 {
+    match ::random::rdrand() {
+        Some(r) => r,
+        None => exit(1),
     }
+}
 
-    exit(1)
+/// The hardware capabilities of the payload for `AT_HWCAP` and `AT_HWCAP2`
+///
+/// Like Linux, `AT_HWCAP` is `edx` of CPUID leaf 1, which the handler
+/// answers like for the payload. `AT_HWCAP2` stays empty: the enclave
+/// can't tell, whether the host kernel enabled the FSGSBASE instructions.
+fn hwcap() -> (usize, usize) {
+    let hwcap = unsafe { core::arch::x86_64::__cpuid(1) }.edx as usize;
+    (hwcap, 0)
 }
 
 fn crt0setup<'a>(
@@ -33,6 +69,7 @@ fn crt0setup<'a>(
     off: *const (),
 ) -> Result<Handle<'a>, OutOfSpace> {
     let rand = unsafe { core::mem::transmute([random(), random()]) };
+    let (hwcap, hwcap2) = hwcap();
     let phdr = off as u64 + hdr.e_phoff;
 
     // Set the arguments
@@ -43,6 +80,19 @@ fn crt0setup<'a>(
     let mut builder = builder.done()?;
     builder.push("LANG=C")?;
 
+    // Tell the payload where to find the data of `exec --data`.
+    if let Some((addr, size)) = crate::data() {
+        let (mut a, mut s) = (Var::new(), Var::new());
+        if write!(a, "ENARX_DATA_ADDR={:#x}", addr).is_err()
+            || write!(s, "ENARX_DATA_SIZE={}", size).is_err()
+        {
+            exit(1);
+        }
+
+        builder.push(a.as_str())?;
+        builder.push(s.as_str())?;
+    }
+
     // Set the aux vector
     let mut builder = builder.done()?;
     builder.push(&Entry::ExecFilename("/init"))?;
@@ -55,8 +105,8 @@ fn crt0setup<'a>(
     builder.push(&Entry::Secure(false))?;
     builder.push(&Entry::ClockTick(100))?;
     builder.push(&Entry::Flags(0))?; // TODO: https://github.com/enarx/enarx/issues/386
-    builder.push(&Entry::HwCap(0))?; // TODO: https://github.com/enarx/enarx/issues/386
-    builder.push(&Entry::HwCap2(0))?; // TODO: https://github.com/enarx/enarx/issues/386
+    builder.push(&Entry::HwCap(hwcap))?;
+    builder.push(&Entry::HwCap2(hwcap2))?;
     builder.push(&Entry::PHdr(phdr as _))?;
     builder.push(&Entry::PHent(hdr.e_phentsize as _))?;
     builder.push(&Entry::PHnum(hdr.e_phnum as _))?;
@@ -0,0 +1,48 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Verification of the `cpuid` answers of the host
+//!
+//! The host executes `cpuid` for the enclave, so it can forge the answers.
+//! `abi::cpuid` checks them against what is known in the enclave, with the
+//! XFRM the CPU reports to the enclave. Answers contradicting this
+//! terminate the enclave. The loader refuses a CPUID policy, which would
+//! cause such answers.
+
+use super::{enarx, Handler};
+
+use core::convert::TryInto;
+use core::ops::Range;
+
+use sallyport::syscall::BaseSyscallHandler;
+
+/// The XFRM in the attributes of a report
+const REPORT_XFRM: Range<usize> = 56..64;
+
+/// The XFRM of the enclave, which the loader chooses
+///
+/// Only the single thread of the enclave accesses it.
+static mut XFRM: u64 = 0;
+
+/// The XFRM of the enclave, from a report of the enclave for itself
+pub(super) fn xfrm() -> u64 {
+    unsafe {
+        if XFRM == 0 {
+            let report = enarx::report(&[0; 64]);
+            XFRM = u64::from_le_bytes(report[REPORT_XFRM].try_into().unwrap());
+        }
+
+        XFRM
+    }
+}
+
+impl<'a> Handler<'a> {
+    /// Verify the `cpuid` answer `regs` of the host for `leaf` and `subleaf`
+    ///
+    /// Returns the answer for the payload.
+    pub(super) fn verify_cpuid(&mut self, leaf: u64, subleaf: u64, regs: [u64; 4]) -> [u64; 4] {
+        match abi::cpuid::verify(leaf, subleaf, regs, xfrm()) {
+            Some(regs) => regs,
+            None => self.attacked(),
+        }
+    }
+}
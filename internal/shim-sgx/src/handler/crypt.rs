@@ -0,0 +1,268 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transparent encryption of files below a configured prefix
+//!
+//! The `crypt` crate encrypts the files. The key is derived from a sealing
+//! key bound to MRENCLAVE and the current security versions, so an older
+//! TCB can't decrypt the files.
+
+use super::enarx::{seal_key, KEYPOLICY_MRENCLAVE};
+use super::Handler;
+
+use ::crypt::{Crypt, Host};
+use primordial::Register;
+use sallyport::request;
+use sallyport::syscall::{BaseSyscallHandler, SyscallHandler};
+use sallyport::untrusted::{UntrustedRef, UntrustedRefMut, Validate, ValidateSlice};
+
+/// The encryption state of the keep
+///
+/// The enclave has a single thread, which is the only one touching it.
+static mut CRYPT: Crypt = Crypt::new();
+
+fn crypt() -> &'static mut Crypt {
+    unsafe { &mut CRYPT }
+}
+
+/// Whether the file at `path` relative to `dirfd` is encrypted
+pub(super) fn is_encrypted(dirfd: libc::c_int, path: &[u8]) -> Result<bool, libc::c_int> {
+    let desc = crate::NOTE_ENARX_ENCRYPT.desc();
+    let len = desc.iter().position(|b| *b == 0).unwrap_or(desc.len());
+
+    ::crypt::is_encrypted(&desc[..len], dirfd, path)
+}
+
+impl<'a> Host for Handler<'a> {
+    fn pread(
+        &mut self,
+        fd: libc::c_int,
+        buf: &mut [u8],
+        offset: u64,
+    ) -> Result<usize, libc::c_int> {
+        let c = self.new_cursor();
+        let (_, untrusted) = c.alloc::<u8>(buf.len()).or(Err(libc::EMSGSIZE))?;
+        let host_virt = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        let req = request!(libc::SYS_pread64 => fd, host_virt, buf.len(), offset as usize);
+        let read: usize = unsafe { self.proxy(req)? }[0].into();
+
+        if read > buf.len() {
+            self.attacked();
+        }
+
+        let c = self.new_cursor();
+        unsafe { c.copy_into_raw_parts(buf.len(), buf.as_mut_ptr(), read) }
+            .or(Err(libc::EMSGSIZE))?;
+
+        Ok(read)
+    }
+
+    fn pwrite(&mut self, fd: libc::c_int, buf: &[u8], offset: u64) -> Result<usize, libc::c_int> {
+        let c = self.new_cursor();
+        let (_, untrusted) = c.copy_from_slice(buf).or(Err(libc::EMSGSIZE))?;
+        let host_virt = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        let req = request!(libc::SYS_pwrite64 => fd, host_virt, buf.len(), offset as usize);
+        let written: usize = unsafe { self.proxy(req)? }[0].into();
+
+        if written > buf.len() {
+            self.attacked();
+        }
+
+        Ok(written)
+    }
+
+    fn size(&mut self, fd: libc::c_int) -> Result<u64, libc::c_int> {
+        let req = request!(libc::SYS_lseek => fd, 0, libc::SEEK_END);
+        let size: usize = unsafe { self.proxy(req)? }[0].into();
+        Ok(size as u64)
+    }
+
+    fn random(&mut self) -> u64 {
+        crate::entry::random()
+    }
+}
+
+impl<'a> Handler<'a> {
+    /// Track a newly opened encrypted file
+    pub(super) fn open_encrypted(
+        &mut self,
+        fd: libc::c_int,
+        path: &[u8],
+        flags: libc::c_int,
+    ) -> Result<(), libc::c_int> {
+        let crypt = crypt();
+
+        if !crypt.has_key() {
+            let ikm = seal_key(KEYPOLICY_MRENCLAVE, &[0; 32])?;
+            crypt.set_key(&ikm)?;
+        }
+
+        crypt.open(fd, path, flags)
+    }
+
+    /// Handle the syscalls on encrypted files
+    ///
+    /// Returns `None` for all other syscalls and file descriptors. The
+    /// syscalls on encrypted files, which are not handled, are refused, so
+    /// neither plaintext nor the size of the ciphertext reaches the payload
+    /// or the host.
+    pub(super) fn crypt_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        let gpr = &self.ssa.gpr;
+        let (a, b, c, d, e, f) = (gpr.rdi, gpr.rsi, gpr.rdx, gpr.r10, gpr.r8, gpr.r9);
+        let fd = a as libc::c_int;
+
+        let args = [a, b, c, d, e, f].map(|arg| arg as usize);
+        let fds = ::crypt::fd_args(nr as _, args);
+        if !fds.iter().flatten().any(|fd| crypt().is_open(*fd)) {
+            return None;
+        }
+
+        Some(match nr as libc::c_long {
+            libc::SYS_read => {
+                self.trace("read", 3);
+                self.crypt_read(fd, b as _, c as _, None)
+            }
+
+            libc::SYS_pread64 => {
+                self.trace("pread64", 4);
+                self.crypt_read(fd, b as _, c as _, Some(d))
+            }
+
+            libc::SYS_write => {
+                self.trace("write", 3);
+                self.crypt_write(fd, b as _, c as _, None)
+            }
+
+            libc::SYS_pwrite64 => {
+                self.trace("pwrite64", 4);
+                self.crypt_write(fd, b as _, c as _, Some(d))
+            }
+
+            libc::SYS_readv => {
+                self.trace("readv", 3);
+                self.crypt_readv(fd, b as _, c as _)
+            }
+
+            libc::SYS_writev => {
+                self.trace("writev", 3);
+                self.crypt_writev(fd, b as _, c as _)
+            }
+
+            libc::SYS_lseek => {
+                self.trace("lseek", 3);
+                crypt()
+                    .seek(self, fd, b as _, c as _)
+                    .map(|pos| [(pos as usize).into(), 0.into()])
+            }
+
+            libc::SYS_fstat => self.crypt_fstat(fd, b as _),
+
+            libc::SYS_close => {
+                crypt().close(fd);
+                return None;
+            }
+
+            // Neither the data nor its size is involved.
+            libc::SYS_fsync | libc::SYS_fdatasync | libc::SYS_flock | libc::SYS_fadvise64 => {
+                return None
+            }
+
+            // Duplicates would not share the file position, and the shim
+            // tracks `O_APPEND`.
+            libc::SYS_fcntl => match b as libc::c_int {
+                libc::F_GETFD | libc::F_SETFD | libc::F_GETFL => return None,
+                _ => Err(libc::EOPNOTSUPP),
+            },
+
+            _ => Err(libc::EOPNOTSUPP),
+        })
+    }
+
+    /// Do `fstat` on the host and replace the size of the ciphertext
+    fn crypt_fstat(&mut self, fd: libc::c_int, statbuf: *mut libc::stat) -> sallyport::Result {
+        let [a, b, c, d, e, f] = [fd as usize, statbuf as usize, 0, 0, 0, 0].map(Register::from);
+        let ret = self.syscall(a, b, c, d, e, f, libc::SYS_fstat as _)?;
+        let size = crypt().size(self, fd)?;
+
+        let stat = UntrustedRefMut::from(statbuf)
+            .validate(self)
+            .ok_or(libc::EFAULT)?;
+        stat.st_size = size as _;
+        stat.st_blocks = ((size + 511) / 512) as _;
+
+        Ok(ret)
+    }
+
+    fn crypt_read(
+        &mut self,
+        fd: libc::c_int,
+        buf: *mut u8,
+        count: usize,
+        offset: Option<u64>,
+    ) -> sallyport::Result {
+        let buf = UntrustedRefMut::from(buf)
+            .validate_slice(count, self)
+            .ok_or(libc::EFAULT)?;
+
+        let read = crypt().read(self, fd, buf, offset)?;
+        Ok([read.into(), 0.into()])
+    }
+
+    fn crypt_write(
+        &mut self,
+        fd: libc::c_int,
+        buf: *const u8,
+        count: usize,
+        offset: Option<u64>,
+    ) -> sallyport::Result {
+        let buf = UntrustedRef::from(buf)
+            .validate_slice(count, self)
+            .ok_or(libc::EFAULT)?;
+
+        let written = crypt().write(self, fd, buf, offset)?;
+        Ok([written.into(), 0.into()])
+    }
+
+    fn crypt_readv(
+        &mut self,
+        fd: libc::c_int,
+        iovec: *const libc::iovec,
+        iovcnt: libc::c_int,
+    ) -> sallyport::Result {
+        let iovec = UntrustedRef::from(iovec)
+            .validate_slice(iovcnt, self)
+            .ok_or(libc::EFAULT)?;
+
+        let mut total = 0usize;
+        for iov in iovec {
+            let read: usize = self.crypt_read(fd, iov.iov_base as _, iov.iov_len, None)?[0].into();
+            total += read;
+
+            if read < iov.iov_len {
+                break;
+            }
+        }
+
+        Ok([total.into(), 0.into()])
+    }
+
+    fn crypt_writev(
+        &mut self,
+        fd: libc::c_int,
+        iovec: *const libc::iovec,
+        iovcnt: libc::c_int,
+    ) -> sallyport::Result {
+        let iovec = UntrustedRef::from(iovec)
+            .validate_slice(iovcnt, self)
+            .ok_or(libc::EFAULT)?;
+
+        let mut total = 0usize;
+        for iov in iovec {
+            self.crypt_write(fd, iov.iov_base as _, iov.iov_len, None)?;
+            total += iov.iov_len;
+        }
+
+        Ok([total.into(), 0.into()])
+    }
+}
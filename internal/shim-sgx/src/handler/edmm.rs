@@ -0,0 +1,328 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Dynamic memory management of SGX2 enclaves (EDMM)
+//!
+//! With EDMM, the loader does not add the pages of the heap before EINIT.
+//! The kernel adds a page on the first access, but the enclave can't use
+//! it before accepting it with `EACCEPT`. The shim accepts the pages the
+//! payload maps, and the pages the handler touches before. Restricting
+//! permissions and removing pages needs the host, but takes effect only
+//! when the shim accepts it, so the host can't change the heap on its own.
+
+use super::Handler;
+
+use primordial::Page;
+use sallyport::request;
+use sallyport::syscall::BaseSyscallHandler;
+
+/// Restrict the permissions of pages
+///
+/// Served by the loader, the number must match its definition.
+///
+/// Arguments: the address and the length of the pages and the SECINFO permissions.
+pub const SYS_ENARX_RESTRICT: i64 = 0xEA11;
+
+/// Change the type of pages to trimmed pages
+///
+/// Served by the loader, the number must match its definition.
+///
+/// Arguments: the address and the length of the pages.
+pub const SYS_ENARX_TRIM: i64 = 0xEA12;
+
+/// Remove trimmed pages
+///
+/// Served by the loader, the number must match its definition.
+///
+/// Arguments: the address and the length of the pages.
+pub const SYS_ENARX_REMOVE: i64 = 0xEA13;
+
+/// The `ENCLU` leaf of `EACCEPT`
+const EACCEPT: u64 = 5;
+
+/// The `ENCLU` leaf of `EMODPE`
+const EMODPE: u64 = 6;
+
+/// The SECINFO flag of readable pages
+const SECINFO_R: u64 = 1 << 0;
+
+/// The SECINFO flag of writable pages
+const SECINFO_W: u64 = 1 << 1;
+
+/// The SECINFO flag of executable pages
+const SECINFO_X: u64 = 1 << 2;
+
+/// The SECINFO flag of pages added after EINIT
+const SECINFO_PENDING: u64 = 1 << 3;
+
+/// The SECINFO flag of pages with a changed type
+const SECINFO_MODIFIED: u64 = 1 << 4;
+
+/// The SECINFO flag of pages with restricted permissions
+const SECINFO_PR: u64 = 1 << 5;
+
+/// The SECINFO page type of regular pages
+const PT_REG: u64 = 2 << 8;
+
+/// The SECINFO page type of trimmed pages
+const PT_TRIM: u64 = 4 << 8;
+
+/// The SECINFO of pages, which the kernel added on demand
+const SECINFO_ADDED: u64 = SECINFO_R | SECINFO_W | SECINFO_PENDING | PT_REG;
+
+/// The number of pages of the heap segment in the linker script
+///
+/// `pages` checks it against the symbols of the linker script, before
+/// `ACCEPTED` is used.
+const HEAP_PAGES: usize = ((1024 - 128) << 20) / Page::SIZE;
+
+/// `SECINFO` of `EACCEPT` and `EMODPE`
+#[repr(C, align(64))]
+struct SecInfo {
+    flags: u64,
+    reserved: [u64; 7],
+}
+
+/// The accepted pages of the heap, a bit per page
+///
+/// Only the single thread of the enclave accesses it.
+static mut ACCEPTED: [u64; HEAP_PAGES / 64] = [0; HEAP_PAGES / 64];
+
+/// The heap pages of the range from `addr` with `len` bytes
+fn pages(addr: usize, len: usize) -> core::ops::Range<usize> {
+    let segment = unsafe {
+        &crate::ENARX_HEAP_END as *const u8 as usize
+            - &crate::ENARX_HEAP_START as *const u8 as usize
+    };
+    assert_eq!(segment / Page::SIZE, HEAP_PAGES);
+
+    let heap = crate::heap();
+    let start = addr.max(heap.start).min(heap.end);
+    let end = addr.saturating_add(len).max(heap.start).min(heap.end);
+    let end = (end + Page::SIZE - 1) / Page::SIZE * Page::SIZE;
+
+    (start - heap.start) / Page::SIZE..(end - heap.start) / Page::SIZE
+}
+
+/// The address of the heap page `page`
+fn address(page: usize) -> usize {
+    crate::heap().start + page * Page::SIZE
+}
+
+fn accepted(page: usize) -> bool {
+    unsafe { ACCEPTED[page / 64] & 1 << (page % 64) != 0 }
+}
+
+fn mark(page: usize, accepted: bool) {
+    unsafe {
+        match accepted {
+            true => ACCEPTED[page / 64] |= 1 << (page % 64),
+            false => ACCEPTED[page / 64] &= !(1 << (page % 64)),
+        }
+    }
+}
+
+/// The SECINFO permissions of the protection `prot`
+///
+/// The pages stay readable, because the enclave can't change them otherwise.
+fn permissions(prot: libc::c_int) -> u64 {
+    let mut flags = SECINFO_R;
+    if prot & libc::PROT_WRITE != 0 {
+        flags |= SECINFO_W;
+    }
+    if prot & libc::PROT_EXEC != 0 {
+        flags |= SECINFO_X;
+    }
+
+    flags
+}
+
+/// Accept the change of the page at `addr` to `flags`
+fn eaccept(addr: usize, flags: u64) -> bool {
+    let secinfo = SecInfo {
+        flags,
+        reserved: [0; 7],
+    };
+    let ret: u64;
+
+    unsafe {
+        asm!(
+            "xchg {SI}, rbx",
+            "enclu",
+            "xchg {SI}, rbx",
+            SI = inout(reg) &secinfo => _,
+            inout("rax") EACCEPT => ret,
+            in("rcx") addr,
+        );
+    }
+
+    ret == 0
+}
+
+/// Extend the permissions of the page at `addr` by `flags`
+fn emodpe(addr: usize, flags: u64) {
+    let secinfo = SecInfo {
+        flags,
+        reserved: [0; 7],
+    };
+
+    unsafe {
+        asm!(
+            "xchg {SI}, rbx",
+            "enclu",
+            "xchg {SI}, rbx",
+            SI = inout(reg) &secinfo => _,
+            in("rax") EMODPE,
+            in("rcx") addr,
+        );
+    }
+}
+
+/// Accept the page at `addr`, which the handler touched before accepting it
+///
+/// The host passes `addr`, so only a page of the heap, which is not
+/// accepted yet, is accepted. It holds no data, which the host could
+/// replace. Returns false, if there is no such page.
+pub fn accept_fault(addr: usize) -> bool {
+    let heap = crate::heap();
+    if !crate::edmm() || !(heap.start..heap.end).contains(&addr) {
+        return false;
+    }
+
+    let page = pages(addr, 1).start;
+    if accepted(page) || !eaccept(address(page), SECINFO_ADDED) {
+        return false;
+    }
+
+    mark(page, true);
+    true
+}
+
+impl<'a> Handler<'a> {
+    /// Accept the heap pages of the range, which are not accepted yet
+    ///
+    /// The kernel adds the pages on the first access of `EACCEPT`.
+    pub(super) fn commit(
+        &mut self,
+        addr: usize,
+        len: usize,
+        prot: libc::c_int,
+    ) -> Result<(), libc::c_int> {
+        if !crate::edmm() {
+            return Ok(());
+        }
+
+        for page in pages(addr, len).filter(|page| !accepted(*page)) {
+            if !eaccept(address(page), SECINFO_ADDED) {
+                return Err(libc::ENOMEM);
+            }
+
+            mark(page, true);
+
+            if prot & libc::PROT_EXEC != 0 {
+                emodpe(address(page), SECINFO_X);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Remove the accepted heap pages of the range from the enclave
+    ///
+    /// Only whole pages are removed. If the host refuses to trim them, the
+    /// keep is terminated, because the pages would stay accepted with the
+    /// permissions of the old mapping.
+    pub(super) fn release(&mut self, addr: usize, len: usize) {
+        if !crate::edmm() {
+            return;
+        }
+
+        let start = (addr + Page::SIZE - 1) / Page::SIZE * Page::SIZE;
+        let end = addr.saturating_add(len) / Page::SIZE * Page::SIZE;
+        if end <= start {
+            return;
+        }
+
+        let range = pages(start, end - start);
+        let mut page = range.start;
+
+        while page < range.end {
+            if !accepted(page) {
+                page += 1;
+                continue;
+            }
+
+            let start = page;
+            while page < range.end && accepted(page) {
+                page += 1;
+            }
+
+            let (addr, len) = (address(start), (page - start) * Page::SIZE);
+            let req = request!(SYS_ENARX_TRIM => addr, len);
+            if unsafe { self.proxy(req) }.is_err() {
+                self.attacked();
+            }
+
+            for trimmed in start..page {
+                if !eaccept(address(trimmed), SECINFO_MODIFIED | PT_TRIM) {
+                    self.attacked();
+                }
+
+                mark(trimmed, false);
+            }
+
+            // Trimmed pages are of no use to the host, so a failure is ignored.
+            let req = request!(SYS_ENARX_REMOVE => addr, len);
+            let _ = unsafe { self.proxy(req) };
+        }
+    }
+
+    /// Change the permissions of the accepted heap pages of the range to `prot`
+    pub(super) fn protect(
+        &mut self,
+        addr: usize,
+        len: usize,
+        prot: libc::c_int,
+    ) -> sallyport::Result {
+        // Like Linux, the range has to start at a page, with or without EDMM.
+        if addr % Page::SIZE != 0 {
+            return Err(libc::EINVAL);
+        }
+
+        if !crate::edmm() {
+            return Ok(Default::default());
+        }
+
+        let flags = permissions(prot);
+        let range = pages(addr, len);
+        let mut page = range.start;
+
+        while page < range.end {
+            if !accepted(page) {
+                page += 1;
+                continue;
+            }
+
+            let start = page;
+            while page < range.end && accepted(page) {
+                page += 1;
+            }
+
+            // The enclave extends the permissions, the host restricts them.
+            for changed in start..page {
+                emodpe(address(changed), flags);
+            }
+
+            let (addr, len) = (address(start), (page - start) * Page::SIZE);
+            let req = request!(SYS_ENARX_RESTRICT => addr, len, flags);
+            unsafe { self.proxy(req)? };
+
+            for changed in start..page {
+                if !eaccept(address(changed), flags | SECINFO_PR | PT_REG) {
+                    self.attacked();
+                }
+            }
+        }
+
+        Ok(Default::default())
+    }
+}
@@ -1,7 +1,250 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use sallyport::syscall::{BaseSyscallHandler, EnarxSyscallHandler};
-use sallyport::untrusted::{UntrustedRef, UntrustedRefMut};
+pub use abi::syscall::{KEYPOLICY_MRENCLAVE, KEYPOLICY_MRSIGNER};
+
+use core::convert::TryInto;
+use core::ops::Range;
+
+use abi::syscall::SYS_ENARX_GETKEY;
+use sallyport::request;
+use sallyport::syscall::{BaseSyscallHandler, EnarxSyscallHandler, SYS_ENARX_GETATT};
+use sallyport::untrusted::{UntrustedRef, UntrustedRefMut, ValidateSlice};
+
+/// The `KEYNAME` of the seal key
+const SEAL_KEY: u16 = 4;
+
+/// The `ENCLU` leaf of `EREPORT`
+const EREPORT: u64 = 0;
+
+/// The `ENCLU` leaf of `EGETKEY`
+const EGETKEY: u64 = 1;
+
+/// The attributes, which must match for a seal key: `INIT`, `DEBUG`,
+/// `MODE64BIT` and the reserved bits
+const ATTRIBUTE_MASK: u64 = 0xFF00_0000_0000_000B;
+
+/// The reserved bits of `MISCSELECT`, which must match for a seal key
+const MISC_MASK: u32 = 0xF000_0000;
+
+/// The CPUSVN in the body of a report
+const REPORT_CPUSVN: Range<usize> = 0..16;
+
+/// The ISVSVN in the body of a report
+const REPORT_ISVSVN: Range<usize> = 258..260;
+
+/// The CONFIGSVN in the body of a report
+const REPORT_CONFIGSVN: Range<usize> = 260..262;
+
+/// `KEYREQUEST` of `EGETKEY`
+#[repr(C, align(512))]
+struct KeyRequest {
+    name: u16,
+    policy: u16,
+    isvsvn: u16,
+    reserved0: u16,
+    cpusvn: [u8; 16],
+    attribute_mask: [u64; 2],
+    key_id: [u8; 32],
+    misc_mask: u32,
+    config_svn: u16,
+    reserved1: [u8; 434],
+}
+
+/// The output of `EGETKEY`
+#[repr(C, align(16))]
+struct Key([u8; 16]);
+
+/// `TARGETINFO` of `EREPORT`
+#[repr(C, align(512))]
+struct TargetInfo([u8; TARGETINFO_LEN]);
+
+/// `REPORTDATA` of `EREPORT`
+#[repr(C, align(128))]
+struct ReportData([u8; 64]);
+
+/// The output of `EREPORT`
+#[repr(C, align(512))]
+struct Report([u8; REPORT_LEN]);
+
+/// The size of a report
+pub const REPORT_LEN: usize = 432;
+
+/// The size of `TARGETINFO`
+const TARGETINFO_LEN: usize = 512;
+
+/// Create a report of the enclave for `target` with `data` as `REPORTDATA`
+fn ereport(target: &TargetInfo, data: &[u8; 64]) -> [u8; REPORT_LEN] {
+    let data = ReportData(*data);
+    let mut report = Report([0; REPORT_LEN]);
+
+    unsafe {
+        asm!(
+            "xchg {TI}, rbx",
+            "enclu",
+            "xchg {TI}, rbx",
+            TI = inout(reg) target => _,
+            in("rax") EREPORT,
+            in("rcx") &data,
+            in("rdx") &mut report,
+        );
+    }
+
+    report.0
+}
+
+/// Create a report of the enclave for itself with `data` as `REPORTDATA`
+///
+/// The report can only be verified by the enclave, but it contains the
+/// identity and the security versions of the enclave.
+pub fn report(data: &[u8; 64]) -> [u8; REPORT_LEN] {
+    ereport(&TargetInfo([0; TARGETINFO_LEN]), data)
+}
+
+/// Get the seal key of the enclave for `policy` and `key_id`
+///
+/// The key is derived for the current security versions of the CPU and the
+/// enclave, which a report of the enclave for itself has, so an enclave on
+/// an older, vulnerable TCB can't derive it. The key changes with updates
+/// of the TCB, so the data has to be sealed again after them.
+pub fn seal_key(policy: u16, key_id: &[u8; 32]) -> Result<[u8; 16], libc::c_int> {
+    if policy == 0 || policy & !(KEYPOLICY_MRENCLAVE | KEYPOLICY_MRSIGNER) != 0 {
+        return Err(libc::EINVAL);
+    }
+
+    let report = report(&[0; 64]);
+    let svn = |range: Range<usize>| u16::from_le_bytes(report[range].try_into().unwrap());
+
+    let request = KeyRequest {
+        name: SEAL_KEY,
+        policy,
+        isvsvn: svn(REPORT_ISVSVN),
+        reserved0: 0,
+        cpusvn: report[REPORT_CPUSVN].try_into().unwrap(),
+        attribute_mask: [ATTRIBUTE_MASK, 0],
+        key_id: *key_id,
+        misc_mask: MISC_MASK,
+        config_svn: svn(REPORT_CONFIGSVN),
+        reserved1: [0; 434],
+    };
+
+    let mut key = Key([0; 16]);
+    let status: u64;
+
+    unsafe {
+        asm!(
+            "xchg {REQ}, rbx",
+            "enclu",
+            "xchg {REQ}, rbx",
+            REQ = inout(reg) &request => _,
+            inout("rax") EGETKEY => status,
+            in("rcx") &mut key,
+        );
+    }
+
+    match status {
+        0 => Ok(key.0),
+        _ => Err(libc::ENOKEY),
+    }
+}
+
+impl<'a> super::Handler<'a> {
+    /// Get a quote of the enclave with `data` as `REPORTDATA` into `quote`
+    ///
+    /// The loader gets the `TARGETINFO` of the quoting enclave, the enclave
+    /// creates a report for it, and the quoting enclave turns the report
+    /// into a quote, which a remote party can verify. Returns the size of
+    /// the quote.
+    pub(super) fn quote(
+        &mut self,
+        data: &[u8; 64],
+        quote: &mut [u8],
+    ) -> Result<usize, libc::c_int> {
+        let c = self.new_cursor();
+        let (_, buf) = c.alloc::<u8>(TARGETINFO_LEN).or(Err(libc::EMSGSIZE))?;
+        let buf = Self::translate_shim_to_host_addr(buf.as_ptr());
+
+        let req = request!(SYS_ENARX_GETATT => 0, 0, buf, TARGETINFO_LEN);
+        let len: usize = unsafe { self.proxy(req)? }[0].into();
+        if len != TARGETINFO_LEN {
+            self.attacked();
+        }
+
+        let mut target = TargetInfo([0; TARGETINFO_LEN]);
+        let c = self.new_cursor();
+        unsafe { c.copy_into_slice(TARGETINFO_LEN, &mut target.0[..]) }.or(Err(libc::EMSGSIZE))?;
+
+        let report = ereport(&target, data);
+
+        let c = self.new_cursor();
+        let (c, untrusted) = c.copy_from_slice(&report[..]).or(Err(libc::EMSGSIZE))?;
+        let report = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+        let (_, buf) = c.alloc::<u8>(quote.len()).or(Err(libc::EMSGSIZE))?;
+        let buf = Self::translate_shim_to_host_addr(buf.as_ptr());
+
+        let req = request!(SYS_ENARX_GETATT => report, REPORT_LEN, buf, quote.len());
+        let len: usize = unsafe { self.proxy(req)? }[0].into();
+        if len > quote.len() {
+            self.attacked();
+        }
+
+        let c = self.new_cursor();
+        let (c, _) = c.alloc::<u8>(REPORT_LEN).or(Err(libc::EMSGSIZE))?;
+        unsafe { c.copy_into_slice(quote.len(), &mut quote[..len]) }.or(Err(libc::EMSGSIZE))?;
+
+        Ok(len)
+    }
+
+    /// Handle the Enarx syscalls, which are emulated by the shim
+    ///
+    /// Returns `None` for all other syscalls.
+    pub(super) fn enarx_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        let gpr = &self.ssa.gpr;
+        let (a, b, c, d) = (gpr.rdi, gpr.rsi, gpr.rdx, gpr.r10);
+
+        Some(match nr as i64 {
+            SYS_ENARX_GETKEY => {
+                self.trace("enarx_getkey", 4);
+                self.get_key(a, b as _, c as _, d as _)
+            }
+
+            _ => return None,
+        })
+    }
+
+    fn get_key(
+        &mut self,
+        policy: u64,
+        key_id: *const u8,
+        buf: *mut u8,
+        buf_len: libc::size_t,
+    ) -> sallyport::Result {
+        // The policy is checked before it is narrowed, so no bit is lost.
+        if policy & !u64::from(KEYPOLICY_MRENCLAVE | KEYPOLICY_MRSIGNER) != 0 {
+            return Err(libc::EINVAL);
+        }
+
+        let mut id = [0u8; 32];
+        if !key_id.is_null() {
+            let key_id = UntrustedRef::from(key_id)
+                .validate_slice(id.len(), self)
+                .ok_or(libc::EFAULT)?;
+            id.copy_from_slice(key_id);
+        }
+
+        let key = seal_key(policy as u16, &id)?;
+
+        if buf_len < key.len() {
+            return Err(libc::EINVAL);
+        }
+
+        let buf = UntrustedRefMut::from(buf)
+            .validate_slice(key.len(), self)
+            .ok_or(libc::EFAULT)?;
+        buf.copy_from_slice(&key);
+
+        Ok([key.len().into(), 0.into()])
+    }
+}
 
 impl<'a> EnarxSyscallHandler for super::Handler<'a> {
     // NOTE: The 'nonce' field is called 'hash' here, as it is used to pass in
@@ -2,7 +2,10 @@
 
 use sallyport::request;
 use sallyport::syscall::{BaseSyscallHandler, FileSyscallHandler};
-use sallyport::untrusted::{UntrustedRef, ValidateSlice};
+use sallyport::untrusted::{UntrustedRef, UntrustedRefMut, Validate, ValidateSlice};
+
+/// The maximum size of the directory entries read at once
+const MAX_DENTS: usize = 4096;
 
 impl<'a> FileSyscallHandler for super::Handler<'a> {
     /// Do a readv() syscall
@@ -95,3 +98,108 @@ impl<'a> FileSyscallHandler for super::Handler<'a> {
         Ok(ret)
     }
 }
+
+impl<'a> super::Handler<'a> {
+    /// Proxy `open` and `openat`, which the loader resolves beneath the granted
+    /// directories, and `getdents64` to list the directories
+    ///
+    /// Returns `None` for all other syscalls.
+    pub(super) fn open_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        let gpr = &self.ssa.gpr;
+        let (a, b, c, d) = (gpr.rdi, gpr.rsi, gpr.rdx, gpr.r10);
+
+        Some(match nr as libc::c_long {
+            libc::SYS_open => {
+                self.trace("open", 3);
+                self.openat(libc::AT_FDCWD, a as _, b as _, c as _)
+            }
+
+            libc::SYS_openat => {
+                self.trace("openat", 4);
+                self.openat(a as _, b as _, c as _, d as _)
+            }
+
+            libc::SYS_getdents64 => {
+                self.trace("getdents64", 3);
+                self.getdents64(a as _, b as _, c as _)
+            }
+
+            _ => return None,
+        })
+    }
+
+    /// Read directory entries, at most `MAX_DENTS` bytes at once
+    fn getdents64(&mut self, fd: libc::c_int, dirp: *mut u8, count: usize) -> sallyport::Result {
+        let buf = UntrustedRefMut::from(dirp)
+            .validate_slice(count, self)
+            .ok_or(libc::EFAULT)?;
+
+        let len = count.min(MAX_DENTS);
+        let c = self.new_cursor();
+        let (_, untrusted) = c.alloc::<u8>(len).or(Err(libc::EMSGSIZE))?;
+        let host_virt = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        let ret = unsafe { self.proxy(request!(libc::SYS_getdents64 => fd, host_virt, len))? };
+        let read: usize = ret[0].into();
+        if read > len {
+            self.attacked();
+        }
+
+        let c = self.new_cursor();
+        unsafe { c.copy_into_raw_parts(len, buf.as_mut_ptr(), read) }.or(Err(libc::EMSGSIZE))?;
+
+        Ok(ret)
+    }
+
+    fn openat(
+        &mut self,
+        dirfd: libc::c_int,
+        path: *const u8,
+        flags: libc::c_int,
+        mode: libc::mode_t,
+    ) -> sallyport::Result {
+        let mut len = 0;
+        loop {
+            if len == libc::PATH_MAX as usize {
+                return Err(libc::ENAMETOOLONG);
+            }
+
+            let byte = UntrustedRef::from(path.wrapping_add(len))
+                .validate(self)
+                .ok_or(libc::EFAULT)?;
+
+            if *byte == 0 {
+                break;
+            }
+
+            len += 1;
+        }
+
+        // Include the terminating NUL.
+        let path = UntrustedRef::from(path)
+            .validate_slice(len + 1, self)
+            .ok_or(libc::EFAULT)?;
+
+        if super::random::is_device(&path[..len]) {
+            return self.open_random(flags);
+        }
+
+        let encrypted = super::crypt::is_encrypted(dirfd, &path[..len])?;
+
+        let c = self.new_cursor();
+        let (_, untrusted) = c.copy_from_slice(path).or(Err(libc::ENAMETOOLONG))?;
+
+        let ret =
+            unsafe { self.proxy(request!(libc::SYS_openat => dirfd, untrusted, flags, mode))? };
+
+        if encrypted {
+            let fd: usize = ret[0].into();
+            if let Err(e) = self.open_encrypted(fd as _, &path[..len], flags) {
+                let _ = unsafe { self.proxy(request!(libc::SYS_close => fd)) };
+                return Err(e);
+            }
+        }
+
+        Ok(ret)
+    }
+}
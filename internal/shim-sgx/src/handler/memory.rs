@@ -8,22 +8,35 @@ impl<'a> MemorySyscallHandler for super::Handler<'a> {
     fn brk(&mut self, addr: *const u8) -> sallyport::Result {
         self.trace("brk", 1);
 
+        let old = self.heap.brk(0 as _) as usize;
         let ret = self.heap.brk(addr as _);
+
+        // Accept the pages of a growing heap, before the payload uses them.
+        let new = ret as usize;
+        if new > old {
+            if let Err(e) = self.commit(old, new - old, libc::PROT_READ | libc::PROT_WRITE) {
+                self.heap.brk(old as _);
+                return Err(e);
+            }
+        } else if new < old {
+            self.release(new, old - new);
+        }
+
         Ok([ret.into(), Default::default()])
     }
 
     /// Do a mprotect() system call
-    // Until EDMM, we can't change any page permissions.
-    // What you get is what you get. Fake success.
+    // Only EDMM changes the permissions of the pages of the heap.
+    // Without it, or elsewhere, what you get is what you get. Fake success.
     fn mprotect(
         &mut self,
-        _addr: UntrustedRef<u8>,
-        _len: libc::size_t,
-        _prot: libc::c_int,
+        addr: UntrustedRef<u8>,
+        len: libc::size_t,
+        prot: libc::c_int,
     ) -> sallyport::Result {
         self.trace("mprotect", 3);
 
-        Ok(Default::default())
+        self.protect(addr.as_ptr() as _, len, prot)
     }
 
     /// Do a mmap() system call
@@ -47,6 +60,12 @@ impl<'a> MemorySyscallHandler for super::Handler<'a> {
             offset,
         )?;
 
+        // Accept the pages, before the payload uses them.
+        if let Err(e) = self.commit(ret as _, length, prot) {
+            self.heap.munmap::<libc::c_void>(ret as _, length)?;
+            return Err(e);
+        }
+
         Ok([ret.into(), Default::default()])
     }
 
@@ -56,6 +75,7 @@ impl<'a> MemorySyscallHandler for super::Handler<'a> {
 
         self.heap
             .munmap::<libc::c_void>(addr.as_ptr() as _, length)?;
+        self.release(addr.as_ptr() as _, length);
         Ok(Default::default())
     }
 
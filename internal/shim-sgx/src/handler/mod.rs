@@ -3,7 +3,7 @@
 macro_rules! debug {
     ($dst:expr, $($arg:tt)*) => {
         #[allow(unused_must_use)] {
-            if $crate::DEBUG {
+            if $crate::debug() {
                 use core::fmt::Write;
                 write!($dst, $($arg)*);
             }
@@ -15,7 +15,7 @@ macro_rules! debugln {
     ($dst:expr) => { debugln!($dst,) };
     ($dst:expr, $($arg:tt)*) => {
         #[allow(unused_must_use)] {
-            if $crate::DEBUG {
+            if $crate::debug() {
                 use core::fmt::Write;
                 writeln!($dst, $($arg)*);
             }
@@ -24,11 +24,19 @@ macro_rules! debugln {
 }
 
 mod base;
+mod cpuid;
+mod crypt;
+mod edmm;
 mod enarx;
 mod file;
 mod memory;
 mod other;
+mod poll;
 mod process;
+mod random;
+mod signal;
+mod time;
+mod tls;
 
 use core::fmt::Write;
 use core::ptr::read_unaligned;
@@ -45,6 +53,17 @@ use x86_64::structures::idt::ExceptionVector;
 const OP_SYSCALL: u16 = 0x050f;
 const OP_CPUID: u16 = 0xa20f;
 
+/// `EXINFO` of the MISC region of an SSA frame
+///
+/// The CPU writes it on an AEX of `#PF` or `#GP`, with `MISCSELECT.EXINFO`
+/// enabled. See Section 38.9.2 of the Intel SDM, Volume 3D.
+#[repr(C)]
+struct ExInfo {
+    maddr: u64,
+    errcd: u32,
+    _reserved: u32,
+}
+
 pub struct Handler<'a> {
     block: &'a mut Block,
     ssa: &'a mut StateSaveArea,
@@ -81,7 +100,18 @@ impl<'a> Handler<'a> {
     }
 
     /// Finish handling an exception
-    pub fn finish(ssa: &'a mut StateSaveArea) {
+    ///
+    /// A non-zero `addr` is a page of the heap, which the handler touched
+    /// before accepting it. The host passes it, so it is checked.
+    pub fn finish(ssa: &'a mut StateSaveArea, addr: usize) {
+        if addr != 0 {
+            if edmm::accept_fault(addr) {
+                return;
+            }
+
+            unsafe { asm!("ud2", options(noreturn)) };
+        }
+
         if let Some(ExceptionVector::InvalidOpcode) = ssa.vector() {
             if let OP_SYSCALL | OP_CPUID = unsafe { read_unaligned(ssa.gpr.rip as _) } {
                 // Skip the instruction.
@@ -94,9 +124,21 @@ impl<'a> Handler<'a> {
     }
 
     /// Handle an exception
-    pub fn handle(ssa: &'a mut StateSaveArea, block: &'a mut Block, heap: Line<usize>) {
+    ///
+    /// A non-zero `signo` is a signal forwarded by the host. The loader enters
+    /// the shim to deliver it, right before resuming the payload.
+    pub fn handle(
+        ssa: &'a mut StateSaveArea,
+        block: &'a mut Block,
+        heap: Line<usize>,
+        signo: usize,
+    ) {
         let mut h = Self::new(ssa, block, heap);
 
+        if signo != 0 {
+            return h.host_signal(signo);
+        }
+
         match h.ssa.vector() {
             Some(ExceptionVector::InvalidOpcode) => {
                 match unsafe { read_unaligned(h.ssa.gpr.rip as _) } {
@@ -104,26 +146,109 @@ impl<'a> Handler<'a> {
                     OP_CPUID => h.handle_cpuid(),
                     r => {
                         debugln!(h, "unsupported opcode: {:?}", r);
-                        h.exit(1)
+                        h.exit((128 + libc::SIGILL) as _)
                     }
                 }
             }
 
-            _ => h.attacked(),
+            // With `EXINFO`, the CPU reports `#PF` and `#GP` in the SSA, too.
+            Some(vector) => h.handle_exception(vector),
+            None => h.attacked(),
         }
     }
 
-    fn handle_syscall(&mut self) {
-        let ret = self.syscall(
-            self.ssa.gpr.rdi.into(),
-            self.ssa.gpr.rsi.into(),
-            self.ssa.gpr.rdx.into(),
-            self.ssa.gpr.r10.into(),
-            self.ssa.gpr.r8.into(),
-            self.ssa.gpr.r9.into(),
-            self.ssa.gpr.rax as usize,
+    /// Handle an exception of the payload, which is not a syscall or cpuid
+    ///
+    /// The exception is delivered as a signal to the payload. Without a
+    /// handler, the keep exits with the status of a shell reporting a
+    /// process killed by the matching signal.
+    fn handle_exception(&mut self, vector: ExceptionVector) {
+        let (signo, code) = match vector {
+            ExceptionVector::Division => (libc::SIGFPE, signals::FPE_INTDIV),
+            ExceptionVector::X87FloatingPoint | ExceptionVector::SimdFloatingPoint => {
+                (libc::SIGFPE, 0)
+            }
+
+            ExceptionVector::Page => (libc::SIGSEGV, signals::SEGV_MAPERR),
+            ExceptionVector::GeneralProtection
+            | ExceptionVector::Stack
+            | ExceptionVector::SegmentNotPresent
+            | ExceptionVector::BoundRange => (libc::SIGSEGV, signals::SI_KERNEL),
+
+            ExceptionVector::Debug | ExceptionVector::Breakpoint => {
+                (libc::SIGTRAP, signals::TRAP_BRKPT)
+            }
+            ExceptionVector::AlignmentCheck => (libc::SIGBUS, signals::BUS_ADRALN),
+            _ => self.attacked(),
+        };
+
+        let exinfo = self.exinfo();
+        let (err, addr) = (u64::from(exinfo.errcd), exinfo.maddr);
+        let rip = self.ssa.gpr.rip;
+
+        debugln!(
+            self,
+            "{:?} at {:#x}, address {:#x}: signal {}",
+            vector,
+            rip,
+            addr,
+            signo
         );
 
+        let (value, cr2) = match vector {
+            ExceptionVector::Page => (addr, addr),
+            ExceptionVector::AlignmentCheck => (0, 0),
+            _ => (rip, 0),
+        };
+
+        let info = signals::Info {
+            signo,
+            code,
+            value,
+            trapno: vector as u64,
+            err,
+        };
+
+        self.deliver_fault(info, cr2)
+    }
+
+    /// The `EXINFO` of the exception, which directly precedes the GPRs of the SSA frame
+    fn exinfo(&self) -> ExInfo {
+        let gpr = &self.ssa.gpr as *const _ as *const ExInfo;
+        unsafe { gpr.sub(1).read_volatile() }
+    }
+
+    fn handle_syscall(&mut self) {
+        let nr = self.ssa.gpr.rax as usize;
+
+        // The registers are restored from the signal frame, including `rip`.
+        if nr == libc::SYS_rt_sigreturn as usize {
+            return self.sigreturn();
+        }
+
+        let ret = match self
+            .signal_syscall(nr)
+            .or_else(|| self.enarx_syscall(nr))
+            .or_else(|| self.poll_syscall(nr))
+            .or_else(|| self.time_syscall(nr))
+            .or_else(|| self.random_syscall(nr))
+            .or_else(|| self.open_syscall(nr))
+            .or_else(|| self.crypt_syscall(nr))
+            .or_else(|| self.tls_syscall(nr))
+            .or_else(|| self.dup_syscall(nr))
+        {
+            Some(ret) => ret,
+            None => self.syscall(
+                self.ssa.gpr.rdi.into(),
+                self.ssa.gpr.rsi.into(),
+                self.ssa.gpr.rdx.into(),
+                self.ssa.gpr.r10.into(),
+                self.ssa.gpr.r8.into(),
+                self.ssa.gpr.r9.into(),
+                nr,
+            ),
+        };
+
         self.ssa.gpr.rip += 2;
 
         match ret {
@@ -133,6 +258,8 @@ impl<'a> Handler<'a> {
                 self.ssa.gpr.rdx = rdx.into();
             }
         }
+
+        self.deliver_signal();
     }
 
     fn handle_cpuid(&mut self) {
@@ -143,22 +270,13 @@ impl<'a> Handler<'a> {
             self.ssa.gpr.rcx.clone(),
         );
 
-        self.block.msg.req = request!(SYS_ENARX_CPUID => self.ssa.gpr.rax, self.ssa.gpr.rcx);
-
-        unsafe {
-            // prevent earlier writes from being moved beyond this point
-            core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::Release);
-
-            asm!("cpuid");
-
-            // prevent later reads from being moved before this point
-            core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::Acquire);
-
-            self.ssa.gpr.rax = self.block.msg.req.arg[0].into();
-            self.ssa.gpr.rbx = self.block.msg.req.arg[1].into();
-            self.ssa.gpr.rcx = self.block.msg.req.arg[2].into();
-            self.ssa.gpr.rdx = self.block.msg.req.arg[3].into();
-        }
+        let (leaf, subleaf) = (self.ssa.gpr.rax, self.ssa.gpr.rcx);
+        let regs = self.host_cpuid(leaf, subleaf);
+        let [rax, rbx, rcx, rdx] = self.verify_cpuid(leaf, subleaf, regs);
+        self.ssa.gpr.rax = rax;
+        self.ssa.gpr.rbx = rbx;
+        self.ssa.gpr.rcx = rcx;
+        self.ssa.gpr.rdx = rdx;
 
         debugln!(
             self,
@@ -171,4 +289,28 @@ impl<'a> Handler<'a> {
 
         self.ssa.gpr.rip += 2;
     }
+
+    /// Ask the host to execute `cpuid` for `leaf` and `subleaf`
+    ///
+    /// Returns `eax`, `ebx`, `ecx` and `edx`, which the host can forge.
+    fn host_cpuid(&mut self, leaf: u64, subleaf: u64) -> [u64; 4] {
+        self.block.msg.req = request!(SYS_ENARX_CPUID => leaf, subleaf);
+
+        unsafe {
+            // prevent earlier writes from being moved beyond this point
+            core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::Release);
+
+            asm!("cpuid");
+
+            // prevent later reads from being moved before this point
+            core::sync::atomic::compiler_fence(core::sync::atomic::Ordering::Acquire);
+
+            [
+                self.block.msg.req.arg[0].into(),
+                self.block.msg.req.arg[1].into(),
+                self.block.msg.req.arg[2].into(),
+                self.block.msg.req.arg[3].into(),
+            ]
+        }
+    }
 }
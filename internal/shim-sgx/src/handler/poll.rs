@@ -0,0 +1,347 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Marshalling of `poll`, `select` and `epoll`
+//!
+//! The `poll` crate checks the events returned by the host and tracks the
+//! `epoll` registrations and the shared file descriptors. The arrays of the
+//! payload are copied through the sallyport block.
+
+use super::Handler;
+
+use ::poll::{poll_timeout, set_words, timespec_timeout, timeval_timeout, Host, Poll};
+use sallyport::request;
+use sallyport::syscall::BaseSyscallHandler;
+use sallyport::untrusted::{UntrustedRef, UntrustedRefMut, Validate, ValidateSlice};
+
+/// The `epoll` registrations and the shared file descriptors of the enclave
+///
+/// The enclave has a single thread, which is the only one touching it.
+static mut POLL: Poll = Poll::new();
+
+fn poll() -> &'static mut Poll {
+    unsafe { &mut POLL }
+}
+
+impl<'a> Host for Handler<'a> {
+    fn ppoll(
+        &mut self,
+        fds: &[libc::pollfd],
+        timeout: Option<&libc::timespec>,
+        returned: &mut [libc::pollfd],
+    ) -> Result<usize, libc::c_int> {
+        let nfds = fds.len();
+
+        let c = self.new_cursor();
+        let (c, untrusted) = c.copy_from_slice(fds).or(Err(libc::EMSGSIZE))?;
+        let host_fds = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        let host_timeout = match timeout {
+            None => 0,
+            Some(timeout) => {
+                let (_, untrusted) = c.copy_from_slice(&[*timeout]).or(Err(libc::EMSGSIZE))?;
+                Self::translate_shim_to_host_addr(untrusted.as_ptr())
+            }
+        };
+
+        let req = request!(libc::SYS_ppoll => host_fds, nfds, host_timeout, 0, 0);
+        let ready: usize = unsafe { self.proxy(req)? }[0].into();
+
+        let c = self.new_cursor();
+        unsafe { c.copy_into_raw_parts(nfds, returned.as_mut_ptr(), nfds) }
+            .or(Err(libc::EMSGSIZE))?;
+
+        Ok(ready)
+    }
+
+    fn epoll_ctl(
+        &mut self,
+        epfd: libc::c_int,
+        op: libc::c_int,
+        fd: libc::c_int,
+        event: Option<&libc::epoll_event>,
+    ) -> Result<(), libc::c_int> {
+        let host_event = match event {
+            None => 0,
+            Some(event) => {
+                let c = self.new_cursor();
+                let (_, untrusted) = c.copy_from_slice(&[*event]).or(Err(libc::EMSGSIZE))?;
+                Self::translate_shim_to_host_addr(untrusted.as_ptr())
+            }
+        };
+
+        let req = request!(libc::SYS_epoll_ctl => epfd, op, fd, host_event);
+        unsafe { self.proxy(req)? };
+
+        Ok(())
+    }
+
+    fn epoll_wait(
+        &mut self,
+        epfd: libc::c_int,
+        events: &mut [libc::epoll_event],
+        timeout: libc::c_int,
+    ) -> Result<usize, libc::c_int> {
+        let len = events.len();
+
+        let c = self.new_cursor();
+        let (_, untrusted) = c.alloc::<libc::epoll_event>(len).or(Err(libc::EMSGSIZE))?;
+        let host_events = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        let req = request!(libc::SYS_epoll_wait => epfd, host_events, len, timeout);
+        let count: usize = unsafe { self.proxy(req)? }[0].into();
+
+        // The `poll` crate terminates the keep for more events than asked for.
+        if count > len {
+            return Ok(count);
+        }
+
+        let c = self.new_cursor();
+        unsafe { c.copy_into_raw_parts(len, events.as_mut_ptr(), count) }
+            .or(Err(libc::EMSGSIZE))?;
+
+        Ok(count)
+    }
+
+    fn close(&mut self, fd: libc::c_int) {
+        let _ = unsafe { self.proxy(request!(libc::SYS_close => fd)) };
+    }
+
+    fn attacked(&mut self) -> ! {
+        BaseSyscallHandler::attacked(self)
+    }
+}
+
+impl<'a> Handler<'a> {
+    /// Handle the syscalls waiting for events on file descriptors
+    ///
+    /// Returns `None` for all other syscalls.
+    pub(super) fn poll_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        let gpr = &self.ssa.gpr;
+        let (a, b, c, d, e) = (gpr.rdi, gpr.rsi, gpr.rdx, gpr.r10, gpr.r8);
+
+        Some(match nr as libc::c_long {
+            libc::SYS_poll => {
+                self.trace("poll", 3);
+                self.poll(a as _, b as _, Ok(poll_timeout(c as _)))
+            }
+
+            libc::SYS_ppoll => {
+                self.trace("ppoll", 5);
+                let timeout = self.timespec(c as _);
+                self.poll(a as _, b as _, timeout)
+            }
+
+            libc::SYS_select => {
+                self.trace("select", 5);
+                let timeout = self.timeval(e as _);
+                self.select(a as _, [b as _, c as _, d as _], timeout)
+            }
+
+            libc::SYS_pselect6 => {
+                self.trace("pselect6", 6);
+                let timeout = self.timespec(e as _);
+                self.select(a as _, [b as _, c as _, d as _], timeout)
+            }
+
+            libc::SYS_epoll_create => {
+                self.trace("epoll_create", 1);
+                self.epoll_create(libc::SYS_epoll_create, a as _)
+            }
+
+            libc::SYS_epoll_create1 => {
+                self.trace("epoll_create1", 1);
+                self.epoll_create(libc::SYS_epoll_create1, a as _)
+            }
+
+            libc::SYS_epoll_ctl => {
+                self.trace("epoll_ctl", 4);
+                self.poll_epoll_ctl(a as _, b as _, c as _, d as _)
+            }
+
+            libc::SYS_epoll_wait => {
+                self.trace("epoll_wait", 4);
+                self.poll_epoll_wait(a as _, b as _, c as _, d as _)
+            }
+
+            libc::SYS_epoll_pwait => {
+                self.trace("epoll_pwait", 6);
+                self.poll_epoll_wait(a as _, b as _, c as _, d as _)
+            }
+
+            // The file descriptor is closed by the next handler.
+            libc::SYS_close => {
+                poll().forget(a as _);
+                return None;
+            }
+
+            _ => return None,
+        })
+    }
+
+    /// Handle the syscalls duplicating file descriptors
+    ///
+    /// Comes after all other handlers, which may refuse to duplicate their
+    /// file descriptors. Returns `None` for all other syscalls.
+    pub(super) fn dup_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        let gpr = &self.ssa.gpr;
+        let (a, b, c) = (gpr.rdi, gpr.rsi, gpr.rdx);
+
+        Some(match nr as libc::c_long {
+            libc::SYS_dup => self.poll_dup(nr, a as _, 0),
+            libc::SYS_dup2 | libc::SYS_dup3 => self.poll_dup(nr, a as _, b as _),
+
+            libc::SYS_fcntl => match b as libc::c_int {
+                libc::F_DUPFD | libc::F_DUPFD_CLOEXEC => self.poll_dup(nr, a as _, c as _),
+                _ => return None,
+            },
+
+            _ => return None,
+        })
+    }
+
+    /// Read an optional `timespec` timeout
+    fn timespec(
+        &mut self,
+        timeout: *const libc::timespec,
+    ) -> Result<Option<libc::timespec>, libc::c_int> {
+        if timeout.is_null() {
+            return Ok(None);
+        }
+
+        let timeout = UntrustedRef::from(timeout)
+            .validate(self)
+            .ok_or(libc::EFAULT)?;
+
+        timespec_timeout(timeout).map(Some)
+    }
+
+    /// Read an optional `timeval` timeout
+    fn timeval(
+        &mut self,
+        timeout: *const libc::timeval,
+    ) -> Result<Option<libc::timespec>, libc::c_int> {
+        if timeout.is_null() {
+            return Ok(None);
+        }
+
+        let timeout = UntrustedRef::from(timeout)
+            .validate(self)
+            .ok_or(libc::EFAULT)?;
+
+        timeval_timeout(timeout).map(Some)
+    }
+
+    fn poll(
+        &mut self,
+        fds: *mut libc::pollfd,
+        nfds: libc::nfds_t,
+        timeout: Result<Option<libc::timespec>, libc::c_int>,
+    ) -> sallyport::Result {
+        let timeout = timeout?;
+
+        let fds = UntrustedRefMut::from(fds)
+            .validate_slice(nfds as usize, self)
+            .ok_or(libc::EFAULT)?;
+
+        let ready = poll().poll(self, fds, timeout)?;
+        Ok([ready.into(), 0.into()])
+    }
+
+    fn select(
+        &mut self,
+        nfds: libc::c_int,
+        sets: [*mut u64; 3],
+        timeout: Result<Option<libc::timespec>, libc::c_int>,
+    ) -> sallyport::Result {
+        let timeout = timeout?;
+
+        // The sets only span the words of the first `nfds` file descriptors.
+        let words = set_words(nfds)?;
+
+        let mut untrusted: [Option<&mut [u64]>; 3] = [None, None, None];
+        for (set, ptr) in untrusted.iter_mut().zip(&sets) {
+            if !ptr.is_null() {
+                let slice = UntrustedRefMut::from(*ptr)
+                    .validate_slice(words, self)
+                    .ok_or(libc::EFAULT)?;
+                *set = Some(slice);
+            }
+        }
+
+        let ready = poll().select(self, nfds, untrusted, timeout)?;
+        Ok([ready.into(), 0.into()])
+    }
+
+    /// Duplicate `fd` to `new`, or to the lowest free number from `new` on for `dup` and `F_DUPFD`
+    fn poll_dup(&mut self, nr: usize, fd: libc::c_int, new: libc::c_int) -> sallyport::Result {
+        let dup = poll().dup(self, fd, new, |handler| {
+            let gpr = &handler.ssa.gpr;
+            let (a, b, c, d, e, f) = (gpr.rdi, gpr.rsi, gpr.rdx, gpr.r10, gpr.r8, gpr.r9);
+            let ret = handler.syscall(
+                a.into(),
+                b.into(),
+                c.into(),
+                d.into(),
+                e.into(),
+                f.into(),
+                nr,
+            )?;
+
+            Ok(usize::from(ret[0]) as _)
+        })?;
+
+        if dup != fd {
+            super::random::close(dup);
+        }
+
+        Ok([(dup as usize).into(), 0.into()])
+    }
+
+    fn epoll_create(&mut self, nr: libc::c_long, arg: libc::c_int) -> sallyport::Result {
+        let ret = unsafe { self.proxy(request!(nr => arg))? };
+
+        // The number may have been an `epoll` instance closed by the host.
+        poll().forget(usize::from(ret[0]) as _);
+
+        Ok(ret)
+    }
+
+    fn poll_epoll_ctl(
+        &mut self,
+        epfd: libc::c_int,
+        op: libc::c_int,
+        fd: libc::c_int,
+        event: *const libc::epoll_event,
+    ) -> sallyport::Result {
+        let event = match op {
+            libc::EPOLL_CTL_ADD | libc::EPOLL_CTL_MOD => Some(
+                *UntrustedRef::from(event)
+                    .validate(self)
+                    .ok_or(libc::EFAULT)?,
+            ),
+            _ => None,
+        };
+
+        poll().epoll_ctl(self, epfd, op, fd, event)?;
+        Ok([0.into(), 0.into()])
+    }
+
+    fn poll_epoll_wait(
+        &mut self,
+        epfd: libc::c_int,
+        events: *mut libc::epoll_event,
+        maxevents: libc::c_int,
+        timeout: libc::c_int,
+    ) -> sallyport::Result {
+        if maxevents <= 0 {
+            return Err(libc::EINVAL);
+        }
+
+        let events = UntrustedRefMut::from(events)
+            .validate_slice(maxevents, self)
+            .ok_or(libc::EFAULT)?;
+
+        let count = poll().epoll_wait(self, epfd, events, timeout)?;
+        Ok([count.into(), 0.into()])
+    }
+}
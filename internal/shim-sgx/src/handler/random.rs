@@ -0,0 +1,145 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Randomness for the payload, which never comes from the host
+//!
+//! The `random` crate serves `getrandom` and reads of `/dev/urandom` and
+//! `/dev/random` from a DRBG seeded with `rdrand`.
+
+use super::Handler;
+
+use ::random::{rdrand, Random, MAX_READ};
+use sallyport::request;
+use sallyport::syscall::BaseSyscallHandler;
+use sallyport::untrusted::{UntrustedRef, UntrustedRefMut, ValidateSlice};
+
+pub(super) use ::random::is_device;
+
+/// The random state of the enclave
+///
+/// The enclave has a single thread, which is the only one touching it.
+static mut RANDOM: Random = Random::new();
+
+fn random() -> &'static mut Random {
+    unsafe { &mut RANDOM }
+}
+
+/// Stop tracking the closed random device `fd`
+pub(super) fn close(fd: libc::c_int) {
+    random().close(fd)
+}
+
+impl<'a> Handler<'a> {
+    /// Handle `getrandom` and the syscalls on random devices
+    ///
+    /// Returns `None` for all other syscalls and file descriptors.
+    pub(super) fn random_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        let gpr = &self.ssa.gpr;
+        let (a, b, c) = (gpr.rdi, gpr.rsi, gpr.rdx);
+        let fd = a as libc::c_int;
+
+        if nr as libc::c_long == libc::SYS_getrandom {
+            self.trace("getrandom", 3);
+            return Some(self.getrandom(a as _, b as _, c as _));
+        }
+
+        if nr as libc::c_long == libc::SYS_close_range {
+            self.trace("close_range", 3);
+            return Some(self.close_range(a as _, b as _, c as _));
+        }
+
+        if !random().is_open(fd) {
+            return None;
+        }
+
+        Some(match nr as libc::c_long {
+            libc::SYS_read | libc::SYS_pread64 => {
+                self.trace("read", 3);
+                self.getrandom(b as _, c as _, 0)
+            }
+
+            libc::SYS_readv => {
+                self.trace("readv", 3);
+                self.random_readv(b as _, c as _)
+            }
+
+            // Like Linux, writes don't add entropy without privileges.
+            libc::SYS_write | libc::SYS_pwrite64 => {
+                self.trace("write", 3);
+                UntrustedRef::from(b as *const u8)
+                    .validate_slice(c as usize, self)
+                    .ok_or(libc::EFAULT)
+                    .map(|buf| [buf.len().into(), 0.into()])
+            }
+
+            libc::SYS_close => {
+                random().close(fd);
+                return None;
+            }
+
+            libc::SYS_dup | libc::SYS_dup2 | libc::SYS_dup3 => Err(libc::EOPNOTSUPP),
+
+            libc::SYS_fcntl => match b as libc::c_int {
+                libc::F_DUPFD | libc::F_DUPFD_CLOEXEC => Err(libc::EOPNOTSUPP),
+                _ => return None,
+            },
+
+            _ => return None,
+        })
+    }
+
+    /// Open a random device served by the shim
+    ///
+    /// The host only provides the file descriptor, with an `eventfd`.
+    pub(super) fn open_random(&mut self, flags: libc::c_int) -> sallyport::Result {
+        if !random().can_open() {
+            return Err(libc::EMFILE);
+        }
+
+        let flags = flags & (libc::O_CLOEXEC | libc::O_NONBLOCK);
+        let ret = unsafe { self.proxy(request!(libc::SYS_eventfd2 => 0, flags))? };
+
+        random().open(usize::from(ret[0]) as _)?;
+        Ok(ret)
+    }
+
+    /// Do `close_range` on the host and stop tracking the random devices it closed
+    fn close_range(
+        &mut self,
+        first: libc::c_uint,
+        last: libc::c_uint,
+        flags: libc::c_uint,
+    ) -> sallyport::Result {
+        let req = request!(libc::SYS_close_range => first as usize, last as usize, flags as usize);
+        let ret = unsafe { self.proxy(req)? };
+
+        random().close_range(first, last, flags);
+        Ok(ret)
+    }
+
+    fn getrandom(&mut self, buf: *mut u8, len: usize, flags: libc::c_uint) -> sallyport::Result {
+        ::random::check_flags(flags)?;
+
+        let len = len.min(MAX_READ);
+        let buf = UntrustedRefMut::from(buf)
+            .validate_slice(len, self)
+            .ok_or(libc::EFAULT)?;
+
+        random().fill(rdrand, buf)?;
+        Ok([len.into(), 0.into()])
+    }
+
+    fn random_readv(&mut self, iovec: *const libc::iovec, iovcnt: usize) -> sallyport::Result {
+        let iovec = UntrustedRef::from(iovec)
+            .validate_slice(iovcnt, self)
+            .ok_or(libc::EFAULT)?;
+
+        let mut total = 0usize;
+        for iov in iovec {
+            let len = iov.iov_len.min(MAX_READ - total);
+            let done: usize = self.getrandom(iov.iov_base as _, len, 0)?[0].into();
+            total += done;
+        }
+
+        Ok([total.into(), 0.into()])
+    }
+}
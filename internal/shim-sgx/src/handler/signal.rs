@@ -0,0 +1,285 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! POSIX signals of the payload
+//!
+//! The shim keeps the signal state of the payload and delivers a signal on
+//! the way back to the payload, by building the signal frame on the payload
+//! stack and pointing the registers in the SSA to the signal handler.
+//! `rt_sigreturn` restores the registers and the extended state saved in the
+//! frame. The extended state of the payload is the XSAVE area of the SSA,
+//! which `ERESUME` restores.
+
+use super::{cpuid, Handler};
+
+use core::mem::size_of;
+
+use sallyport::request;
+use sallyport::syscall::{BaseSyscallHandler, ProcessSyscallHandler};
+use sallyport::untrusted::{AddressValidator, UntrustedRef, UntrustedRefMut, Validate};
+use sgx::ssa::StateSaveArea;
+use signals::frame::{self, Payload};
+use signals::{xsave, Context, Delivery, Info, SigAction, Signals, SI_TKILL, SI_USER, USER_FLAGS};
+
+/// The signal state of the payload
+///
+/// The enclave has a single thread, which is the only one touching it.
+static mut SIGNALS: Signals = Signals::new();
+
+fn signals() -> &'static mut Signals {
+    unsafe { &mut SIGNALS }
+}
+
+impl<'a> Payload for Handler<'a> {
+    fn is_payload(&self, addr: u64, len: u64) -> bool {
+        self.validate_mut_mem_fn(addr as *mut (), len as usize)
+    }
+
+    fn xfeatures(&self) -> u64 {
+        cpuid::xfrm()
+    }
+
+    unsafe fn xsave(&mut self, area: *mut u8) {
+        let ssa = &*self.ssa as *const StateSaveArea as *const u8;
+        let size = xsave::size(self.xfeatures() & xsave::KNOWN) as usize;
+        core::ptr::copy_nonoverlapping(ssa, area, size);
+    }
+
+    unsafe fn xrstor(&mut self, area: *const u8) {
+        let ssa = &mut *self.ssa as *mut StateSaveArea as *mut u8;
+        let size = xsave::size(self.xfeatures() & xsave::KNOWN) as usize;
+        core::ptr::copy_nonoverlapping(area, ssa, size);
+    }
+}
+
+impl<'a> Handler<'a> {
+    /// Handle the signal syscalls, which are emulated by the shim
+    ///
+    /// Returns `None` for all other syscalls.
+    pub(super) fn signal_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        let gpr = &self.ssa.gpr;
+        let (a, b, c, d) = (gpr.rdi, gpr.rsi, gpr.rdx, gpr.r10);
+
+        Some(match nr as libc::c_long {
+            libc::SYS_rt_sigaction => {
+                self.trace("rt_sigaction", 4);
+                self.rt_sigaction(a as _, b as _, c as _, d as _)
+            }
+
+            libc::SYS_rt_sigprocmask => {
+                self.trace("rt_sigprocmask", 4);
+                self.rt_sigprocmask(a as _, b as _, c as _, d as _)
+            }
+
+            libc::SYS_kill => {
+                self.trace("kill", 2);
+                self.kill(libc::SYS_getpid, a as _, b as _, SI_USER)
+            }
+
+            libc::SYS_tkill => {
+                self.trace("tkill", 2);
+                self.kill(libc::SYS_gettid, a as _, b as _, SI_TKILL)
+            }
+
+            libc::SYS_tgkill => {
+                self.trace("tgkill", 3);
+                self.kill(libc::SYS_gettid, b as _, c as _, SI_TKILL)
+            }
+
+            _ => return None,
+        })
+    }
+
+    fn rt_sigaction(
+        &mut self,
+        signo: libc::c_int,
+        act: *const SigAction,
+        oldact: *mut SigAction,
+        size: usize,
+    ) -> sallyport::Result {
+        if size != size_of::<u64>() {
+            return Err(libc::EINVAL);
+        }
+
+        let act = match act.is_null() {
+            true => None,
+            false => Some(*UntrustedRef::from(act).validate(self).ok_or(libc::EFAULT)?),
+        };
+
+        let old = signals().action(signo, act)?;
+
+        if !oldact.is_null() {
+            *UntrustedRefMut::from(oldact)
+                .validate(self)
+                .ok_or(libc::EFAULT)? = old;
+        }
+
+        Ok(Default::default())
+    }
+
+    fn rt_sigprocmask(
+        &mut self,
+        how: libc::c_int,
+        set: *const u64,
+        oldset: *mut u64,
+        size: usize,
+    ) -> sallyport::Result {
+        if size != size_of::<u64>() {
+            return Err(libc::EINVAL);
+        }
+
+        let set = match set.is_null() {
+            true => None,
+            false => Some(*UntrustedRef::from(set).validate(self).ok_or(libc::EFAULT)?),
+        };
+
+        let old = signals().mask(how, set)?;
+
+        if !oldset.is_null() {
+            *UntrustedRefMut::from(oldset)
+                .validate(self)
+                .ok_or(libc::EFAULT)? = old;
+        }
+
+        Ok(Default::default())
+    }
+
+    /// Send a signal to the payload itself
+    ///
+    /// The payload can't signal any other process of the host.
+    fn kill(
+        &mut self,
+        id_nr: libc::c_long,
+        id: usize,
+        signo: libc::c_int,
+        code: libc::c_int,
+    ) -> sallyport::Result {
+        let own: usize = unsafe { self.proxy(request!(id_nr)) }?[0].into();
+
+        if id != own && !(id_nr == libc::SYS_getpid && id == 0) {
+            return Err(libc::EPERM);
+        }
+
+        signals().raise(signo, code)?;
+        Ok(Default::default())
+    }
+
+    fn registers(&self) -> Context {
+        let gpr = &self.ssa.gpr;
+
+        Context {
+            r8: gpr.r8,
+            r9: gpr.r9,
+            r10: gpr.r10,
+            r11: gpr.r11,
+            r12: gpr.r12,
+            r13: gpr.r13,
+            r14: gpr.r14,
+            r15: gpr.r15,
+            rdi: gpr.rdi,
+            rsi: gpr.rsi,
+            rbp: gpr.rbp,
+            rbx: gpr.rbx,
+            rdx: gpr.rdx,
+            rax: gpr.rax,
+            rcx: gpr.rcx,
+            rsp: gpr.rsp,
+            rip: gpr.rip,
+            rflags: gpr.rflags,
+        }
+    }
+
+    fn set_registers(&mut self, regs: &Context) {
+        let gpr = &mut self.ssa.gpr;
+
+        gpr.r8 = regs.r8;
+        gpr.r9 = regs.r9;
+        gpr.r10 = regs.r10;
+        gpr.r11 = regs.r11;
+        gpr.r12 = regs.r12;
+        gpr.r13 = regs.r13;
+        gpr.r14 = regs.r14;
+        gpr.r15 = regs.r15;
+        gpr.rdi = regs.rdi;
+        gpr.rsi = regs.rsi;
+        gpr.rbp = regs.rbp;
+        gpr.rbx = regs.rbx;
+        gpr.rdx = regs.rdx;
+        gpr.rax = regs.rax;
+        gpr.rcx = regs.rcx;
+        gpr.rsp = regs.rsp;
+        gpr.rip = regs.rip;
+        gpr.rflags = regs.rflags;
+    }
+
+    /// Terminate the payload, like the default action of `signo` would
+    fn terminate(&mut self, signo: libc::c_int) -> ! {
+        debugln!(self, "payload killed by signal {}", signo);
+        self.exit(128 + signo)
+    }
+
+    /// Build the signal frame on the payload stack and run the handler
+    fn run_handler(&mut self, info: Info, action: SigAction, mask: u64, cr2: u64) {
+        let ctx = self.registers();
+
+        match frame::enter(self, &ctx, &info, &action, mask, cr2, 0) {
+            Some(regs) => self.set_registers(&regs),
+            None => self.terminate(libc::SIGSEGV),
+        }
+    }
+
+    /// Deliver a signal forwarded by the host
+    ///
+    /// Invalid signal numbers are ignored, because the host can't be trusted.
+    pub(super) fn host_signal(&mut self, signo: usize) {
+        debugln!(self, "host signal {}", signo);
+
+        if let 1..=64 = signo {
+            let _ = signals().raise(signo as _, SI_USER);
+            self.deliver_signal();
+        }
+    }
+
+    /// Deliver the next pending signal to the payload
+    pub(super) fn deliver_signal(&mut self) {
+        match signals().take() {
+            None => {}
+            Some((info, Delivery::Terminate)) => self.terminate(info.signo),
+            Some((info, Delivery::Handler(action, mask))) => {
+                self.run_handler(info, action, mask, 0)
+            }
+        }
+    }
+
+    /// Deliver a synchronous fault of the payload
+    ///
+    /// The faulting address in `cr2` is reported by the CPU in `EXINFO`.
+    pub(super) fn deliver_fault(&mut self, info: Info, cr2: u64) {
+        match signals().fault(info.signo) {
+            Delivery::Terminate => self.terminate(info.signo),
+            Delivery::Handler(action, mask) => self.run_handler(info, action, mask, cr2),
+        }
+    }
+
+    /// Return from a signal handler
+    ///
+    /// The restorer calls `rt_sigreturn`, after the handler returned and
+    /// popped the restorer address, so the stack points to the `ucontext`.
+    pub(super) fn sigreturn(&mut self) {
+        self.trace("rt_sigreturn", 0);
+
+        let rsp = self.ssa.gpr.rsp;
+
+        let (mut regs, mask) = match frame::sigreturn(self, rsp) {
+            Some(saved) => saved,
+            None => self.terminate(libc::SIGSEGV),
+        };
+
+        regs.rflags = (regs.rflags & USER_FLAGS) | (self.ssa.gpr.rflags & !USER_FLAGS);
+
+        self.set_registers(&regs);
+        signals().restore(mask);
+
+        // Signals unblocked by the restored mask are delivered right away.
+        self.deliver_signal();
+    }
+}
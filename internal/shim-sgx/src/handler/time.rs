@@ -0,0 +1,159 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The clocks of the payload, which are served by the `clock` crate
+//!
+//! `rdtsc` is only legal in SGX2 enclaves. Without SGX2, the monotonic
+//! clocks follow the host, but they never go backwards.
+
+use super::Handler;
+
+use ::clock::{timespec, timeval, Clock, Host, Mode, NSEC_PER_SEC};
+use sallyport::request;
+use sallyport::syscall::BaseSyscallHandler;
+use sallyport::untrusted::{UntrustedRefMut, Validate, ValidateSlice};
+
+/// The CPUID leaf of the SGX capabilities
+const CPUID_SGX: u64 = 0x12;
+
+/// The bit of the SGX2 instructions in `eax` of the SGX capabilities
+const CPUID_SGX2: u64 = 1 << 1;
+
+/// The clocks of the enclave
+///
+/// The enclave has a single thread, which is the only one touching it.
+static mut CLOCK: Clock = Clock::new();
+
+fn clock() -> &'static mut Clock {
+    unsafe { &mut CLOCK }
+}
+
+fn mode() -> Mode {
+    match crate::NOTE_ENARX_TIME.desc() {
+        0 => Mode::Permissive,
+        _ => Mode::Strict,
+    }
+}
+
+impl<'a> Handler<'a> {
+    /// Handle the time syscalls on the clocks served by the shim
+    ///
+    /// Returns `None` for all other syscalls and clocks.
+    pub(super) fn time_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        let gpr = &self.ssa.gpr;
+        let (a, b) = (gpr.rdi, gpr.rsi);
+
+        Some(match nr as libc::c_long {
+            libc::SYS_clock_gettime => {
+                let ns = self.read_clock(a as _)?;
+                self.trace("clock_gettime", 2);
+                ns.and_then(|ns| self.get_clock(ns, b as _))
+            }
+
+            libc::SYS_gettimeofday => {
+                self.trace("gettimeofday", 2);
+                self.get_timeofday(a as _, b as _)
+            }
+
+            libc::SYS_time => {
+                self.trace("time", 1);
+                self.get_time(a as _)
+            }
+
+            _ => return None,
+        })
+    }
+
+    /// Read the clock `clockid` in nanoseconds
+    ///
+    /// Returns `None` for the clocks, which are not served by the shim.
+    fn read_clock(&mut self, clockid: libc::clockid_t) -> Option<Result<u64, libc::c_int>> {
+        clock().now(self, mode(), clockid)
+    }
+
+    fn get_clock(&mut self, ns: u64, tp: *mut libc::timespec) -> sallyport::Result {
+        *UntrustedRefMut::from(tp)
+            .validate(self)
+            .ok_or(libc::EFAULT)? = timespec(ns);
+
+        Ok(Default::default())
+    }
+
+    fn get_timeofday(&mut self, tv: *mut libc::timeval, tz: *mut libc::c_int) -> sallyport::Result {
+        if !tv.is_null() {
+            let tv = UntrustedRefMut::from(tv)
+                .validate(self)
+                .ok_or(libc::EFAULT)?;
+
+            *tv = timeval(self.read_clock(libc::CLOCK_REALTIME).unwrap()?);
+        }
+
+        // The enclave is always in UTC, without daylight saving time.
+        if !tz.is_null() {
+            UntrustedRefMut::from(tz)
+                .validate_slice(2, self)
+                .ok_or(libc::EFAULT)?
+                .fill(0);
+        }
+
+        Ok(Default::default())
+    }
+
+    fn get_time(&mut self, tloc: *mut libc::time_t) -> sallyport::Result {
+        let sec = timespec(self.read_clock(libc::CLOCK_REALTIME).unwrap()?).tv_sec;
+
+        if !tloc.is_null() {
+            *UntrustedRefMut::from(tloc)
+                .validate(self)
+                .ok_or(libc::EFAULT)? = sec;
+        }
+
+        Ok([(sec as usize).into(), 0.into()])
+    }
+}
+
+impl<'a> Host for Handler<'a> {
+    fn has_tsc(&mut self) -> bool {
+        self.host_cpuid(CPUID_SGX, 0)[0] & CPUID_SGX2 != 0
+    }
+
+    fn tsc(&mut self) -> u64 {
+        unsafe { core::arch::x86_64::_rdtsc() }
+    }
+
+    fn now(&mut self, clockid: libc::clockid_t) -> Result<u64, libc::c_int> {
+        let c = self.new_cursor();
+        let (_, untrusted) = c.alloc::<libc::timespec>(1).or(Err(libc::EMSGSIZE))?;
+        let host_virt = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        unsafe { self.proxy(request!(libc::SYS_clock_gettime => clockid, host_virt))? };
+
+        let mut ts = libc::timespec {
+            tv_sec: 0,
+            tv_nsec: 0,
+        };
+
+        let c = self.new_cursor();
+        unsafe { c.copy_into_raw_parts(1, &mut ts, 1) }.or(Err(libc::EMSGSIZE))?;
+
+        if ts.tv_sec < 0 || !(0..NSEC_PER_SEC as _).contains(&ts.tv_nsec) {
+            self.attacked();
+        }
+
+        match (ts.tv_sec as u64)
+            .checked_mul(NSEC_PER_SEC)
+            .and_then(|ns| ns.checked_add(ts.tv_nsec as u64))
+        {
+            Some(ns) => Ok(ns),
+            None => self.attacked(),
+        }
+    }
+
+    fn sleep(&mut self, ns: u64) -> Result<(), libc::c_int> {
+        let c = self.new_cursor();
+        let (_, untrusted) = c.copy_from_slice(&[timespec(ns)]).or(Err(libc::EMSGSIZE))?;
+        let host_virt = Self::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        unsafe { self.proxy(request!(libc::SYS_nanosleep => host_virt, 0))? };
+        Ok(())
+    }
+}
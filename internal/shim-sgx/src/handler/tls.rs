@@ -0,0 +1,507 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Transparent TLS termination on a configured port
+//!
+//! The loader sets the port with `exec --tls`. Sockets bound to it become
+//! TLS listeners: the shim runs the handshake on every accepted connection,
+//! and the payload reads and writes the plaintext of the session.
+//!
+//! The key of the keep is generated on first use. Its certificate carries an
+//! SGX quote with the SHA-256 hash of the public key as `REPORTDATA`, which
+//! a remote party can verify. If the host can't provide a quote, because it
+//! runs no AESM daemon, the certificate carries no evidence, unless the
+//! loader set `exec --attest`, which refuses the connections instead.
+//!
+//! The enclave has a single thread, so the handshake and all I/O on the
+//! sessions block. Non-blocking sessions are not supported.
+
+use super::Handler;
+use crate::entry::random;
+
+use sallyport::request;
+use sallyport::syscall::{BaseSyscallHandler, SyscallHandler};
+use sallyport::untrusted::{UntrustedRef, UntrustedRefMut, Validate, ValidateSlice};
+use sha2::{Digest, Sha256};
+use tls::{Evidence, Identity, Io, Session};
+
+/// The maximum number of TLS listeners
+const MAX_LISTENERS: usize = 4;
+
+/// The maximum number of TLS sessions open at the same time
+const MAX_SESSIONS: usize = 4;
+
+/// The maximum size of a single read or write on the host socket
+const MAX_IO: usize = 2048;
+
+/// The maximum size of the quote, which still fits into the certificate
+///
+/// DCAP quotes carry the certification data of the PCK, which takes 4-5 KiB.
+const MAX_QUOTE: usize = 8192;
+
+/// An open TLS session
+struct Slot {
+    fd: libc::c_int,
+    shutdown: bool,
+    session: Session,
+}
+
+/// The TLS state of the keep
+struct Tls {
+    identity: Option<Identity>,
+    listeners: [libc::c_int; MAX_LISTENERS],
+    slots: [Slot; MAX_SESSIONS],
+}
+
+const FREE: Slot = Slot {
+    fd: -1,
+    shutdown: false,
+    session: Session::new(),
+};
+
+/// The TLS state of the keep
+///
+/// The enclave has a single thread, which is the only one touching it.
+static mut TLS: Tls = Tls {
+    identity: None,
+    listeners: [-1; MAX_LISTENERS],
+    slots: [FREE; MAX_SESSIONS],
+};
+
+fn tls() -> &'static mut Tls {
+    unsafe { &mut TLS }
+}
+
+/// The port to terminate TLS on, 0 if disabled
+fn port() -> u16 {
+    crate::NOTE_ENARX_TLS.desc() as u16
+}
+
+/// Whether the certificate has to carry a quote
+fn attest() -> bool {
+    crate::NOTE_ENARX_TLS.desc() & abi::note::TLS_ATTEST != 0
+}
+
+/// The host socket of a session
+struct Socket<'h, 'a> {
+    handler: &'h mut Handler<'a>,
+    fd: libc::c_int,
+}
+
+impl Io for Socket<'_, '_> {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, libc::c_int> {
+        let len = buf.len().min(MAX_IO);
+        let c = self.handler.new_cursor();
+        let (_, untrusted) = c.alloc::<u8>(len).or(Err(libc::EMSGSIZE))?;
+        let host_virt = Handler::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        let req = request!(libc::SYS_read => self.fd, host_virt, len);
+        let read: usize = unsafe { self.handler.proxy(req)? }[0].into();
+        if read > len {
+            self.handler.attacked();
+        }
+
+        let c = self.handler.new_cursor();
+        unsafe { c.copy_into_raw_parts(len, buf.as_mut_ptr(), read) }.or(Err(libc::EMSGSIZE))?;
+
+        Ok(read)
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, libc::c_int> {
+        let len = buf.len().min(MAX_IO);
+        let c = self.handler.new_cursor();
+        let (_, untrusted) = c.copy_from_slice(&buf[..len]).or(Err(libc::EMSGSIZE))?;
+        let host_virt = Handler::translate_shim_to_host_addr(untrusted.as_ptr());
+
+        let req = request!(libc::SYS_write => self.fd, host_virt, len);
+        let written: usize = unsafe { self.handler.proxy(req)? }[0].into();
+        if written > len {
+            self.handler.attacked();
+        }
+
+        Ok(written)
+    }
+}
+
+impl<'a> Handler<'a> {
+    /// Handle the syscalls on TLS listeners and sessions
+    ///
+    /// Returns `None` for all other syscalls and file descriptors.
+    pub(super) fn tls_syscall(&mut self, nr: usize) -> Option<sallyport::Result> {
+        if port() == 0 {
+            return None;
+        }
+
+        let gpr = &self.ssa.gpr;
+        let (a, b, c, d, e, f) = (gpr.rdi, gpr.rsi, gpr.rdx, gpr.r10, gpr.r8, gpr.r9);
+        let fd = a as libc::c_int;
+
+        if nr as libc::c_long == libc::SYS_bind {
+            return Some(self.tls_bind(fd, b as _, c as _));
+        }
+
+        if let Some(index) = tls().listeners.iter().position(|l| *l == fd) {
+            return self.tls_listener(nr, index);
+        }
+
+        let index = tls().slots.iter().position(|s| s.fd == fd)?;
+
+        Some(match nr as libc::c_long {
+            libc::SYS_read => {
+                self.trace("read", 3);
+                self.tls_read(index, b as _, c as _)
+            }
+
+            libc::SYS_recvfrom => {
+                self.trace("recvfrom", 6);
+                match d {
+                    0 => self.tls_recvfrom(index, b as _, c as _, e as _, f as _),
+                    _ => Err(libc::EOPNOTSUPP),
+                }
+            }
+
+            libc::SYS_readv => {
+                self.trace("readv", 3);
+                self.tls_readv(index, b as _, c as _)
+            }
+
+            libc::SYS_write => {
+                self.trace("write", 3);
+                self.tls_write(index, b as _, c as _)
+            }
+
+            libc::SYS_sendto => {
+                self.trace("sendto", 6);
+                match (d as libc::c_int & !libc::MSG_NOSIGNAL, e) {
+                    (0, 0) => self.tls_write(index, b as _, c as _),
+                    _ => Err(libc::EOPNOTSUPP),
+                }
+            }
+
+            libc::SYS_writev => {
+                self.trace("writev", 3);
+                self.tls_writev(index, b as _, c as _)
+            }
+
+            libc::SYS_shutdown => {
+                if b as libc::c_int != libc::SHUT_RD {
+                    self.tls_close_notify(index);
+                }
+                return None;
+            }
+
+            libc::SYS_close => {
+                self.tls_close_notify(index);
+                tls().slots[index].fd = -1;
+                return None;
+            }
+
+            // The session can't be shared or used without blocking.
+            libc::SYS_dup
+            | libc::SYS_dup2
+            | libc::SYS_dup3
+            | libc::SYS_recvmsg
+            | libc::SYS_sendmsg
+            | libc::SYS_sendfile
+            | libc::SYS_splice => Err(libc::EOPNOTSUPP),
+
+            libc::SYS_fcntl => match b as libc::c_int {
+                libc::F_DUPFD | libc::F_DUPFD_CLOEXEC => Err(libc::EOPNOTSUPP),
+                libc::F_SETFL if c as libc::c_int & libc::O_NONBLOCK != 0 => Err(libc::EOPNOTSUPP),
+                _ => return None,
+            },
+
+            libc::SYS_ioctl if b == libc::FIONBIO as u64 => Err(libc::EOPNOTSUPP),
+
+            _ => return None,
+        })
+    }
+
+    /// Handle the syscalls on a TLS listener
+    fn tls_listener(&mut self, nr: usize, index: usize) -> Option<sallyport::Result> {
+        let gpr = &self.ssa.gpr;
+        let (a, b, c, d, e, f) = (gpr.rdi, gpr.rsi, gpr.rdx, gpr.r10, gpr.r8, gpr.r9);
+
+        Some(match nr as libc::c_long {
+            libc::SYS_accept | libc::SYS_accept4 => {
+                // The accepted socket must block for the handshake.
+                let flags = match nr as libc::c_long {
+                    libc::SYS_accept => 0,
+                    _ => d & !(libc::SOCK_NONBLOCK as u64),
+                };
+
+                let ret = self.syscall(
+                    a.into(),
+                    b.into(),
+                    c.into(),
+                    flags.into(),
+                    e.into(),
+                    f.into(),
+                    libc::SYS_accept4 as _,
+                );
+
+                match ret {
+                    Ok(ret) => self.tls_accept(usize::from(ret[0]) as _).map(|_| ret),
+                    Err(e) => Err(e),
+                }
+            }
+
+            libc::SYS_close => {
+                tls().listeners[index] = -1;
+                return None;
+            }
+
+            libc::SYS_dup | libc::SYS_dup2 | libc::SYS_dup3 => Err(libc::EOPNOTSUPP),
+
+            libc::SYS_fcntl => match b as libc::c_int {
+                libc::F_DUPFD | libc::F_DUPFD_CLOEXEC => Err(libc::EOPNOTSUPP),
+                _ => return None,
+            },
+
+            _ => return None,
+        })
+    }
+
+    /// Bind a socket, which becomes a TLS listener for the configured port
+    fn tls_bind(
+        &mut self,
+        fd: libc::c_int,
+        addr: *const u8,
+        addrlen: libc::socklen_t,
+    ) -> sallyport::Result {
+        let mut bound = None;
+
+        if !addr.is_null() && addrlen >= 4 {
+            let addr = UntrustedRef::from(addr)
+                .validate_slice(4, self)
+                .ok_or(libc::EFAULT)?;
+
+            // The port follows the family in `sockaddr_in` and `sockaddr_in6`.
+            let family = u16::from_ne_bytes([addr[0], addr[1]]) as libc::c_int;
+            if family == libc::AF_INET || family == libc::AF_INET6 {
+                bound = Some(u16::from_be_bytes([addr[2], addr[3]]));
+            }
+        }
+
+        let listener = match bound == Some(port()) {
+            false => None,
+            true => Some(
+                tls()
+                    .listeners
+                    .iter()
+                    .position(|l| *l == -1)
+                    .ok_or(libc::ENOBUFS)?,
+            ),
+        };
+
+        let gpr = &self.ssa.gpr;
+        let (a, b, c, d, e, f) = (gpr.rdi, gpr.rsi, gpr.rdx, gpr.r10, gpr.r8, gpr.r9);
+        let ret = self.syscall(
+            a.into(),
+            b.into(),
+            c.into(),
+            d.into(),
+            e.into(),
+            f.into(),
+            libc::SYS_bind as _,
+        )?;
+
+        if let Some(index) = listener {
+            tls().listeners[index] = fd;
+        }
+
+        Ok(ret)
+    }
+
+    /// The identity of the keep, created on first use
+    fn tls_identity(&mut self) -> Result<&'static Identity, libc::c_int> {
+        let tls = tls();
+
+        if tls.identity.is_none() {
+            let mut seed = [0u8; 32];
+            for chunk in seed.chunks_mut(8) {
+                chunk.copy_from_slice(&random().to_ne_bytes());
+            }
+
+            let mut data = [0u8; 64];
+            data[..32].copy_from_slice(&Sha256::digest(&Identity::public_key(&seed)));
+
+            let mut quote = [0u8; MAX_QUOTE];
+            let evidence = match self.quote(&data, &mut quote) {
+                Ok(len) => Evidence::Sgx(&quote[..len]),
+                Err(e) => {
+                    debugln!(self, "No quote for the TLS certificate: {}", e);
+                    if attest() {
+                        return Err(e);
+                    }
+
+                    Evidence::None
+                }
+            };
+
+            let identity = Identity::new(&seed, evidence).or(Err(libc::ENOMEM))?;
+            tls.identity = Some(identity);
+        }
+
+        Ok(tls.identity.as_ref().unwrap())
+    }
+
+    /// Run the handshake on an accepted socket
+    ///
+    /// On failure, the socket is closed and the payload sees an aborted
+    /// connection.
+    fn tls_accept(&mut self, fd: libc::c_int) -> Result<(), libc::c_int> {
+        let ret = self.tls_handshake(fd);
+
+        if ret.is_err() {
+            let req = request!(libc::SYS_close => fd);
+            let _ = unsafe { self.proxy(req) };
+        }
+
+        ret
+    }
+
+    fn tls_handshake(&mut self, fd: libc::c_int) -> Result<(), libc::c_int> {
+        let index = tls()
+            .slots
+            .iter()
+            .position(|s| s.fd == -1)
+            .ok_or(libc::ECONNABORTED)?;
+
+        let identity = self.tls_identity()?;
+
+        let mut entropy = [0u8; 64];
+        for chunk in entropy.chunks_mut(8) {
+            chunk.copy_from_slice(&random().to_ne_bytes());
+        }
+
+        let slot = &mut tls().slots[index];
+        let mut socket = Socket { handler: self, fd };
+        if let Err(e) = slot.session.accept(&mut socket, identity, &entropy) {
+            debugln!(socket.handler, "TLS handshake failed: {:?}", e);
+            return Err(libc::ECONNABORTED);
+        }
+
+        slot.fd = fd;
+        slot.shutdown = false;
+        Ok(())
+    }
+
+    /// Send `close_notify` once, when the payload is done writing
+    fn tls_close_notify(&mut self, index: usize) {
+        let slot = &mut tls().slots[index];
+
+        if !slot.shutdown {
+            slot.shutdown = true;
+            let mut socket = Socket {
+                handler: self,
+                fd: slot.fd,
+            };
+            let _ = slot.session.close(&mut socket);
+        }
+    }
+
+    fn tls_read(&mut self, index: usize, buf: *mut u8, count: usize) -> sallyport::Result {
+        let slot = &mut tls().slots[index];
+        let buf = UntrustedRefMut::from(buf)
+            .validate_slice(count, self)
+            .ok_or(libc::EFAULT)?;
+
+        let mut socket = Socket {
+            handler: self,
+            fd: slot.fd,
+        };
+        let read = slot.session.read(&mut socket, buf).map_err(|e| e.errno())?;
+
+        Ok([read.into(), 0.into()])
+    }
+
+    fn tls_recvfrom(
+        &mut self,
+        index: usize,
+        buf: *mut u8,
+        count: usize,
+        addr: *mut u8,
+        addrlen: *mut libc::socklen_t,
+    ) -> sallyport::Result {
+        let ret = self.tls_read(index, buf, count)?;
+
+        // A connected stream socket reports no address, like Linux.
+        if !addr.is_null() && !addrlen.is_null() {
+            let addrlen = UntrustedRefMut::from(addrlen)
+                .validate(self)
+                .ok_or(libc::EFAULT)?;
+            *addrlen = 0;
+        }
+
+        Ok(ret)
+    }
+
+    fn tls_write(&mut self, index: usize, buf: *const u8, count: usize) -> sallyport::Result {
+        let slot = &mut tls().slots[index];
+        if slot.shutdown {
+            return Err(libc::EPIPE);
+        }
+
+        let buf = UntrustedRef::from(buf)
+            .validate_slice(count, self)
+            .ok_or(libc::EFAULT)?;
+
+        let mut socket = Socket {
+            handler: self,
+            fd: slot.fd,
+        };
+        let written = slot
+            .session
+            .write(&mut socket, buf)
+            .map_err(|e| e.errno())?;
+
+        Ok([written.into(), 0.into()])
+    }
+
+    fn tls_readv(
+        &mut self,
+        index: usize,
+        iovec: *const libc::iovec,
+        iovcnt: libc::c_int,
+    ) -> sallyport::Result {
+        let iovec = UntrustedRef::from(iovec)
+            .validate_slice(iovcnt, self)
+            .ok_or(libc::EFAULT)?;
+
+        let mut total = 0usize;
+        for iov in iovec {
+            // Only wait for more data, if nothing was read yet.
+            if total > 0 && tls().slots[index].session.pending() == 0 {
+                break;
+            }
+
+            let read: usize = self.tls_read(index, iov.iov_base as _, iov.iov_len)?[0].into();
+            total += read;
+
+            if read < iov.iov_len {
+                break;
+            }
+        }
+
+        Ok([total.into(), 0.into()])
+    }
+
+    fn tls_writev(
+        &mut self,
+        index: usize,
+        iovec: *const libc::iovec,
+        iovcnt: libc::c_int,
+    ) -> sallyport::Result {
+        let iovec = UntrustedRef::from(iovec)
+            .validate_slice(iovcnt, self)
+            .ok_or(libc::EFAULT)?;
+
+        let mut total = 0usize;
+        for iov in iovec {
+            self.tls_write(index, iov.iov_base as _, iov.iov_len)?;
+            total += iov.iov_len;
+        }
+
+        Ok([total.into(), 0.into()])
+    }
+}
@@ -51,16 +51,130 @@ pub extern "C" fn rust_eh_personality() {
 mod entry;
 mod handler;
 
+use abi::note::{Note, PATH_MAX};
 use noted::noted;
 use sallyport::{elf::note, REQUIRES};
 use sgx::parameters::{Attributes, Features, MiscSelect, Xfrm};
 use sgx::ssa::StateSaveArea;
 
-const DEBUG: bool = false;
+/// Whether debug output is enabled
+///
+/// The loader sets the descriptor to a non-zero value with `exec --debug`.
+/// The note is part of the measured pages, so enabling debug output
+/// changes MRENCLAVE.
+#[used]
+#[link_section = ".note"]
+static NOTE_ENARX_DEBUG: Note<u32> = Note::new(abi::note::DEBUG, 0);
+
+/// The port of the listening sockets to terminate TLS on
+///
+/// The loader sets the descriptor with `exec --tls`. The port 0 disables
+/// TLS termination. The `TLS_ATTEST` flag, set with `exec --attest`,
+/// refuses connections without a quote. Like the debug switch, the
+/// descriptor is measured.
+#[used]
+#[link_section = ".note"]
+static NOTE_ENARX_TLS: Note<u32> = Note::new(abi::note::TLS, 0);
+
+/// Whether the host time hints are kept from moving the wall clock backwards
+///
+/// The loader sets the descriptor to a non-zero value with
+/// `exec --strict-time`. Like the debug switch, the switch is measured.
+#[used]
+#[link_section = ".note"]
+static NOTE_ENARX_TIME: Note<u32> = Note::new(abi::note::TIME, 0);
+
+/// Whether the pages of the heap are added on demand
+///
+/// The loader sets the descriptor to a non-zero value for `--edmm`, which
+/// needs SGX2. The heap is then not measured, but the switch is.
+#[used]
+#[link_section = ".note"]
+static NOTE_ENARX_EDMM: Note<u32> = Note::new(abi::note::EDMM, 0);
+
+/// The size of the heap in pages
+///
+/// The loader sets the descriptor for the memory of the keep. The heap is
+/// the heap segment, if it is 0. Like the debug switch, the size is measured.
+#[used]
+#[link_section = ".note"]
+static NOTE_ENARX_HEAP: Note<u32> = Note::new(abi::note::HEAP, 0);
+
+/// The size of the data in bytes, which follows the heap
+///
+/// The loader sets the descriptor with `exec --data`. The size is always
+/// measured, but the data only with `exec --measure-data`.
+#[used]
+#[link_section = ".note"]
+static NOTE_ENARX_DATA: Note<u32> = Note::new(abi::note::DATA, 0);
+
+/// The prefix of the files, which are encrypted transparently
+///
+/// The loader sets the descriptor with `exec --encrypt`. An empty prefix
+/// disables encryption. Like the debug switch, the prefix is measured.
+#[used]
+#[link_section = ".note"]
+static NOTE_ENARX_ENCRYPT: Note<[u8; PATH_MAX]> = Note::new(abi::note::ENCRYPT, [0; PATH_MAX]);
+
+/// Returns true, if the loader enabled debug output
+#[inline(always)]
+fn debug() -> bool {
+    NOTE_ENARX_DEBUG.desc() != 0
+}
+
+/// The heap, as large as the loader sized it
+fn heap() -> lset::Line<usize> {
+    let (start, end) = unsafe {
+        (
+            &ENARX_HEAP_START as *const _ as usize,
+            &ENARX_HEAP_END as *const _ as usize,
+        )
+    };
+
+    match NOTE_ENARX_HEAP.desc() as usize {
+        0 => lset::Line::new(start, end),
+        pages => lset::Line::new(start, end.min(start + pages * primordial::Page::SIZE)),
+    }
+}
+
+/// The address and the size of the data, if the loader added it
+fn data() -> Option<(usize, usize)> {
+    match NOTE_ENARX_DATA.desc() as usize {
+        0 => None,
+        size => Some((heap().end, size)),
+    }
+}
+
+/// Returns true, if the loader enabled EDMM
+#[inline(always)]
+fn edmm() -> bool {
+    NOTE_ENARX_EDMM.desc() != 0
+}
 
-const ENCL_SIZE_BITS: u8 = 31;
-const ENCL_SIZE: usize = 1 << ENCL_SIZE_BITS;
+/// The maximal size of the enclave, which the loader sizes for the heap
+///
+/// The heap segment of the linker script ends at 1 GiB, so this is the
+/// enclave with all of it. The enclave used to be 2 GiB, when its size was
+/// fixed, but now the loader only makes it as large as the heap it chose.
+const ENCL_SIZE_BITS: u8 = 30;
 
+/// The minimal size of the enclave, which holds the shim and a heap of a page
+///
+/// The shim ends with the executable slot at 128 MiB and the enclave is a
+/// power of two.
+const ENCL_SIZE_MIN: usize = 1 << 28;
+
+/// The alignment of the base of the enclave, which `relocate` finds it with
+///
+/// The enclave is naturally aligned to its size, which is at least
+/// `ENCL_SIZE_MIN`, and `_DYNAMIC` is below this alignment.
+const ENCL_BASE_ALIGN: usize = 1 << 28;
+
+const _: () = assert!(ENCL_BASE_ALIGN <= ENCL_SIZE_MIN && ENCL_SIZE_MIN <= 1 << ENCL_SIZE_BITS);
+
+/// The minimal XFRM of the enclave, the loader adds the extended state the CPU supports
+///
+/// An SSA frame of a page holds the XSAVE area of up to AVX-512.
 const XFRM: Xfrm = Xfrm::from_bits_truncate(Xfrm::X87.bits() | Xfrm::SSE.bits());
 const ATTR: Attributes = Attributes::new(Features::MODE64BIT, XFRM);
 
@@ -72,8 +186,9 @@ noted! {
 
     static NOTE_PID<note::NAME, note::sgx::PID, u16> = 0;
     static NOTE_SVN<note::NAME, note::sgx::SVN, u16> = 0;
-    static NOTE_MISC<note::NAME, note::sgx::MISC, MiscSelect> = MiscSelect::empty();
-    static NOTE_MISCMASK<note::NAME, note::sgx::MISCMASK, MiscSelect> = MiscSelect::empty();
+    // `EXINFO` reports `#PF` and `#GP` in the SSA, with their address and error code.
+    static NOTE_MISC<note::NAME, note::sgx::MISC, MiscSelect> = MiscSelect::EXINFO;
+    static NOTE_MISCMASK<note::NAME, note::sgx::MISCMASK, MiscSelect> = MiscSelect::EXINFO;
     static NOTE_ATTR<note::NAME, note::sgx::ATTR, Attributes> = ATTR;
     static NOTE_ATTRMASK<note::NAME, note::sgx::ATTRMASK, Attributes> = ATTR;
 }
@@ -111,6 +226,8 @@ extern "sysv64" fn clearx() {
             "cld",
 
             // Clear the extended CPU state
+            // The components missing in the header of the synthetic state
+            // are initialized, so this clears the state of any XFRM.
             "push    rax            ",  // Save rax
             "push    rdx            ",  // Save rdx
             "mov     rdx,   ~0      ",  // Set mask for xrstor in rdx
@@ -175,7 +292,7 @@ unsafe extern "sysv64" fn relocate() {
         "push   r11",
 
         "lea    rdi,    [rip + _DYNAMIC]", // rdi = address of _DYNAMIC section
-        "mov    rsi,    -{SIZE}         ", // rsi = enclave start address mask
+        "mov    rsi,    -{ALIGN}        ", // rsi = enclave start address mask
         "and    rsi,    rdi             ", // rsi = relocation address
         "call   {DYN_RELOC}             ", // relocate the dynamic symbols
 
@@ -191,7 +308,7 @@ unsafe extern "sysv64" fn relocate() {
 
         "ret",
 
-        SIZE = const ENCL_SIZE,
+        ALIGN = const ENCL_BASE_ALIGN,
         DYN_RELOC = sym rcrt1::dyn_reloc,
         options(noreturn)
     )
@@ -208,6 +325,9 @@ unsafe extern "sysv64" fn relocate() {
 /// If rax == 0, we are doing normal execution.
 /// Otherwise, we are handling an exception.
 ///
+/// The loader passes a pending host signal in r8. While handling an
+/// exception, it passes the address of a page to accept in r8 instead.
+///
 /// # Safety
 ///
 /// Do not call this function from Rust. It is the entry point for SGX.
@@ -255,6 +375,7 @@ pub unsafe extern "sysv64" fn _start() -> ! {
         "4:                                 ",  // rdi = &mut sallyport::Block (passthrough)
         "lea    rsi,    [rcx + 4096]        ",  // rsi = &mut [StateSaveArea; N]
         "mov    rdx,    rax                 ",  // rdx = CSSA
+        "mov    rcx,    r8                  ",  // rcx = host signal (passthrough r8)
         "call   {CLEARX}                    ",  // Clear CPU state
         "call   {ENTRY}                     ",  // Jump to Rust
         "call   {CLEARX}                    ",  // Clear CPU state
@@ -275,15 +396,17 @@ pub unsafe extern "sysv64" fn _start() -> ! {
     )
 }
 
-unsafe extern "C" fn main(port: &mut sallyport::Block, ssas: &mut [StateSaveArea; 3], cssa: usize) {
-    let heap = lset::Line::new(
-        &ENARX_HEAP_START as *const _ as usize,
-        &ENARX_HEAP_END as *const _ as usize,
-    );
+unsafe extern "C" fn main(
+    port: &mut sallyport::Block,
+    ssas: &mut [StateSaveArea; 3],
+    cssa: usize,
+    signo: usize,
+) {
+    let heap = heap();
 
     match cssa {
         0 => entry::entry(&ENARX_EXEC_START as *const u8 as _),
-        1 => handler::Handler::handle(&mut ssas[0], port, heap),
-        n => handler::Handler::finish(&mut ssas[n - 1]),
+        1 => handler::Handler::handle(&mut ssas[0], port, heap, signo),
+        n => handler::Handler::finish(&mut ssas[n - 1], signo),
     }
 }
@@ -0,0 +1,254 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The signal frame on the payload stack
+//!
+//! Like Linux, the frame holds the extended state in the XSAVE format
+//! below the interrupted stack, aligned to 64 bytes and described by
+//! `struct _fpx_sw_bytes`. `struct rt_sigframe` follows below it.
+
+use crate::xsave;
+use crate::{Context, Info, SigAction, DIRECTION_FLAG, TRAP_FLAG};
+
+use core::mem::size_of;
+
+/// The size of the red zone of the payload stack
+const RED_ZONE: u64 = 128;
+
+/// `uc_flags` of a frame with the extended state in the XSAVE format
+const UC_FP_XSTATE: u64 = 1;
+
+/// The magic number of `struct _fpx_sw_bytes`
+const FP_XSTATE_MAGIC1: u32 = 0x4650_5853;
+
+/// The magic number at the end of the XSAVE area
+const FP_XSTATE_MAGIC2: u32 = 0x4650_5845;
+
+/// The offset of `struct _fpx_sw_bytes` in the legacy area
+const SW_BYTES: u64 = 464;
+
+/// The alignment of the XSAVE area
+const XSAVE_ALIGN: u64 = 64;
+
+/// `struct sigcontext` of the Linux kernel
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct SigContext {
+    regs: Context,
+    segments: u64,
+    err: u64,
+    trapno: u64,
+    oldmask: u64,
+    cr2: u64,
+    fpstate: u64,
+    reserved: [u64; 8],
+}
+
+/// `struct ucontext` of the Linux kernel
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct UContext {
+    flags: u64,
+    link: u64,
+    stack_sp: u64,
+    stack_flags: u64,
+    stack_size: u64,
+    mcontext: SigContext,
+    sigmask: u64,
+}
+
+/// `siginfo_t` of the Linux kernel
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct SigInfo {
+    signo: i32,
+    errno: i32,
+    code: i32,
+    pad: i32,
+    fields: [u64; 14],
+}
+
+/// `struct rt_sigframe` of the Linux kernel
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct SigFrame {
+    restorer: u64,
+    uc: UContext,
+    info: SigInfo,
+}
+
+/// `struct _fpx_sw_bytes` of the Linux kernel
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+struct SwBytes {
+    magic1: u32,
+    extended_size: u32,
+    xfeatures: u64,
+    xstate_size: u32,
+    padding: [u32; 7],
+}
+
+/// The payload, as the shim sees it
+pub trait Payload {
+    /// Check, if the shim may access `len` bytes of payload memory at `addr`
+    fn is_payload(&self, addr: u64, len: u64) -> bool;
+
+    /// The state components of the extended state of the payload
+    ///
+    /// Only the components in `xsave::KNOWN` are saved in a frame.
+    fn xfeatures(&self) -> u64;
+
+    /// Save the extended state of the payload to the XSAVE area at `area`
+    ///
+    /// # Safety
+    ///
+    /// `area` is payload memory of `xsave::size(self.xfeatures())` bytes,
+    /// aligned to 64 bytes and cleared.
+    unsafe fn xsave(&mut self, area: *mut u8);
+
+    /// Restore the extended state of the payload from the XSAVE area at `area`
+    ///
+    /// # Safety
+    ///
+    /// `area` is payload memory of `xsave::size(self.xfeatures())` bytes,
+    /// aligned to 64 bytes, which holds a valid XSAVE area.
+    unsafe fn xrstor(&mut self, area: *const u8);
+}
+
+/// The state components of the extended state, which are saved in a frame
+fn xfeatures(payload: &impl Payload) -> u64 {
+    payload.xfeatures() & xsave::KNOWN
+}
+
+/// Build the signal frame on the payload stack and return the registers of the handler
+///
+/// `ctx` are the registers of the interrupted payload, `info` is the signal
+/// to deliver to the handler of `action` and `mask` is the signal mask to
+/// restore on `rt_sigreturn`. Returns `None`, if the frame does not fit on
+/// the payload stack.
+pub fn enter(
+    payload: &mut impl Payload,
+    ctx: &Context,
+    info: &Info,
+    action: &SigAction,
+    mask: u64,
+    cr2: u64,
+    segments: u64,
+) -> Option<Context> {
+    let xfeatures = xfeatures(payload);
+    let xsize = xsave::size(xfeatures);
+    let extended = xsize.wrapping_add(size_of::<u32>() as u64);
+    let size = size_of::<SigFrame>() as u64;
+
+    // Skip the red zone and place the extended state and the frame below
+    // it. Align the stack like a `call` would.
+    let top = ctx.rsp.checked_sub(RED_ZONE)?;
+    let fpstate = top.checked_sub(extended)? & !XSAVE_ALIGN.wrapping_sub(1);
+    let sp = (fpstate.checked_sub(size)? & !0xF).checked_sub(8)?;
+
+    if !payload.is_payload(sp, top.wrapping_sub(sp)) {
+        return None;
+    }
+
+    let sw = SwBytes {
+        magic1: FP_XSTATE_MAGIC1,
+        extended_size: extended as u32,
+        xfeatures,
+        xstate_size: xsize as u32,
+        ..Default::default()
+    };
+
+    unsafe {
+        core::ptr::write_bytes(fpstate as *mut u8, 0, xsize as usize);
+        payload.xsave(fpstate as *mut u8);
+        (fpstate.wrapping_add(SW_BYTES) as *mut SwBytes).write(sw);
+        (fpstate.wrapping_add(xsize) as *mut u32).write_unaligned(FP_XSTATE_MAGIC2);
+    }
+
+    let mut frame = SigFrame {
+        restorer: action.restorer,
+        ..Default::default()
+    };
+
+    frame.uc.flags = UC_FP_XSTATE;
+    frame.uc.mcontext.regs = *ctx;
+    frame.uc.mcontext.segments = segments;
+    frame.uc.mcontext.err = info.err;
+    frame.uc.mcontext.trapno = info.trapno;
+    frame.uc.mcontext.oldmask = mask;
+    frame.uc.mcontext.cr2 = cr2;
+    frame.uc.mcontext.fpstate = fpstate;
+    frame.uc.sigmask = mask;
+
+    frame.info.signo = info.signo;
+    frame.info.code = info.code;
+    frame.info.fields[0] = info.value;
+
+    unsafe { (sp as *mut SigFrame).write(frame) };
+
+    let mut regs = *ctx;
+    regs.rdi = info.signo as u64;
+    regs.rsi = sp.wrapping_add(size_of::<u64>().wrapping_add(size_of::<UContext>()) as u64);
+    regs.rdx = sp.wrapping_add(size_of::<u64>() as u64);
+    regs.rax = 0;
+    regs.rsp = sp;
+    regs.rip = action.handler;
+    regs.rflags &= !(TRAP_FLAG | DIRECTION_FLAG);
+
+    Some(regs)
+}
+
+/// Check the extended state of a frame at `fpstate`, before `xrstor` may load it
+///
+/// `XRSTOR` faults on a reserved bit in the header or in `MXCSR`.
+fn valid(payload: &impl Payload, fpstate: u64) -> bool {
+    let xfeatures = xfeatures(payload);
+    let xsize = xsave::size(xfeatures);
+    let extended = xsize.wrapping_add(size_of::<u32>() as u64);
+
+    if fpstate & XSAVE_ALIGN.wrapping_sub(1) != 0 || !payload.is_payload(fpstate, extended) {
+        return false;
+    }
+
+    let read = |offset: u64| unsafe { (fpstate.wrapping_add(offset) as *const u64).read() };
+
+    let sw = unsafe { (fpstate.wrapping_add(SW_BYTES) as *const SwBytes).read() };
+    let magic2 = unsafe { (fpstate.wrapping_add(xsize) as *const u32).read_unaligned() };
+    let mxcsr = read(xsave::MXCSR) as u32;
+
+    // The state components, the compaction and the reserved bytes of the header
+    let xstate_bv = read(xsave::HEADER);
+    let reserved = (1..8u64).map(|i| read(xsave::HEADER.wrapping_add(i.wrapping_mul(8))));
+
+    sw.magic1 == FP_XSTATE_MAGIC1
+        && sw.xstate_size as u64 == xsize
+        && magic2 == FP_XSTATE_MAGIC2
+        && mxcsr & !xsave::MXCSR_MASK == 0
+        && xstate_bv & !xfeatures == 0
+        && reserved.fold(0, |all, r| all | r) == 0
+}
+
+/// Restore the payload from the signal frame of `rt_sigreturn`
+///
+/// The restorer calls `rt_sigreturn` with `rsp`, after the handler returned
+/// and popped the restorer address, so the stack points to the `ucontext`.
+/// Restores the extended state and returns the registers and the signal
+/// mask saved in the frame. A frame without extended state leaves it as
+/// it is. Returns `None` for a bad frame.
+pub fn sigreturn(payload: &mut impl Payload, rsp: u64) -> Option<(Context, u64)> {
+    if !payload.is_payload(rsp, size_of::<UContext>() as u64) {
+        return None;
+    }
+
+    let uc = unsafe { (rsp as *const UContext).read_unaligned() };
+    let fpstate = uc.mcontext.fpstate;
+
+    if fpstate != 0 {
+        if !valid(payload, fpstate) {
+            return None;
+        }
+
+        unsafe { payload.xrstor(fpstate as *const u8) };
+    }
+
+    Some((uc.mcontext.regs, uc.sigmask))
+}
@@ -0,0 +1,299 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! POSIX signals of the payload, shared by the shims
+//!
+//! The shims keep the signal actions, the signal mask and the pending signals
+//! of the payload in `Signals`. A signal is delivered on the way back to the
+//! payload: `frame::enter` builds a Linux compatible `rt_sigframe` on the
+//! payload stack, which holds the registers and the extended state, and
+//! returns the registers to resume the payload in the signal handler.
+//! `frame::sigreturn` restores them from the frame.
+
+#![no_std]
+#![deny(clippy::all)]
+#![deny(missing_docs)]
+
+pub mod frame;
+pub mod xsave;
+
+/// The number of signals
+const NSIG: usize = 64;
+
+/// The default action of a signal
+const SIG_DFL: u64 = 0;
+
+/// Ignore a signal
+const SIG_IGN: u64 = 1;
+
+/// Don't block the signal in its handler
+const SA_NODEFER: u64 = 0x4000_0000;
+
+/// Reset the action to the default, when the signal is delivered
+const SA_RESETHAND: u64 = 0x8000_0000;
+
+/// `si_code` of a signal sent by `kill`
+pub const SI_USER: libc::c_int = 0;
+
+/// `si_code` of a signal sent by `tkill` or `tgkill`
+pub const SI_TKILL: libc::c_int = -6;
+
+/// `si_code` of a signal sent by the kernel
+pub const SI_KERNEL: libc::c_int = 0x80;
+
+/// `si_code` of an integer divide by zero
+pub const FPE_INTDIV: libc::c_int = 1;
+
+/// `si_code` of an illegal operand
+pub const ILL_ILLOPN: libc::c_int = 2;
+
+/// `si_code` of a breakpoint
+pub const TRAP_BRKPT: libc::c_int = 1;
+
+/// `si_code` of an invalid address alignment
+pub const BUS_ADRALN: libc::c_int = 1;
+
+/// `si_code` of an address not mapped to an object
+pub const SEGV_MAPERR: libc::c_int = 1;
+
+/// `si_code` of invalid permissions for a mapped object
+pub const SEGV_ACCERR: libc::c_int = 2;
+
+/// The signals, which can't be caught or blocked
+const UNBLOCKABLE: u64 = bit(libc::SIGKILL) | bit(libc::SIGSTOP);
+
+/// The signals, which are ignored by default
+const IGNORED: u64 =
+    bit(libc::SIGCHLD) | bit(libc::SIGCONT) | bit(libc::SIGURG) | bit(libc::SIGWINCH);
+
+/// The flags of the payload, which can be restored by `rt_sigreturn`
+///
+/// CF, PF, AF, ZF, SF, TF, DF, OF, AC and ID
+pub const USER_FLAGS: u64 = 0x0024_0DD5;
+
+/// The trap flag
+const TRAP_FLAG: u64 = 1 << 8;
+
+/// The direction flag
+const DIRECTION_FLAG: u64 = 1 << 10;
+
+/// The bit of `signo` in a signal set
+pub const fn bit(signo: libc::c_int) -> u64 {
+    1u64.wrapping_shl((signo as u32).wrapping_sub(1))
+}
+
+/// `struct sigaction` of the Linux kernel
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+pub struct SigAction {
+    handler: u64,
+    flags: u64,
+    restorer: u64,
+    mask: u64,
+}
+
+/// The registers of the payload in the order of `struct sigcontext`
+#[repr(C)]
+#[derive(Copy, Clone, Debug, Default)]
+#[allow(missing_docs)]
+pub struct Context {
+    pub r8: u64,
+    pub r9: u64,
+    pub r10: u64,
+    pub r11: u64,
+    pub r12: u64,
+    pub r13: u64,
+    pub r14: u64,
+    pub r15: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rbp: u64,
+    pub rbx: u64,
+    pub rdx: u64,
+    pub rax: u64,
+    pub rcx: u64,
+    pub rsp: u64,
+    pub rip: u64,
+    pub rflags: u64,
+}
+
+/// A signal to deliver to the payload
+#[derive(Copy, Clone, Debug, Default)]
+pub struct Info {
+    /// The signal number
+    pub signo: libc::c_int,
+
+    /// The `si_code` of the signal
+    pub code: libc::c_int,
+
+    /// The faulting address or the sender of the signal
+    pub value: u64,
+
+    /// The exception vector of a fault
+    pub trapno: u64,
+
+    /// The error code of a fault
+    pub err: u64,
+}
+
+/// What to do with a signal
+pub enum Delivery {
+    /// Run the handler with the signal mask to restore afterwards
+    Handler(SigAction, u64),
+
+    /// Terminate the payload
+    Terminate,
+}
+
+/// The signal state of the payload
+pub struct Signals {
+    actions: [SigAction; NSIG],
+    codes: [libc::c_int; NSIG],
+    blocked: u64,
+    pending: u64,
+}
+
+impl Default for Signals {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Signals {
+    /// The signal state of a new payload, with the default actions
+    pub const fn new() -> Self {
+        const DFL: SigAction = SigAction {
+            handler: SIG_DFL,
+            flags: 0,
+            restorer: 0,
+            mask: 0,
+        };
+
+        Self {
+            actions: [DFL; NSIG],
+            codes: [0; NSIG],
+            blocked: 0,
+            pending: 0,
+        }
+    }
+
+    fn index(signo: libc::c_int) -> Result<usize, libc::c_int> {
+        match signo {
+            1..=64 => Ok((signo as usize).wrapping_sub(1)),
+            _ => Err(libc::EINVAL),
+        }
+    }
+
+    /// Set the action of `signo`, if `new` is given, and return the old action
+    pub fn action(
+        &mut self,
+        signo: libc::c_int,
+        new: Option<SigAction>,
+    ) -> Result<SigAction, libc::c_int> {
+        let index = Self::index(signo)?;
+        let old = self.actions[index];
+
+        if let Some(mut new) = new {
+            if bit(signo) & UNBLOCKABLE != 0 {
+                return Err(libc::EINVAL);
+            }
+
+            new.mask &= !UNBLOCKABLE;
+            self.actions[index] = new;
+        }
+
+        Ok(old)
+    }
+
+    /// Change the signal mask, if `set` is given, and return the old mask
+    pub fn mask(&mut self, how: libc::c_int, set: Option<u64>) -> Result<u64, libc::c_int> {
+        let old = self.blocked;
+
+        if let Some(set) = set {
+            self.blocked = match how {
+                libc::SIG_BLOCK => old | set,
+                libc::SIG_UNBLOCK => old & !set,
+                libc::SIG_SETMASK => set,
+                _ => return Err(libc::EINVAL),
+            } & !UNBLOCKABLE;
+        }
+
+        Ok(old)
+    }
+
+    /// Restore the signal mask saved in a signal frame
+    pub fn restore(&mut self, mask: u64) {
+        self.blocked = mask & !UNBLOCKABLE;
+    }
+
+    /// Mark `signo` as pending
+    ///
+    /// A `signo` of zero only checks, if a signal could be sent.
+    pub fn raise(&mut self, signo: libc::c_int, code: libc::c_int) -> Result<(), libc::c_int> {
+        if signo == 0 {
+            return Ok(());
+        }
+
+        let index = Self::index(signo)?;
+        self.pending |= bit(signo);
+        self.codes[index] = code;
+        Ok(())
+    }
+
+    /// Enter the handler of `signo`
+    fn enter(&mut self, signo: libc::c_int, action: SigAction) -> Delivery {
+        let old = self.blocked;
+
+        self.blocked |= action.mask;
+        if action.flags & SA_NODEFER == 0 {
+            self.blocked |= bit(signo);
+        }
+        self.blocked &= !UNBLOCKABLE;
+
+        if action.flags & SA_RESETHAND != 0 {
+            self.actions[(signo as usize).wrapping_sub(1)] = SigAction::default();
+        }
+
+        Delivery::Handler(action, old)
+    }
+
+    /// Take the next pending signal, which is not blocked or ignored
+    pub fn take(&mut self) -> Option<(Info, Delivery)> {
+        loop {
+            let deliverable = self.pending & !self.blocked;
+            if deliverable == 0 {
+                return None;
+            }
+
+            let signo = deliverable.trailing_zeros().wrapping_add(1) as libc::c_int;
+            let index = (signo as usize).wrapping_sub(1);
+            self.pending &= !bit(signo);
+
+            let info = Info {
+                signo,
+                code: self.codes[index],
+                ..Default::default()
+            };
+
+            let action = self.actions[index];
+            match action.handler {
+                SIG_IGN => continue,
+                SIG_DFL if bit(signo) & IGNORED != 0 => continue,
+                SIG_DFL => return Some((info, Delivery::Terminate)),
+                _ => return Some((info, self.enter(signo, action))),
+            }
+        }
+    }
+
+    /// Decide what to do with a synchronous fault
+    ///
+    /// A fault, which is blocked or ignored, terminates the payload.
+    pub fn fault(&mut self, signo: libc::c_int) -> Delivery {
+        let action = self.actions[(signo as usize).wrapping_sub(1)];
+
+        match action.handler {
+            SIG_DFL | SIG_IGN => Delivery::Terminate,
+            _ if self.blocked & bit(signo) != 0 => Delivery::Terminate,
+            _ => self.enter(signo, action),
+        }
+    }
+}
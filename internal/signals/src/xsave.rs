@@ -0,0 +1,43 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The XSAVE area of the extended state in the standard format
+
+/// The size of the legacy area and the header of the XSAVE area
+pub const LEGACY_SIZE: u64 = 576;
+
+/// The offset of the header in the XSAVE area
+pub const HEADER: u64 = 512;
+
+/// The offset of `MXCSR` in the legacy area
+pub const MXCSR: u64 = 24;
+
+/// The bits of `MXCSR`, which may be set
+pub const MXCSR_MASK: u32 = 0xFFFF;
+
+/// The size and the offset of the user state components in the standard
+/// format of the XSAVE area, which are architectural
+pub const COMPONENTS: [(u64, u64); 10] = [
+    (0, 0),
+    (0, 0),
+    (256, 576),
+    (64, 960),
+    (64, 1024),
+    (64, 1088),
+    (512, 1152),
+    (1024, 1664),
+    (0, 0),
+    (8, 2688),
+];
+
+/// The state components with an architectural layout: x87 to AVX-512 and PKRU
+pub const KNOWN: u64 = 0x2FF;
+
+/// The size of the XSAVE area in the standard format for the state components `xfeatures`
+pub fn size(xfeatures: u64) -> u64 {
+    COMPONENTS
+        .iter()
+        .enumerate()
+        .filter(|(i, _)| xfeatures & 1u64.wrapping_shl(*i as u32) != 0)
+        .map(|(_, (size, offset))| offset.wrapping_add(*size))
+        .fold(LEGACY_SIZE, u64::max)
+}
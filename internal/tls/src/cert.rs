@@ -0,0 +1,261 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The self-signed certificate of the keep
+
+use crate::{Alert, Error};
+
+use ed25519_dalek::{ExpandedSecretKey, PublicKey, SecretKey};
+
+/// The maximum size of the certificate, which has to hold an SGX DCAP quote
+/// with its certification data of up to 8 KiB
+const CERT_MAX: usize = 10 * 1024;
+
+/// The maximum nesting of DER structures
+const DEPTH_MAX: usize = 8;
+
+/// `id-Ed25519` (1.3.101.112)
+const OID_ED25519: &[u8] = &[0x2b, 0x65, 0x70];
+
+/// `id-at-commonName` (2.5.4.3)
+const OID_COMMON_NAME: &[u8] = &[0x55, 0x04, 0x03];
+
+/// The evidence of an SEV keep (1.3.6.1.4.1.58270.1.2)
+const OID_SEV: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xc7, 0x1e, 0x01, 0x02];
+
+/// The evidence of an SGX keep (1.3.6.1.4.1.58270.1.3)
+const OID_SGX: &[u8] = &[0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xc7, 0x1e, 0x01, 0x03];
+
+/// The subject and issuer of the certificate
+const COMMON_NAME: &[u8] = b"Enarx Keep";
+
+/// The start of the validity of the certificate, the keep has no trusted time
+const NOT_BEFORE: &[u8] = b"700101000000Z";
+
+/// The end of the validity of the certificate, meaning no expiry (RFC 5280)
+const NOT_AFTER: &[u8] = b"99991231235959Z";
+
+const SEQUENCE: u8 = 0x30;
+const SET: u8 = 0x31;
+const INTEGER: u8 = 0x02;
+const BIT_STRING: u8 = 0x03;
+const OCTET_STRING: u8 = 0x04;
+const OBJECT_IDENTIFIER: u8 = 0x06;
+const UTF8_STRING: u8 = 0x0c;
+const UTC_TIME: u8 = 0x17;
+const GENERALIZED_TIME: u8 = 0x18;
+const EXPLICIT_0: u8 = 0xa0;
+const EXPLICIT_3: u8 = 0xa3;
+
+/// The attestation evidence embedded in the certificate
+///
+/// The evidence is bound to the public key of the certificate, so a
+/// client can check it was generated in the keep.
+#[derive(Copy, Clone, Debug)]
+pub enum Evidence<'a> {
+    /// The keep can't provide evidence
+    None,
+
+    /// The evidence of an SEV keep
+    Sev(&'a [u8]),
+
+    /// The evidence of an SGX keep
+    Sgx(&'a [u8]),
+}
+
+/// A writer of DER into a fixed buffer
+struct Der<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+    stack: [usize; DEPTH_MAX],
+    depth: usize,
+}
+
+impl<'a> Der<'a> {
+    fn new(buf: &'a mut [u8]) -> Self {
+        Self {
+            buf,
+            len: 0,
+            stack: [0; DEPTH_MAX],
+            depth: 0,
+        }
+    }
+
+    fn bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        let end = self.len + data.len();
+        if end > self.buf.len() {
+            return Err(Alert::InternalError.into());
+        }
+
+        self.buf[self.len..end].copy_from_slice(data);
+        self.len = end;
+        Ok(())
+    }
+
+    /// Start a structure, the length is filled in by `end`
+    fn begin(&mut self, tag: u8) -> Result<(), Error> {
+        if self.depth == DEPTH_MAX {
+            return Err(Alert::InternalError.into());
+        }
+
+        // Reserve the longest length encoding used: 0x82 and a `u16`.
+        self.bytes(&[tag, 0, 0, 0])?;
+        self.stack[self.depth] = self.len;
+        self.depth += 1;
+        Ok(())
+    }
+
+    /// End the last structure started, encoding its length minimally
+    fn end(&mut self) -> Result<(), Error> {
+        self.depth -= 1;
+        let start = self.stack[self.depth];
+        let len = self.len - start;
+
+        let (header, n) = match len {
+            0..=0x7f => ([len as u8, 0, 0], 1),
+            0x80..=0xff => ([0x81, len as u8, 0], 2),
+            0x100..=0xffff => ([0x82, (len >> 8) as u8, len as u8], 3),
+            _ => return Err(Alert::InternalError.into()),
+        };
+
+        let at = start - 3;
+        self.buf.copy_within(start..self.len, at + n);
+        self.buf[at..][..n].copy_from_slice(&header[..n]);
+        self.len = at + n + len;
+        Ok(())
+    }
+
+    /// Write a structure with the contents `data`
+    fn value(&mut self, tag: u8, data: &[u8]) -> Result<(), Error> {
+        self.begin(tag)?;
+        self.bytes(data)?;
+        self.end()
+    }
+
+    fn name(&mut self) -> Result<(), Error> {
+        self.begin(SEQUENCE)?;
+        self.begin(SET)?;
+        self.begin(SEQUENCE)?;
+        self.value(OBJECT_IDENTIFIER, OID_COMMON_NAME)?;
+        self.value(UTF8_STRING, COMMON_NAME)?;
+        self.end()?;
+        self.end()?;
+        self.end()
+    }
+
+    fn algorithm(&mut self) -> Result<(), Error> {
+        self.begin(SEQUENCE)?;
+        self.value(OBJECT_IDENTIFIER, OID_ED25519)?;
+        self.end()
+    }
+
+    fn bit_string(&mut self, data: &[u8]) -> Result<(), Error> {
+        self.begin(BIT_STRING)?;
+        self.bytes(&[0])?;
+        self.bytes(data)?;
+        self.end()
+    }
+}
+
+/// The key and self-signed certificate of the keep
+pub struct Identity {
+    secret: ExpandedSecretKey,
+    public: PublicKey,
+    cert: [u8; CERT_MAX],
+    len: usize,
+}
+
+impl Identity {
+    /// The public key of the identity created from `seed`
+    ///
+    /// The evidence passed to `Identity::new` has to be bound to it.
+    pub fn public_key(seed: &[u8; 32]) -> [u8; 32] {
+        let secret = SecretKey::from_bytes(seed).unwrap();
+        PublicKey::from(&secret).to_bytes()
+    }
+
+    /// Create the identity from the random `seed` and the evidence of the keep
+    pub fn new(seed: &[u8; 32], evidence: Evidence<'_>) -> Result<Self, Error> {
+        let secret = SecretKey::from_bytes(seed).unwrap();
+        let public = PublicKey::from(&secret);
+
+        let mut identity = Self {
+            secret: ExpandedSecretKey::from(&secret),
+            public,
+            cert: [0; CERT_MAX],
+            len: 0,
+        };
+
+        // The serial is derived from the key, positive and minimally encoded.
+        let mut serial = [0u8; 8];
+        serial.copy_from_slice(&public.as_bytes()[..8]);
+        serial[0] = (serial[0] & 0x7f) | 0x40;
+
+        // The certificate is built in place and the signature of the
+        // `TBSCertificate` is appended afterwards.
+        let mut der = Der::new(&mut identity.cert);
+        der.begin(SEQUENCE)?;
+
+        der.begin(SEQUENCE)?;
+        let tbs = der.len;
+        der.begin(EXPLICIT_0)?;
+        der.value(INTEGER, &[2])?;
+        der.end()?;
+        der.value(INTEGER, &serial)?;
+        der.algorithm()?;
+        der.name()?;
+        der.begin(SEQUENCE)?;
+        der.value(UTC_TIME, NOT_BEFORE)?;
+        der.value(GENERALIZED_TIME, NOT_AFTER)?;
+        der.end()?;
+        der.name()?;
+        der.begin(SEQUENCE)?;
+        der.algorithm()?;
+        der.bit_string(public.as_bytes())?;
+        der.end()?;
+
+        let extension = match evidence {
+            Evidence::None => None,
+            Evidence::Sev(data) => Some((OID_SEV, data)),
+            Evidence::Sgx(data) => Some((OID_SGX, data)),
+        };
+
+        if let Some((oid, data)) = extension {
+            der.begin(EXPLICIT_3)?;
+            der.begin(SEQUENCE)?;
+            der.begin(SEQUENCE)?;
+            der.value(OBJECT_IDENTIFIER, oid)?;
+            der.begin(OCTET_STRING)?;
+            der.value(OCTET_STRING, data)?;
+            der.end()?;
+            der.end()?;
+            der.end()?;
+            der.end()?;
+        }
+
+        der.end()?;
+
+        // The `TBSCertificate` starts with its tag and length.
+        let tbs = tbs - 4;
+        let signature = identity
+            .secret
+            .sign(&der.buf[tbs..der.len], &identity.public)
+            .to_bytes();
+
+        der.algorithm()?;
+        der.bit_string(&signature)?;
+        der.end()?;
+
+        identity.len = der.len;
+        Ok(identity)
+    }
+
+    /// The DER encoded certificate
+    pub fn cert(&self) -> &[u8] {
+        &self.cert[..self.len]
+    }
+
+    /// Sign `msg` with the key of the certificate
+    pub fn sign(&self, msg: &[u8]) -> [u8; 64] {
+        self.secret.sign(msg, &self.public).to_bytes()
+    }
+}
@@ -0,0 +1,133 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Encoding and decoding of TLS structures
+
+use crate::{Alert, Error};
+
+/// A reader of TLS structures
+pub struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    /// Create a reader of `buf`
+    pub fn new(buf: &'a [u8]) -> Self {
+        Self(buf)
+    }
+
+    /// Whether all bytes were read
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Read all remaining bytes
+    pub fn rest(&mut self) -> &'a [u8] {
+        let rest = self.0;
+        self.0 = &[];
+        rest
+    }
+
+    /// Read `n` bytes
+    pub fn bytes(&mut self, n: usize) -> Result<&'a [u8], Error> {
+        if n > self.0.len() {
+            return Err(Alert::DecodeError.into());
+        }
+
+        let (head, tail) = self.0.split_at(n);
+        self.0 = tail;
+        Ok(head)
+    }
+
+    /// Read a `u8`
+    pub fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    /// Read a big endian `u16`
+    pub fn u16(&mut self) -> Result<u16, Error> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+
+    /// Read a big endian 24 bit integer
+    pub fn u24(&mut self) -> Result<usize, Error> {
+        let b = self.bytes(3)?;
+        Ok(usize::from(b[0]) << 16 | usize::from(b[1]) << 8 | usize::from(b[2]))
+    }
+
+    /// Read a vector with a `u8` length
+    pub fn vec8(&mut self) -> Result<Reader<'a>, Error> {
+        let len = self.u8()?;
+        Ok(Reader(self.bytes(len.into())?))
+    }
+
+    /// Read a vector with a `u16` length
+    pub fn vec16(&mut self) -> Result<Reader<'a>, Error> {
+        let len = self.u16()?;
+        Ok(Reader(self.bytes(len.into())?))
+    }
+
+    /// Read a vector with a 24 bit length
+    pub fn vec24(&mut self) -> Result<Reader<'a>, Error> {
+        let len = self.u24()?;
+        Ok(Reader(self.bytes(len)?))
+    }
+}
+
+/// A writer of TLS structures into a fixed buffer
+pub struct Writer<'a> {
+    buf: &'a mut [u8],
+    len: usize,
+}
+
+impl<'a> Writer<'a> {
+    /// Create a writer into `buf`
+    pub fn new(buf: &'a mut [u8]) -> Self {
+        Self { buf, len: 0 }
+    }
+
+    /// The number of bytes written
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Write `data`
+    pub fn bytes(&mut self, data: &[u8]) -> Result<(), Error> {
+        let end = self.len + data.len();
+        if end > self.buf.len() {
+            return Err(Alert::InternalError.into());
+        }
+
+        self.buf[self.len..end].copy_from_slice(data);
+        self.len = end;
+        Ok(())
+    }
+
+    /// Write a `u8`
+    pub fn u8(&mut self, value: u8) -> Result<(), Error> {
+        self.bytes(&[value])
+    }
+
+    /// Write a big endian `u16`
+    pub fn u16(&mut self, value: u16) -> Result<(), Error> {
+        self.bytes(&value.to_be_bytes())
+    }
+
+    /// Write a vector with a length of `n` bytes and the contents written by `f`
+    pub fn vec(
+        &mut self,
+        n: usize,
+        f: impl FnOnce(&mut Self) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let start = self.len;
+        self.bytes(&[0; 4][..n])?;
+        f(self)?;
+
+        let len = self.len - start - n;
+        if len >> (8 * n) != 0 {
+            return Err(Alert::InternalError.into());
+        }
+
+        let be = (len as u32).to_be_bytes();
+        self.buf[start..][..n].copy_from_slice(&be[4 - n..]);
+        Ok(())
+    }
+}
@@ -0,0 +1,140 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The key schedule of TLS 1.3 with SHA-256 and the record protection with AES-128-GCM
+
+use crate::codec::Writer;
+use crate::{Alert, Error};
+
+use aes_gcm::aead::{AeadInPlace, NewAead};
+use aes_gcm::{Aes128Gcm, Key, Nonce, Tag};
+use hkdf::Hkdf;
+use hmac::{Hmac, Mac, NewMac};
+use sha2::Sha256;
+
+/// The size of a SHA-256 hash
+pub const HASH_LEN: usize = 32;
+
+/// The size of an AES-GCM tag
+pub const TAG_LEN: usize = 16;
+
+/// The size of an AES-GCM nonce
+const NONCE_LEN: usize = 12;
+
+/// A secret of the key schedule
+pub type Secret = [u8; HASH_LEN];
+
+/// `HKDF-Extract`
+pub fn extract(salt: &[u8], ikm: &[u8]) -> Secret {
+    let (prk, _) = Hkdf::<Sha256>::extract(Some(salt), ikm);
+    prk.into()
+}
+
+/// `HKDF-Expand-Label`
+pub fn expand_label(secret: &Secret, label: &[u8], context: &[u8], out: &mut [u8]) {
+    let mut info = [0u8; 2 + 1 + 32 + 1 + HASH_LEN];
+    let mut w = Writer::new(&mut info);
+
+    // The labels and contexts are constants of this crate, so they fit.
+    w.u16(out.len() as u16)
+        .and_then(|_| w.vec(1, |w| w.bytes(b"tls13 ").and_then(|_| w.bytes(label))))
+        .and_then(|_| w.vec(1, |w| w.bytes(context)))
+        .unwrap();
+
+    let len = w.len();
+    Hkdf::<Sha256>::from_prk(secret)
+        .unwrap()
+        .expand(&info[..len], out)
+        .unwrap();
+}
+
+/// `Derive-Secret` with the hash of the transcript
+pub fn derive_secret(secret: &Secret, label: &[u8], hash: &[u8]) -> Secret {
+    let mut out = [0u8; HASH_LEN];
+    expand_label(secret, label, hash, &mut out);
+    out
+}
+
+/// The MAC of a `Finished` message
+fn finished_mac(secret: &Secret, hash: &[u8]) -> Hmac<Sha256> {
+    let mut key = [0u8; HASH_LEN];
+    expand_label(secret, b"finished", &[], &mut key);
+
+    let mut mac = Hmac::<Sha256>::new_from_slice(&key).unwrap();
+    mac.update(hash);
+    mac
+}
+
+/// The `verify_data` of a `Finished` message
+pub fn finished(secret: &Secret, hash: &[u8]) -> [u8; HASH_LEN] {
+    finished_mac(secret, hash).finalize().into_bytes().into()
+}
+
+/// Check the `verify_data` of a `Finished` message in constant time
+pub fn verify_finished(secret: &Secret, hash: &[u8], verify_data: &[u8]) -> Result<(), Error> {
+    finished_mac(secret, hash)
+        .verify(verify_data)
+        .or(Err(Alert::DecryptError.into()))
+}
+
+/// The keys protecting the records in one direction
+pub struct Traffic {
+    secret: Secret,
+    cipher: Aes128Gcm,
+    iv: [u8; NONCE_LEN],
+    seq: u64,
+}
+
+impl Traffic {
+    /// Derive the keys from a traffic secret
+    pub fn new(secret: Secret) -> Self {
+        let mut key = [0u8; 16];
+        let mut iv = [0u8; NONCE_LEN];
+        expand_label(&secret, b"key", &[], &mut key);
+        expand_label(&secret, b"iv", &[], &mut iv);
+
+        Self {
+            secret,
+            cipher: Aes128Gcm::new(Key::from_slice(&key)),
+            iv,
+            seq: 0,
+        }
+    }
+
+    /// Switch to the next traffic secret after a `KeyUpdate`
+    pub fn update(&mut self) {
+        *self = Self::new(derive_secret(&self.secret, b"traffic upd", &[]));
+    }
+
+    /// The nonce of the next record
+    fn nonce(&mut self) -> Result<[u8; NONCE_LEN], Error> {
+        let mut nonce = self.iv;
+        for (n, s) in nonce[NONCE_LEN - 8..]
+            .iter_mut()
+            .zip(&self.seq.to_be_bytes())
+        {
+            *n ^= s;
+        }
+
+        self.seq = self.seq.checked_add(1).ok_or(Alert::InternalError)?;
+        Ok(nonce)
+    }
+
+    /// Encrypt `data` in place, returning the tag
+    pub fn seal(&mut self, aad: &[u8], data: &mut [u8]) -> Result<[u8; TAG_LEN], Error> {
+        let nonce = self.nonce()?;
+        let tag = self
+            .cipher
+            .encrypt_in_place_detached(Nonce::from_slice(&nonce), aad, data)
+            .or(Err(Alert::InternalError))?;
+
+        Ok(tag.into())
+    }
+
+    /// Decrypt `data` in place, if `tag` matches
+    pub fn open(&mut self, aad: &[u8], data: &mut [u8], tag: &[u8]) -> Result<(), Error> {
+        let nonce = self.nonce()?;
+        self.cipher
+            .decrypt_in_place_detached(Nonce::from_slice(&nonce), aad, data, Tag::from_slice(tag))
+            .or(Err(Alert::BadRecordMac.into()))
+    }
+}
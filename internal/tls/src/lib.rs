@@ -0,0 +1,99 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! A minimal TLS 1.3 server for the shims
+//!
+//! The shims terminate TLS on the listening sockets configured with
+//! `exec --tls`, so the host only sees encrypted traffic. The server
+//! identifies itself with a self-signed Ed25519 certificate, which is
+//! generated in the keep and carries the attestation evidence of the keep.
+//!
+//! Only what is needed to talk to common clients is supported:
+//!
+//! * `TLS_AES_128_GCM_SHA256` with an X25519 key share
+//! * Ed25519 signatures
+//! * no client certificates, session resumption, early data or
+//!   `HelloRetryRequest`
+//! * no handshake messages fragmented across records
+//!
+//! The crate has no allocator and no threads. All state lives in the
+//! fixed size `Session` and `Identity` structures.
+
+#![no_std]
+#![deny(clippy::all)]
+#![deny(missing_docs)]
+
+mod cert;
+mod codec;
+mod keys;
+mod session;
+
+pub use cert::{Evidence, Identity};
+pub use session::Session;
+
+/// The alerts sent to the peer on failures
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Alert {
+    /// The connection is closed
+    CloseNotify = 0,
+    /// A message was not expected
+    UnexpectedMessage = 10,
+    /// A record failed to decrypt
+    BadRecordMac = 20,
+    /// A record exceeded the maximum size
+    RecordOverflow = 22,
+    /// No common parameters could be negotiated
+    HandshakeFailure = 40,
+    /// A field of a message was out of range
+    IllegalParameter = 47,
+    /// A message could not be decoded
+    DecodeError = 50,
+    /// The `Finished` message of the client was wrong
+    DecryptError = 51,
+    /// The client does not support TLS 1.3
+    ProtocolVersion = 70,
+    /// The server ran out of space
+    InternalError = 80,
+}
+
+/// A failure of a TLS session
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Error {
+    /// The I/O on the socket failed with this `errno`
+    Io(libc::c_int),
+
+    /// The peer closed the socket without a `close_notify` alert
+    Eof,
+
+    /// The session failed and the alert was sent to the peer
+    Alert(Alert),
+
+    /// The peer sent a fatal alert with this description
+    Peer(u8),
+}
+
+impl From<Alert> for Error {
+    fn from(alert: Alert) -> Self {
+        Self::Alert(alert)
+    }
+}
+
+impl Error {
+    /// The `errno` to report to the payload for this error
+    pub fn errno(&self) -> libc::c_int {
+        match self {
+            Self::Io(errno) => *errno,
+            Self::Eof => libc::ECONNRESET,
+            Self::Alert(_) | Self::Peer(_) => libc::EPROTO,
+        }
+    }
+}
+
+/// The socket a session runs on
+pub trait Io {
+    /// Read from the socket, returning 0 at the end of the stream
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, libc::c_int>;
+
+    /// Write to the socket, returning the number of bytes written
+    fn write(&mut self, buf: &[u8]) -> Result<usize, libc::c_int>;
+}
@@ -0,0 +1,612 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The handshake and record layer of a TLS 1.3 server session
+
+use crate::codec::{Reader, Writer};
+use crate::keys::{self, Traffic, HASH_LEN, TAG_LEN};
+use crate::{Alert, Error, Identity, Io};
+
+use core::ops::Range;
+use sha2::{Digest, Sha256};
+use x25519_dalek::{PublicKey, StaticSecret};
+
+/// The maximum size of the plaintext of a record
+const MAX_PLAINTEXT: usize = 1 << 14;
+
+/// The size of a record header
+const HEADER: usize = 5;
+
+/// The maximum size of a received record, the ciphertext may be 256 bytes
+/// larger than the plaintext
+const MAX_RECORD: usize = HEADER + MAX_PLAINTEXT + 256;
+
+/// The maximum size of the plaintext of a sent record, which has to hold the
+/// `Certificate` message with the largest certificate
+const MAX_SEND: usize = MAX_PLAINTEXT;
+
+/// The size of the buffer of sent records, including content type and tag
+const SEND_BUF: usize = HEADER + MAX_SEND + 1 + TAG_LEN;
+
+/// The record version of TLS 1.2, which TLS 1.3 uses on the wire
+const LEGACY_VERSION: u16 = 0x0303;
+
+/// The protocol version of TLS 1.3
+const TLS13: u16 = 0x0304;
+
+/// `TLS_AES_128_GCM_SHA256`
+const TLS_AES_128_GCM_SHA256: u16 = 0x1301;
+
+/// The `ed25519` signature scheme
+const ED25519: u16 = 0x0807;
+
+/// The `x25519` group
+const X25519: u16 = 0x001d;
+
+// Content types
+const CHANGE_CIPHER_SPEC: u8 = 20;
+const ALERT: u8 = 21;
+const HANDSHAKE: u8 = 22;
+const APPLICATION_DATA: u8 = 23;
+
+// Handshake message types
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+const ENCRYPTED_EXTENSIONS: u8 = 8;
+const CERTIFICATE: u8 = 11;
+const CERTIFICATE_VERIFY: u8 = 15;
+const FINISHED: u8 = 20;
+const KEY_UPDATE: u8 = 24;
+
+// Extension types
+const SIGNATURE_ALGORITHMS: u16 = 13;
+const SUPPORTED_VERSIONS: u16 = 43;
+const KEY_SHARE: u16 = 51;
+
+// Alert levels
+const WARNING: u8 = 1;
+const FATAL: u8 = 2;
+
+/// The context string of the signature of `CertificateVerify`
+const SERVER_VERIFY: &[u8] = b"TLS 1.3, server CertificateVerify";
+
+/// The parameters of a `ClientHello` the server needs
+struct ClientHello {
+    session_id: [u8; 32],
+    session_id_len: usize,
+    key_share: [u8; 32],
+}
+
+impl ClientHello {
+    fn parse(mut r: Reader<'_>) -> Result<Self, Error> {
+        r.u16()?; // legacy_version
+        r.bytes(32)?; // random
+
+        let id = r.vec8()?.rest();
+        if id.len() > 32 {
+            return Err(Alert::IllegalParameter.into());
+        }
+
+        let mut hello = Self {
+            session_id: [0; 32],
+            session_id_len: id.len(),
+            key_share: [0; 32],
+        };
+        hello.session_id[..id.len()].copy_from_slice(id);
+
+        let mut suites = r.vec16()?;
+        let mut aes128 = false;
+        while !suites.is_empty() {
+            aes128 |= suites.u16()? == TLS_AES_128_GCM_SHA256;
+        }
+
+        r.vec8()?; // legacy_compression_methods
+
+        let mut tls13 = false;
+        let mut ed25519 = false;
+        let mut x25519 = false;
+
+        let mut extensions = r.vec16()?;
+        while !extensions.is_empty() {
+            let kind = extensions.u16()?;
+            let mut data = extensions.vec16()?;
+
+            match kind {
+                SUPPORTED_VERSIONS => {
+                    let mut versions = data.vec8()?;
+                    while !versions.is_empty() {
+                        tls13 |= versions.u16()? == TLS13;
+                    }
+                }
+
+                SIGNATURE_ALGORITHMS => {
+                    let mut schemes = data.vec16()?;
+                    while !schemes.is_empty() {
+                        ed25519 |= schemes.u16()? == ED25519;
+                    }
+                }
+
+                KEY_SHARE => {
+                    let mut shares = data.vec16()?;
+                    while !shares.is_empty() {
+                        let group = shares.u16()?;
+                        let key = shares.vec16()?.rest();
+
+                        if group == X25519 && key.len() == 32 && !x25519 {
+                            hello.key_share.copy_from_slice(key);
+                            x25519 = true;
+                        }
+                    }
+                }
+
+                _ => {}
+            }
+        }
+
+        if !tls13 {
+            return Err(Alert::ProtocolVersion.into());
+        }
+
+        // Without an X25519 key share, a `HelloRetryRequest` would be needed.
+        if !aes128 || !ed25519 || !x25519 {
+            return Err(Alert::HandshakeFailure.into());
+        }
+
+        Ok(hello)
+    }
+}
+
+/// A TLS 1.3 server session on a socket
+///
+/// The session is large, so it is meant to be kept in a static and
+/// reused with `Session::accept`.
+pub struct Session {
+    rx: Option<Traffic>,
+    tx: Option<Traffic>,
+    input: [u8; MAX_RECORD],
+    plaintext: Range<usize>,
+    output: [u8; SEND_BUF],
+    eof: bool,
+    error: Option<Error>,
+}
+
+impl Default for Session {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Session {
+    /// Create an idle session
+    pub const fn new() -> Self {
+        Self {
+            rx: None,
+            tx: None,
+            input: [0; MAX_RECORD],
+            plaintext: 0..0,
+            output: [0; SEND_BUF],
+            eof: false,
+            error: None,
+        }
+    }
+
+    /// Run the handshake on a newly accepted socket
+    ///
+    /// `entropy` has to be random, it provides the server random and the
+    /// key share.
+    pub fn accept(
+        &mut self,
+        io: &mut impl Io,
+        identity: &Identity,
+        entropy: &[u8; 64],
+    ) -> Result<(), Error> {
+        self.rx = None;
+        self.tx = None;
+        self.plaintext = 0..0;
+        self.eof = false;
+        self.error = None;
+
+        let ret = self.handshake(io, identity, entropy);
+        self.check(io, ret)
+    }
+
+    /// Read application data, returning 0 after the peer closed the session
+    pub fn read(&mut self, io: &mut impl Io, buf: &mut [u8]) -> Result<usize, Error> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        let ret = self.read_data(io, buf);
+        self.check(io, ret)
+    }
+
+    /// The number of bytes `Session::read` returns without reading from the socket
+    pub fn pending(&self) -> usize {
+        self.plaintext.len()
+    }
+
+    /// Write all of `buf` as application data
+    pub fn write(&mut self, io: &mut impl Io, buf: &[u8]) -> Result<usize, Error> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        for chunk in buf.chunks(MAX_SEND) {
+            self.output[HEADER..][..chunk.len()].copy_from_slice(chunk);
+
+            let ret = self.send(io, APPLICATION_DATA, chunk.len());
+            self.check(io, ret)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    /// Tell the peer no more data is sent
+    pub fn close(&mut self, io: &mut impl Io) -> Result<(), Error> {
+        if let Some(e) = self.error {
+            return Err(e);
+        }
+
+        self.alert(io, WARNING, Alert::CloseNotify)
+    }
+
+    /// Remember a failure and send its alert to the peer
+    fn check<T>(&mut self, io: &mut impl Io, ret: Result<T, Error>) -> Result<T, Error> {
+        if let Err(e) = &ret {
+            if let Error::Alert(alert) = e {
+                let _ = self.alert(io, FATAL, *alert);
+            }
+
+            self.error = Some(*e);
+        }
+
+        ret
+    }
+
+    fn handshake(
+        &mut self,
+        io: &mut impl Io,
+        identity: &Identity,
+        entropy: &[u8; 64],
+    ) -> Result<(), Error> {
+        let mut transcript = Sha256::new();
+
+        let (kind, range) = self.read_record(io)?;
+        if kind != HANDSHAKE {
+            return Err(Alert::UnexpectedMessage.into());
+        }
+
+        let record = &self.input[range];
+        let mut r = Reader::new(record);
+        if r.u8()? != CLIENT_HELLO {
+            return Err(Alert::UnexpectedMessage.into());
+        }
+
+        let hello = ClientHello::parse(r.vec24()?)?;
+        if !r.is_empty() {
+            return Err(Alert::UnexpectedMessage.into());
+        }
+
+        transcript.update(record);
+
+        let mut seed = [0u8; 32];
+        seed.copy_from_slice(&entropy[32..]);
+        let secret = StaticSecret::from(seed);
+        let public = PublicKey::from(&secret);
+        let shared = secret.diffie_hellman(&PublicKey::from(hello.key_share));
+
+        // A key share of low order results in zeros (RFC 7748, section 6.1).
+        if shared.as_bytes().iter().all(|b| *b == 0) {
+            return Err(Alert::IllegalParameter.into());
+        }
+
+        self.handshake_message(io, &mut transcript, SERVER_HELLO, |w| {
+            w.u16(LEGACY_VERSION)?;
+            w.bytes(&entropy[..32])?;
+            w.vec(1, |w| w.bytes(&hello.session_id[..hello.session_id_len]))?;
+            w.u16(TLS_AES_128_GCM_SHA256)?;
+            w.u8(0)?; // legacy_compression_method
+            w.vec(2, |w| {
+                w.u16(SUPPORTED_VERSIONS)?;
+                w.vec(2, |w| w.u16(TLS13))?;
+                w.u16(KEY_SHARE)?;
+                w.vec(2, |w| {
+                    w.u16(X25519)?;
+                    w.vec(2, |w| w.bytes(public.as_bytes()))
+                })
+            })
+        })?;
+
+        let empty = Sha256::digest(&[]);
+        let early = keys::extract(&[0; HASH_LEN], &[0; HASH_LEN]);
+        let salt = keys::derive_secret(&early, b"derived", &empty);
+        let handshake = keys::extract(&salt, shared.as_bytes());
+
+        let hash = transcript.clone().finalize();
+        let client_hs = keys::derive_secret(&handshake, b"c hs traffic", &hash);
+        let server_hs = keys::derive_secret(&handshake, b"s hs traffic", &hash);
+        self.tx = Some(Traffic::new(server_hs));
+
+        self.handshake_message(io, &mut transcript, ENCRYPTED_EXTENSIONS, |w| {
+            w.vec(2, |_| Ok(()))
+        })?;
+
+        self.handshake_message(io, &mut transcript, CERTIFICATE, |w| {
+            w.vec(1, |_| Ok(()))?; // certificate_request_context
+            w.vec(3, |w| {
+                w.vec(3, |w| w.bytes(identity.cert()))?;
+                w.vec(2, |_| Ok(())) // extensions
+            })
+        })?;
+
+        let hash = transcript.clone().finalize();
+        let mut content = [0x20u8; 64 + SERVER_VERIFY.len() + 1 + HASH_LEN];
+        content[64..][..SERVER_VERIFY.len()].copy_from_slice(SERVER_VERIFY);
+        content[64 + SERVER_VERIFY.len()] = 0;
+        content[64 + SERVER_VERIFY.len() + 1..].copy_from_slice(&hash);
+        let signature = identity.sign(&content);
+
+        self.handshake_message(io, &mut transcript, CERTIFICATE_VERIFY, |w| {
+            w.u16(ED25519)?;
+            w.vec(2, |w| w.bytes(&signature))
+        })?;
+
+        let hash = transcript.clone().finalize();
+        let verify_data = keys::finished(&server_hs, &hash);
+        self.handshake_message(io, &mut transcript, FINISHED, |w| w.bytes(&verify_data))?;
+
+        let hash = transcript.finalize();
+        let salt = keys::derive_secret(&handshake, b"derived", &empty);
+        let master = keys::extract(&salt, &[0; HASH_LEN]);
+        let client_ap = keys::derive_secret(&master, b"c ap traffic", &hash);
+        let server_ap = keys::derive_secret(&master, b"s ap traffic", &hash);
+
+        // The `Finished` of the client, maybe after a `ChangeCipherSpec`
+        // for middlebox compatibility
+        self.rx = Some(Traffic::new(client_hs));
+        loop {
+            let (kind, range) = self.read_record(io)?;
+            let record = &self.input[range];
+
+            match kind {
+                CHANGE_CIPHER_SPEC if record == [1] => continue,
+
+                HANDSHAKE => {
+                    let mut r = Reader::new(record);
+                    if r.u8()? != FINISHED {
+                        return Err(Alert::UnexpectedMessage.into());
+                    }
+
+                    let verify_data = r.vec24()?.rest();
+                    if !r.is_empty() {
+                        return Err(Alert::UnexpectedMessage.into());
+                    }
+
+                    keys::verify_finished(&client_hs, &hash, verify_data)?;
+                    break;
+                }
+
+                ALERT => return Err(alert(record)),
+
+                _ => return Err(Alert::UnexpectedMessage.into()),
+            }
+        }
+
+        self.rx = Some(Traffic::new(client_ap));
+        self.tx = Some(Traffic::new(server_ap));
+        Ok(())
+    }
+
+    fn read_data(&mut self, io: &mut impl Io, buf: &mut [u8]) -> Result<usize, Error> {
+        loop {
+            if !self.plaintext.is_empty() {
+                let n = self.plaintext.len().min(buf.len());
+                buf[..n].copy_from_slice(&self.input[self.plaintext.start..][..n]);
+                self.plaintext.start += n;
+                return Ok(n);
+            }
+
+            if self.eof || buf.is_empty() {
+                return Ok(0);
+            }
+
+            let (kind, range) = self.read_record(io)?;
+            match kind {
+                APPLICATION_DATA => self.plaintext = range,
+
+                ALERT => match alert(&self.input[range]) {
+                    Error::Peer(0) => self.eof = true,
+                    e => return Err(e),
+                },
+
+                HANDSHAKE => self.post_handshake(io, range)?,
+
+                _ => return Err(Alert::UnexpectedMessage.into()),
+            }
+        }
+    }
+
+    /// Handle the handshake messages after the handshake
+    ///
+    /// Only `KeyUpdate` is expected from a client.
+    fn post_handshake(&mut self, io: &mut impl Io, range: Range<usize>) -> Result<(), Error> {
+        let mut r = Reader::new(&self.input[range]);
+        let mut reply = false;
+
+        // An empty record carries no `KeyUpdate` to act on (RFC 8446, 5.1).
+        if r.is_empty() {
+            return Err(Alert::UnexpectedMessage.into());
+        }
+
+        while !r.is_empty() {
+            let kind = r.u8()?;
+            let mut body = r.vec24()?;
+
+            if kind != KEY_UPDATE {
+                return Err(Alert::UnexpectedMessage.into());
+            }
+
+            let requested = match body.u8()? {
+                0 => false,
+                1 => true,
+                _ => return Err(Alert::IllegalParameter.into()),
+            };
+
+            if !body.is_empty() {
+                return Err(Alert::DecodeError.into());
+            }
+
+            // A `KeyUpdate` has to be the last message of its record.
+            if !r.is_empty() {
+                return Err(Alert::UnexpectedMessage.into());
+            }
+
+            reply |= requested;
+        }
+
+        if let Some(rx) = &mut self.rx {
+            rx.update();
+        }
+
+        if reply {
+            let mut w = Writer::new(&mut self.output[HEADER..][..MAX_SEND]);
+            w.u8(KEY_UPDATE)?;
+            w.vec(3, |w| w.u8(0))?;
+
+            let len = w.len();
+            self.send(io, HANDSHAKE, len)?;
+
+            if let Some(tx) = &mut self.tx {
+                tx.update();
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Send a handshake message with the body written by `f`
+    fn handshake_message(
+        &mut self,
+        io: &mut impl Io,
+        transcript: &mut Sha256,
+        kind: u8,
+        f: impl FnOnce(&mut Writer<'_>) -> Result<(), Error>,
+    ) -> Result<(), Error> {
+        let mut w = Writer::new(&mut self.output[HEADER..][..MAX_SEND]);
+        w.u8(kind)?;
+        w.vec(3, f)?;
+
+        let len = w.len();
+        transcript.update(&self.output[HEADER..][..len]);
+        self.send(io, HANDSHAKE, len)
+    }
+
+    fn alert(&mut self, io: &mut impl Io, level: u8, alert: Alert) -> Result<(), Error> {
+        self.output[HEADER] = level;
+        self.output[HEADER + 1] = alert as u8;
+        self.send(io, ALERT, 2)
+    }
+
+    /// Send a record with the first `len` bytes after the header of the output buffer
+    fn send(&mut self, io: &mut impl Io, kind: u8, len: usize) -> Result<(), Error> {
+        let total = match &mut self.tx {
+            None => {
+                self.output[..HEADER].copy_from_slice(&header(kind, len));
+                HEADER + len
+            }
+
+            Some(tx) => {
+                let inner = len + 1;
+                self.output[HEADER + len] = kind;
+                self.output[..HEADER].copy_from_slice(&header(APPLICATION_DATA, inner + TAG_LEN));
+
+                let (head, body) = self.output.split_at_mut(HEADER);
+                let tag = tx.seal(head, &mut body[..inner])?;
+                body[inner..][..TAG_LEN].copy_from_slice(&tag);
+                HEADER + inner + TAG_LEN
+            }
+        };
+
+        let mut done = 0;
+        while done < total {
+            match io.write(&self.output[done..total]).map_err(Error::Io)? {
+                0 => return Err(Error::Eof),
+                n => done += n,
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Read a record, returning its content type and the range of its plaintext
+    fn read_record(&mut self, io: &mut impl Io) -> Result<(u8, Range<usize>), Error> {
+        read_exact(io, &mut self.input[..HEADER])?;
+
+        let kind = self.input[0];
+        let len = usize::from(u16::from_be_bytes([self.input[3], self.input[4]]));
+        if len > MAX_RECORD - HEADER {
+            return Err(Alert::RecordOverflow.into());
+        }
+
+        read_exact(io, &mut self.input[HEADER..][..len])?;
+
+        match (&mut self.rx, kind) {
+            (_, CHANGE_CIPHER_SPEC) | (None, _) => {
+                if len > MAX_PLAINTEXT {
+                    return Err(Alert::RecordOverflow.into());
+                }
+
+                Ok((kind, HEADER..HEADER + len))
+            }
+
+            (Some(rx), APPLICATION_DATA) => {
+                if len < 1 + TAG_LEN {
+                    return Err(Alert::BadRecordMac.into());
+                }
+
+                let (head, body) = self.input.split_at_mut(HEADER);
+                let (data, tag) = body[..len].split_at_mut(len - TAG_LEN);
+                rx.open(head, data, tag)?;
+
+                // The content type is the last non-zero byte, then padding.
+                let inner = data
+                    .iter()
+                    .rposition(|b| *b != 0)
+                    .ok_or(Alert::UnexpectedMessage)?;
+
+                if inner > MAX_PLAINTEXT {
+                    return Err(Alert::RecordOverflow.into());
+                }
+
+                Ok((data[inner], HEADER..HEADER + inner))
+            }
+
+            _ => Err(Alert::UnexpectedMessage.into()),
+        }
+    }
+}
+
+/// The header of a record with `len` bytes
+fn header(kind: u8, len: usize) -> [u8; HEADER] {
+    let version = LEGACY_VERSION.to_be_bytes();
+    let len = (len as u16).to_be_bytes();
+    [kind, version[0], version[1], len[0], len[1]]
+}
+
+/// The error for an alert received from the peer
+fn alert(record: &[u8]) -> Error {
+    match record {
+        [_, description] => Error::Peer(*description),
+        _ => Alert::DecodeError.into(),
+    }
+}
+
+fn read_exact(io: &mut impl Io, mut buf: &mut [u8]) -> Result<(), Error> {
+    while !buf.is_empty() {
+        match io.read(buf).map_err(Error::Io)? {
+            0 => return Err(Error::Eof),
+            n => buf = &mut buf[n..],
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,483 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use tls::{Alert, Error, Evidence, Identity, Io, Session};
+
+use std::convert::{TryFrom, TryInto};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use aes_gcm::aead::{AeadInPlace, NewAead};
+use aes_gcm::{Aes128Gcm, Tag};
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::crypto::{ring, CryptoProvider};
+use rustls::pki_types::{CertificateDer, ServerName, UnixTime};
+use rustls::{ClientConfig, ClientConnection, ConnectionTrafficSecrets, StreamOwned};
+use rustls::{DigitallySignedStruct, SignatureScheme};
+
+const ALERT: u8 = 21;
+const HANDSHAKE: u8 = 22;
+const APPLICATION_DATA: u8 = 23;
+
+/// The socket of the server
+struct Stream(UnixStream);
+
+impl Io for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> Result<usize, libc::c_int> {
+        self.0.read(buf).map_err(|e| e.raw_os_error().unwrap())
+    }
+
+    fn write(&mut self, buf: &[u8]) -> Result<usize, libc::c_int> {
+        self.0.write(buf).map_err(|e| e.raw_os_error().unwrap())
+    }
+}
+
+/// The evidence is as large as an SGX DCAP quote with its certification data.
+fn identity() -> Identity {
+    Identity::new(&[7; 32], Evidence::Sgx(&[0x5a; 8192])).unwrap()
+}
+
+/// Run `f` on a server session after its handshake, returning the socket of the client
+fn serve<T: Send + 'static>(
+    f: impl FnOnce(&mut Session, &mut Stream) -> Result<T, Error> + Send + 'static,
+) -> (UnixStream, JoinHandle<Result<T, Error>>) {
+    let (client, server) = UnixStream::pair().unwrap();
+
+    let thread = std::thread::spawn(move || {
+        let mut session = Box::new(Session::new());
+        let mut io = Stream(server);
+        session.accept(&mut io, &identity(), &[0x42; 64])?;
+        f(&mut session, &mut io)
+    });
+
+    (client, thread)
+}
+
+/// Accepts the certificate of `identity()` and checks the signatures made with it
+#[derive(Debug)]
+struct Verifier(CryptoProvider);
+
+impl ServerCertVerifier for Verifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        assert_eq!(end_entity.as_ref(), identity().cert());
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        _message: &[u8],
+        _cert: &CertificateDer<'_>,
+        _dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        unreachable!("TLS 1.2")
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        let algs = &self.0.signature_verification_algorithms;
+        rustls::crypto::verify_tls13_signature(message, cert, dss, algs)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        vec![SignatureScheme::ED25519]
+    }
+}
+
+fn connect(
+    socket: UnixStream,
+    provider: CryptoProvider,
+) -> StreamOwned<ClientConnection, UnixStream> {
+    let verifier = Verifier(ring::default_provider());
+    let mut config = ClientConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(&[&rustls::version::TLS13])
+        .unwrap()
+        .dangerous()
+        .with_custom_certificate_verifier(Arc::new(verifier))
+        .with_no_client_auth();
+    config.enable_secret_extraction = true;
+
+    let name = ServerName::try_from("keep").unwrap();
+    let conn = ClientConnection::new(Arc::new(config), name).unwrap();
+    StreamOwned::new(conn, socket)
+}
+
+/// A record with `data`
+fn record(kind: u8, data: &[u8]) -> Vec<u8> {
+    let mut record = vec![kind, 3, 3];
+    record.extend(&(data.len() as u16).to_be_bytes());
+    record.extend(data);
+    record
+}
+
+/// A `ClientHello` message offering `version` with an X25519 key share
+fn client_hello(version: u16, share: [u8; 32]) -> Vec<u8> {
+    let mut extensions = vec![0, 43, 0, 3, 2];
+    extensions.extend(&version.to_be_bytes());
+    extensions.extend(&[0, 13, 0, 4, 0, 2, 8, 7]);
+    extensions.extend(&[0, 51, 0, 38, 0, 36, 0, 0x1d, 0, 32]);
+    extensions.extend(&share);
+
+    let mut body = vec![3, 3];
+    body.extend(&[0; 32]); // random
+    body.push(0); // legacy_session_id
+    body.extend(&[0, 2, 0x13, 0x01]);
+    body.extend(&[1, 0]); // legacy_compression_methods
+    body.extend(&(extensions.len() as u16).to_be_bytes());
+    body.extend(extensions);
+
+    let mut msg = vec![1];
+    msg.extend(&(body.len() as u32).to_be_bytes()[1..]);
+    msg.extend(body);
+    msg
+}
+
+/// Send `record` instead of a `ClientHello`, returning the error of the
+/// server and the alert it sent
+fn hello(record: &[u8]) -> (Error, u8) {
+    let (mut client, server) = serve(|_, _| Ok(()));
+    client.write_all(record).unwrap();
+
+    let mut alert = [0u8; 7];
+    client.read_exact(&mut alert).unwrap();
+    assert_eq!(alert[..6], [ALERT, 3, 3, 0, 2, 2]);
+
+    (server.join().unwrap().unwrap_err(), alert[6])
+}
+
+/// The keys protecting the records in one direction
+struct Keys {
+    cipher: Aes128Gcm,
+    iv: [u8; 12],
+    seq: u64,
+}
+
+impl Keys {
+    fn new((seq, secrets): (u64, ConnectionTrafficSecrets)) -> Self {
+        match secrets {
+            ConnectionTrafficSecrets::Aes128Gcm { key, iv } => {
+                let mut keys = Self {
+                    cipher: Aes128Gcm::new_from_slice(key.as_ref()).unwrap(),
+                    iv: [0; 12],
+                    seq,
+                };
+
+                keys.iv.copy_from_slice(iv.as_ref());
+                keys
+            }
+
+            _ => panic!("unexpected cipher suite"),
+        }
+    }
+
+    fn nonce(&mut self) -> [u8; 12] {
+        let mut nonce = self.iv;
+        for (n, s) in nonce[4..].iter_mut().zip(&self.seq.to_be_bytes()) {
+            *n ^= s;
+        }
+
+        self.seq += 1;
+        nonce
+    }
+}
+
+/// A client writing the records after the handshake itself
+struct Raw {
+    socket: UnixStream,
+    tx: Keys,
+    rx: Keys,
+}
+
+impl Raw {
+    /// Finish a handshake with `rustls` and take over its keys
+    fn new(socket: UnixStream) -> Self {
+        let mut client = connect(socket, ring::default_provider());
+        while client.conn.is_handshaking() || client.conn.wants_write() {
+            client.conn.complete_io(&mut client.sock).unwrap();
+        }
+
+        let secrets = client.conn.dangerous_extract_secrets().unwrap();
+        Self {
+            socket: client.sock,
+            tx: Keys::new(secrets.tx),
+            rx: Keys::new(secrets.rx),
+        }
+    }
+
+    /// A protected record with `data` of content type `kind`
+    fn seal(&mut self, kind: u8, data: &[u8]) -> Vec<u8> {
+        let mut inner = data.to_vec();
+        inner.push(kind);
+
+        let mut header = record(APPLICATION_DATA, &[]);
+        header[3..].copy_from_slice(&((inner.len() + 16) as u16).to_be_bytes());
+        let nonce = self.tx.nonce();
+        let tag = self
+            .tx
+            .cipher
+            .encrypt_in_place_detached(&nonce.into(), &header, &mut inner)
+            .unwrap();
+
+        header.extend(inner);
+        header.extend(tag);
+        header
+    }
+
+    fn send(&mut self, kind: u8, data: &[u8]) {
+        let record = self.seal(kind, data);
+        self.socket.write_all(&record).unwrap();
+    }
+
+    /// Receive a protected record, returning its content type and data
+    fn recv(&mut self) -> (u8, Vec<u8>) {
+        let mut header = [0u8; 5];
+        self.socket.read_exact(&mut header).unwrap();
+        assert_eq!(header[..3], [APPLICATION_DATA, 3, 3]);
+
+        let len = usize::from(u16::from_be_bytes([header[3], header[4]]));
+        let mut data = vec![0; len];
+        self.socket.read_exact(&mut data).unwrap();
+
+        let tag: [u8; 16] = data.split_off(len - 16).try_into().unwrap();
+        let nonce = self.rx.nonce();
+        self.rx
+            .cipher
+            .decrypt_in_place_detached(&nonce.into(), &header, &mut data, &Tag::from(tag))
+            .unwrap();
+
+        while data.last() == Some(&0) {
+            data.pop();
+        }
+
+        let kind = data.pop().unwrap();
+        (kind, data)
+    }
+}
+
+/// Send the records made by `f` after the handshake, returning the error of
+/// the server on its next read
+fn after_handshake(f: impl FnOnce(&mut Raw)) -> Error {
+    let (client, server) = serve(|session, io| session.read(io, &mut [0; 64]));
+    let mut raw = Raw::new(client);
+    f(&mut raw);
+
+    let error = server.join().unwrap().unwrap_err();
+    if let Error::Alert(alert) = error {
+        assert_eq!(raw.recv(), (ALERT, vec![2, alert as u8]));
+    }
+
+    error
+}
+
+#[test]
+fn handshake() {
+    let (client, server) = serve(|session, io| {
+        let mut data = Vec::new();
+        let mut buf = [0u8; 1000];
+        loop {
+            match session.read(io, &mut buf)? {
+                0 => break,
+                n => data.extend(&buf[..n]),
+            }
+        }
+
+        session.write(io, &data)?;
+        session.close(io)?;
+        Ok(data.len())
+    });
+
+    // More than fits into one record in each direction
+    let data: Vec<u8> = (0..40000u32).map(|i| i as u8).collect();
+
+    let mut client = connect(client, ring::default_provider());
+    client.write_all(&data).unwrap();
+    client.conn.send_close_notify();
+    client.flush().unwrap();
+
+    let mut echo = Vec::new();
+    client.read_to_end(&mut echo).unwrap();
+    assert_eq!(echo, data);
+    assert_eq!(server.join().unwrap(), Ok(data.len()));
+
+    let certs = client.conn.peer_certificates().unwrap();
+    assert_eq!(certs.len(), 1);
+    assert_eq!(certs[0].as_ref(), identity().cert());
+}
+
+#[test]
+fn evidence_too_large() {
+    let error = Identity::new(&[7; 32], Evidence::Sgx(&[0x5a; 16384])).err();
+    assert_eq!(error, Some(Alert::InternalError.into()));
+}
+
+#[test]
+fn key_update() {
+    let (client, server) = serve(|session, io| {
+        let mut buf = [0u8; 64];
+        loop {
+            match session.read(io, &mut buf)? {
+                0 => return session.close(io),
+                n => session.write(io, &buf[..n])?,
+            };
+        }
+    });
+
+    let mut client = connect(client, ring::default_provider());
+    for msg in [b"one", b"two", b"six"].iter() {
+        client.write_all(*msg).unwrap();
+
+        let mut echo = [0u8; 3];
+        client.read_exact(&mut echo).unwrap();
+        assert_eq!(&echo, *msg);
+
+        // Requests a `KeyUpdate` from the server in turn
+        client.conn.refresh_traffic_keys().unwrap();
+    }
+
+    client.conn.send_close_notify();
+    client.flush().unwrap();
+    assert_eq!(client.read(&mut [0; 8]).unwrap(), 0);
+    assert_eq!(server.join().unwrap(), Ok(()));
+}
+
+#[test]
+fn hello_retry() {
+    // The key share is for P-256 only, so X25519 would need a `HelloRetryRequest`.
+    let provider = CryptoProvider {
+        kx_groups: vec![ring::kx_group::SECP256R1, ring::kx_group::X25519],
+        ..ring::default_provider()
+    };
+
+    let (client, server) = serve(|_, _| Ok(()));
+    let mut client = connect(client, provider);
+    let error = client.write_all(b"data").and_then(|_| client.flush());
+
+    let error = error.unwrap_err().into_inner().unwrap();
+    let error = error.downcast::<rustls::Error>().unwrap();
+    assert_eq!(
+        *error,
+        rustls::Error::AlertReceived(rustls::AlertDescription::HandshakeFailure)
+    );
+
+    let error = server.join().unwrap().unwrap_err();
+    assert_eq!(error, Alert::HandshakeFailure.into());
+}
+
+#[test]
+fn malformed_hello() {
+    let share = [9; 32];
+    let msg = client_hello(0x0304, share);
+
+    let truncated = record(HANDSHAKE, &msg[..msg.len() - 1]);
+    assert_eq!(hello(&truncated), (Alert::DecodeError.into(), 50));
+
+    let trailing = record(HANDSHAKE, &[&msg[..], &[0]].concat());
+    assert_eq!(hello(&trailing), (Alert::UnexpectedMessage.into(), 10));
+
+    let server_hello = record(HANDSHAKE, &[&[2], &msg[1..]].concat());
+    assert_eq!(hello(&server_hello), (Alert::UnexpectedMessage.into(), 10));
+
+    let data = record(APPLICATION_DATA, &msg);
+    assert_eq!(hello(&data), (Alert::UnexpectedMessage.into(), 10));
+
+    let tls12 = record(HANDSHAKE, &client_hello(0x0303, share));
+    assert_eq!(hello(&tls12), (Alert::ProtocolVersion.into(), 70));
+
+    let low_order = record(HANDSHAKE, &client_hello(0x0304, [0; 32]));
+    assert_eq!(hello(&low_order), (Alert::IllegalParameter.into(), 47));
+
+    let oversized = record(HANDSHAKE, &[0; (1 << 14) + 1]);
+    assert_eq!(hello(&oversized), (Alert::RecordOverflow.into(), 22));
+
+    let overflow = [HANDSHAKE, 3, 3, 0xff, 0xff];
+    assert_eq!(hello(&overflow), (Alert::RecordOverflow.into(), 22));
+}
+
+#[test]
+fn client_hello_eof() {
+    let (mut client, server) = serve(|_, _| Ok(()));
+    let record = record(HANDSHAKE, &client_hello(0x0304, [9; 32]));
+    client.write_all(&record[..record.len() / 2]).unwrap();
+    client.shutdown(std::net::Shutdown::Write).unwrap();
+
+    assert_eq!(server.join().unwrap(), Err(Error::Eof));
+    assert_eq!(client.read(&mut [0; 8]).unwrap(), 0);
+}
+
+#[test]
+fn renegotiation() {
+    let msg = client_hello(0x0304, [9; 32]);
+    let error = after_handshake(|raw| raw.send(HANDSHAKE, &msg));
+    assert_eq!(error, Alert::UnexpectedMessage.into());
+}
+
+#[test]
+fn key_update_invalid() {
+    let error = after_handshake(|raw| raw.send(HANDSHAKE, &[24, 0, 0, 1, 2]));
+    assert_eq!(error, Alert::IllegalParameter.into());
+
+    // Another message after a `KeyUpdate` in the same record
+    let error = after_handshake(|raw| raw.send(HANDSHAKE, &[24, 0, 0, 1, 0, 24, 0, 0, 1, 0]));
+    assert_eq!(error, Alert::UnexpectedMessage.into());
+}
+
+#[test]
+fn empty_handshake() {
+    // Data under the current keys follows, which fails if the keys changed.
+    // The server might have closed the socket already.
+    let error = after_handshake(|raw| {
+        raw.send(HANDSHAKE, &[]);
+        let record = raw.seal(APPLICATION_DATA, b"data");
+        let _ = raw.socket.write_all(&record);
+    });
+
+    assert_eq!(error, Alert::UnexpectedMessage.into());
+}
+
+#[test]
+fn bad_record_mac() {
+    let error = after_handshake(|raw| {
+        let mut record = raw.seal(APPLICATION_DATA, b"data");
+        *record.last_mut().unwrap() ^= 1;
+        raw.socket.write_all(&record).unwrap();
+    });
+
+    assert_eq!(error, Alert::BadRecordMac.into());
+}
+
+#[test]
+fn record_overflow() {
+    let error = after_handshake(|raw| raw.send(APPLICATION_DATA, &[1; (1 << 14) + 1]));
+    assert_eq!(error, Alert::RecordOverflow.into());
+
+    let error = after_handshake(|raw| {
+        raw.socket
+            .write_all(&[APPLICATION_DATA, 3, 3, 0xff, 0xff])
+            .unwrap()
+    });
+    assert_eq!(error, Alert::RecordOverflow.into());
+}
+
+#[test]
+fn alerts() {
+    let error = after_handshake(|raw| raw.send(ALERT, &[2, 40]));
+    assert_eq!(error, Error::Peer(40));
+
+    let error = after_handshake(|raw| raw.send(ALERT, &[2]));
+    assert_eq!(error, Alert::DecodeError.into());
+
+    // Padding only, without a content type
+    let error = after_handshake(|raw| raw.send(0, &[]));
+    assert_eq!(error, Alert::UnexpectedMessage.into());
+}
@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use super::{Config, Loader, Mapper};
+use super::{Config, Loader, Mapper, Options};
 
 use std::convert::TryInto;
 
@@ -11,6 +11,102 @@ use primordial::Page;
 use sallyport::elf;
 
 use std::ops::Range;
+use std::os::unix::ffi::OsStrExt;
+use std::path::{Component, Path, PathBuf};
+
+/// The ELF note name for notes of the shims, which are not defined by `sallyport`
+pub const NOTE_NAME: &str = "enarx";
+
+/// The ELF note type of the shim debug output switch (`u32`, non-zero if enabled)
+pub const NOTE_DEBUG: u32 = abi::note::DEBUG;
+
+/// The ELF note type of the prefix of encrypted files (`[u8; 64]`, NUL-padded)
+pub const NOTE_ENCRYPT: u32 = abi::note::ENCRYPT;
+
+/// The ELF note type of the port to terminate TLS on (`u32`, 0 if disabled)
+///
+/// The `abi::note::TLS_ATTEST` flag refuses TLS without attestation evidence.
+pub const NOTE_TLS: u32 = abi::note::TLS;
+
+/// The ELF note type of the strict time switch (`u32`, non-zero if enabled)
+pub const NOTE_TIME: u32 = abi::note::TIME;
+
+/// The size of the descriptor of the `NOTE_ENCRYPT` note
+const ENCRYPT_PREFIX_MAX: usize = abi::note::PATH_MAX;
+
+/// Normalize the prefix of encrypted files into the descriptor of the `NOTE_ENCRYPT` note
+fn encrypt_prefix(prefix: &str) -> Result<[u8; ENCRYPT_PREFIX_MAX]> {
+    let path = Path::new(prefix);
+    if !path.is_absolute() {
+        return Err(anyhow!("The encrypted path {:?} is not absolute!", prefix));
+    }
+
+    let mut normalized = PathBuf::from("/");
+    for component in path.components() {
+        match component {
+            Component::Normal(name) => normalized.push(name),
+            Component::ParentDir => {
+                normalized.pop();
+            }
+            Component::RootDir | Component::CurDir | Component::Prefix(_) => {}
+        }
+    }
+
+    let bytes = normalized.as_os_str().as_bytes();
+    if bytes.len() >= ENCRYPT_PREFIX_MAX {
+        return Err(anyhow!(
+            "The encrypted path {:?} is longer than {} bytes!",
+            prefix,
+            ENCRYPT_PREFIX_MAX - 1
+        ));
+    }
+
+    let mut desc = [0u8; ENCRYPT_PREFIX_MAX];
+    desc[..bytes.len()].copy_from_slice(bytes);
+    Ok(desc)
+}
+
+/// The notes of the shim, which the loader sets before the keep starts
+///
+/// Each option of the keep, which the shim has to know about, adds the
+/// descriptor of its note, which is then set in the loaded segment.
+#[derive(Clone, Debug, Default)]
+pub struct Notes(Vec<(usize, Vec<u8>)>);
+
+impl Notes {
+    /// Set the descriptor of the note `kind` of the `shim` to `desc`
+    ///
+    /// Returns `None`, if the shim lacks the note or its descriptor has
+    /// another size.
+    pub fn set(&mut self, shim: &Binary, kind: u32, desc: &[u8]) -> Option<()> {
+        let len = shim.notes(NOTE_NAME, kind).next()?.len();
+        let addr = shim.note_addr(NOTE_NAME, kind)?;
+
+        if len != desc.len() {
+            return None;
+        }
+
+        self.0.push((addr, desc.to_vec()));
+        Some(())
+    }
+
+    /// Set the notes in the `pages` loaded to `to` and forget them
+    fn apply(&mut self, pages: &mut [u8], to: usize) {
+        self.0.retain(|(addr, desc)| {
+            if !(to..to + pages.len()).contains(addr) {
+                return true;
+            }
+
+            pages[addr - to..][..desc.len()].copy_from_slice(desc);
+            false
+        });
+    }
+
+    /// Whether all notes are set
+    fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
 
 #[derive(Clone, Debug)]
 struct Segment<'a> {
@@ -116,6 +212,19 @@ impl<'a> Binary<'a> {
             .map(|n| n.desc)
     }
 
+    /// Find the virtual address of the descriptor of a note
+    ///
+    /// This allows the loader to patch the value of a note in the loaded
+    /// segment, before the keep starts.
+    pub fn note_addr(&self, name: &str, kind: u32) -> Option<usize> {
+        let desc = self.notes(name, kind).next()?;
+        let offset = desc.as_ptr() as usize - self.0.as_ptr() as usize;
+
+        self.headers(PT_LOAD)
+            .find(|ph| ph.file_range().contains(&offset))
+            .map(|ph| ph.p_vaddr as usize + offset - ph.p_offset as usize)
+    }
+
     /// Read a note from the note section
     ///
     /// # Safety
@@ -136,7 +245,11 @@ impl<'a> Binary<'a> {
 }
 
 impl<T: Mapper> Loader for T {
-    fn load(shim: impl AsRef<[u8]>, exec: impl AsRef<[u8]>) -> Result<Self::Output> {
+    fn load(
+        shim: impl AsRef<[u8]>,
+        exec: impl AsRef<[u8]>,
+        opts: &Options,
+    ) -> Result<Self::Output> {
         // Parse the ELF files.
         let sbin = Binary::new(shim.as_ref())?;
         let ebin = Binary::new(exec.as_ref())?;
@@ -165,8 +278,46 @@ impl<T: Mapper> Loader for T {
             return Err(anyhow!("Unable to satisfy sallyport version requirement!"));
         }
 
+        // Set the notes of the shim for the options.
+        // They are set before the pages are mapped, so they are part of the measurement.
+        let mut notes = Notes::default();
+
+        if opts.debug {
+            notes
+                .set(&sbin, NOTE_DEBUG, &1u32.to_ne_bytes())
+                .ok_or_else(|| anyhow!("Shim does not support runtime debug output!"))?;
+        }
+
+        if let Some(prefix) = &opts.encrypt {
+            notes
+                .set(&sbin, NOTE_ENCRYPT, &encrypt_prefix(prefix)?)
+                .ok_or_else(|| anyhow!("Shim does not support file encryption!"))?;
+        }
+
+        match opts.tls {
+            None => {}
+            Some(0) => return Err(anyhow!("TLS can't be terminated on port 0!")),
+            Some(port) => {
+                let desc = match opts.attest {
+                    false => u32::from(port),
+                    true => u32::from(port) | abi::note::TLS_ATTEST,
+                };
+
+                notes
+                    .set(&sbin, NOTE_TLS, &desc.to_ne_bytes())
+                    .ok_or_else(|| anyhow!("Shim does not support TLS termination!"))?;
+            }
+        }
+
+        if opts.strict_time {
+            notes
+                .set(&sbin, NOTE_TIME, &1u32.to_ne_bytes())
+                .ok_or_else(|| anyhow!("Shim does not support strict time!"))?;
+        }
+
         // Parse the config and create a builder.
-        let mut loader: Self = Self::Config::new(&sbin, &ebin)?.try_into()?;
+        // The backend sets its own notes, too.
+        let mut loader: Self = Self::Config::new(&sbin, &ebin, opts, &mut notes)?.try_into()?;
 
         // Get an array of all final segment locations (relocated).
         let ssegs: Vec<Segment> = sbin.segments(0).collect();
@@ -190,11 +341,18 @@ impl<T: Mapper> Loader for T {
                 .known::<perms::ReadWrite>(Kind::Private)?;
             map[seg.skipb..][..seg.bytes.len()].copy_from_slice(seg.bytes);
 
+            // Set the notes in the segment.
+            notes.apply(&mut map, seg.range.start);
+
             // Pass the region to the builder.
             let flags = Self::Config::flags(seg.flags);
             loader.map(map, seg.range.start, flags)?;
         }
 
+        if !notes.is_empty() {
+            return Err(anyhow!("Unable to set the notes of the shim!"));
+        }
+
         loader.try_into()
     }
 }
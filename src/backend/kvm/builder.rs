@@ -1,11 +1,14 @@
 // SPDX-License-Identifier: Apache-2.0
 
+use super::config::Config;
 use super::mem::Region;
+use super::ring::Service;
 use anyhow::{Error, Result};
 use kvm_bindings::bindings::kvm_userspace_memory_region;
 use kvm_bindings::fam_wrappers::KVM_MAX_CPUID_ENTRIES;
 use kvm_ioctls::{Kvm, VmFd};
 use mmarinus::{perms, Map};
+use primordial::Page;
 use sallyport::Block;
 use std::convert::TryFrom;
 use std::mem::size_of;
@@ -15,33 +18,37 @@ use x86_64::VirtAddr;
 pub struct Builder {
     kvm_fd: Kvm,
     vm_fd: VmFd,
+    cnfg: Config,
     regions: Vec<Region>,
     sallyports: Vec<Option<VirtAddr>>,
+    ring: Option<VirtAddr>,
 }
 
-impl TryFrom<super::config::Config> for Builder {
+impl TryFrom<Config> for Builder {
     type Error = Error;
 
-    fn try_from(_config: super::config::Config) -> Result<Self> {
+    fn try_from(config: Config) -> Result<Self> {
         let kvm_fd = Kvm::new()?;
         let vm_fd = kvm_fd.create_vm()?;
 
         Ok(Builder {
             kvm_fd,
             vm_fd,
+            cnfg: config,
             regions: Vec::new(),
             sallyports: Vec::new(),
+            ring: None,
         })
     }
 }
 
 impl super::super::Mapper for Builder {
-    type Config = super::config::Config;
+    type Config = Config;
     type Output = Arc<dyn super::super::Keep>;
 
     fn map(
         &mut self,
-        pages: Map<perms::ReadWrite>,
+        mut pages: Map<perms::ReadWrite>,
         to: usize,
         sallyport: bool,
     ) -> anyhow::Result<()> {
@@ -51,14 +58,41 @@ impl super::super::Mapper for Builder {
         }
 
         if sallyport {
-            for start in (0..pages.size()).step_by(size_of::<Block>()) {
-                if start + size_of::<Block>() <= pages.size() {
-                    let virt = VirtAddr::from_ptr(pages.as_ptr()) + start;
-                    self.sallyports.push(Some(virt));
-                }
+            // The hostcall ring lives in the last page of the sallyport segment.
+            if self.cnfg.ring {
+                let offset = pages.size() - Page::SIZE;
+                let (head, ring) = pages.split(offset)?;
+                pages = head;
+
+                self.ring = Some(VirtAddr::from_ptr(ring.as_ptr()));
+                self.add_region(ring, to + offset)?;
+            }
+
+            // The shim copies the secret from the first block, before it is used.
+            if let Some(secret) = self.cnfg.secret.as_ref() {
+                pages[..secret.len()].copy_from_slice(secret);
+            }
+
+            // Only back as many blocks as the shim is told to use.
+            let size = self.cnfg.sallyport_blocks * size_of::<Block>();
+            let size = (size + Page::SIZE - 1) / Page::SIZE * Page::SIZE;
+            if size < pages.size() {
+                let (head, _) = pages.split(size)?;
+                pages = head;
+            }
+
+            for block in 0..self.cnfg.sallyport_blocks {
+                let virt = VirtAddr::from_ptr(pages.as_ptr()) + block * size_of::<Block>();
+                self.sallyports.push(Some(virt));
             }
         }
 
+        self.add_region(pages, to)
+    }
+}
+
+impl Builder {
+    fn add_region(&mut self, pages: Map<perms::ReadWrite>, to: usize) -> Result<()> {
         let mem_region = kvm_userspace_memory_region {
             slot: self.regions.len() as _,
             flags: 0,
@@ -84,22 +118,46 @@ impl TryFrom<Builder> for Arc<dyn super::super::Keep> {
             anyhow::bail!("No sallyport blocks defined!");
         }
 
-        let cpuids = builder.kvm_fd.get_supported_cpuid(KVM_MAX_CPUID_ENTRIES)?;
+        let mut cpuids = builder.kvm_fd.get_supported_cpuid(KVM_MAX_CPUID_ENTRIES)?;
+        for entry in cpuids.as_mut_slice() {
+            let regs = [entry.eax, entry.ebx, entry.ecx, entry.edx];
+            let [eax, ebx, ecx, edx] = builder.cnfg.cpuid.apply(entry.function, entry.index, regs);
+            entry.eax = eax;
+            entry.ebx = ebx;
+            entry.ecx = ecx;
+            entry.edx = edx;
+        }
 
-        let vcpu_fd = builder.vm_fd.create_vcpu(0)?;
-        vcpu_fd.set_cpuid2(&cpuids)?;
+        let mut cpu_fds = Vec::with_capacity(builder.cnfg.vcpus);
+        for id in 0..builder.cnfg.vcpus {
+            let vcpu_fd = builder.vm_fd.create_vcpu(id as _)?;
+            vcpu_fd.set_cpuid2(&cpuids)?;
+            cpu_fds.push(vcpu_fd);
+        }
 
         // FIXME: this will be removed with relative addresses in sallyport
         // unwrap, because we have at least one block
         let sallyport_block_start = builder.sallyports.first().unwrap().unwrap();
 
+        let ring = match builder.ring {
+            Some(ring) => Some(Arc::new(Service::start(
+                ring,
+                sallyport_block_start,
+                builder.sallyports.len(),
+                builder.cnfg.handler.clone(),
+            )?)),
+            None => None,
+        };
+
         Ok(Arc::new(RwLock::new(super::Keep {
             kvm_fd: builder.kvm_fd,
             vm_fd: builder.vm_fd,
-            cpu_fds: vec![vcpu_fd],
+            cpu_fds,
             regions: builder.regions,
             sallyports: builder.sallyports,
             sallyport_start: sallyport_block_start,
+            ring,
+            gdb: builder.cnfg.gdb,
         })))
     }
 }
@@ -1,10 +1,73 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use anyhow::Result;
+use super::ring::RING_SIZE;
+use crate::cpuid::Policy;
+use crate::handler::Handler;
+use anyhow::{anyhow, Context, Result};
 use goblin::elf64::program_header::PT_LOAD;
+use primordial::Page;
 use sallyport::elf::pf::kvm::SALLYPORT;
+use sallyport::Block;
+use std::mem::size_of;
+use std::sync::{Arc, Mutex};
 
-pub struct Config {}
+/// The ELF note type of the number of usable sallyport blocks (`u32`)
+pub const NOTE_SALLYPORT_BLOCKS: u32 = abi::note::SALLYPORT_BLOCKS;
+
+/// The ELF note type of the hostcall ring switch (`u32`, non-zero if enabled)
+pub const NOTE_SALLYPORT_RING: u32 = abi::note::SALLYPORT_RING;
+
+/// The maximum size of the injected secret, like the shim reads it
+const SECRET_MAX: usize = 16 * 1024;
+
+/// The number of vCPUs of a keep
+const VCPUS: usize = 1;
+
+/// The number of sallyport blocks needed per vCPU without `--sallyport-blocks`
+///
+/// One block is used for the syscall itself and one for the debug output
+/// the shim might emit while handling the syscall.
+const BLOCKS_PER_VCPU: usize = 2;
+
+pub struct Config {
+    /// The number of vCPUs to create
+    pub vcpus: usize,
+
+    /// The number of sallyport blocks to map into the keep
+    pub sallyport_blocks: usize,
+
+    /// Whether the hostcall ring is enabled
+    pub ring: bool,
+
+    /// The address to wait for GDB on, if debugging is enabled
+    pub gdb: Option<String>,
+
+    /// Executes the syscalls of the keep, which the hostcall ring services
+    pub handler: Arc<Mutex<Handler>>,
+
+    /// The CPUID policy of the vCPUs
+    pub cpuid: Policy,
+
+    /// The secret to inject at the start of the sallyport blocks, as CBOR bytes
+    pub secret: Option<Vec<u8>>,
+}
+
+/// Encode `secret` as CBOR bytes, the format the shim expects the SEV secret in
+fn cbor_bytes(secret: &[u8]) -> Vec<u8> {
+    let len = secret.len();
+    let mut cbor = match len {
+        0..=23 => vec![0x40 | len as u8],
+        24..=0xFF => vec![0x58, len as u8],
+        _ => {
+            let mut head = vec![0x59];
+            head.extend_from_slice(&(len as u16).to_be_bytes());
+            head
+        }
+    };
+
+    cbor.extend_from_slice(secret);
+    cbor
+}
 
 impl super::super::Config for Config {
     type Flags = bool;
@@ -13,13 +76,94 @@ impl super::super::Config for Config {
         flags & SALLYPORT != 0
     }
 
-    fn new(shim: &super::super::Binary, _exec: &super::super::Binary) -> Result<Self> {
-        let sallyport_headers = shim.headers(PT_LOAD).filter(|p| p.p_flags & SALLYPORT != 0);
+    fn new(
+        shim: &super::super::Binary,
+        _exec: &super::super::Binary,
+        opts: &super::super::Options,
+        notes: &mut super::super::Notes,
+    ) -> Result<Self> {
+        if opts.data.is_some() {
+            anyhow::bail!("The kvm backend does not support data pages");
+        }
 
-        if sallyport_headers.count() != 1 {
+        if opts.edmm {
+            anyhow::bail!("The kvm backend does not support EDMM");
+        }
+
+        let sallyport_headers: Vec<_> = shim
+            .headers(PT_LOAD)
+            .filter(|p| p.p_flags & SALLYPORT != 0)
+            .collect();
+
+        if sallyport_headers.len() != 1 {
             anyhow::bail!("KVM shim must contain exactly one sallyport PT_LOAD segment.")
         }
 
-        Ok(Self {})
+        // The last page of the sallyport segment is reserved for the hostcall ring.
+        let capacity =
+            (sallyport_headers[0].p_memsz as usize).saturating_sub(Page::SIZE) / size_of::<Block>();
+        let sallyport_blocks = opts.sallyport_blocks.unwrap_or(VCPUS * BLOCKS_PER_VCPU);
+
+        if sallyport_blocks == 0 {
+            anyhow::bail!("The KVM shim needs at least one sallyport block.")
+        }
+
+        if opts.ring && sallyport_blocks > RING_SIZE {
+            anyhow::bail!(
+                "The hostcall ring can serve {} blocks, but {} are needed.",
+                RING_SIZE,
+                sallyport_blocks
+            )
+        }
+
+        if sallyport_blocks > capacity {
+            anyhow::bail!(
+                "KVM shim sallyport segment has room for {} blocks, but {} are needed.",
+                capacity,
+                sallyport_blocks
+            )
+        }
+
+        // Tell the shim how many sallyport blocks it may use.
+        notes
+            .set(
+                shim,
+                NOTE_SALLYPORT_BLOCKS,
+                &(sallyport_blocks as u32).to_ne_bytes(),
+            )
+            .ok_or_else(|| anyhow!("KVM shim is missing SALLYPORT_BLOCKS"))?;
+
+        // Tell the shim to use the hostcall ring.
+        if opts.ring {
+            notes
+                .set(shim, NOTE_SALLYPORT_RING, &1u32.to_ne_bytes())
+                .ok_or_else(|| anyhow!("KVM shim is missing SALLYPORT_RING"))?;
+        }
+
+        let secret = match opts.secret.as_ref() {
+            None => None,
+            Some(path) => {
+                let secret = std::fs::read(path)
+                    .with_context(|| format!("Failed to read the secret {}", path.display()))?;
+                let secret = cbor_bytes(&secret);
+
+                let room = SECRET_MAX.min(sallyport_blocks * size_of::<Block>());
+                if secret.len() > room {
+                    anyhow::bail!("The secret is larger than the {} bytes of the keep", room)
+                }
+
+                Some(secret)
+            }
+        };
+
+        Ok(Self {
+            vcpus: VCPUS,
+            sallyport_blocks,
+            ring: opts.ring,
+            gdb: opts.gdb.clone(),
+            handler: opts.handler.clone(),
+            cpuid: opts.cpuid.clone(),
+            secret,
+        })
     }
 }
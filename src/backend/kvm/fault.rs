@@ -0,0 +1,156 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Faults of a KVM keep
+//!
+//! Every `VcpuExit` the loader can't service ends the keep with a
+//! `KeepFault`. It is returned wrapped in an `anyhow::Error` by
+//! `Thread::enter` and can be recovered with `downcast_ref::<KeepFault>()`.
+
+use std::fmt;
+
+use kvm_bindings::{kvm_regs, kvm_sregs};
+
+use crate::symbolize::SHIM_VIRT_OFFSET;
+
+/// The reason a KVM keep was stopped
+#[derive(Debug)]
+pub enum KeepFault {
+    /// The shim detected an attack and shut down the keep on purpose
+    ///
+    /// `_enarx_asm_triple_fault` loads an empty IDT and raises `#UD`. The
+    /// general purpose registers carry the stack trace of the shim. See
+    /// `KeepFault::shutdown` for how it is told apart from a triple fault.
+    Attack { regs: Box<kvm_regs> },
+
+    /// The vCPU triple faulted
+    TripleFault {
+        regs: Box<kvm_regs>,
+        sregs: Box<kvm_sregs>,
+    },
+
+    /// The vCPU halted
+    Halt { regs: Box<kvm_regs> },
+
+    /// The vCPU wrote to an I/O port the loader does not service
+    IoOut { port: u16, data: Vec<u8> },
+
+    /// The vCPU read from an I/O port the loader does not service
+    IoIn { port: u16, len: usize },
+
+    /// The vCPU accessed memory which is not backed by any region
+    Mmio { addr: u64, write: bool, len: usize },
+
+    /// The vCPU could not be entered
+    FailEntry { reason: u64, cpu: u32 },
+
+    /// KVM failed to handle an exit
+    InternalError,
+
+    /// Any other exit
+    Unexpected(String),
+}
+
+impl KeepFault {
+    /// Classify the shutdown of the vCPU with the registers `regs` and `sregs`
+    ///
+    /// A shutdown is reported as an attack, only if the vCPU shut down in
+    /// the shim, in ring 0, with an IDT of base 0 and limit 0. `lidt` is
+    /// privileged, so only the shim can load that IDT, and the shim loads
+    /// it only in `_enarx_asm_triple_fault`. Any other shutdown, also one
+    /// of an exception without an IDT before the shim loaded its own, is
+    /// a triple fault.
+    pub fn shutdown(regs: Box<kvm_regs>, sregs: Box<kvm_sregs>) -> Self {
+        let attack = sregs.idt.limit == 0
+            && sregs.idt.base == 0
+            && sregs.cs.dpl == 0
+            && regs.rip >= SHIM_VIRT_OFFSET;
+
+        match attack {
+            true => Self::Attack { regs },
+            false => Self::TripleFault { regs, sregs },
+        }
+    }
+
+    /// Describe the hardware reason of a failed VM entry
+    ///
+    /// AMD reports `VMEXIT_INVALID`. Intel reports the full VMX exit reason,
+    /// with bit 31 set for failed entries, so only its basic exit reason in
+    /// the low 16 bits is matched.
+    fn entry_failure(reason: u64) -> &'static str {
+        if reason == 0xFFFF_FFFF_FFFF_FFFF {
+            return "invalid VMCB";
+        }
+
+        match reason & 0xffff {
+            33 => "invalid guest state",
+            34 => "MSR loading",
+            41 => "machine-check event",
+            _ => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for KeepFault {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Attack { regs } => write!(
+                f,
+                "The shim detected an attack and shut down the keep\n{:#x?}",
+                regs
+            ),
+
+            Self::TripleFault { regs, sregs } => {
+                write!(f, "The keep triple faulted\n{:#x?}\n{:#x?}", regs, sregs)
+            }
+
+            Self::Halt { regs } => write!(f, "The keep halted\n{:#x?}", regs),
+
+            Self::IoOut { port, data } => write!(
+                f,
+                "The keep wrote {:02x?} to the unexpected I/O port {:#x}",
+                data, port
+            ),
+
+            Self::IoIn { port, len } => write!(
+                f,
+                "The keep read {} bytes from the unexpected I/O port {:#x}",
+                len, port
+            ),
+
+            Self::Mmio { addr, write, len } => write!(
+                f,
+                "The keep {} {} bytes of unmapped memory at {:#x}",
+                if *write { "wrote" } else { "read" },
+                len,
+                addr
+            ),
+
+            Self::FailEntry { reason, cpu } => write!(
+                f,
+                "The keep could not be entered on cpu {}: {} (hardware reason {:#x})",
+                cpu,
+                Self::entry_failure(*reason),
+                reason
+            ),
+
+            Self::InternalError => write!(f, "KVM failed to handle an exit of the keep"),
+
+            Self::Unexpected(reason) => write!(f, "The keep exited unexpectedly: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for KeepFault {}
+
+#[cfg(test)]
+mod tests {
+    use super::KeepFault;
+
+    #[test]
+    fn entry_failure() {
+        assert_eq!(KeepFault::entry_failure(0x8000_0021), "invalid guest state");
+        assert_eq!(KeepFault::entry_failure(0x8000_0022), "MSR loading");
+        assert_eq!(KeepFault::entry_failure(u64::MAX), "invalid VMCB");
+        assert_eq!(KeepFault::entry_failure(0x8000_0000), "unknown");
+    }
+}
@@ -0,0 +1,509 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! GDB remote serial protocol stub
+//!
+//! The stub lets GDB attach to the vCPU of a plain KVM keep. It supports
+//! reading and writing registers and memory, software breakpoints and
+//! single-stepping. Guest virtual addresses are translated by walking the
+//! page tables of the vCPU.
+//!
+//! A thread reads everything GDB sends. It passes the packets on to the
+//! vCPU thread and kicks the running vCPU out of `KVM_RUN`, if GDB sends
+//! an interrupt, because the user pressed Ctrl-C.
+
+use std::collections::HashMap;
+use std::io::{BufReader, Read, Write};
+use std::net::{Shutdown, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use iocuddle::{Group, Ioctl, Write as IocWrite};
+use kvm_bindings::{
+    kvm_guest_debug, kvm_regs, kvm_sregs, KVM_GUESTDBG_ENABLE, KVM_GUESTDBG_SINGLESTEP,
+    KVM_GUESTDBG_USE_SW_BP,
+};
+use kvm_ioctls::VcpuFd;
+
+use crate::signal;
+
+const KVM: Group = Group::new(0xAE);
+
+/// IOCTL identifier for enabling the debugging of a vCPU
+const KVM_SET_GUEST_DEBUG: Ioctl<IocWrite, &kvm_guest_debug> = unsafe { KVM.write(0x9b) };
+
+/// The signal reported to GDB, if the vCPU stopped
+const SIGTRAP: u8 = 5;
+
+/// The signal reported to GDB, if GDB interrupted the vCPU
+const SIGINT: u8 = 2;
+
+/// The byte GDB sends outside of packets to interrupt the vCPU
+const INTERRUPT: u8 = 0x03;
+
+/// How often an interrupt kicks the vCPU, until it stopped
+///
+/// A kick right before the vCPU enters `KVM_RUN` is lost, so it is repeated.
+const KICK_INTERVAL: Duration = Duration::from_millis(10);
+
+/// The `int3` instruction
+const INT3: u8 = 0xCC;
+
+/// The mask of the physical address in a page table entry
+const ADDR_MASK: u64 = 0x000F_FFFF_FFFF_F000;
+
+/// What the vCPU should do after GDB has handled a stop
+enum Resume {
+    Continue,
+    Step,
+    Detach,
+}
+
+/// The state of the vCPU shared with the thread reading from GDB
+#[derive(Default)]
+struct Shared {
+    /// The vCPU runs, so an interrupt has to kick it
+    running: AtomicBool,
+
+    /// GDB interrupted the vCPU, which has not stopped yet
+    interrupt: AtomicBool,
+}
+
+/// Where the reader is in the byte stream from GDB
+#[derive(Copy, Clone)]
+enum Framing {
+    Between,
+    Data,
+    Checksum(u8),
+}
+
+/// A GDB connection to a vCPU
+pub struct Stub {
+    stream: TcpStream,
+    bytes: Receiver<u8>,
+    shared: Arc<Shared>,
+    breakpoints: HashMap<u64, u8>,
+    stopped: Option<u8>,
+    resumed: bool,
+}
+
+impl Drop for Stub {
+    fn drop(&mut self) {
+        // Stop kicking the vCPU and end the reader.
+        self.shared.running.store(false, Ordering::SeqCst);
+        self.shared.interrupt.store(false, Ordering::SeqCst);
+        let _ = self.stream.shutdown(Shutdown::Both);
+    }
+}
+
+impl Stub {
+    /// Wait for GDB to connect to `addr`
+    ///
+    /// The vCPU is stopped before it runs the first instruction. It has
+    /// to run on the calling thread, which the reader kicks.
+    pub fn listen(addr: &str) -> Result<Self> {
+        let listener = TcpListener::bind(addr)?;
+        eprintln!("Waiting for GDB to connect to {}", listener.local_addr()?);
+        let (stream, _) = listener.accept()?;
+
+        signal::kickable()?;
+
+        let shared = Arc::new(Shared::default());
+        let (sender, bytes) = channel();
+        let vcpu = unsafe { libc::pthread_self() };
+
+        let reader = stream.try_clone()?;
+        let state = shared.clone();
+        std::thread::spawn(move || Self::read(reader, &state, &sender, vcpu));
+
+        Ok(Self {
+            stream,
+            bytes,
+            shared,
+            breakpoints: HashMap::new(),
+            stopped: Some(SIGTRAP),
+            resumed: false,
+        })
+    }
+
+    /// Pass everything GDB sends on to `bytes`, but kick the vCPU on an interrupt
+    fn read(stream: TcpStream, shared: &Shared, bytes: &Sender<u8>, vcpu: libc::pthread_t) {
+        let mut framing = Framing::Between;
+
+        for byte in BufReader::new(stream).bytes() {
+            let byte = match byte {
+                Ok(byte) => byte,
+                Err(_) => break,
+            };
+
+            framing = match (framing, byte) {
+                (Framing::Between, INTERRUPT) => {
+                    // The vCPU might be about to resume, so wait for it to run.
+                    shared.interrupt.store(true, Ordering::SeqCst);
+
+                    while shared.interrupt.load(Ordering::SeqCst) {
+                        if shared.running.load(Ordering::SeqCst) {
+                            unsafe { libc::pthread_kill(vcpu, signal::KICK) };
+                        }
+
+                        std::thread::sleep(KICK_INTERVAL);
+                    }
+
+                    continue;
+                }
+                (Framing::Between, b'$') => Framing::Data,
+                (Framing::Between, _) => Framing::Between,
+                (Framing::Data, b'#') => Framing::Checksum(2),
+                (Framing::Data, _) => Framing::Data,
+                (Framing::Checksum(1), _) => Framing::Between,
+                (Framing::Checksum(n), _) => Framing::Checksum(n - 1),
+            };
+
+            if bytes.send(byte).is_err() {
+                break;
+            }
+        }
+    }
+
+    /// Mark the vCPU as stopped by a debug exit
+    pub fn stop(&mut self) {
+        self.stopped = Some(SIGTRAP);
+    }
+
+    /// Let GDB handle a pending stop of the vCPU
+    ///
+    /// Returns `false`, if GDB detached.
+    pub fn handle(&mut self, vcpu_fd: &mut VcpuFd, keep: &RwLock<super::Keep>) -> Result<bool> {
+        // The kicked vCPU left `KVM_RUN` for the interrupt.
+        if self.shared.interrupt.load(Ordering::SeqCst) && self.stopped.is_none() {
+            self.stopped = Some(SIGINT);
+        }
+
+        let signal = match self.stopped.take() {
+            Some(signal) => signal,
+            None => return Ok(true),
+        };
+
+        // The vCPU stopped, so an interrupt sent until now is handled.
+        self.shared.running.store(false, Ordering::SeqCst);
+        self.shared.interrupt.store(false, Ordering::SeqCst);
+
+        let resume = self.session(vcpu_fd, keep, signal)?;
+
+        let control = match resume {
+            Resume::Continue => KVM_GUESTDBG_ENABLE | KVM_GUESTDBG_USE_SW_BP,
+            Resume::Step => KVM_GUESTDBG_ENABLE | KVM_GUESTDBG_USE_SW_BP | KVM_GUESTDBG_SINGLESTEP,
+            Resume::Detach => 0,
+        };
+
+        let debug = kvm_guest_debug {
+            control,
+            ..Default::default()
+        };
+        KVM_SET_GUEST_DEBUG.ioctl(vcpu_fd, &debug)?;
+
+        let attached = !matches!(resume, Resume::Detach);
+        self.shared.running.store(attached, Ordering::SeqCst);
+        Ok(attached)
+    }
+
+    fn session(
+        &mut self,
+        vcpu_fd: &mut VcpuFd,
+        keep: &RwLock<super::Keep>,
+        signal: u8,
+    ) -> Result<Resume> {
+        // The stop is the reply to the packet, which resumed the vCPU.
+        if std::mem::take(&mut self.resumed) {
+            self.send(&format!("S{:02x}", signal))?;
+        }
+
+        loop {
+            // The packet may start with a replacement character, which takes more than a byte.
+            let packet = self.recv()?;
+            let (cmd, args) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+
+            let reply = match cmd {
+                "?" => format!("S{:02x}", signal),
+                "g" => Self::regs(&vcpu_fd.get_regs()?, &vcpu_fd.get_sregs()?),
+                "G" => {
+                    let mut regs = vcpu_fd.get_regs()?;
+                    match Self::set_regs(&mut regs, args) {
+                        Some(()) => {
+                            vcpu_fd.set_regs(&regs)?;
+                            "OK".into()
+                        }
+                        None => "E01".into(),
+                    }
+                }
+                "m" => self
+                    .read_mem(vcpu_fd, keep, args)
+                    .unwrap_or_else(|| "E01".into()),
+                "M" => match self.write_mem(vcpu_fd, keep, args) {
+                    Some(()) => "OK".into(),
+                    None => "E01".into(),
+                },
+                "Z" | "z" if args.starts_with("0,") => {
+                    match self.breakpoint(vcpu_fd, keep, &args[2..], cmd == "Z") {
+                        Some(()) => "OK".into(),
+                        None => "E01".into(),
+                    }
+                }
+                "c" => {
+                    self.resumed = true;
+                    return Ok(Resume::Continue);
+                }
+                "s" => {
+                    self.resumed = true;
+                    return Ok(Resume::Step);
+                }
+                "D" => {
+                    self.clear_breakpoints(vcpu_fd, keep);
+                    self.send("OK")?;
+                    return Ok(Resume::Detach);
+                }
+                "k" => return Err(anyhow!("The keep was killed by GDB")),
+                "q" if args.starts_with("Supported") => "PacketSize=4000".into(),
+                "q" if args.starts_with("Attached") => "1".into(),
+                "q" if args == "C" => "QC1".into(),
+                "H" => "OK".into(),
+                _ => String::new(),
+            };
+
+            self.send(&reply)?;
+        }
+    }
+
+    /// Receive a byte from the reader
+    fn byte(&mut self) -> Result<u8> {
+        self.bytes.recv().map_err(|_| anyhow!("GDB disconnected"))
+    }
+
+    /// Receive a packet and acknowledge it
+    fn recv(&mut self) -> Result<String> {
+        // Skip acknowledgements and anything else up to the start of a packet.
+        while self.byte()? != b'$' {}
+
+        let mut data = Vec::new();
+        loop {
+            match self.byte()? {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+
+        // The checksum is not verified, TCP already ensures integrity.
+        self.byte()?;
+        self.byte()?;
+        self.stream.write_all(b"+")?;
+
+        Ok(String::from_utf8_lossy(&data).into_owned())
+    }
+
+    /// Send a packet
+    fn send(&mut self, data: &str) -> Result<()> {
+        let checksum = data.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.stream, "${}#{:02x}", data, checksum)?;
+        self.stream.flush()?;
+        Ok(())
+    }
+
+    /// Encode the registers in the order of the GDB `amd64` core registers
+    ///
+    /// The floating point registers follow the segment registers, GDB
+    /// shows them as unavailable.
+    fn regs(regs: &kvm_regs, sregs: &kvm_sregs) -> String {
+        let mut out = String::new();
+
+        for reg in &[
+            regs.rax, regs.rbx, regs.rcx, regs.rdx, regs.rsi, regs.rdi, regs.rbp, regs.rsp,
+            regs.r8, regs.r9, regs.r10, regs.r11, regs.r12, regs.r13, regs.r14, regs.r15, regs.rip,
+        ] {
+            out.push_str(&hex::encode(reg.to_le_bytes()));
+        }
+
+        out.push_str(&hex::encode((regs.rflags as u32).to_le_bytes()));
+
+        for seg in &[sregs.cs, sregs.ss, sregs.ds, sregs.es, sregs.fs, sregs.gs] {
+            out.push_str(&hex::encode(u32::from(seg.selector).to_le_bytes()));
+        }
+
+        out
+    }
+
+    /// Decode the registers in the order of the GDB `amd64` core registers
+    ///
+    /// The segment registers are ignored, because a selector can't be
+    /// loaded without its descriptor.
+    fn set_regs(regs: &mut kvm_regs, data: &str) -> Option<()> {
+        let bytes = hex::decode(data).ok()?;
+        let mut chunks = bytes.chunks_exact(8);
+
+        for reg in [
+            &mut regs.rax,
+            &mut regs.rbx,
+            &mut regs.rcx,
+            &mut regs.rdx,
+            &mut regs.rsi,
+            &mut regs.rdi,
+            &mut regs.rbp,
+            &mut regs.rsp,
+            &mut regs.r8,
+            &mut regs.r9,
+            &mut regs.r10,
+            &mut regs.r11,
+            &mut regs.r12,
+            &mut regs.r13,
+            &mut regs.r14,
+            &mut regs.r15,
+            &mut regs.rip,
+        ] {
+            let mut buf = [0u8; 8];
+            buf.copy_from_slice(chunks.next()?);
+            *reg = u64::from_le_bytes(buf);
+        }
+
+        if let Some(eflags) = bytes.get(17 * 8..17 * 8 + 4) {
+            let mut buf = [0u8; 4];
+            buf.copy_from_slice(eflags);
+            regs.rflags = u32::from_le_bytes(buf).into();
+        }
+
+        Some(())
+    }
+
+    /// Parse `addr,len`
+    fn range(args: &str) -> Option<(u64, usize)> {
+        let mut split = args.splitn(2, ',');
+        let addr = u64::from_str_radix(split.next()?, 16).ok()?;
+        let len = usize::from_str_radix(split.next()?, 16).ok()?;
+        Some((addr, len))
+    }
+
+    /// Access `len` bytes of guest virtual memory at `addr` with `f`
+    fn access(
+        vcpu_fd: &VcpuFd,
+        keep: &RwLock<super::Keep>,
+        mut addr: u64,
+        mut len: usize,
+        mut f: impl FnMut(&mut [u8]),
+    ) -> Option<()> {
+        let sregs = vcpu_fd.get_sregs().ok()?;
+        let paging = sregs.cr0 & (1 << 31) != 0;
+        let keep = keep.read().unwrap();
+
+        while len > 0 {
+            let phys = match paging {
+                true => translate(&keep, sregs.cr3, addr)?,
+                false => addr,
+            };
+
+            // Do not cross a page boundary, the next page might be mapped elsewhere.
+            let chunk = len.min(0x1000 - (addr & 0xFFF) as usize);
+            let mem = keep.phys(phys, chunk)?;
+            f(unsafe { std::slice::from_raw_parts_mut(mem, chunk) });
+
+            addr += chunk as u64;
+            len -= chunk;
+        }
+
+        Some(())
+    }
+
+    fn read_mem(&self, vcpu_fd: &VcpuFd, keep: &RwLock<super::Keep>, args: &str) -> Option<String> {
+        let (addr, len) = Self::range(args)?;
+        let mut out = Vec::with_capacity(len);
+        Self::access(vcpu_fd, keep, addr, len, |mem| out.extend_from_slice(mem))?;
+
+        // Hide the breakpoints from GDB.
+        for (bp, orig) in &self.breakpoints {
+            if (addr..addr + len as u64).contains(bp) {
+                out[(bp - addr) as usize] = *orig;
+            }
+        }
+
+        Some(hex::encode(&out))
+    }
+
+    fn write_mem(&self, vcpu_fd: &VcpuFd, keep: &RwLock<super::Keep>, args: &str) -> Option<()> {
+        let mut split = args.splitn(2, ':');
+        let (addr, len) = Self::range(split.next()?)?;
+        let data = hex::decode(split.next()?).ok()?;
+
+        if data.len() != len {
+            return None;
+        }
+
+        let mut data = &data[..];
+        Self::access(vcpu_fd, keep, addr, len, |mem| {
+            let (head, tail) = data.split_at(mem.len());
+            mem.copy_from_slice(head);
+            data = tail;
+        })
+    }
+
+    fn breakpoint(
+        &mut self,
+        vcpu_fd: &VcpuFd,
+        keep: &RwLock<super::Keep>,
+        args: &str,
+        insert: bool,
+    ) -> Option<()> {
+        let addr = u64::from_str_radix(args.split(',').next()?, 16).ok()?;
+
+        match (insert, self.breakpoints.get(&addr).copied()) {
+            (true, Some(_)) => Some(()),
+            (true, None) => {
+                let mut orig = 0;
+                Self::access(vcpu_fd, keep, addr, 1, |mem| {
+                    orig = mem[0];
+                    mem[0] = INT3;
+                })?;
+                self.breakpoints.insert(addr, orig);
+                Some(())
+            }
+            (false, None) => Some(()),
+            (false, Some(orig)) => {
+                Self::access(vcpu_fd, keep, addr, 1, |mem| mem[0] = orig)?;
+                self.breakpoints.remove(&addr);
+                Some(())
+            }
+        }
+    }
+
+    fn clear_breakpoints(&mut self, vcpu_fd: &VcpuFd, keep: &RwLock<super::Keep>) {
+        for (addr, orig) in self.breakpoints.drain() {
+            let _ = Self::access(vcpu_fd, keep, addr, 1, |mem| mem[0] = orig);
+        }
+    }
+}
+
+/// Translate a guest virtual address with the 4-level page tables at `cr3`
+fn translate(keep: &super::Keep, cr3: u64, addr: u64) -> Option<u64> {
+    let mut table = cr3 & ADDR_MASK;
+
+    for level in (0..4).rev() {
+        let shift = 12 + 9 * level;
+        let index = (addr >> shift) & 0x1FF;
+
+        let entry = keep.phys(table + index * 8, 8)?;
+        let entry = unsafe { (entry as *const u64).read_unaligned() };
+
+        // Not present
+        if entry & 1 == 0 {
+            return None;
+        }
+
+        // Huge page of a PDPT or PD entry
+        if (level == 1 || level == 2) && entry & (1 << 7) != 0 {
+            let size = 1u64 << shift;
+            return Some((entry & ADDR_MASK & !(size - 1)) + (addr & (size - 1)));
+        }
+
+        table = entry & ADDR_MASK;
+    }
+
+    Some(table + (addr & 0xFFF))
+}
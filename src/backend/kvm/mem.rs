@@ -4,7 +4,7 @@ use super::KvmUserspaceMemoryRegion;
 
 use lset::Span;
 use mmarinus::{perms, Map};
-use x86_64::VirtAddr;
+use x86_64::{PhysAddr, VirtAddr};
 
 pub struct Region {
     kvm_region: KvmUserspaceMemoryRegion,
@@ -25,4 +25,11 @@ impl Region {
             count: self.kvm_region.memory_size,
         }
     }
+
+    pub fn as_guest(&self) -> Span<PhysAddr, u64> {
+        Span {
+            start: PhysAddr::new(self.kvm_region.guest_phys_addr),
+            count: self.kvm_region.memory_size,
+        }
+    }
 }
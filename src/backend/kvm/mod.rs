@@ -1,6 +1,6 @@
 // SPDX-License-Identifier: Apache-2.0
 
-use super::Loader;
+use super::{Loader, Options};
 use crate::backend::kvm::data::{dev_kvm, kvm_version};
 use crate::backend::kvm::mem::Region;
 use anyhow::Result;
@@ -12,10 +12,15 @@ use mmarinus::{perms, Map};
 use std::sync::Arc;
 use x86_64::VirtAddr;
 
+pub use fault::KeepFault;
+
 mod builder;
 mod config;
 mod data;
+mod fault;
+mod gdb;
 mod mem;
+mod ring;
 mod thread;
 
 impl Keep {
@@ -33,6 +38,20 @@ impl Keep {
         self.regions.push(Region::new(region, pages));
         Ok(self.regions.last_mut().unwrap())
     }
+
+    /// Get the host memory backing `len` bytes of guest physical memory at `addr`
+    pub fn phys(&self, addr: u64, len: usize) -> Option<*mut u8> {
+        self.regions.iter().find_map(|region| {
+            let guest = region.as_guest();
+            let offset = addr.checked_sub(guest.start.as_u64())?;
+
+            if offset.checked_add(len as u64)? > guest.count {
+                return None;
+            }
+
+            Some((region.as_virt().start + offset).as_mut_ptr())
+        })
+    }
 }
 
 struct Keep {
@@ -42,7 +61,10 @@ struct Keep {
     // FIXME: This will be removed in the near future
     sallyport_start: VirtAddr,
     sallyports: Vec<Option<VirtAddr>>,
+    // Dropped before `regions`, so the service thread stops before the memory is unmapped
+    ring: Option<Arc<ring::Service>>,
     regions: Vec<Region>,
+    gdb: Option<String>,
 }
 
 pub struct Backend;
@@ -68,12 +90,12 @@ impl crate::backend::Backend for Backend {
     }
 
     #[inline]
-    fn keep(&self, shim: &[u8], exec: &[u8]) -> Result<Arc<dyn super::Keep>> {
-        builder::Builder::load(shim, exec)
+    fn keep(&self, shim: &[u8], exec: &[u8], opts: &Options) -> Result<Arc<dyn super::Keep>> {
+        builder::Builder::load(shim, exec, opts)
     }
 
     #[inline]
-    fn hash(&self, _shim: &[u8], _exec: &[u8]) -> Result<Vec<u8>> {
+    fn hash(&self, _shim: &[u8], _exec: &[u8], _opts: &Options) -> Result<Vec<u8>> {
         Ok(Vec::new())
     }
 }
@@ -0,0 +1,223 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Hostcall ring
+//!
+//! Instead of exiting the keep for every proxied syscall, the shim can push
+//! the index of a filled sallyport block onto a ring in shared memory. A host
+//! thread pops the indices, executes the requests and marks the blocks as
+//! done, while the vCPU keeps running. The vCPU only exits via
+//! `KVM_RING_DOORBELL_PORT`, if the shim wants to block until a request is done.
+//!
+//! The requests are executed by the same `Handler` as the requests of the
+//! vCPU thread, so they are traced and symbolized, too.
+
+use std::convert::TryFrom;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::handler::Handler;
+use sallyport::syscall::{SYS_ENARX_BALLOON_MEMORY, SYS_ENARX_MEM_INFO};
+use sallyport::{Block, KVM_SYSCALL_TRIGGER_PORT};
+use x86_64::VirtAddr;
+
+/// The port the shim writes a block index to, to wait for the request to be done
+pub const KVM_RING_DOORBELL_PORT: u16 =
+    KVM_SYSCALL_TRIGGER_PORT.wrapping_add(abi::kvm::RING_DOORBELL_PORT_OFFSET);
+
+/// The number of entries of the ring and the maximum number of blocks it can serve
+pub const RING_SIZE: usize = 32;
+
+/// The number of empty polls before the service thread goes to sleep
+const SPIN_LIMIT: usize = 1 << 14;
+
+/// The maximum time the doorbell waits before checking the block again
+const DOORBELL_TIMEOUT: Duration = Duration::from_millis(1);
+
+/// The shared memory layout of the ring
+///
+/// Has to match the layout in `internal/shim-sev/src/hostcall.rs`.
+#[repr(C)]
+pub struct Ring {
+    /// Written by the shim, the next free entry
+    head: AtomicU32,
+
+    /// Written by the host, the next entry to service
+    tail: AtomicU32,
+
+    /// Non-zero, if the service thread is sleeping and has to be woken up
+    idle: AtomicU32,
+
+    /// Indices of the blocks to service
+    entries: [AtomicU32; RING_SIZE],
+
+    /// Non-zero, if the request in the block with the same index is done
+    done: [AtomicU32; RING_SIZE],
+}
+
+impl Ring {
+    fn pop(&self) -> Option<u32> {
+        let tail = self.tail.load(Ordering::Relaxed);
+
+        if tail == self.head.load(Ordering::Acquire) {
+            return None;
+        }
+
+        let index = self.entries[tail as usize % RING_SIZE].load(Ordering::Relaxed);
+        self.tail.store(tail.wrapping_add(1), Ordering::Release);
+        Some(index)
+    }
+
+    fn is_empty(&self) -> bool {
+        self.tail.load(Ordering::SeqCst) == self.head.load(Ordering::SeqCst)
+    }
+
+    fn is_done(&self, index: usize) -> bool {
+        self.done[index].load(Ordering::Acquire) != 0
+    }
+}
+
+/// The host thread servicing the ring
+///
+/// The thread accesses the memory of the keep, so the `Service` has to be
+/// dropped before the memory is unmapped. Dropping it stops the thread,
+/// after it finished the request in progress.
+pub struct Service {
+    ring: usize,
+    blocks: usize,
+    wait: Arc<(Mutex<()>, Condvar)>,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Drop for Service {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+
+        if let Some(thread) = self.thread.take() {
+            thread.thread().unpark();
+            let _ = thread.join();
+        }
+    }
+}
+
+impl Service {
+    /// Start servicing the ring at `ring` for the `count` blocks starting at `blocks`
+    pub fn start(
+        ring: VirtAddr,
+        blocks: VirtAddr,
+        count: usize,
+        handler: Arc<Mutex<Handler>>,
+    ) -> Result<Self> {
+        if count > RING_SIZE {
+            anyhow::bail!("The hostcall ring can serve at most {} blocks", RING_SIZE);
+        }
+
+        let wait = Arc::new((Mutex::new(()), Condvar::new()));
+        let stop = Arc::new(AtomicBool::new(false));
+
+        let thread = std::thread::Builder::new()
+            .name("hostcall-ring".into())
+            .spawn({
+                let ring = ring.as_u64() as usize;
+                let blocks = blocks.as_u64() as usize;
+                let wait = wait.clone();
+                let stop = stop.clone();
+                move || Self::run(ring, blocks, count, &wait, &stop, &handler)
+            })?;
+
+        Ok(Self {
+            ring: ring.as_u64() as usize,
+            blocks: count,
+            wait,
+            stop,
+            thread: Some(thread),
+        })
+    }
+
+    fn ring(&self) -> &Ring {
+        unsafe { &*(self.ring as *const Ring) }
+    }
+
+    /// Wake up the service thread and wait for the block `index` to be done
+    ///
+    /// The done flag is reset by the shim, not by the host.
+    pub fn doorbell(&self, index: usize) {
+        if let Some(thread) = self.thread.as_ref() {
+            thread.thread().unpark();
+        }
+
+        if index >= self.blocks {
+            return;
+        }
+
+        let (lock, cvar) = &*self.wait;
+        let mut guard = lock.lock().unwrap();
+        while !self.ring().is_done(index) {
+            guard = cvar.wait_timeout(guard, DOORBELL_TIMEOUT).unwrap().0;
+        }
+    }
+
+    fn run(
+        ring: usize,
+        blocks: usize,
+        count: usize,
+        wait: &(Mutex<()>, Condvar),
+        stop: &AtomicBool,
+        handler: &Mutex<Handler>,
+    ) {
+        let ring = unsafe { &*(ring as *const Ring) };
+        let mut empty = 0;
+
+        while !stop.load(Ordering::SeqCst) {
+            let index = match ring.pop() {
+                Some(index) => usize::try_from(index).unwrap(),
+
+                None if empty < SPIN_LIMIT => {
+                    empty += 1;
+                    std::hint::spin_loop();
+                    continue;
+                }
+
+                None => {
+                    // Announce going to sleep, before checking the ring for the last time,
+                    // so a concurrent push will either be seen here or ring the doorbell.
+                    ring.idle.store(1, Ordering::SeqCst);
+                    if ring.is_empty() && !stop.load(Ordering::SeqCst) {
+                        std::thread::park();
+                    }
+                    ring.idle.store(0, Ordering::SeqCst);
+                    empty = 0;
+                    continue;
+                }
+            };
+
+            empty = 0;
+
+            // The index comes from the keep and must not be trusted.
+            if index >= count {
+                continue;
+            }
+
+            let block = unsafe { &mut *((blocks + index * size_of::<Block>()) as *mut Block) };
+            let req = unsafe { block.msg.req };
+
+            block.msg.rep = match i64::from(req.num) {
+                // These need the vCPU and have to be sent via `KVM_SYSCALL_TRIGGER_PORT`.
+                SYS_ENARX_BALLOON_MEMORY | SYS_ENARX_MEM_INFO => {
+                    sallyport::Result::Err(libc::ENOSYS).into()
+                }
+                _ => unsafe { handler.lock().unwrap().syscall(&req) },
+            };
+
+            ring.done[index].store(1, Ordering::Release);
+
+            let _guard = wait.0.lock().unwrap();
+            wait.1.notify_all();
+        }
+    }
+}
@@ -1,9 +1,14 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::super::Command;
+use super::fault::KeepFault;
+use super::gdb::Stub;
+use super::ring::KVM_RING_DOORBELL_PORT;
+use crate::signal;
 
 use std::sync::{Arc, RwLock};
 
+use abi::kvm::{SIGNAL_PORT_OFFSET, SIGNAL_VECTOR};
 use anyhow::{anyhow, Result};
 use kvm_ioctls::{VcpuExit, VcpuFd};
 use mmarinus::{perms, Kind, Map};
@@ -13,13 +18,20 @@ use sallyport::syscall::{SYS_ENARX_BALLOON_MEMORY, SYS_ENARX_MEM_INFO};
 use sallyport::Block;
 use sallyport::{Request, KVM_SYSCALL_TRIGGER_PORT};
 
+/// The port the shim writes to, to fetch a pending host signal
+const KVM_SIGNAL_PORT: u16 = KVM_SYSCALL_TRIGGER_PORT.wrapping_add(SIGNAL_PORT_OFFSET);
+
 pub struct Thread {
     keep: Arc<RwLock<super::Keep>>,
     vcpu_fd: Option<VcpuFd>,
+    gdb: Option<Stub>,
+    hostcall: bool,
 }
 
 impl Drop for Thread {
     fn drop(&mut self) {
+        unsafe { signal::immediate_exit(std::ptr::null_mut()) };
+
         let vcpu_fd = self.vcpu_fd.take().unwrap();
         self.keep.write().unwrap().cpu_fds.push(vcpu_fd);
     }
@@ -27,13 +39,24 @@ impl Drop for Thread {
 
 impl super::super::Keep for RwLock<super::Keep> {
     fn spawn(self: Arc<Self>) -> Result<Option<Box<dyn super::super::Thread>>> {
-        let cpu_opt = self.write().unwrap().cpu_fds.pop();
+        let (cpu_opt, gdb) = {
+            let mut keep = self.write().unwrap();
+            (keep.cpu_fds.pop(), keep.gdb.take())
+        };
+
         match cpu_opt {
             None => Ok(None),
-            Some(vcpu_fd) => Ok(Some(Box::new(Thread {
-                keep: self,
-                vcpu_fd: Some(vcpu_fd),
-            }))),
+            Some(vcpu_fd) => {
+                // Only the first thread is attached to GDB.
+                let gdb = gdb.as_deref().map(Stub::listen).transpose()?;
+
+                Ok(Some(Box::new(Thread {
+                    keep: self,
+                    vcpu_fd: Some(vcpu_fd),
+                    gdb,
+                    hostcall: false,
+                })))
+            }
         }
     }
 }
@@ -100,8 +123,42 @@ impl Thread {
 impl super::super::Thread for Thread {
     fn enter(&mut self) -> Result<Command> {
         let vcpu_fd = self.vcpu_fd.as_mut().unwrap();
-        match vcpu_fd.run()? {
+
+        if let Some(stub) = self.gdb.as_mut() {
+            if !stub.handle(vcpu_fd, &self.keep)? {
+                self.gdb = None;
+            }
+        }
+
+        // The shim reads a pending host signal from `rdi`, after the port
+        // write of a hostcall returns.
+        if std::mem::take(&mut self.hostcall) {
+            if let Some(signo) = signal::take() {
+                let mut regs = vcpu_fd.get_regs()?;
+                regs.rdi = signo as _;
+                vcpu_fd.set_regs(&regs)?;
+            }
+        }
+
+        // A payload, which makes no hostcalls, is interrupted for a pending
+        // signal, as soon as it can take interrupts. A signal arriving
+        // from now on makes `KVM_RUN` return right away.
+        let run = vcpu_fd.get_kvm_run();
+        run.immediate_exit = 0;
+        unsafe { signal::immediate_exit(&mut run.immediate_exit) };
+        run.request_interrupt_window = signal::pending() as u8;
+
+        let exit = match vcpu_fd.run() {
+            // A host signal interrupted `KVM_RUN`, so the next `enter`
+            // waits for the payload to take it.
+            Err(e) if e.errno() == libc::EINTR => return Ok(Command::Continue),
+            exit => exit?,
+        };
+
+        match exit {
             VcpuExit::IoOut(KVM_SYSCALL_TRIGGER_PORT, data) => {
+                self.hostcall = true;
+
                 debug_assert_eq!(data.len(), 2);
                 let block_nr = data[0] as usize + ((data[1] as usize) << 8);
 
@@ -134,16 +191,90 @@ impl super::super::Thread for Thread {
                 ret
             }
 
-            #[cfg(debug_assertions)]
-            reason => Err(anyhow!(
-                "{:?} {:#x?} {:#x?}",
-                reason,
-                vcpu_fd.get_regs(),
-                vcpu_fd.get_sregs()
-            )),
+            VcpuExit::IoOut(KVM_RING_DOORBELL_PORT, data) => {
+                self.hostcall = true;
+                debug_assert_eq!(data.len(), 2);
+                let block_nr = data[0] as usize + ((data[1] as usize) << 8);
+
+                // Block the vCPU until the ring has serviced the block.
+                let ring = self
+                    .keep
+                    .read()
+                    .unwrap()
+                    .ring
+                    .clone()
+                    .ok_or_else(|| anyhow!("The hostcall ring is not enabled"))?;
+                ring.doorbell(block_nr);
+
+                Ok(Command::Continue)
+            }
+
+            VcpuExit::IrqWindowOpen => {
+                if signal::pending() {
+                    let mut events = vcpu_fd.get_vcpu_events()?;
+                    events.interrupt.injected = 1;
+                    events.interrupt.nr = SIGNAL_VECTOR;
+                    events.interrupt.soft = 0;
+                    vcpu_fd.set_vcpu_events(&events)?;
+                }
+
+                Ok(Command::Continue)
+            }
+
+            // The shim took `SIGNAL_VECTOR` and fetches the signal.
+            VcpuExit::IoOut(KVM_SIGNAL_PORT, _) => {
+                self.hostcall = true;
+                Ok(Command::Continue)
+            }
+
+            VcpuExit::Debug(_) if self.gdb.is_some() => {
+                self.gdb.as_mut().unwrap().stop();
+                Ok(Command::Continue)
+            }
+
+            VcpuExit::Shutdown => {
+                let regs = Box::new(vcpu_fd.get_regs()?);
+                let sregs = Box::new(vcpu_fd.get_sregs()?);
+
+                Err(KeepFault::shutdown(regs, sregs).into())
+            }
+
+            VcpuExit::Hlt => Err(KeepFault::Halt {
+                regs: Box::new(vcpu_fd.get_regs()?),
+            }
+            .into()),
+
+            VcpuExit::IoOut(port, data) => Err(KeepFault::IoOut {
+                port,
+                data: data.to_vec(),
+            }
+            .into()),
+
+            VcpuExit::IoIn(port, data) => Err(KeepFault::IoIn {
+                port,
+                len: data.len(),
+            }
+            .into()),
+
+            VcpuExit::MmioRead(addr, data) => Err(KeepFault::Mmio {
+                addr,
+                write: false,
+                len: data.len(),
+            }
+            .into()),
+
+            VcpuExit::MmioWrite(addr, data) => Err(KeepFault::Mmio {
+                addr,
+                write: true,
+                len: data.len(),
+            }
+            .into()),
+
+            VcpuExit::FailEntry(reason, cpu) => Err(KeepFault::FailEntry { reason, cpu }.into()),
+
+            VcpuExit::InternalError => Err(KeepFault::InternalError.into()),
 
-            #[cfg(not(debug_assertions))]
-            reason => Err(anyhow!("{:?}", reason)),
+            reason => Err(KeepFault::Unexpected(format!("{:?}", reason)).into()),
         }
     }
 }
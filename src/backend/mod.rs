@@ -9,20 +9,24 @@ pub mod sgx;
 mod binary;
 mod probe;
 
-use binary::Binary;
+use binary::{Binary, Notes};
 
 use std::convert::TryFrom;
-use std::sync::Arc;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 use anyhow::{Error, Result};
 use mmarinus::{perms, Map};
 use sallyport::Block;
 
+use crate::cpuid::Policy;
+use crate::handler::Handler;
+
 trait Config: Sized {
     type Flags;
 
     fn flags(flags: u32) -> Self::Flags;
-    fn new(shim: &Binary, exec: &Binary) -> Result<Self>;
+    fn new(shim: &Binary, exec: &Binary, opts: &Options, notes: &mut Notes) -> Result<Self>;
 }
 
 trait Mapper: Sized + TryFrom<Self::Config, Error = Error> {
@@ -38,7 +42,85 @@ trait Mapper: Sized + TryFrom<Self::Config, Error = Error> {
 }
 
 trait Loader: Mapper {
-    fn load(shim: impl AsRef<[u8]>, exec: impl AsRef<[u8]>) -> Result<Self::Output>;
+    fn load(shim: impl AsRef<[u8]>, exec: impl AsRef<[u8]>, opts: &Options)
+        -> Result<Self::Output>;
+}
+
+/// Options for the creation of a keep
+#[derive(Clone, Debug, Default)]
+pub struct Options {
+    /// Service hostcalls via a shared ring instead of exiting the keep for every call
+    pub ring: bool,
+
+    /// The number of sallyport blocks of KVM keeps
+    ///
+    /// This limits the number of hostcalls in flight. Defaults to the
+    /// number the vCPUs of the keep need.
+    pub sallyport_blocks: Option<usize>,
+
+    /// Enable the debug output of the shim
+    ///
+    /// This changes the measurement of the keep.
+    pub debug: bool,
+
+    /// Wait for GDB to connect to this address before running the keep
+    pub gdb: Option<String>,
+
+    /// Executes the syscalls of the keep with the granted files and directories
+    pub handler: Arc<Mutex<Handler>>,
+
+    /// Encrypt the files the keep opens below this path
+    ///
+    /// This changes the measurement of the keep.
+    pub encrypt: Option<String>,
+
+    /// Terminate TLS in the keep on the sockets listening on this port
+    ///
+    /// This changes the measurement of the keep.
+    pub tls: Option<u16>,
+
+    /// Refuse TLS connections, if the certificate can't carry attestation evidence
+    ///
+    /// This changes the measurement of the keep.
+    pub attest: bool,
+
+    /// Reject host time hints moving the wall clock of the keep backwards
+    ///
+    /// This changes the measurement of the keep.
+    pub strict_time: bool,
+
+    /// The CPUID policy of the keep
+    pub cpuid: Policy,
+
+    /// The extended state of SGX keeps: `sse`, `avx` or `avx512`
+    ///
+    /// Defaults to the most the CPU and the host kernel support and the
+    /// CPUID policy exposes.
+    pub xstate: Option<String>,
+
+    /// The memory of SGX keeps for the heap of the payload, like `64M`
+    ///
+    /// This sizes the enclave and changes the measurement of the keep.
+    pub memory: Option<String>,
+
+    /// Add the pages of the heap of SGX keeps on demand with EDMM
+    ///
+    /// This needs SGX2 and changes the measurement of the keep.
+    pub edmm: bool,
+
+    /// A file to map read-only into SGX keeps after the heap
+    pub data: Option<PathBuf>,
+
+    /// Measure the data like the code of the keep
+    ///
+    /// Otherwise only the size of the data is measured, and the payload
+    /// has to verify it.
+    pub measure_data: bool,
+
+    /// A file with the secret to inject into plain KVM keeps
+    ///
+    /// The secret is not measured, like the secret the SEV guest owner injects.
+    pub secret: Option<PathBuf>,
 }
 
 pub trait Backend {
@@ -52,10 +134,10 @@ pub trait Backend {
     fn data(&self) -> Vec<Datum>;
 
     /// Create a keep instance
-    fn keep(&self, shim: &[u8], exec: &[u8]) -> Result<Arc<dyn Keep>>;
+    fn keep(&self, shim: &[u8], exec: &[u8], opts: &Options) -> Result<Arc<dyn Keep>>;
 
     /// Hash the inputs
-    fn hash(&self, shim: &[u8], exec: &[u8]) -> Result<Vec<u8>>;
+    fn hash(&self, shim: &[u8], exec: &[u8], opts: &Options) -> Result<Vec<u8>>;
 
     /// Whether or not the platform has support for this keep type
     fn have(&self) -> bool {
@@ -0,0 +1,164 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The quotes of SGX keeps, created by the quoting enclave of the AESM daemon
+//!
+//! The shim gets a quote in two steps with `SYS_ENARX_GETATT`. Without a
+//! report, the loader returns the `TARGETINFO` of the quoting enclave, so
+//! the shim can create a report for it. With the report, the loader returns
+//! the quote, which a remote party can verify.
+
+use crate::protobuf::aesm_proto::{
+    Request, Request_GetQuoteExRequest, Request_GetQuoteSizeExRequest, Request_InitQuoteExRequest,
+    Request_SelectAttKeyIDRequest, Response,
+};
+
+use std::io::{Read, Write};
+use std::mem::size_of;
+use std::os::unix::net::UnixStream;
+use std::time::Duration;
+
+use primordial::Register;
+use protobuf::Message;
+use sallyport::syscall::SGX_TECH;
+use sallyport::Block;
+
+/// The socket of the AESM daemon
+const AESM_SOCKET: &str = "/var/run/aesmd/aesm.socket";
+
+/// The time the AESM daemon gets to answer
+const AESM_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The size of `TARGETINFO`
+const TARGETINFO_LEN: usize = 512;
+
+/// The size of `REPORT`
+const REPORT_LEN: usize = 432;
+
+/// Send `req` to the AESM daemon and return its response
+///
+/// The messages are prefixed with their length as a little endian `u32`.
+fn aesm(req: Request) -> Result<Response, libc::c_int> {
+    let mut stream = UnixStream::connect(AESM_SOCKET).or(Err(libc::ENOSYS))?;
+    stream
+        .set_read_timeout(Some(AESM_TIMEOUT))
+        .or(Err(libc::EIO))?;
+
+    let req = req.write_to_bytes().or(Err(libc::EIO))?;
+    stream
+        .write_all(&(req.len() as u32).to_le_bytes())
+        .and_then(|_| stream.write_all(&req))
+        .or(Err(libc::EIO))?;
+
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len).or(Err(libc::EIO))?;
+    let mut rep = vec![0u8; u32::from_le_bytes(len) as usize];
+    stream.read_exact(&mut rep).or(Err(libc::EIO))?;
+
+    Response::parse_from_bytes(&rep).or(Err(libc::EIO))
+}
+
+/// Fail with `EIO`, if the AESM daemon reported an error
+fn check(error: u32) -> Result<(), libc::c_int> {
+    match error {
+        0 => Ok(()),
+        _ => Err(libc::EIO),
+    }
+}
+
+/// The attestation key selected by the AESM daemon
+fn att_key_id() -> Result<Vec<u8>, libc::c_int> {
+    let mut req = Request::new();
+    req.set_selectAttKeyIDReq(Request_SelectAttKeyIDRequest::new());
+
+    let mut rep = aesm(req)?.take_selectAttKeyIDRes();
+    check(rep.get_errorCode())?;
+    Ok(rep.take_selected_att_key_id())
+}
+
+/// The `TARGETINFO` of the quoting enclave for `att_key_id`
+fn target_info(att_key_id: &[u8]) -> Result<Vec<u8>, libc::c_int> {
+    // The daemon only returns the `TARGETINFO` with the public key ID,
+    // so its size is asked for first.
+    let mut msg = Request_InitQuoteExRequest::new();
+    msg.set_att_key_id(att_key_id.to_vec());
+    msg.set_b_pub_key_id(false);
+
+    let mut req = Request::new();
+    req.set_initQuoteExReq(msg);
+    let rep = aesm(req)?.take_initQuoteExRes();
+    check(rep.get_errorCode())?;
+
+    let mut msg = Request_InitQuoteExRequest::new();
+    msg.set_att_key_id(att_key_id.to_vec());
+    msg.set_b_pub_key_id(true);
+    msg.set_buf_size(rep.get_pub_key_id_size());
+
+    let mut req = Request::new();
+    req.set_initQuoteExReq(msg);
+    let mut rep = aesm(req)?.take_initQuoteExRes();
+    check(rep.get_errorCode())?;
+    Ok(rep.take_target_info())
+}
+
+/// The quote of `report` with the attestation key `att_key_id`
+fn quote(att_key_id: &[u8], report: &[u8]) -> Result<Vec<u8>, libc::c_int> {
+    let mut msg = Request_GetQuoteSizeExRequest::new();
+    msg.set_att_key_id(att_key_id.to_vec());
+
+    let mut req = Request::new();
+    req.set_getQuoteSizeExReq(msg);
+    let rep = aesm(req)?.take_getQuoteSizeExRes();
+    check(rep.get_errorCode())?;
+
+    let mut msg = Request_GetQuoteExRequest::new();
+    msg.set_report(report.to_vec());
+    msg.set_att_key_id(att_key_id.to_vec());
+    msg.set_buf_size(rep.get_quote_size());
+
+    let mut req = Request::new();
+    req.set_getQuoteExReq(msg);
+    let mut rep = aesm(req)?.take_getQuoteExRes();
+    check(rep.get_errorCode())?;
+    Ok(rep.take_quote())
+}
+
+/// The `len` bytes at `addr`, which must be in `block`
+fn slice(block: &mut Block, addr: usize, len: usize) -> Result<&mut [u8], libc::c_int> {
+    let start = block as *mut Block as usize;
+    let offset = addr.checked_sub(start).ok_or(libc::EFAULT)?;
+    match offset.checked_add(len) {
+        Some(end) if end <= size_of::<Block>() => {
+            Ok(unsafe { std::slice::from_raw_parts_mut(addr as *mut u8, len) })
+        }
+        _ => Err(libc::EFAULT),
+    }
+}
+
+/// Serve `SYS_ENARX_GETATT` of the shim
+///
+/// The arguments are the report and the output buffer in the block. Without
+/// a report, the `TARGETINFO` of the quoting enclave is returned.
+pub fn get_attestation(block: &mut Block) -> Result<[Register<usize>; 2], libc::c_int> {
+    let req = unsafe { block.msg.req };
+    let (report, report_len): (usize, usize) = (req.arg[0].into(), req.arg[1].into());
+    let (buf, buf_len): (usize, usize) = (req.arg[2].into(), req.arg[3].into());
+
+    let att_key_id = att_key_id()?;
+    let data = match report {
+        0 => target_info(&att_key_id)?,
+        _ if report_len != REPORT_LEN => return Err(libc::EINVAL),
+        _ => quote(&att_key_id, slice(block, report, report_len)?)?,
+    };
+
+    if report == 0 && data.len() != TARGETINFO_LEN {
+        return Err(libc::EIO);
+    }
+
+    let buf = slice(block, buf, buf_len)?;
+    if data.len() > buf.len() {
+        return Err(libc::EMSGSIZE);
+    }
+
+    buf[..data.len()].copy_from_slice(&data);
+    Ok([data.len().into(), SGX_TECH.into()])
+}
@@ -5,7 +5,7 @@ use super::ioctls::*;
 
 use std::convert::TryFrom;
 use std::fs::{File, OpenOptions};
-use std::sync::{Arc, RwLock};
+use std::sync::{Arc, Mutex, RwLock};
 
 use anyhow::{Error, Result};
 use mmarinus::{perms, Kind, Map};
@@ -29,6 +29,11 @@ impl TryFrom<super::config::Config> for Builder {
     fn try_from(config: super::config::Config) -> Result<Self> {
         assert!(config.size.is_power_of_two()); // This is verified by `Config`...
 
+        // Only the hasher measures EDMM keeps without SGX2.
+        if config.edmm && !super::edmm::supported() {
+            anyhow::bail!("EDMM needs SGX2 and Linux 6.0 or later");
+        }
+
         // Map the memory for the enclave
         // We map twice as much as we need so that we can naturally align it.
         let map = Map::map(config.size * 2)
@@ -73,19 +78,25 @@ impl super::super::Mapper for Builder {
         &mut self,
         pages: Map<perms::ReadWrite>,
         to: usize,
-        with: (SecInfo, bool),
+        with: (SecInfo, bool, bool),
     ) -> anyhow::Result<()> {
+        // Size the heap.
+        let pages = self.cnfg.prepare(pages, to, with.2)?;
+
         // Ignore regions with no pages.
         if pages.is_empty() {
             return Ok(());
         }
 
-        // Update the enclave.
-        let mut ap = AddPages::new(&*pages, to, &with.0, with.1);
-        ENCLAVE_ADD_PAGES.ioctl(&mut self.file, &mut ap)?;
+        // With EDMM, heap pages are added after EINIT, when the shim accepts them.
+        if !(self.cnfg.edmm && with.2) {
+            // Update the enclave.
+            let mut ap = AddPages::new(&*pages, to, &with.0, with.1);
+            ENCLAVE_ADD_PAGES.ioctl(&mut self.file, &mut ap)?;
 
-        // Update the hasher.
-        self.hash.load(&*pages, to, with.0, with.1).unwrap();
+            // Update the hasher.
+            self.hash.load(&*pages, to, with.0, with.1).unwrap();
+        }
 
         // Save permissions fixups for later.
         let mut addr = self.mmap.addr() + to;
@@ -107,6 +118,12 @@ impl TryFrom<Builder> for Arc<dyn super::super::Keep> {
     type Error = Error;
 
     fn try_from(mut builder: Builder) -> Result<Self> {
+        // Add the data after the segments, readable only.
+        if let Some((pages, to, measure)) = builder.cnfg.data.take() {
+            let with = (SecInfo::reg(Flags::READ), measure, false);
+            super::super::Mapper::map(&mut builder, pages, to, with)?;
+        }
+
         // Create the enclave signature
         let hash = builder.hash.finish();
         let author = Author::new(0, 0);
@@ -153,7 +170,8 @@ impl TryFrom<Builder> for Arc<dyn super::super::Keep> {
         }
 
         Ok(Arc::new(super::Keep {
-            _mem: builder.mmap,
+            mem: builder.mmap,
+            file: Mutex::new(builder.file),
             tcs: RwLock::new(builder.tcsp),
         }))
     }
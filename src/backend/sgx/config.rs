@@ -1,21 +1,192 @@
 // SPDX-License-Identifier: Apache-2.0
 
+use super::super::binary::NOTE_NAME;
+use crate::cpuid::Policy;
+
+use std::arch::x86_64::__cpuid_count;
+use std::convert::TryFrom;
 use std::num::NonZeroU32;
+use std::ops::Range;
 
-use anyhow::{anyhow, Result};
-use goblin::elf::program_header::{PF_R, PF_W, PF_X};
+use anyhow::{anyhow, bail, Context, Result};
+use goblin::elf::program_header::{PF_R, PF_W, PF_X, PT_LOAD};
+use mmarinus::{perms, Kind, Map};
+use primordial::Page;
 use sallyport::elf;
 use sgx::page::{Flags, SecInfo};
-use sgx::parameters::{Masked, Parameters};
+use sgx::parameters::{Attributes, Features, Masked, MiscSelect, Parameters, Xfrm};
+
+/// The ELF note type of the EDMM switch (`u32`, non-zero if enabled)
+pub const NOTE_EDMM: u32 = abi::note::EDMM;
+
+/// The ELF note type of the size of the heap in pages (`u32`)
+pub const NOTE_HEAP: u32 = abi::note::HEAP;
+
+/// The ELF note type of the size of the data in bytes (`u32`)
+pub const NOTE_DATA: u32 = abi::note::DATA;
+
+/// The segment flag of the heap, which is sized for the keep and whose
+/// pages are added on demand, if the enclave uses EDMM
+///
+/// This must match the heap segment in the linker script of the shim.
+const PF_HEAP: u32 = 1 << 23;
+
+/// The size of the heap without `--memory`
+const HEAP_SIZE: usize = 128 << 20;
+
+/// The extended states of the keep and their XFRM
+const XSTATES: &[(&str, u64)] = &[("sse", 0x03), ("avx", 0x07), ("avx512", 0xe7)];
+
+/// The size of the GPR area at the end of an SSA frame
+const SSA_GPR_SIZE: usize = 184;
+
+/// The size of the MISC region before the GPRs, which holds `EXINFO`
+const SSA_MISC_SIZE: usize = 16;
+
+/// The size of the legacy area and the header of the XSAVE area
+const XSAVE_LEGACY_SIZE: usize = 576;
+
+/// The XFRM, which the CPU allows for enclaves and the host kernel enabled
+fn supported_xfrm() -> u64 {
+    let sgx = unsafe { __cpuid_count(0x12, 1) };
+    let sgx = u64::from(sgx.edx) << 32 | u64::from(sgx.ecx);
+
+    let (eax, edx): (u32, u32);
+    unsafe { asm!("xgetbv", in("ecx") 0u32, out("eax") eax, out("edx") edx) };
+
+    sgx & (u64::from(edx) << 32 | u64::from(eax))
+}
+
+/// The `cpuid` answer of the loader for `leaf` and `subleaf` with the CPUID `policy`
+fn cpuid(policy: &Policy, leaf: u32, subleaf: u32) -> [u64; 4] {
+    let host = unsafe { __cpuid_count(leaf, subleaf) };
+    let [eax, ebx, ecx, edx] =
+        policy.apply(leaf, subleaf, [host.eax, host.ebx, host.ecx, host.edx]);
+    [eax.into(), ebx.into(), ecx.into(), edx.into()]
+}
+
+/// Choose the XFRM of the enclave for the extended state `xstate`
+///
+/// Without `xstate`, it is the most the CPU supports and the CPUID `policy`
+/// exposes.
+fn xfrm(xstate: Option<&str>, policy: &Policy) -> Result<u64> {
+    let supported = supported_xfrm();
+    let [eax, _, _, edx] = cpuid(policy, 0xd, 0);
+    let exposed = edx << 32 | eax;
+
+    let name = match xstate {
+        Some(name) => name,
+        None => XSTATES
+            .iter()
+            .rev()
+            .find(|(_, xfrm)| supported & exposed & xfrm == *xfrm)
+            .map_or("sse", |(name, _)| name),
+    };
+
+    match XSTATES.iter().find(|(n, _)| *n == name) {
+        None => bail!("Unknown extended state {:?}", name),
+        Some((_, xfrm)) if supported & xfrm != *xfrm => {
+            bail!(
+                "The CPU does not support the extended state {:?} in enclaves",
+                name
+            )
+        }
+        Some((_, xfrm)) => Ok(*xfrm),
+    }
+}
+
+/// Check that the CPUID `policy` doesn't contradict what the shim knows about the enclave with `xfrm`
+///
+/// The shim terminates the enclave on such `cpuid` answers, see `abi::cpuid`.
+fn check_cpuid(policy: &Policy, xfrm: u64) -> Result<()> {
+    for &(leaf, subleaf) in abi::cpuid::VERIFIED {
+        let regs = cpuid(policy, leaf as _, subleaf as _);
+
+        if abi::cpuid::verify(leaf, subleaf, regs, xfrm).is_none() {
+            bail!(
+                "The CPUID policy answers leaf {:#x}, subleaf {:#x} with {:#010x?}, which the SGX shim refuses for the XFRM {:#x}",
+                leaf,
+                subleaf,
+                regs,
+                xfrm
+            );
+        }
+    }
+
+    Ok(())
+}
+
+/// The size of the XSAVE area in the standard format for `xfrm`
+fn xsave_size(xfrm: u64) -> usize {
+    (2..64)
+        .filter(|i| xfrm & (1 << i) != 0)
+        .map(|i| unsafe { __cpuid_count(0xd, i) })
+        .map(|c| c.ebx as usize + c.eax as usize)
+        .fold(XSAVE_LEGACY_SIZE, usize::max)
+}
+
+/// Parse a memory size in bytes, like `64M`
+fn memory(size: &str) -> Result<usize> {
+    let (number, unit) = match size.char_indices().last() {
+        Some((i, 'K')) => (&size[..i], 1 << 10),
+        Some((i, 'M')) => (&size[..i], 1 << 20),
+        Some((i, 'G')) => (&size[..i], 1 << 30),
+        _ => (size, 1),
+    };
+
+    number
+        .parse::<usize>()
+        .ok()
+        .and_then(|n| n.checked_mul(unit))
+        .filter(|bytes| *bytes > 0 && bytes % Page::SIZE == 0)
+        .ok_or_else(|| anyhow!("Invalid memory size {:?}", size))
+}
+
+fn attributes([features, xfrm]: [u64; 2]) -> Result<Attributes> {
+    let features = Features::from_bits(features).ok_or_else(|| anyhow!("Invalid SGX features"))?;
+    let xfrm = Xfrm::from_bits(xfrm).ok_or_else(|| anyhow!("Invalid SGX XFRM"))?;
+    Ok(Attributes::new(features, xfrm))
+}
 
 pub struct Config {
     pub parameters: Parameters,
     pub ssap: NonZeroU32,
     pub size: usize,
+
+    /// The heap of the shim, sized for the keep
+    pub heap: Range<usize>,
+
+    /// Whether the pages of the heap are added on demand with EDMM
+    pub edmm: bool,
+
+    /// The pages of the data, their shim address and whether they are measured
+    pub data: Option<(Map<perms::ReadWrite>, usize, bool)>,
+}
+
+impl Config {
+    /// Prepare the pages of a segment mapped to `to` for the enclave
+    ///
+    /// This cuts the heap to its size.
+    pub fn prepare(
+        &self,
+        mut pages: Map<perms::ReadWrite>,
+        to: usize,
+        heap: bool,
+    ) -> Result<Map<perms::ReadWrite>> {
+        let size = self.heap.end.saturating_sub(to);
+        if heap && size < pages.size() {
+            let (head, _) = pages.split(size)?;
+            pages = head;
+        }
+
+        Ok(pages)
+    }
 }
 
 impl super::super::Config for Config {
-    type Flags = (SecInfo, bool);
+    /// The page permissions, whether the pages are measured and whether
+    /// they are the heap
+    type Flags = (SecInfo, bool, bool);
 
     fn flags(flags: u32) -> Self::Flags {
         let mut rwx = Flags::empty();
@@ -35,27 +206,74 @@ impl super::super::Config for Config {
             _ => SecInfo::tcs(),
         };
 
-        (si, m)
+        let h = flags & PF_HEAP != 0;
+
+        (si, m, h)
     }
 
-    fn new(shim: &super::super::Binary, _exec: &super::super::Binary) -> Result<Self> {
+    fn new(
+        shim: &super::super::Binary,
+        _exec: &super::super::Binary,
+        opts: &super::super::Options,
+        notes: &mut super::super::Notes,
+    ) -> Result<Self> {
+        if opts.ring {
+            return Err(anyhow!(
+                "The sgx backend does not support the hostcall ring"
+            ));
+        }
+
+        if opts.sallyport_blocks.is_some() {
+            return Err(anyhow!(
+                "The sgx backend does not support setting the number of sallyport blocks"
+            ));
+        }
+
+        if opts.gdb.is_some() {
+            return Err(anyhow!(
+                "The sgx backend does not support debugging with GDB"
+            ));
+        }
+
+        if opts.secret.is_some() {
+            return Err(anyhow!(
+                "The sgx backend does not support injecting a secret"
+            ));
+        }
+
         unsafe {
+            // The shim has the minimal attributes, the XFRM is chosen here.
+            let attr: [u64; 2] = shim
+                .note(elf::note::NAME, elf::note::sgx::ATTR)
+                .ok_or_else(|| anyhow!("SGX shim is missing ATTR"))?;
+            let mask: [u64; 2] = shim
+                .note(elf::note::NAME, elf::note::sgx::ATTRMASK)
+                .ok_or_else(|| anyhow!("SGX shim is missing ATTRMASK"))?;
+            let xfrm = attr[1] | xfrm(opts.xstate.as_deref(), &opts.cpuid)?;
+            check_cpuid(&opts.cpuid, xfrm)?;
+
+            // The shim relies on the CPU reporting the exceptions in the SSA.
+            let misc: MiscSelect = shim
+                .note(elf::note::NAME, elf::note::sgx::MISC)
+                .ok_or_else(|| anyhow!("SGX shim is missing MISC"))?;
+            let supported = MiscSelect::from_bits_truncate(__cpuid_count(0x12, 0).ebx);
+            if !supported.contains(misc) {
+                bail!(
+                    "The CPU does not support the MISCSELECT {:?} of the SGX shim",
+                    misc - supported
+                );
+            }
+
             let params: Parameters = Parameters {
                 misc: Masked {
-                    data: shim
-                        .note(elf::note::NAME, elf::note::sgx::MISC)
-                        .ok_or_else(|| anyhow!("SGX shim is missing MISC"))?,
+                    data: misc,
                     mask: shim
                         .note(elf::note::NAME, elf::note::sgx::MISCMASK)
                         .ok_or_else(|| anyhow!("SGX shim is missing MISCMASK"))?,
                 },
                 attr: Masked {
-                    data: shim
-                        .note(elf::note::NAME, elf::note::sgx::ATTR)
-                        .ok_or_else(|| anyhow!("SGX shim is missing ATTR"))?,
-                    mask: shim
-                        .note(elf::note::NAME, elf::note::sgx::ATTRMASK)
-                        .ok_or_else(|| anyhow!("SGX shim is missing ATTRMASK"))?,
+                    data: attributes([attr[0], xfrm])?,
+                    mask: attributes([mask[0], mask[1] | xfrm])?,
                 },
                 pid: shim
                     .note(elf::note::NAME, elf::note::sgx::PID)
@@ -71,14 +289,99 @@ impl super::super::Config for Config {
             let ssap =
                 NonZeroU32::new(ssap.into()).ok_or_else(|| anyhow!("SGX shim SSAP is invalid"))?;
 
+            // An SSA frame holds the extended state, the MISC region and the GPRs.
+            let ssa_size = xsave_size(xfrm) + SSA_MISC_SIZE + SSA_GPR_SIZE;
+            if ssa_size > ssap.get() as usize * Page::SIZE {
+                bail!(
+                    "The SSA frames of the SGX shim are too small for the XFRM {:#x}",
+                    xfrm
+                );
+            }
+
+            // The shim supports enclaves up to this size.
             let bits: u8 = shim
                 .note(elf::note::NAME, elf::note::sgx::BITS)
                 .ok_or_else(|| anyhow!("SGX shim is missing BITS"))?;
 
+            let segment = shim
+                .headers(PT_LOAD)
+                .find(|phdr| phdr.p_flags & PF_HEAP != 0)
+                .map(|phdr| phdr.vm_range())
+                .ok_or_else(|| anyhow!("SGX shim is missing the heap"))?;
+
+            // A shim, which can't be told the size of its heap, gets all of it.
+            let heap = match shim.note_addr(NOTE_NAME, NOTE_HEAP) {
+                None => segment,
+                Some(_) => {
+                    let size = match opts.memory.as_deref() {
+                        None => HEAP_SIZE.min(segment.end - segment.start),
+                        Some(size) => memory(size)?,
+                    };
+
+                    if size > segment.end - segment.start {
+                        bail!(
+                            "The memory of the keep is limited to {} MiB",
+                            (segment.end - segment.start) >> 20
+                        );
+                    }
+
+                    notes
+                        .set(shim, NOTE_HEAP, &((size / Page::SIZE) as u32).to_ne_bytes())
+                        .ok_or_else(|| anyhow!("SGX shim has an invalid HEAP"))?;
+                    segment.start..segment.start + size
+                }
+            };
+
+            // The data follows the heap, so the shim only needs its size.
+            let data = match &opts.data {
+                None => None,
+                Some(path) => {
+                    let bytes = std::fs::read(path)
+                        .with_context(|| format!("Unable to read the data {:?}", path))?;
+                    let len = u32::try_from(bytes.len())
+                        .ok()
+                        .filter(|len| *len > 0)
+                        .ok_or_else(|| anyhow!("The data {:?} is empty or too large", path))?;
+                    notes
+                        .set(shim, NOTE_DATA, &len.to_ne_bytes())
+                        .ok_or_else(|| anyhow!("SGX shim does not support data pages"))?;
+
+                    let size = (bytes.len() + Page::SIZE - 1) / Page::SIZE * Page::SIZE;
+                    let mut pages = Map::map(size)
+                        .anywhere()
+                        .anonymously()
+                        .known::<perms::ReadWrite>(Kind::Private)?;
+                    pages[..bytes.len()].copy_from_slice(&bytes);
+
+                    Some((pages, heap.end, opts.measure_data))
+                }
+            };
+
+            // The enclave is as small as possible, which saves EPC.
+            let end = match &data {
+                None => heap.end,
+                Some((pages, addr, _)) => addr + pages.size(),
+            };
+            let (size, max) = (end.next_power_of_two(), 1usize << bits);
+            if size > max {
+                bail!("The SGX shim is limited to enclaves of {} MiB", max >> 20);
+            }
+
+            // With EDMM, the shim adds the pages of its heap on demand.
+            // It is an option of the keep, so the measurement doesn't depend on the host.
+            if opts.edmm {
+                notes
+                    .set(shim, NOTE_EDMM, &1u32.to_ne_bytes())
+                    .ok_or_else(|| anyhow!("SGX shim does not support EDMM"))?;
+            }
+
             Ok(Self {
                 parameters: params,
-                size: 1 << bits,
+                size,
                 ssap,
+                heap,
+                edmm: opts.edmm,
+                data,
             })
         }
     }
@@ -0,0 +1,109 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Dynamic memory management of SGX2 enclaves (EDMM)
+//!
+//! With EDMM, the heap of the shim is not added before EINIT. The kernel
+//! adds its pages on demand, when the shim accepts them. Restricting the
+//! permissions of pages and removing pages needs the kernel, so the shim
+//! asks the loader for it. The shim accepts every change, so the host
+//! can't change the memory of the enclave behind its back.
+
+use super::ioctls::*;
+
+use std::arch::x86_64::__cpuid_count;
+use std::ffi::CStr;
+
+use primordial::{Page, Register};
+use sallyport::Request;
+
+/// Restrict the permissions of pages
+///
+/// Arguments: the address and the length of the pages and the SECINFO permissions.
+pub const SYS_ENARX_RESTRICT: i64 = 0xEA11;
+
+/// Change the type of pages to trimmed pages
+///
+/// Arguments: the address and the length of the pages.
+pub const SYS_ENARX_TRIM: i64 = 0xEA12;
+
+/// Remove trimmed pages
+///
+/// Arguments: the address and the length of the pages.
+pub const SYS_ENARX_REMOVE: i64 = 0xEA13;
+
+/// The SGX page type of trimmed pages
+const PT_TRIM: u64 = 4;
+
+/// The release of the host kernel as major and minor version
+fn kernel() -> (u32, u32) {
+    let mut uts: libc::utsname = unsafe { std::mem::zeroed() };
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return (0, 0);
+    }
+
+    let release = unsafe { CStr::from_ptr(uts.release.as_ptr()) }.to_string_lossy();
+    let mut version = release
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|v| v.parse().unwrap_or(0));
+
+    (version.next().unwrap_or(0), version.next().unwrap_or(0))
+}
+
+/// Whether the CPU has SGX2 and the kernel has the EDMM ioctls of Linux 6.0
+pub fn supported() -> bool {
+    let sgx2 = unsafe { __cpuid_count(0x12, 0) }.eax & (1 << 1) != 0;
+    sgx2 && kernel() >= (6, 0)
+}
+
+impl super::Keep {
+    /// Serve an EDMM request of the shim
+    pub fn edmm(&self, req: &Request) -> Result<[Register<usize>; 2], libc::c_int> {
+        let addr: usize = req.arg[0].into();
+        let len: usize = req.arg[1].into();
+        let permissions: usize = req.arg[2].into();
+
+        let offset = addr.checked_sub(self.mem.addr()).ok_or(libc::EINVAL)?;
+        let end = offset.checked_add(len).ok_or(libc::EINVAL)?;
+        if end > self.mem.size() || offset % Page::SIZE != 0 || len % Page::SIZE != 0 {
+            return Err(libc::EINVAL);
+        }
+
+        let mut file = self.file.lock().unwrap();
+        let mut done = 0;
+
+        // The kernel might only process a part of the pages, then it is asked again.
+        while done < len {
+            let (offset, len) = (offset + done, len - done);
+
+            let (ret, count) = match i64::from(req.num) {
+                SYS_ENARX_RESTRICT => {
+                    let mut arg = RestrictPermissions::new(offset, len, permissions as _);
+                    let ret = ENCLAVE_RESTRICT_PERMISSIONS.ioctl(&mut *file, &mut arg);
+                    (ret, arg.count())
+                }
+
+                SYS_ENARX_TRIM => {
+                    let mut arg = ModifyTypes::new(offset, len, PT_TRIM);
+                    let ret = ENCLAVE_MODIFY_TYPES.ioctl(&mut *file, &mut arg);
+                    (ret, arg.count())
+                }
+
+                SYS_ENARX_REMOVE => {
+                    let mut arg = RemovePages::new(offset, len);
+                    let ret = ENCLAVE_REMOVE_PAGES.ioctl(&mut *file, &mut arg);
+                    (ret, arg.count())
+                }
+
+                _ => return Err(libc::ENOSYS),
+            };
+
+            match ret.map_err(|e| e.raw_os_error().unwrap_or(libc::EIO)) {
+                Ok(_) => break,
+                Err(libc::EAGAIN) | Err(libc::EINTR) => done += count as usize,
+                Err(e) => return Err(e),
+            }
+        }
+
+        Ok([0.into(), 0.into()])
+    }
+}
@@ -3,16 +3,22 @@
 use std::convert::TryFrom;
 
 use anyhow::{Error, Result};
-use sgx::page::SecInfo;
+use sgx::page::{Flags, SecInfo};
 
-pub struct Hasher(sgx::signature::Hasher<sgx::crypto::openssl::S256Digest>);
+pub struct Hasher {
+    hash: sgx::signature::Hasher<sgx::crypto::openssl::S256Digest>,
+    cnfg: super::config::Config,
+}
 
 impl TryFrom<super::config::Config> for Hasher {
     type Error = Error;
 
     #[inline]
     fn try_from(config: super::config::Config) -> Result<Self> {
-        Ok(Self(sgx::signature::Hasher::new(config.size, config.ssap)))
+        Ok(Self {
+            hash: sgx::signature::Hasher::new(config.size, config.ssap),
+            cnfg: config,
+        })
     }
 }
 
@@ -25,9 +31,16 @@ impl super::super::Mapper for Hasher {
         &mut self,
         pages: mmarinus::Map<mmarinus::perms::ReadWrite>,
         to: usize,
-        with: (SecInfo, bool),
+        with: (SecInfo, bool, bool),
     ) -> anyhow::Result<()> {
-        self.0.load(&*pages, to, with.0, with.1).unwrap();
+        // Prepare the pages like the builder, so the hash matches.
+        let pages = self.cnfg.prepare(pages, to, with.2)?;
+
+        // Heap pages are not part of the measurement with EDMM.
+        if !(self.cnfg.edmm && with.2) {
+            self.hash.load(&*pages, to, with.0, with.1).unwrap();
+        }
+
         Ok(())
     }
 }
@@ -36,7 +49,13 @@ impl TryFrom<Hasher> for Vec<u8> {
     type Error = Error;
 
     #[inline]
-    fn try_from(hasher: Hasher) -> Result<Self> {
-        Ok(hasher.0.finish().into())
+    fn try_from(mut hasher: Hasher) -> Result<Self> {
+        // Hash the data like the builder adds it.
+        if let Some((pages, to, measure)) = hasher.cnfg.data.take() {
+            let with = (SecInfo::reg(Flags::READ), measure, false);
+            super::super::Mapper::map(&mut hasher, pages, to, with)?;
+        }
+
+        Ok(hasher.hash.finish().into())
     }
 }
@@ -23,6 +23,16 @@ pub const ENCLAVE_INIT: Ioctl<Write, &Init> = unsafe { SGX.write(0x02) };
 
 //pub const ENCLAVE_SET_ATTRIBUTE: Ioctl<Write, &SetAttribute> = unsafe { SGX.write(0x03) };
 
+/// IOCTL identifier for EMODPR (see Section 41-49), since Linux 6.0
+pub const ENCLAVE_RESTRICT_PERMISSIONS: Ioctl<WriteRead, &RestrictPermissions> =
+    unsafe { SGX.write_read(0x05) };
+
+/// IOCTL identifier for EMODT (see Section 41-51), since Linux 6.0
+pub const ENCLAVE_MODIFY_TYPES: Ioctl<WriteRead, &ModifyTypes> = unsafe { SGX.write_read(0x06) };
+
+/// IOCTL identifier for EREMOVE of trimmed pages (see Section 41-57), since Linux 6.0
+pub const ENCLAVE_REMOVE_PAGES: Ioctl<WriteRead, &RemovePages> = unsafe { SGX.write_read(0x07) };
+
 #[repr(C)]
 #[derive(Debug)]
 /// Struct for creating a new enclave from SECS
@@ -101,3 +111,86 @@ impl<'a> SetAttribute<'a> {
         SetAttribute(fd.as_raw_fd() as _, PhantomData)
     }
 }
+
+#[repr(C)]
+#[derive(Debug)]
+/// Struct for restricting the permissions of enclave pages
+pub struct RestrictPermissions {
+    offset: u64,
+    length: u64,
+    permissions: u64,
+    result: u64,
+    count: u64,
+}
+
+impl RestrictPermissions {
+    /// Restrict the pages at `offset` to the SECINFO `permissions`
+    pub fn new(offset: usize, length: usize, permissions: u64) -> Self {
+        Self {
+            offset: offset as _,
+            length: length as _,
+            permissions,
+            result: 0,
+            count: 0,
+        }
+    }
+
+    /// The number of bytes processed
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+/// Struct for changing the type of enclave pages
+pub struct ModifyTypes {
+    offset: u64,
+    length: u64,
+    page_type: u64,
+    result: u64,
+    count: u64,
+}
+
+impl ModifyTypes {
+    /// Change the pages at `offset` to the page type `page_type`
+    pub fn new(offset: usize, length: usize, page_type: u64) -> Self {
+        Self {
+            offset: offset as _,
+            length: length as _,
+            page_type,
+            result: 0,
+            count: 0,
+        }
+    }
+
+    /// The number of bytes processed
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+#[repr(C)]
+#[derive(Debug)]
+/// Struct for removing trimmed enclave pages
+pub struct RemovePages {
+    offset: u64,
+    length: u64,
+    count: u64,
+}
+
+impl RemovePages {
+    /// Remove the trimmed pages at `offset`
+    pub fn new(offset: usize, length: usize) -> Self {
+        Self {
+            offset: offset as _,
+            length: length as _,
+            count: 0,
+        }
+    }
+
+    /// The number of bytes processed
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
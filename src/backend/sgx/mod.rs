@@ -1,24 +1,28 @@
 // SPDX-License-Identifier: Apache-2.0
 
+mod attestation;
 mod builder;
 mod config;
 mod data;
+mod edmm;
 mod hasher;
 mod ioctls;
 mod thread;
 
-use super::Loader;
+use super::{Loader, Options};
 
 use anyhow::Result;
 use mmarinus::{perms, Map};
 
 use std::arch::x86_64::__cpuid_count;
-use std::sync::{Arc, RwLock};
+use std::fs::File;
+use std::sync::{Arc, Mutex, RwLock};
 
 struct Tcs;
 
 struct Keep {
-    _mem: Map<perms::Unknown>,
+    mem: Map<perms::Unknown>,
+    file: Mutex<File>,
     tcs: RwLock<Vec<*const Tcs>>,
 }
 
@@ -52,12 +56,12 @@ impl crate::backend::Backend for Backend {
     }
 
     #[inline]
-    fn keep(&self, shim: &[u8], exec: &[u8]) -> Result<Arc<dyn super::Keep>> {
-        builder::Builder::load(shim, exec)
+    fn keep(&self, shim: &[u8], exec: &[u8], opts: &Options) -> Result<Arc<dyn super::Keep>> {
+        builder::Builder::load(shim, exec, opts)
     }
 
     #[inline]
-    fn hash(&self, shim: &[u8], exec: &[u8]) -> Result<Vec<u8>> {
-        hasher::Hasher::load(shim, exec)
+    fn hash(&self, shim: &[u8], exec: &[u8], opts: &Options) -> Result<Vec<u8>> {
+        hasher::Hasher::load(shim, exec, opts)
     }
 }
@@ -1,12 +1,17 @@
 // SPDX-License-Identifier: Apache-2.0
 
 use super::super::Command;
+use super::attestation;
+use super::edmm::{SYS_ENARX_REMOVE, SYS_ENARX_RESTRICT, SYS_ENARX_TRIM};
+use crate::signal;
 
+use std::fmt;
 use std::mem::MaybeUninit;
 use std::sync::Arc;
 
-use anyhow::Result;
-use sallyport::{syscall::SYS_ENARX_CPUID, Block};
+use anyhow::{anyhow, Result};
+use sallyport::syscall::{SYS_ENARX_CPUID, SYS_ENARX_GETATT};
+use sallyport::Block;
 use sgx::enclu::{EENTER, EEXIT, ERESUME};
 use sgx::ssa::Vector;
 use vdso::Symbol;
@@ -18,6 +23,55 @@ pub struct Thread {
     block: Block,
     cssa: usize,
     how: usize,
+    exception: Option<Exception>,
+    fault: Option<u64>,
+}
+
+/// The error code bit of page faults caused by the EPCM
+const PF_SGX: u16 = 1 << 15;
+
+/// An exception of the payload, as reported by the kernel
+///
+/// The shim decides how to handle the exception. The loader only reports
+/// it, if the keep exits while handling it.
+#[derive(Copy, Clone, Debug)]
+pub struct Exception {
+    vector: u8,
+    error_code: u16,
+    addr: u64,
+}
+
+impl Exception {
+    fn name(&self) -> &'static str {
+        match self.vector {
+            0 => "divide error (#DE)",
+            1 => "debug exception (#DB)",
+            3 => "breakpoint (#BP)",
+            5 => "bound range exceeded (#BR)",
+            13 => "general protection fault (#GP)",
+            14 => "page fault (#PF)",
+            16 => "x87 floating-point exception (#MF)",
+            17 => "alignment check (#AC)",
+            19 => "SIMD floating-point exception (#XM)",
+            _ => "exception",
+        }
+    }
+}
+
+impl fmt::Display for Exception {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} (vector {})", self.name(), self.vector)?;
+
+        match self.vector {
+            14 => write!(
+                f,
+                " at address {:#x}, error code {:#x}",
+                self.addr, self.error_code
+            ),
+            13 => write!(f, ", error code {:#x}", self.error_code),
+            _ => Ok(()),
+        }
+    }
 }
 
 impl Drop for Thread {
@@ -45,6 +99,8 @@ impl super::super::Keep for super::Keep {
             block: Block::default(),
             cssa: usize::default(),
             how: EENTER,
+            exception: None,
+            fault: None,
         })))
     }
 }
@@ -53,6 +109,25 @@ impl super::super::Thread for Thread {
     fn enter(&mut self) -> Result<Command> {
         let mut run: Run = unsafe { MaybeUninit::zeroed().assume_init() };
         run.tcs = self.tcs as u64;
+
+        // Instead of resuming the payload, enter the shim once more to
+        // deliver a pending host signal. The shim reads it from `r8`.
+        let mut signo = 0;
+        if let (ERESUME, 0) = (self.how, self.cssa) {
+            if let Some(pending) = signal::take() {
+                signo = pending;
+                self.how = EENTER;
+                self.cssa = 1;
+            }
+        }
+
+        // The shim accepts a pending page of its heap, which it touched
+        // while handling an exception. The address is passed in `r8`, too.
+        let r8 = match self.fault.take() {
+            Some(addr) => addr,
+            None => signo as u64,
+        };
+
         let how = self.how;
 
         // The `enclu` instruction consumes `rax`, `rbx` and `rcx`. However,
@@ -78,7 +153,7 @@ impl super::super::Thread for Thread {
                 lateout("rsi") _,
                 lateout("rdx") _,
                 inout("rcx") how => _,
-                lateout("r8") _,
+                inout("r8") r8 => _,
                 lateout("r9") _,
                 inout("r10") &mut run => _,
                 inout("r11") self.vdso => _,
@@ -92,8 +167,39 @@ impl super::super::Thread for Thread {
 
         self.how = match run.function as usize {
             EENTER | ERESUME if run.vector == Vector::InvalidOpcode => EENTER,
+
+            // Let the shim handle any other exception of the payload. The
+            // CPU reports it to the shim in the SSA, so the loader only
+            // keeps it to report it.
+            EENTER | ERESUME if self.cssa == 0 => {
+                self.exception = Some(Exception {
+                    vector: run.vector as u8,
+                    error_code: run.exception_error_code,
+                    addr: run.exception_addr,
+                });
+                EENTER
+            }
+
+            // With EDMM, the kernel adds a page on the first access, but
+            // the shim has to accept it, before it can use it.
+            EENTER | ERESUME
+                if self.cssa == 1
+                    && run.vector == Vector::Page
+                    && run.exception_error_code & PF_SGX != 0 =>
+            {
+                self.fault = Some(run.exception_addr);
+                EENTER
+            }
+
             EEXIT => ERESUME,
-            _ => panic!("Unexpected AEX: {:?}", run.vector),
+
+            _ => {
+                return Err(anyhow!(
+                    "Unexpected AEX in the shim: {:?} at address {:#x}",
+                    run.vector,
+                    run.exception_addr
+                ))
+            }
         };
 
         // Keep track of the CSSA
@@ -106,10 +212,35 @@ impl super::super::Thread for Thread {
             _ => unreachable!(),
         }
 
+        // The payload resumes, so the shim has handled the exception.
+        if self.cssa == 0 {
+            self.exception = None;
+        }
+
         // If we have handled an InvalidOpcode error, evaluate the sallyport.
-        if let (EENTER, ERESUME) = (how, self.how) {
+        if let (EENTER, ERESUME, 0) = (how, self.how, r8) {
             match unsafe { self.block.msg.req }.num.into() {
                 SYS_ENARX_CPUID => return Ok(Command::CpuId(&mut self.block)),
+
+                SYS_ENARX_GETATT => {
+                    self.block.msg.rep = attestation::get_attestation(&mut self.block).into();
+                    return Ok(Command::Continue);
+                }
+
+                SYS_ENARX_RESTRICT | SYS_ENARX_TRIM | SYS_ENARX_REMOVE => {
+                    let req = unsafe { self.block.msg.req };
+                    self.block.msg.rep = self.enclave.edmm(&req).into();
+                    return Ok(Command::Continue);
+                }
+
+                libc::SYS_exit | libc::SYS_exit_group => {
+                    if let Some(exception) = self.exception.take() {
+                        eprintln!("Keep exception: {}", exception);
+                    }
+
+                    return Ok(Command::SysCall(&mut self.block));
+                }
+
                 _ => return Ok(Command::SysCall(&mut self.block)),
             }
         }
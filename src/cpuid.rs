@@ -0,0 +1,421 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The CPUID policy of a keep
+//!
+//! The policy rewrites the results of `cpuid` for the keep, both the ones
+//! the loader serves and the ones KVM serves from the vCPU configuration.
+//! `exec --cpuid` takes a built-in profile or a policy file:
+//!
+//! * `host` passes the results of the host through, the default.
+//! * `x86-64-v2`, `x86-64-v3` and `x86-64-v4` only expose the features of
+//!   the x86-64 microarchitecture level, plus the ones the shims need,
+//!   with a fixed vendor, CPU model, cache hierarchy, topology and brand
+//!   string. The host has to support all of them, so the keep sees the
+//!   same CPU on every host. All leaves the profile doesn't define are
+//!   zero, except for what the platform decides: the SGX leaf 0x12, the
+//!   memory encryption leaf 0x8000001f, the address sizes, the APIC IDs
+//!   and the size of the enabled extended state.
+//!
+//! A policy file has a rule per line, `#` starts a comment:
+//!
+//! ```text
+//! profile x86-64-v2            # start with the rules of a profile
+//! vendor GenuineIntel          # set the vendor string of leaf 0
+//! brand Enarx Virtual CPU      # set the brand string of leaves 0x80000002-4
+//! 0x7:0 ebx and 0x00000129     # mask a register of leaf 7, subleaf 0
+//! 0x1 eax set 0x00060fb1       # set a register of leaf 1, all subleaves
+//! 0x1 ecx or 0x80000000        # set bits of a register
+//! 0x6 eax max 0x4              # limit a register
+//! 0x1 ecx require 0x00100000   # refuse to run, unless the host has the bits
+//! ```
+//!
+//! The rules are applied in order, after a profile zeroed the leaves it
+//! doesn't define.
+//!
+//! The SGX backend refuses policies, whose answers shim-sgx would take for
+//! an attack of the host, like hiding XSAVE or the SGX leaf, see
+//! `abi::cpuid`.
+
+use std::arch::x86_64::__cpuid_count;
+use std::convert::TryInto;
+use std::str::FromStr;
+
+use anyhow::{anyhow, bail, Context, Result};
+
+/// The registers of a `cpuid` result
+const REGISTERS: [&str; 4] = ["eax", "ebx", "ecx", "edx"];
+
+/// The vendor of the profiles, whose cache and topology leaves they define
+const VENDOR: &str = "GenuineIntel";
+
+/// The highest basic leaf of the profiles
+const MAX_LEAF: u32 = 0x14;
+
+/// The highest extended leaf of the profiles, which covers the memory encryption leaf
+const MAX_EXT_LEAF: u32 = 0x8000_001f;
+
+/// The leaves of the profiles the platform decides: the SGX capabilities and
+/// the memory encryption capabilities
+const PLATFORM: &[(u32, Option<u32>)] = &[(0x12, None), (0x8000_001f, None)];
+
+/// The family, model and stepping of the profiles, like QEMU's `qemu64`
+const SIGNATURE: u32 = 0x0006_0fb1;
+
+/// Leaf 1 `ebx` of the profiles: a CLFLUSH line of 64 bytes and a single
+/// logical processor, the APIC ID is left to the platform
+const LEAF1_EBX: u32 = 0x0001_0800;
+
+/// Leaf 1 `ebx` bits, which the platform decides: the APIC ID
+const LEAF1_EBX_PLATFORM: u32 = 0xff00_0000;
+
+/// Leaf 2 of the profiles: descriptor 0xff, the caches are in leaf 4
+const LEAF2: [u32; 4] = [0x0000_ff01, 0, 0, 0];
+
+/// The subleaves of leaf 4 of the profiles: L1d and L1i caches with 32 KiB,
+/// an L2 cache with 1 MiB and an L3 cache with 16 MiB
+const CACHES: [[u32; 4]; 4] = [
+    [0x0000_0121, 0x01c0_003f, 0x0000_003f, 0],
+    [0x0000_0122, 0x01c0_003f, 0x0000_003f, 0],
+    [0x0000_0143, 0x03c0_003f, 0x0000_03ff, 0],
+    [0x0000_0163, 0x03c0_003f, 0x0000_3fff, 0],
+];
+
+/// `eax`, `ebx` and `ecx` of the subleaves of leaf 0xb of the profiles: a
+/// single thread and core per package, the x2APIC ID is left to the platform
+const TOPOLOGY: [[u32; 3]; 2] = [[0, 1, 0x0000_0100], [0, 1, 0x0000_0201]];
+
+/// The extended state components of the profiles, with their size and
+/// offset in the standard format of the XSAVE area
+const XSTATE: &[(u32, u32, u32)] = &[
+    (2, 256, 576),
+    (5, 64, 1088),
+    (6, 512, 1152),
+    (7, 1024, 1664),
+];
+
+/// The size of the legacy area and the header of the XSAVE area
+const XSAVE_LEGACY_SIZE: u32 = 576;
+
+/// Leaf 0x80000006 `ecx` of the profiles: the L2 cache of leaf 4
+const EXT6_ECX: u32 = 0x0400_8040;
+
+/// The features of leaf 1 `edx`, which every x86-64 CPU has
+const LEAF1_EDX: u32 = 0x078b_fbff;
+
+/// The features of leaf 1 `ecx` of the profiles, which every host of a keep
+/// has: PCLMULQDQ, AES, XSAVE and RDRAND
+const LEAF1_ECX: u32 = 0x4600_0002;
+
+/// The features of leaf 1 `ecx`, which the platform decides: x2APIC, the
+/// TSC deadline timer, OSXSAVE and the hypervisor bit
+const LEAF1_ECX_PLATFORM: u32 = 0x8920_0000;
+
+/// The features of leaf 7 `ebx` of the profiles, which the shims need: FSGSBASE
+const LEAF7_EBX: u32 = 0x0000_0001;
+
+/// The features of leaf 0x80000001 `edx` of the profiles: SYSCALL, NX,
+/// 1 GiB pages, RDTSCP and long mode
+const EXT1_EDX: u32 = 0x2c10_0800;
+
+/// The features of the profiles in leaf 1 `ecx`, leaf 7 `ebx`, leaf 0xd `eax`
+/// and leaf 0x80000001 `ecx`
+const PROFILES: &[(&str, [u32; 4])] = &[
+    // SSE3, SSSE3, CX16, SSE4.1, SSE4.2, POPCNT and LAHF
+    ("x86-64-v2", [0x0098_2201, 0, 0x0000_0003, 0x0000_0001]),
+    // + FMA, MOVBE, AVX, F16C, BMI1, AVX2, BMI2 and LZCNT
+    (
+        "x86-64-v3",
+        [0x3cd8_3201, 0x0000_0128, 0x0000_0007, 0x0000_0021],
+    ),
+    // + AVX512F, AVX512DQ, AVX512CD, AVX512BW and AVX512VL
+    (
+        "x86-64-v4",
+        [0x3cd8_3201, 0xd003_0128, 0x0000_00e7, 0x0000_0021],
+    ),
+];
+
+/// An operation of a rule on a register
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Op {
+    And,
+    Or,
+    Set,
+    Max,
+    Require,
+}
+
+/// A rule for a register of a leaf
+#[derive(Copy, Clone, Debug)]
+struct Rule {
+    leaf: u32,
+    subleaf: Option<u32>,
+    reg: usize,
+    op: Op,
+    value: u32,
+}
+
+impl Rule {
+    fn new(leaf: u32, subleaf: Option<u32>, reg: usize, op: Op, value: u32) -> Self {
+        Self {
+            leaf,
+            subleaf,
+            reg,
+            op,
+            value,
+        }
+    }
+
+    fn matches(&self, leaf: u32, subleaf: u32) -> bool {
+        self.leaf == leaf && self.subleaf.map_or(true, |s| s == subleaf)
+    }
+}
+
+fn number(s: &str) -> Result<u32> {
+    match s.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .with_context(|| format!("Invalid number {:?}", s))
+}
+
+impl FromStr for Rule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let words: Vec<_> = s.split_whitespace().collect();
+        let (leaf, reg, op, value) = match words[..] {
+            [leaf, reg, op, value] => (leaf, reg, op, value),
+            _ => bail!(
+                "Expected LEAF[:SUBLEAF] REGISTER OPERATION VALUE, got {:?}",
+                s
+            ),
+        };
+
+        let (leaf, subleaf) = match leaf.split_once(':') {
+            Some((leaf, subleaf)) => (leaf, Some(number(subleaf)?)),
+            None => (leaf, None),
+        };
+
+        let reg = REGISTERS
+            .iter()
+            .position(|r| *r == reg)
+            .ok_or_else(|| anyhow!("Invalid register {:?}", reg))?;
+
+        let op = match op {
+            "and" => Op::And,
+            "or" => Op::Or,
+            "set" => Op::Set,
+            "max" => Op::Max,
+            "require" => Op::Require,
+            _ => bail!("Invalid operation {:?}", op),
+        };
+
+        Ok(Self::new(number(leaf)?, subleaf, reg, op, number(value)?))
+    }
+}
+
+/// The CPUID policy of a keep
+#[derive(Clone, Debug, Default)]
+pub struct Policy {
+    rules: Vec<Rule>,
+
+    /// The leaves and subleaves the profiles define, all others are zero
+    defined: Vec<(u32, Option<u32>)>,
+}
+
+impl FromStr for Policy {
+    type Err = anyhow::Error;
+
+    /// Load a built-in profile or a policy file
+    fn from_str(s: &str) -> Result<Self> {
+        let mut policy = Self::default();
+
+        if !policy.profile(s) {
+            let text = std::fs::read_to_string(s)
+                .with_context(|| format!("{:?} is neither a CPUID profile nor a file", s))?;
+
+            for (n, line) in text.lines().enumerate() {
+                policy
+                    .parse(line)
+                    .with_context(|| format!("Invalid rule in line {} of {:?}", n + 1, s))?;
+            }
+        }
+
+        Ok(policy)
+    }
+}
+
+impl Policy {
+    /// Add the rules of the built-in profile `name`, returns false if there is none
+    fn profile(&mut self, name: &str) -> bool {
+        if name == "host" {
+            return true;
+        }
+
+        let [leaf1, leaf7, leafd, ext1] = match PROFILES.iter().find(|(n, _)| *n == name) {
+            Some((_, features)) => *features,
+            None => return false,
+        };
+
+        let leaf1 = leaf1 | LEAF1_ECX;
+        let leaf7 = leaf7 | LEAF7_EBX;
+        let xstate = XSTATE.iter().filter(|(i, ..)| leafd & 1 << i != 0);
+        let xsave_size = xstate
+            .clone()
+            .map(|(_, size, offset)| size + offset)
+            .fold(XSAVE_LEGACY_SIZE, u32::max);
+
+        let start = self.rules.len();
+        let rules = [
+            Rule::new(0x0, None, 0, Op::Set, MAX_LEAF),
+            Rule::new(0x1, None, 0, Op::Set, SIGNATURE),
+            Rule::new(0x1, None, 1, Op::And, LEAF1_EBX_PLATFORM),
+            Rule::new(0x1, None, 1, Op::Or, LEAF1_EBX),
+            Rule::new(0x1, None, 2, Op::Require, leaf1),
+            Rule::new(0x1, None, 2, Op::And, leaf1 | LEAF1_ECX_PLATFORM),
+            Rule::new(0x1, None, 3, Op::Require, LEAF1_EDX),
+            Rule::new(0x1, None, 3, Op::And, LEAF1_EDX),
+            Rule::new(0x7, Some(0), 0, Op::Set, 0),
+            Rule::new(0x7, Some(0), 1, Op::Require, leaf7),
+            Rule::new(0x7, Some(0), 1, Op::And, leaf7),
+            Rule::new(0x7, Some(0), 2, Op::And, 0),
+            Rule::new(0x7, Some(0), 3, Op::And, 0),
+            Rule::new(0xd, Some(0), 0, Op::Require, leafd),
+            Rule::new(0xd, Some(0), 0, Op::And, leafd),
+            Rule::new(0xd, Some(0), 2, Op::Set, xsave_size),
+            Rule::new(0xd, Some(0), 3, Op::And, 0),
+            Rule::new(0x8000_0001, None, 0, Op::Set, 0),
+            Rule::new(0x8000_0001, None, 1, Op::Set, 0),
+            Rule::new(0x8000_0001, None, 2, Op::Require, ext1),
+            Rule::new(0x8000_0001, None, 2, Op::And, ext1),
+            Rule::new(0x8000_0001, None, 3, Op::Require, EXT1_EDX),
+            Rule::new(0x8000_0001, None, 3, Op::And, EXT1_EDX),
+            Rule::new(0x8000_0006, None, 2, Op::Set, EXT6_ECX),
+            Rule::new(0x8000_0008, None, 1, Op::Set, 0),
+            Rule::new(0x8000_0008, None, 2, Op::Set, 0),
+            Rule::new(0x8000_0008, None, 3, Op::Set, 0),
+        ];
+
+        self.rules.extend_from_slice(&rules);
+        self.set(0x2, None, &LEAF2);
+        self.set(0x8000_0000, None, &[MAX_EXT_LEAF, 0, 0, 0]);
+
+        for (subleaf, cache) in CACHES.iter().enumerate() {
+            self.set(0x4, Some(subleaf as u32), cache);
+        }
+
+        for (subleaf, level) in TOPOLOGY.iter().enumerate() {
+            self.set(0xb, Some(subleaf as u32), level);
+        }
+
+        for (i, size, offset) in xstate {
+            self.set(0xd, Some(*i), &[*size, *offset, 0, 0]);
+        }
+
+        self.vendor(VENDOR).unwrap();
+        self.brand(&format!("Enarx Virtual CPU ({})", name));
+
+        let defined: Vec<_> = self.rules[start..]
+            .iter()
+            .map(|r| (r.leaf, r.subleaf))
+            .chain(PLATFORM.iter().copied())
+            .collect();
+        self.defined.extend(defined);
+        true
+    }
+
+    /// Set the registers of a leaf, starting with `eax`
+    fn set(&mut self, leaf: u32, subleaf: Option<u32>, regs: &[u32]) {
+        for (reg, value) in regs.iter().enumerate() {
+            self.rules
+                .push(Rule::new(leaf, subleaf, reg, Op::Set, *value));
+        }
+    }
+
+    /// Set the vendor string of leaf 0
+    fn vendor(&mut self, vendor: &str) -> Result<()> {
+        let vendor: [u8; 12] = vendor
+            .as_bytes()
+            .try_into()
+            .map_err(|_| anyhow!("The vendor {:?} is not 12 bytes long", vendor))?;
+
+        // The vendor string is in `ebx`, `edx` and `ecx`.
+        for (reg, chunk) in [1, 3, 2].iter().zip(vendor.chunks(4)) {
+            let value = u32::from_le_bytes(chunk.try_into().unwrap());
+            self.rules.push(Rule::new(0x0, None, *reg, Op::Set, value));
+        }
+
+        Ok(())
+    }
+
+    /// Set the brand string of leaves 0x80000002 to 0x80000004
+    fn brand(&mut self, brand: &str) {
+        let mut bytes = [0u8; 48];
+        let len = brand.len().min(bytes.len() - 1);
+        bytes[..len].copy_from_slice(&brand.as_bytes()[..len]);
+
+        for (i, chunk) in bytes.chunks(4).enumerate() {
+            let value = u32::from_le_bytes(chunk.try_into().unwrap());
+            let leaf = 0x8000_0002 + (i / 4) as u32;
+            self.rules
+                .push(Rule::new(leaf, None, i % 4, Op::Set, value));
+        }
+    }
+
+    /// Parse a line of a policy file
+    fn parse(&mut self, line: &str) -> Result<()> {
+        let line = line.split('#').next().unwrap().trim();
+
+        match line.split_once(char::is_whitespace) {
+            _ if line.is_empty() => (),
+            Some(("profile", name)) if self.profile(name.trim()) => (),
+            Some(("profile", name)) => bail!("Unknown profile {:?}", name.trim()),
+            Some(("vendor", vendor)) => self.vendor(vendor.trim())?,
+            Some(("brand", brand)) => self.brand(brand.trim()),
+            _ => self.rules.push(line.parse()?),
+        }
+
+        Ok(())
+    }
+
+    /// Check that the host has all features the policy requires
+    pub fn check(&self) -> Result<()> {
+        for rule in self.rules.iter().filter(|r| r.op == Op::Require) {
+            let host = unsafe { __cpuid_count(rule.leaf, rule.subleaf.unwrap_or(0)) };
+            let host = [host.eax, host.ebx, host.ecx, host.edx][rule.reg];
+
+            if host & rule.value != rule.value {
+                bail!(
+                    "The host lacks the CPU features {:#010x} in {} of leaf {:#x}, which the CPUID policy requires",
+                    rule.value & !host,
+                    REGISTERS[rule.reg],
+                    rule.leaf
+                );
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Apply the policy to the `cpuid` result `regs` of `leaf` and `subleaf`
+    pub fn apply(&self, leaf: u32, subleaf: u32, mut regs: [u32; 4]) -> [u32; 4] {
+        let defined = |(l, s): &(u32, Option<u32>)| *l == leaf && s.map_or(true, |s| s == subleaf);
+        if !self.defined.is_empty() && !self.defined.iter().any(defined) {
+            regs = [0; 4];
+        }
+
+        for rule in self.rules.iter().filter(|r| r.matches(leaf, subleaf)) {
+            let reg = &mut regs[rule.reg];
+
+            *reg = match rule.op {
+                Op::And => *reg & rule.value,
+                Op::Or => *reg | rule.value,
+                Op::Set => rule.value,
+                Op::Max => (*reg).min(rule.value),
+                Op::Require => *reg,
+            };
+        }
+
+        regs
+    }
+}
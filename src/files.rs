@@ -0,0 +1,730 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The file descriptors and directories granted to a keep
+//!
+//! The keep has its own file descriptor numbers. Every proxied syscall,
+//! which takes a file descriptor, has it translated to the host file
+//! descriptor, before it is executed. File descriptors, which were neither
+//! granted with `--fd` nor created by the keep, are rejected with `EBADF`.
+//! Syscalls, which are not known to take no file descriptor or to take them
+//! in the translated arguments, are rejected with `ENOSYS`.
+//!
+//! `openat` is resolved beneath the directories granted with `--dir`. All
+//! other syscalls taking a host path are rejected with `EACCES`, like
+//! binding or connecting to an `AF_UNIX` socket path.
+//!
+//! Files granted read only, or opened beneath a directory granted read
+//! only, can't be written, and their mode, owner, attributes and size
+//! can't be changed.
+//!
+//! File descriptors passed with `SCM_RIGHTS` are translated, too: the sent
+//! ones to the host file descriptors and the received ones to new file
+//! descriptors of the keep.
+
+use std::collections::BTreeMap;
+use std::ffi::{CStr, CString};
+use std::fs::{File, OpenOptions};
+use std::io::Error;
+use std::mem::size_of;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::OpenOptionsExt;
+use std::os::unix::io::{AsRawFd, IntoRawFd};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::{anyhow, Context, Result};
+use primordial::Register;
+use sallyport::syscall::SYS_ENARX_GETATT;
+use sallyport::{Reply, Request};
+
+/// Only resolve the path beneath the directory
+const RESOLVE_BENEATH: u64 = 0x08;
+
+/// Don't follow magic links like `/proc/self/exe`
+const RESOLVE_NO_MAGICLINKS: u64 = 0x02;
+
+/// `struct open_how` of `openat2`
+#[repr(C)]
+struct OpenHow {
+    flags: u64,
+    mode: u64,
+    resolve: u64,
+}
+
+/// The syscalls taking a host path, which are not resolved beneath the granted directories
+const PATHS: &[libc::c_long] = &[
+    libc::SYS_open,
+    libc::SYS_creat,
+    libc::SYS_stat,
+    libc::SYS_lstat,
+    libc::SYS_access,
+    libc::SYS_readlink,
+    libc::SYS_truncate,
+    libc::SYS_chdir,
+    libc::SYS_rename,
+    libc::SYS_mkdir,
+    libc::SYS_rmdir,
+    libc::SYS_link,
+    libc::SYS_unlink,
+    libc::SYS_symlink,
+    libc::SYS_chmod,
+    libc::SYS_chown,
+    libc::SYS_lchown,
+    libc::SYS_execve,
+    libc::SYS_newfstatat,
+    libc::SYS_faccessat,
+    libc::SYS_readlinkat,
+    libc::SYS_mkdirat,
+    libc::SYS_mknodat,
+    libc::SYS_unlinkat,
+    libc::SYS_renameat,
+    libc::SYS_renameat2,
+    libc::SYS_linkat,
+    libc::SYS_symlinkat,
+    libc::SYS_fchmodat,
+    libc::SYS_fchownat,
+    libc::SYS_utimensat,
+    libc::SYS_openat2,
+];
+
+/// The arguments of a syscall, which are file descriptors
+///
+/// Returns `None` for a syscall, which is not classified, so it is refused.
+fn fd_args(num: libc::c_long) -> Option<&'static [usize]> {
+    Some(match num {
+        libc::SYS_read
+        | libc::SYS_write
+        | libc::SYS_fstat
+        | libc::SYS_lseek
+        | libc::SYS_ioctl
+        | libc::SYS_pread64
+        | libc::SYS_pwrite64
+        | libc::SYS_readv
+        | libc::SYS_writev
+        | libc::SYS_preadv
+        | libc::SYS_pwritev
+        | libc::SYS_preadv2
+        | libc::SYS_pwritev2
+        | libc::SYS_fcntl
+        | libc::SYS_flock
+        | libc::SYS_fsync
+        | libc::SYS_fdatasync
+        | libc::SYS_syncfs
+        | libc::SYS_ftruncate
+        | libc::SYS_fallocate
+        | libc::SYS_readahead
+        | libc::SYS_getdents64
+        | libc::SYS_fchmod
+        | libc::SYS_fchown
+        | libc::SYS_fstatfs
+        | libc::SYS_fadvise64
+        | libc::SYS_sendto
+        | libc::SYS_recvfrom
+        | libc::SYS_sendmsg
+        | libc::SYS_recvmsg
+        | libc::SYS_sendmmsg
+        | libc::SYS_recvmmsg
+        | libc::SYS_shutdown
+        | libc::SYS_bind
+        | libc::SYS_listen
+        | libc::SYS_accept
+        | libc::SYS_accept4
+        | libc::SYS_connect
+        | libc::SYS_getsockname
+        | libc::SYS_getpeername
+        | libc::SYS_setsockopt
+        | libc::SYS_getsockopt
+        | libc::SYS_epoll_wait
+        | libc::SYS_epoll_pwait
+        | libc::SYS_timerfd_settime
+        | libc::SYS_timerfd_gettime => &[0],
+
+        libc::SYS_sendfile | libc::SYS_tee => &[0, 1],
+        libc::SYS_epoll_ctl | libc::SYS_copy_file_range | libc::SYS_splice => &[0, 2],
+
+        // The syscalls taking no file descriptor
+        libc::SYS_socket
+        | libc::SYS_socketpair
+        | libc::SYS_pipe
+        | libc::SYS_pipe2
+        | libc::SYS_epoll_create
+        | libc::SYS_epoll_create1
+        | libc::SYS_eventfd
+        | libc::SYS_eventfd2
+        | libc::SYS_timerfd_create
+        | libc::SYS_memfd_create
+        | libc::SYS_exit
+        | libc::SYS_exit_group
+        | libc::SYS_getpid
+        | libc::SYS_getppid
+        | libc::SYS_gettid
+        | libc::SYS_getuid
+        | libc::SYS_geteuid
+        | libc::SYS_getgid
+        | libc::SYS_getegid
+        | libc::SYS_uname
+        | libc::SYS_getrandom
+        | libc::SYS_clock_gettime
+        | libc::SYS_clock_getres
+        | libc::SYS_gettimeofday
+        | libc::SYS_nanosleep
+        | libc::SYS_clock_nanosleep
+        | libc::SYS_sched_yield
+        | SYS_ENARX_GETATT => &[],
+
+        _ => return None,
+    })
+}
+
+/// The error of a syscall changing the file of its file descriptor in argument 0
+///
+/// Returns `None` for a syscall, which is allowed on read-only file
+/// descriptors. Opening the files without write access only stops the
+/// writes on the host, so changes of the mode, the owner, the attributes
+/// and the size of a read-only file are refused here.
+fn changes(num: libc::c_long) -> Option<libc::c_int> {
+    match num {
+        libc::SYS_fchmod | libc::SYS_fchown | libc::SYS_ioctl => Some(libc::EROFS),
+        libc::SYS_fallocate | libc::SYS_ftruncate => Some(libc::EBADF),
+        _ => None,
+    }
+}
+
+/// Refuse an `AF_UNIX` socket address with a path, which is a host path
+///
+/// Unnamed and abstract addresses are allowed.
+unsafe fn unix_path(addr: *const libc::sockaddr, len: libc::socklen_t) -> Result<(), libc::c_int> {
+    let family = size_of::<libc::sa_family_t>();
+    if addr.is_null() || (len as usize) <= family {
+        return Ok(());
+    }
+
+    let kind = (addr as *const libc::sa_family_t).read_unaligned();
+    match (kind as libc::c_int, *(addr as *const u8).add(family)) {
+        (libc::AF_UNIX, first) if first != 0 => Err(libc::EACCES),
+        _ => Ok(()),
+    }
+}
+
+/// The message headers of `sendmsg`, `recvmsg`, `sendmmsg` and `recvmmsg`
+unsafe fn msghdrs(num: libc::c_long, req: &Request) -> Vec<*mut libc::msghdr> {
+    let msg = usize::from(req.arg[1]);
+
+    match num {
+        libc::SYS_sendmsg | libc::SYS_recvmsg => vec![msg as *mut libc::msghdr],
+        libc::SYS_sendmmsg | libc::SYS_recvmmsg => (0..usize::from(req.arg[2]))
+            .map(|i| &mut (*(msg as *mut libc::mmsghdr).add(i)).msg_hdr as *mut _)
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Translate the file descriptors in the `SCM_RIGHTS` control messages of `msg` with `f`
+unsafe fn rights(
+    msg: *mut libc::msghdr,
+    mut f: impl FnMut(libc::c_int) -> Result<libc::c_int, libc::c_int>,
+) -> Result<(), libc::c_int> {
+    let mut cmsg = libc::CMSG_FIRSTHDR(msg);
+
+    while !cmsg.is_null() {
+        if (*cmsg).cmsg_level == libc::SOL_SOCKET && (*cmsg).cmsg_type == libc::SCM_RIGHTS {
+            let data = libc::CMSG_DATA(cmsg) as *mut libc::c_int;
+            let len = (*cmsg).cmsg_len as usize - libc::CMSG_LEN(0) as usize;
+
+            for i in 0..len / size_of::<libc::c_int>() {
+                let fd = data.add(i);
+                fd.write_unaligned(f(fd.read_unaligned())?);
+            }
+        }
+
+        cmsg = libc::CMSG_NXTHDR(msg, cmsg);
+    }
+
+    Ok(())
+}
+
+/// How a granted file or directory may be accessed
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Access {
+    /// Read only
+    ReadOnly,
+
+    /// Read and write
+    ReadWrite,
+}
+
+/// Split `path:ro` or `path:rw`, defaulting to read only
+fn access(s: &str) -> (&str, Access) {
+    match s.rsplit_once(':') {
+        Some((path, "ro")) => (path, Access::ReadOnly),
+        Some((path, "rw")) => (path, Access::ReadWrite),
+        _ => (s, Access::ReadOnly),
+    }
+}
+
+/// A host file opened as a file descriptor of the keep, like `3=./data.db:ro`
+#[derive(Clone, Debug)]
+pub struct FdGrant {
+    fd: libc::c_int,
+    path: PathBuf,
+    access: Access,
+}
+
+impl FromStr for FdGrant {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (fd, rest) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected FD=PATH[:ro|:rw], got {:?}", s))?;
+        let (path, access) = access(rest);
+
+        Ok(Self {
+            fd: fd.parse().context("Invalid file descriptor")?,
+            path: path.into(),
+            access,
+        })
+    }
+}
+
+/// A host directory mapped into the keep, like `/data=./host/data:rw`
+#[derive(Clone, Debug)]
+pub struct DirGrant {
+    keep: PathBuf,
+    path: PathBuf,
+    access: Access,
+}
+
+impl FromStr for DirGrant {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let (keep, rest) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow!("Expected KEEP_PATH=PATH[:ro|:rw], got {:?}", s))?;
+        let (path, access) = access(rest);
+
+        if !keep.starts_with('/') {
+            anyhow::bail!("The keep path {:?} is not absolute", keep);
+        }
+
+        Ok(Self {
+            keep: keep.into(),
+            path: path.into(),
+            access,
+        })
+    }
+}
+
+/// A file descriptor of the keep
+#[derive(Debug)]
+struct Entry {
+    /// The host file descriptor
+    host: libc::c_int,
+
+    /// The access to the file or directory
+    access: Access,
+
+    /// Whether the file descriptor is a directory, which paths are resolved beneath
+    dir: bool,
+}
+
+impl Entry {
+    /// A file descriptor created by the keep, like a socket
+    fn new(host: libc::c_int) -> Self {
+        Self {
+            host,
+            access: Access::ReadWrite,
+            dir: false,
+        }
+    }
+
+    /// The entry of `host`, a duplicate of this file descriptor
+    fn duplicate(&self, host: libc::c_int) -> Self {
+        Self {
+            host,
+            access: self.access,
+            dir: self.dir,
+        }
+    }
+}
+
+/// A directory mapped into the keep
+#[derive(Debug)]
+struct Dir {
+    keep: PathBuf,
+    host: File,
+    access: Access,
+}
+
+/// The file descriptors and directories of a keep
+#[derive(Debug)]
+pub struct Files {
+    fds: BTreeMap<libc::c_int, Entry>,
+    dirs: Vec<Dir>,
+}
+
+impl Default for Files {
+    /// Only grant the standard streams
+    fn default() -> Self {
+        let mut fds = BTreeMap::new();
+        for fd in 0..=2 {
+            fds.insert(fd, Entry::new(fd));
+        }
+
+        Self {
+            fds,
+            dirs: Vec::new(),
+        }
+    }
+}
+
+/// Open a directory as a handle to resolve paths beneath it
+fn open_dir(path: &Path) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_PATH | libc::O_DIRECTORY)
+        .open(path)
+}
+
+impl Files {
+    /// Open the granted files and directories
+    pub fn new(fds: &[FdGrant], dirs: &[DirGrant]) -> Result<Self> {
+        let mut files = Self::default();
+
+        for grant in fds {
+            if grant.fd < 0 {
+                anyhow::bail!("Invalid file descriptor {}", grant.fd);
+            }
+
+            let err = || format!("Failed to open {:?}", grant.path);
+
+            // Unlike the handles of `--dir`, the keep can list the directory.
+            let entry = match grant.path.is_dir() {
+                true => Entry {
+                    host: OpenOptions::new()
+                        .read(true)
+                        .custom_flags(libc::O_DIRECTORY)
+                        .open(&grant.path)
+                        .with_context(err)?
+                        .into_raw_fd(),
+                    access: grant.access,
+                    dir: true,
+                },
+
+                false => Entry {
+                    host: OpenOptions::new()
+                        .read(true)
+                        .write(grant.access == Access::ReadWrite)
+                        .open(&grant.path)
+                        .with_context(err)?
+                        .into_raw_fd(),
+                    access: grant.access,
+                    dir: false,
+                },
+            };
+
+            files.fds.insert(grant.fd, entry);
+        }
+
+        for grant in dirs {
+            let host = open_dir(&grant.path)
+                .with_context(|| format!("Failed to open the directory {:?}", grant.path))?;
+
+            files.dirs.push(Dir {
+                keep: grant.keep.clone(),
+                host,
+                access: grant.access,
+            });
+        }
+
+        Ok(files)
+    }
+
+    /// Execute a proxied syscall of the keep with the host file descriptors
+    ///
+    /// # Safety
+    ///
+    /// Like `Request::syscall()`, this executes an arbitrary syscall.
+    pub unsafe fn syscall(&mut self, req: &Request) -> Reply {
+        self.service(*req).into()
+    }
+
+    unsafe fn service(&mut self, mut req: Request) -> sallyport::Result {
+        let num = i64::from(req.num);
+
+        match num {
+            libc::SYS_openat => return self.openat(&req),
+            libc::SYS_close => return self.close(Self::keep_fd(&req, 0)),
+            libc::SYS_dup | libc::SYS_dup2 | libc::SYS_dup3 => return self.dup(num, &req),
+            libc::SYS_poll | libc::SYS_ppoll => return self.poll(&req),
+            n if PATHS.contains(&n) => return Err(libc::EACCES),
+            _ => {}
+        }
+
+        if let Some(err) = changes(num) {
+            let entry = self.fds.get(&Self::keep_fd(&req, 0)).ok_or(libc::EBADF)?;
+            if entry.access == Access::ReadOnly {
+                return Err(err);
+            }
+        }
+
+        for &i in fd_args(num).ok_or(libc::ENOSYS)? {
+            req.arg[i] = (self.host(Self::keep_fd(&req, i))? as usize).into();
+        }
+
+        let arg = |i: usize| usize::from(req.arg[i]);
+        match num {
+            libc::SYS_bind | libc::SYS_connect => unix_path(arg(1) as _, arg(2) as _)?,
+            libc::SYS_sendto => unix_path(arg(4) as _, arg(5) as _)?,
+            _ => {}
+        }
+
+        let msgs = msghdrs(num, &req);
+        if num == libc::SYS_sendmsg || num == libc::SYS_sendmmsg {
+            for &msg in &msgs {
+                unix_path((*msg).msg_name as _, (*msg).msg_namelen)?;
+                rights(msg, |fd| self.host(fd))?;
+            }
+        }
+
+        let [rax, rdx] = sallyport::Result::from(req.syscall())?;
+        let host = usize::from(rax) as libc::c_int;
+
+        if num == libc::SYS_recvmsg || num == libc::SYS_recvmmsg {
+            let received = match num {
+                libc::SYS_recvmsg => 1,
+                _ => usize::from(rax),
+            };
+
+            for &msg in msgs.iter().take(received) {
+                rights(msg, |host| Ok(self.insert(0, Entry::new(host))))?;
+            }
+        }
+
+        let fd = match num {
+            libc::SYS_socket
+            | libc::SYS_accept
+            | libc::SYS_accept4
+            | libc::SYS_epoll_create
+            | libc::SYS_epoll_create1
+            | libc::SYS_eventfd
+            | libc::SYS_eventfd2
+            | libc::SYS_timerfd_create
+            | libc::SYS_memfd_create => self.insert(0, Entry::new(host)),
+
+            libc::SYS_fcntl => match usize::from(req.arg[1]) as libc::c_int {
+                libc::F_DUPFD | libc::F_DUPFD_CLOEXEC => {
+                    // The file descriptor was translated, so it has an entry.
+                    let entry = self.fds[&Self::keep_fd(&req, 0)].duplicate(host);
+                    self.insert(Self::keep_fd(&req, 2), entry)
+                }
+                _ => return Ok([rax, rdx]),
+            },
+
+            libc::SYS_pipe | libc::SYS_pipe2 => return self.pair(&req, 0, [rax, rdx]),
+            libc::SYS_socketpair => return self.pair(&req, 3, [rax, rdx]),
+
+            _ => return Ok([rax, rdx]),
+        };
+
+        Ok([(fd as usize).into(), rdx])
+    }
+
+    /// The keep file descriptor in argument `i`
+    fn keep_fd(req: &Request, i: usize) -> libc::c_int {
+        usize::from(req.arg[i]) as libc::c_int
+    }
+
+    /// Translate a keep file descriptor to the host file descriptor
+    fn host(&self, fd: libc::c_int) -> Result<libc::c_int, libc::c_int> {
+        self.fds.get(&fd).map(|e| e.host).ok_or(libc::EBADF)
+    }
+
+    /// Add a host file descriptor with the lowest free keep number, starting at `min`
+    fn insert(&mut self, min: libc::c_int, entry: Entry) -> libc::c_int {
+        let mut fd = min.max(0);
+        while self.fds.contains_key(&fd) {
+            fd += 1;
+        }
+
+        self.fds.insert(fd, entry);
+        fd
+    }
+
+    fn close(&mut self, fd: libc::c_int) -> sallyport::Result {
+        let entry = self.fds.remove(&fd).ok_or(libc::EBADF)?;
+
+        match unsafe { libc::close(entry.host) } {
+            0 => Ok(Default::default()),
+            _ => Err(Error::last_os_error().raw_os_error().unwrap_or(libc::EIO)),
+        }
+    }
+
+    /// Duplicate a keep file descriptor on the host and number it like the keep asked for
+    fn dup(&mut self, num: libc::c_long, req: &Request) -> sallyport::Result {
+        let old = Self::keep_fd(req, 0);
+        let entry = self.fds.get(&old).ok_or(libc::EBADF)?;
+
+        if num != libc::SYS_dup && Self::keep_fd(req, 1) == old {
+            return match num {
+                libc::SYS_dup3 => Err(libc::EINVAL),
+                _ => Ok([(old as usize).into(), 0.into()]),
+            };
+        }
+
+        let copy = match unsafe { libc::dup(entry.host) } {
+            -1 => return Err(Error::last_os_error().raw_os_error().unwrap_or(libc::EIO)),
+            host => entry.duplicate(host),
+        };
+
+        let fd = match num {
+            libc::SYS_dup => self.insert(0, copy),
+            _ => {
+                let new = Self::keep_fd(req, 1);
+                if new < 0 {
+                    unsafe { libc::close(copy.host) };
+                    return Err(libc::EBADF);
+                }
+
+                if let Some(entry) = self.fds.insert(new, copy) {
+                    unsafe { libc::close(entry.host) };
+                }
+
+                new
+            }
+        };
+
+        Ok([(fd as usize).into(), 0.into()])
+    }
+
+    /// Number the two host file descriptors written to the array in argument `i`
+    unsafe fn pair(
+        &mut self,
+        req: &Request,
+        i: usize,
+        ret: [Register<usize>; 2],
+    ) -> sallyport::Result {
+        let fds = usize::from(req.arg[i]) as *mut [libc::c_int; 2];
+        let [a, b] = fds.read_unaligned();
+        let a = self.insert(0, Entry::new(a));
+        let b = self.insert(0, Entry::new(b));
+        fds.write_unaligned([a, b]);
+        Ok(ret)
+    }
+
+    /// Poll the `pollfd` array in argument 0 with the host file descriptors
+    ///
+    /// Unknown file descriptors are polled as an invalid host file
+    /// descriptor, which reports `POLLNVAL` like Linux does for them.
+    unsafe fn poll(&mut self, req: &Request) -> sallyport::Result {
+        let fds: &mut [libc::pollfd] = match usize::from(req.arg[1]) {
+            0 => &mut [],
+            n => std::slice::from_raw_parts_mut(usize::from(req.arg[0]) as *mut libc::pollfd, n),
+        };
+
+        let keep: Vec<libc::c_int> = fds.iter().map(|p| p.fd).collect();
+        for p in fds.iter_mut().filter(|p| p.fd >= 0) {
+            p.fd = self.host(p.fd).unwrap_or(libc::c_int::MAX);
+        }
+
+        let ret = sallyport::Result::from(req.syscall());
+
+        for (p, fd) in fds.iter_mut().zip(keep) {
+            p.fd = fd;
+        }
+
+        ret
+    }
+
+    /// Open a path beneath a granted directory
+    unsafe fn openat(&mut self, req: &Request) -> sallyport::Result {
+        let dirfd = Self::keep_fd(req, 0);
+        let ptr = usize::from(req.arg[1]) as *const libc::c_char;
+        let flags = usize::from(req.arg[2]) as libc::c_int;
+        let mode = usize::from(req.arg[3]) as libc::mode_t;
+
+        if ptr.is_null() {
+            return Err(libc::EFAULT);
+        }
+
+        let len = libc::strnlen(ptr, libc::PATH_MAX as usize);
+        if len == libc::PATH_MAX as usize {
+            return Err(libc::ENAMETOOLONG);
+        }
+
+        let path = Path::new(std::ffi::OsStr::from_bytes(CStr::from_ptr(ptr).to_bytes()));
+
+        let (root, rest, access) = match (path.is_absolute(), dirfd) {
+            (false, fd) if fd != libc::AT_FDCWD => {
+                let entry = self.fds.get(&fd).ok_or(libc::EBADF)?;
+                if !entry.dir {
+                    return Err(libc::ENOTDIR);
+                }
+
+                (entry.host, path.to_path_buf(), entry.access)
+            }
+
+            // The working directory of the keep is `/`.
+            _ => {
+                let path = Path::new("/").join(path);
+                let dir = self
+                    .dirs
+                    .iter()
+                    .filter(|d| path.starts_with(&d.keep))
+                    .max_by_key(|d| d.keep.components().count())
+                    .ok_or(libc::ENOENT)?;
+
+                let rest = path.strip_prefix(&dir.keep).unwrap();
+                (dir.host.as_raw_fd(), rest.to_path_buf(), dir.access)
+            }
+        };
+
+        // `O_TMPFILE` includes `O_DIRECTORY`, so all of its bits have to be set.
+        let writes = flags & libc::O_ACCMODE != libc::O_RDONLY
+            || flags & (libc::O_CREAT | libc::O_TRUNC) != 0
+            || flags & libc::O_TMPFILE == libc::O_TMPFILE;
+        if access == Access::ReadOnly && writes {
+            return Err(libc::EROFS);
+        }
+
+        let rest = match rest.components().next() {
+            None => PathBuf::from("."),
+            Some(_) => rest,
+        };
+        let rest = CString::new(rest.as_os_str().as_bytes()).or(Err(libc::EINVAL))?;
+
+        let how = OpenHow {
+            flags: (flags | libc::O_CLOEXEC) as u64,
+            mode: mode.into(),
+            resolve: RESOLVE_BENEATH | RESOLVE_NO_MAGICLINKS,
+        };
+
+        let host = libc::syscall(
+            libc::SYS_openat2,
+            root,
+            rest.as_ptr(),
+            &how as *const OpenHow,
+            size_of::<OpenHow>(),
+        );
+
+        if host < 0 {
+            return Err(Error::last_os_error().raw_os_error().unwrap_or(libc::EIO));
+        }
+
+        let host = host as libc::c_int;
+        let mut stat: libc::stat = std::mem::zeroed();
+        let is_dir =
+            libc::fstat(host, &mut stat) == 0 && stat.st_mode & libc::S_IFMT == libc::S_IFDIR;
+
+        let fd = self.insert(
+            0,
+            Entry {
+                host,
+                access,
+                dir: is_dir,
+            },
+        );
+        Ok([(fd as usize).into(), 0.into()])
+    }
+}
@@ -0,0 +1,114 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! The host side of the syscalls of a keep
+//!
+//! The loader thread and the service thread of the hostcall ring both
+//! execute the syscalls of the keep through the `Handler`, so the trace and
+//! the symbolizer see every syscall, however the keep made it.
+
+use std::io::Write;
+use std::time::Instant;
+
+use sallyport::{Reply, Request};
+
+use crate::cpuid::Policy;
+use crate::files::Files;
+use crate::symbolize::Symbolizer;
+use crate::trace::Tracer;
+
+/// Executes the syscalls of a keep on the host
+pub struct Handler {
+    files: Files,
+    tracer: Option<Tracer>,
+    symbolizer: Option<Symbolizer>,
+}
+
+impl Default for Handler {
+    /// Only grant the standard streams, without tracing
+    fn default() -> Self {
+        Self::new(Files::default(), None, None)
+    }
+}
+
+impl std::fmt::Debug for Handler {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Handler")
+            .field("files", &self.files)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Handler {
+    /// Create a handler for the granted `files`, which traces and symbolizes optionally
+    pub fn new(files: Files, tracer: Option<Tracer>, symbolizer: Option<Symbolizer>) -> Self {
+        Self {
+            files,
+            tracer,
+            symbolizer,
+        }
+    }
+
+    /// Execute the syscall request `req` of the keep
+    ///
+    /// # Safety
+    ///
+    /// The pointers of the request must point to the memory of the keep,
+    /// which is mapped into the loader.
+    pub unsafe fn syscall(&mut self, req: &Request) -> Reply {
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.syscall_enter(req);
+        }
+
+        let start = Instant::now();
+        let rep = match self.symbolizer.as_mut().and_then(|s| s.syscall(req)) {
+            Some(rep) => rep.into(),
+            None => self.files.syscall(req),
+        };
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.syscall(req, Some(&rep.into()), start.elapsed());
+        }
+
+        rep
+    }
+
+    /// Answer the cpuid request of the keep for `leaf` and `subleaf` with the `policy`
+    pub fn cpuid(&mut self, policy: &Policy, leaf: u32, subleaf: u32) -> [u32; 4] {
+        let start = Instant::now();
+        let cpuid = unsafe { core::arch::x86_64::__cpuid_count(leaf, subleaf) };
+        let res = [cpuid.eax, cpuid.ebx, cpuid.ecx, cpuid.edx];
+        let res = policy.apply(leaf, subleaf, res);
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.cpuid(leaf, subleaf, &res, start.elapsed());
+        }
+
+        res
+    }
+
+    /// Report the error `text`, which ends the keep, symbolized if possible
+    pub fn error(&mut self, text: &str) {
+        let stderr = &mut std::io::stderr();
+
+        match self.symbolizer.as_mut() {
+            Some(symbolizer) => {
+                symbolizer.flush(stderr);
+                symbolizer.text(stderr, text);
+            }
+            None => {
+                let _ = writeln!(stderr, "{}", text);
+            }
+        }
+    }
+
+    /// Write out all pending output, before the loader exits
+    pub fn flush(&mut self) {
+        if let Some(symbolizer) = self.symbolizer.as_mut() {
+            symbolizer.flush(&mut std::io::stderr());
+        }
+
+        if let Some(tracer) = self.tracer.as_mut() {
+            tracer.flush();
+        }
+    }
+}
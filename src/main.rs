@@ -51,18 +51,107 @@
 //! Or specific backends can be compiled in:
 //!
 //!     $ cargo build --features=backend-sgx,backend-kvm
+//!
+//! # Trace the Syscalls of an Application
+//!
+//! The loader can trace all syscalls and cpuid requests it services for
+//! the keep, which works with release shims, too:
+//!
+//!     $ target/debug/enarx-keepldr exec --trace ./test
+//!
+//! To write the trace as JSON lines to a file instead:
+//!
+//!     $ target/debug/enarx-keepldr exec --trace-file trace.jsonl ./test
+//!
+//! The internal diagnostics of the shims can be enabled at launch, too.
+//! Note that this changes the measurement of the keep:
+//!
+//!     $ target/debug/enarx-keepldr exec --debug ./test
+//!
+//! The files a keep opens below a path can be encrypted transparently with
+//! a key only the keep can derive. This changes the measurement, too:
+//!
+//!     $ target/debug/enarx-keepldr exec --dir /data=./data:rw --encrypt /data ./test
+//!
+//! The keep can terminate TLS on the sockets it listens on for a port, so
+//! the host only sees encrypted traffic. The keep generates its key and a
+//! self-signed certificate carrying its attestation evidence. SGX keeps
+//! get a quote for it from the AESM daemon of the host. This changes the
+//! measurement, too:
+//!
+//!     $ target/debug/enarx-keepldr exec --tls 8443 ./server
+//!
+//! Without evidence, like without an AESM daemon on the host, the
+//! certificate carries none. To refuse the connections instead, which is
+//! measured as well:
+//!
+//!     $ target/debug/enarx-keepldr exec --tls 8443 --attest ./server
+//!
+//! The keep serves time itself: its monotonic clocks count the TSC, which
+//! is calibrated once against the host, and the host time is only a hint
+//! for its wall clock, which is resynchronized every second. By default,
+//! the wall clock follows the hints, even backwards. Strict time rejects
+//! the hints, which would move the wall clock backwards. This changes the
+//! measurement, too:
+//!
+//!     $ target/debug/enarx-keepldr exec --strict-time ./test
+//!
+//! The keep sees the CPU of the host by default. A CPUID policy hides the
+//! features beyond an x86-64 microarchitecture level, fixes the CPU vendor,
+//! model, caches and brand string and zeroes all other leaves, so the payload
+//! sees the same CPU on every host, which has to support the level. A policy file can rewrite the results further,
+//! see `src/cpuid.rs`:
+//!
+//!     $ target/debug/enarx-keepldr exec --cpuid x86-64-v3 ./test
+//!
+//! SGX keeps get the extended state of AVX and AVX-512, if the CPU supports
+//! it in enclaves and the CPUID policy exposes it. It is part of the
+//! attributes of the enclave, which its reports include. It can be limited:
+//!
+//!     $ target/debug/enarx-keepldr exec --xstate avx ./test
+//!
+//! With SGX2 and Linux 6.0 or later, SGX keeps add the pages of their heap on
+//! demand and remove them again, and `mprotect` changes the permissions of
+//! the heap pages. The heap is then not part of the measurement.
+//!
+//! SGX keeps give the payload a heap of 128 MiB, and the enclave is only as
+//! large as the heap needs. A smaller heap starts the keep faster and uses
+//! less EPC, a larger one can have up to 896 MiB. Its size is measured:
+//!
+//!     $ target/debug/enarx-keepldr exec --memory 16M ./test
+//!
+//! SGX keeps can map a file read-only after the heap, like a model for the
+//! payload, which finds it with the `ENARX_DATA_ADDR` and `ENARX_DATA_SIZE`
+//! environment variables. Only its size is measured, so the payload has to
+//! verify the data, unless `--measure-data` measures it, too:
+//!
+//!     $ target/debug/enarx-keepldr exec --data model.bin ./test
 
 #![deny(clippy::all)]
 #![deny(missing_docs)]
 #![feature(asm)]
 
 mod backend;
+mod cpuid;
+mod files;
+mod handler;
 mod protobuf;
+mod signal;
+mod symbolize;
+mod trace;
 
 use backend::{Backend, Command};
+use cpuid::Policy;
+use files::{DirGrant, FdGrant, Files};
+use handler::Handler;
+use symbolize::Symbolizer;
+use trace::{Format, Tracer};
 
 use std::convert::TryInto;
+use std::fs::File;
+use std::io::{BufRead, BufReader};
 use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
 use anyhow::Result;
 use structopt::StructOpt;
@@ -79,6 +168,106 @@ struct Info {}
 struct Exec {
     /// The payload to run inside the keep
     code: PathBuf,
+
+    /// Service hostcalls via a shared ring instead of exiting the keep for every call
+    #[structopt(long)]
+    ring: bool,
+
+    /// The number of sallyport blocks, which limits the hostcalls in flight, defaults to 2 per vCPU (KVM keeps only)
+    #[structopt(long)]
+    sallyport_blocks: Option<usize>,
+
+    /// Print a trace of all syscalls and cpuid requests of the keep to stderr
+    #[structopt(long)]
+    trace: bool,
+
+    /// Write a trace of all syscalls and cpuid requests of the keep as JSON lines to a file
+    #[structopt(long, parse(from_os_str))]
+    trace_file: Option<PathBuf>,
+
+    /// Enable the debug output of the shim, which changes the measurement of the keep
+    #[structopt(long)]
+    debug: bool,
+
+    /// The unstripped shim to symbolize stack traces and register dumps with
+    #[structopt(long, parse(from_os_str))]
+    debug_shim: Option<PathBuf>,
+
+    /// Wait for GDB to connect to this address, like `127.0.0.1:1234` (plain KVM keeps only)
+    #[structopt(long)]
+    gdb: Option<String>,
+
+    /// Seconds to wait for the payload to exit after forwarding SIGTERM or SIGINT, 0 waits forever
+    #[structopt(long, default_value = "10")]
+    grace: u32,
+
+    /// Open a host file as a file descriptor of the keep, like `3=./data.db:ro`
+    #[structopt(long = "fd", number_of_values = 1)]
+    fds: Vec<FdGrant>,
+
+    /// Map a host directory into the keep for `openat`, like `/data=./host/data:rw`
+    #[structopt(long = "dir", number_of_values = 1)]
+    dirs: Vec<DirGrant>,
+
+    /// Encrypt the files the keep opens below this path, which changes the measurement of the keep
+    #[structopt(long)]
+    encrypt: Option<String>,
+
+    /// Terminate TLS in the keep on the sockets listening on this port, which changes the measurement of the keep
+    #[structopt(long)]
+    tls: Option<u16>,
+
+    /// Refuse TLS connections without attestation evidence in the certificate, which changes the measurement of the keep
+    #[structopt(long, requires = "tls")]
+    attest: bool,
+
+    /// Reject host time hints moving the wall clock of the keep backwards, which changes the measurement of the keep
+    #[structopt(long)]
+    strict_time: bool,
+
+    /// The CPUID policy of the keep: `host`, `x86-64-v2`, `x86-64-v3`, `x86-64-v4` or a policy file
+    #[structopt(long, default_value = "host")]
+    cpuid: Policy,
+
+    /// The extended state of the keep: `sse`, `avx` or `avx512`, defaults to the most the CPU supports and the CPUID policy exposes (SGX keeps only)
+    #[structopt(long)]
+    xstate: Option<String>,
+
+    /// The memory for the heap of the payload, like `64M`, which changes the measurement of the keep (SGX keeps only)
+    #[structopt(long)]
+    memory: Option<String>,
+
+    /// Add the heap pages on demand with SGX2, which changes the measurement of the keep (SGX keeps only)
+    #[structopt(long)]
+    edmm: bool,
+
+    /// Map a file read-only into the keep, without measuring it (SGX keeps only)
+    #[structopt(long, parse(from_os_str))]
+    data: Option<PathBuf>,
+
+    /// Measure the file of `--data`, which changes the measurement of the keep
+    #[structopt(long, requires = "data")]
+    measure_data: bool,
+
+    /// Inject the secret in this file, like the SEV guest owner would, to derive the file and TLS keys from (plain KVM keeps only)
+    #[structopt(long, parse(from_os_str))]
+    secret: Option<PathBuf>,
+}
+
+/// Symbolizes stack traces and register dumps of a saved log
+#[derive(StructOpt)]
+struct Symbolize {
+    /// The unstripped shim
+    #[structopt(long, parse(from_os_str))]
+    shim: Option<PathBuf>,
+
+    /// The payload
+    #[structopt(long, parse(from_os_str))]
+    payload: Option<PathBuf>,
+
+    /// The log to symbolize, defaults to stdin
+    #[structopt(parse(from_os_str))]
+    log: Option<PathBuf>,
 }
 
 #[derive(StructOpt)]
@@ -86,6 +275,7 @@ struct Exec {
 enum Options {
     Info(Info),
     Exec(Exec),
+    Symbolize(Symbolize),
 }
 
 #[allow(clippy::unnecessary_wraps)]
@@ -100,6 +290,7 @@ fn main() -> Result<()> {
     match Options::from_args() {
         Options::Info(_) => info(backends),
         Options::Exec(e) => exec(backends, e),
+        Options::Symbolize(s) => symbolize(s),
     }
 }
 
@@ -156,27 +347,103 @@ fn exec(backends: &[Box<dyn Backend>], opts: Exec) -> Result<()> {
 
     let map = mmarinus::Kind::Private.load::<mmarinus::perms::Read, _>(&opts.code)?;
 
-    let keep = backend.keep(backend.shim(), &map)?;
+    let tracer = match (opts.trace_file, opts.trace) {
+        (Some(path), _) => Some(Tracer::new(Box::new(File::create(path)?), Format::Json)),
+        (None, true) => Some(Tracer::new(Box::new(std::io::stderr()), Format::Text)),
+        (None, false) => None,
+    };
+
+    opts.cpuid.check()?;
+
+    let symbolizer = match opts.debug_shim {
+        Some(shim) => Symbolizer::new(Some(&shim), Some(&opts.code))?,
+        None => None,
+    };
+
+    let files = Files::new(&opts.fds, &opts.dirs)?;
+    let handler = Arc::new(Mutex::new(Handler::new(files, tracer, symbolizer)));
+
+    let keep_opts = backend::Options {
+        ring: opts.ring,
+        sallyport_blocks: opts.sallyport_blocks,
+        debug: opts.debug,
+        gdb: opts.gdb,
+        handler: handler.clone(),
+        encrypt: opts.encrypt,
+        tls: opts.tls,
+        attest: opts.attest,
+        strict_time: opts.strict_time,
+        cpuid: opts.cpuid,
+        xstate: opts.xstate,
+        memory: opts.memory,
+        edmm: opts.edmm,
+        data: opts.data,
+        measure_data: opts.measure_data,
+        secret: opts.secret,
+    };
+
+    let keep = backend.keep(backend.shim(), &map, &keep_opts)?;
     let mut thread = keep.clone().spawn()?.unwrap();
+    signal::forward(opts.grace)?;
     loop {
-        match thread.enter()? {
+        let command = match thread.enter() {
+            Ok(command) => command,
+            Err(e) => {
+                let mut handler = handler.lock().unwrap();
+                handler.error(&render(&e));
+                handler.flush();
+                std::process::exit(1);
+            }
+        };
+
+        match command {
             Command::SysCall(block) => unsafe {
-                block.msg.rep = block.msg.req.syscall();
+                let req = block.msg.req;
+                block.msg.rep = handler.lock().unwrap().syscall(&req);
             },
 
             Command::CpuId(block) => unsafe {
-                let cpuid = core::arch::x86_64::__cpuid_count(
-                    block.msg.req.arg[0].try_into().unwrap(),
-                    block.msg.req.arg[1].try_into().unwrap(),
-                );
-
-                block.msg.req.arg[0] = cpuid.eax.into();
-                block.msg.req.arg[1] = cpuid.ebx.into();
-                block.msg.req.arg[2] = cpuid.ecx.into();
-                block.msg.req.arg[3] = cpuid.edx.into();
+                let leaf = block.msg.req.arg[0].try_into().unwrap();
+                let subleaf = block.msg.req.arg[1].try_into().unwrap();
+
+                let res = handler
+                    .lock()
+                    .unwrap()
+                    .cpuid(&keep_opts.cpuid, leaf, subleaf);
+
+                for (arg, reg) in block.msg.req.arg.iter_mut().zip(res.iter()) {
+                    *arg = (*reg).into();
+                }
             },
 
             Command::Continue => (),
         }
     }
 }
+
+/// Render an error of a keep, describing faults of the keep without the error chain
+fn render(e: &anyhow::Error) -> String {
+    #[cfg(feature = "backend-kvm")]
+    if let Some(fault) = e.downcast_ref::<backend::kvm::KeepFault>() {
+        return format!("Keep fault: {}", fault);
+    }
+
+    format!("Error: {:?}", e)
+}
+
+fn symbolize(opts: Symbolize) -> Result<()> {
+    let mut symbolizer = Symbolizer::new(opts.shim.as_deref(), opts.payload.as_deref())?
+        .ok_or_else(|| anyhow::anyhow!("Neither the shim nor the payload has debug info"))?;
+
+    let input: Box<dyn BufRead> = match opts.log {
+        Some(path) => Box::new(BufReader::new(File::open(path)?)),
+        None => Box::new(BufReader::new(std::io::stdin())),
+    };
+
+    let stdout = &mut std::io::stdout();
+    for line in input.lines() {
+        symbolizer.line(stdout, &line?);
+    }
+
+    Ok(())
+}
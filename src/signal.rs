@@ -0,0 +1,133 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Forwarding of termination signals to the payload
+//!
+//! `SIGTERM` and `SIGINT` sent to the loader don't kill it. They are marked
+//! pending and handed to the shim by the next `Thread::enter`, so the payload
+//! can shut down gracefully. If the keep is still running after the grace
+//! period, the loader exits forcibly.
+//!
+//! The loader kicks a vCPU thread out of `KVM_RUN` with `KICK`, too. A
+//! pending signal kicks the vCPU thread, which may be running the payload
+//! without ever making a hostcall.
+
+use std::io::Error;
+use std::mem::zeroed;
+use std::sync::atomic::{AtomicI32, AtomicPtr, AtomicU32, AtomicU64, Ordering};
+
+use anyhow::Result;
+
+/// The signal to hand to the shim
+static PENDING: AtomicI32 = AtomicI32::new(0);
+
+/// The first termination signal, which started the grace period
+static TERMINATING: AtomicI32 = AtomicI32::new(0);
+
+/// The grace period in seconds
+static GRACE: AtomicU32 = AtomicU32::new(0);
+
+/// The thread, which runs the vCPU
+static VCPU: AtomicU64 = AtomicU64::new(0);
+
+/// The `immediate_exit` flag of the vCPU, which stops it entering `KVM_RUN`
+static IMMEDIATE_EXIT: AtomicPtr<u8> = AtomicPtr::new(std::ptr::null_mut());
+
+/// The signal, which kicks a vCPU thread out of `KVM_RUN`
+pub const KICK: libc::c_int = libc::SIGUSR1;
+
+extern "C" fn terminate(signo: libc::c_int) {
+    PENDING.store(signo, Ordering::SeqCst);
+
+    // A signal right before `KVM_RUN` is not lost, because `KVM_RUN`
+    // returns right away. A signal taken by another thread kicks the vCPU.
+    let flag = IMMEDIATE_EXIT.load(Ordering::SeqCst);
+    if !flag.is_null() {
+        unsafe { flag.write_volatile(1) };
+    }
+
+    let vcpu = VCPU.load(Ordering::SeqCst) as libc::pthread_t;
+    if vcpu != 0 && unsafe { libc::pthread_equal(vcpu, libc::pthread_self()) } == 0 {
+        unsafe { libc::pthread_kill(vcpu, KICK) };
+    }
+
+    let first = TERMINATING.compare_exchange(0, signo, Ordering::SeqCst, Ordering::SeqCst);
+    if first.is_ok() {
+        unsafe { libc::alarm(GRACE.load(Ordering::SeqCst)) };
+    }
+}
+
+extern "C" fn expired(_signo: libc::c_int) {
+    const MSG: &[u8] = b"The keep did not exit within the grace period\n";
+
+    // The trace is written unbuffered, so `_exit` loses none of it.
+    unsafe {
+        libc::write(libc::STDERR_FILENO, MSG.as_ptr() as _, MSG.len());
+        libc::_exit(128 + TERMINATING.load(Ordering::SeqCst));
+    }
+}
+
+extern "C" fn kicked(_signo: libc::c_int) {}
+
+fn install(
+    signo: libc::c_int,
+    handler: extern "C" fn(libc::c_int),
+    flags: libc::c_int,
+) -> Result<()> {
+    unsafe {
+        let mut action: libc::sigaction = zeroed();
+        action.sa_sigaction = handler as libc::sighandler_t;
+        action.sa_flags = flags;
+        libc::sigemptyset(&mut action.sa_mask);
+
+        if libc::sigaction(signo, &action, std::ptr::null_mut()) != 0 {
+            return Err(Error::last_os_error().into());
+        }
+    }
+
+    Ok(())
+}
+
+/// Forward `SIGTERM` and `SIGINT` to the payload, which runs on the calling thread
+///
+/// The loader exits `grace` seconds after the first signal, unless `grace` is 0.
+pub fn forward(grace: u32) -> Result<()> {
+    GRACE.store(grace, Ordering::SeqCst);
+    VCPU.store(unsafe { libc::pthread_self() } as u64, Ordering::SeqCst);
+
+    // The signals interrupt `KVM_RUN`, which is never restarted. The
+    // syscalls of the loader and the hostcalls of the payload are.
+    kickable()?;
+    install(libc::SIGALRM, expired, libc::SA_RESTART)?;
+    install(libc::SIGTERM, terminate, libc::SA_RESTART)?;
+    install(libc::SIGINT, terminate, libc::SA_RESTART)
+}
+
+/// Let a pending signal set the `immediate_exit` flag of the vCPU
+///
+/// # Safety
+///
+/// `flag` has to stay valid, until `immediate_exit` is called with another flag.
+pub unsafe fn immediate_exit(flag: *mut u8) {
+    IMMEDIATE_EXIT.store(flag, Ordering::SeqCst);
+}
+
+/// Let `KICK` interrupt `KVM_RUN`
+///
+/// `KVM_RUN` is never restarted, but the hostcalls of the payload are,
+/// if the host kernel can restart them.
+pub fn kickable() -> Result<()> {
+    install(KICK, kicked, libc::SA_RESTART)
+}
+
+/// Check, if a signal is pending, without taking it
+pub fn pending() -> bool {
+    PENDING.load(Ordering::SeqCst) != 0
+}
+
+/// Take the signal to hand to the shim, if any
+pub fn take() -> Option<libc::c_int> {
+    match PENDING.swap(0, Ordering::SeqCst) {
+        0 => None,
+        signo => Some(signo),
+    }
+}
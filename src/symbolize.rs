@@ -0,0 +1,237 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Symbolization of stack traces and register dumps of a keep
+//!
+//! shim-sev prints `TRACE:` followed by one frame per line, either as an
+//! offset into the shim or, prefixed with `P `, as an offset into the payload.
+//! KVM shutdowns dump the registers of the vCPU with absolute shim addresses.
+//! With the unstripped shim and the payload, the frames and the registers
+//! pointing into the shim are resolved to functions, files and lines.
+
+use std::borrow::Cow;
+use std::io::Write;
+use std::path::Path;
+use std::sync::Arc;
+
+use addr2line::gimli::{self, EndianArcSlice, RunTimeEndian};
+use addr2line::object::{self, Object, ObjectSection};
+use addr2line::Context;
+use anyhow::Result;
+use sallyport::Request;
+
+/// The offset of the shim virtual addresses to the shim ELF addresses
+///
+/// Has to match `SHIM_VIRT_OFFSET` in `internal/shim-sev/src/addr.rs`.
+pub(crate) const SHIM_VIRT_OFFSET: u64 = 0xFFFF_FF80_0000_0000;
+
+/// The debug info of an ELF file
+///
+/// Unlike `addr2line::ObjectContext`, it can be sent to the service thread
+/// of the hostcall ring.
+type ObjectContext = Context<EndianArcSlice<RunTimeEndian>>;
+
+/// Resolves addresses of the shim and the payload in the output of a keep
+pub struct Symbolizer {
+    shim: Option<ObjectContext>,
+    payload: Option<ObjectContext>,
+    trace: bool,
+    pending: Vec<u8>,
+}
+
+/// Load the debug info of an ELF file, if it has any
+fn context(path: &Path) -> Result<Option<ObjectContext>> {
+    let data = std::fs::read(path)?;
+    let file = object::File::parse(&*data)?;
+
+    if file.section_by_name(".debug_info").is_none() {
+        return Ok(None);
+    }
+
+    let endian = match file.is_little_endian() {
+        true => RunTimeEndian::Little,
+        false => RunTimeEndian::Big,
+    };
+
+    let dwarf = gimli::Dwarf::load(|id| -> Result<_> {
+        let data = file
+            .section_by_name(id.name())
+            .and_then(|section| section.uncompressed_data().ok())
+            .unwrap_or(Cow::Borrowed(&[][..]));
+
+        Ok(EndianArcSlice::new(Arc::from(&*data), endian))
+    })?;
+
+    Ok(Some(Context::from_dwarf(dwarf)?))
+}
+
+/// Parse a hexadecimal number with a `0x` prefix
+fn hex(s: &str) -> Option<u64> {
+    u64::from_str_radix(s.strip_prefix("0x")?, 16).ok()
+}
+
+impl Symbolizer {
+    /// Create a symbolizer from the unstripped shim and the payload
+    ///
+    /// Returns `None`, if neither has any debug info.
+    pub fn new(shim: Option<&Path>, payload: Option<&Path>) -> Result<Option<Self>> {
+        let shim = shim.map(context).transpose()?.flatten();
+        let payload = payload.map(context).transpose()?.flatten();
+
+        if shim.is_none() && payload.is_none() {
+            return Ok(None);
+        }
+
+        Ok(Some(Self {
+            shim,
+            payload,
+            trace: false,
+            pending: Vec::new(),
+        }))
+    }
+
+    /// Write the frames of `addr` in `ctx` with the `prefix`
+    fn frames(out: &mut impl Write, ctx: &ObjectContext, prefix: &str, addr: u64) {
+        let mut frames = match ctx.find_frames(addr) {
+            Ok(frames) => frames,
+            Err(_) => return,
+        };
+
+        let mut first = true;
+        while let Ok(Some(frame)) = frames.next() {
+            let function = frame
+                .function
+                .as_ref()
+                .and_then(|f| f.demangle().ok())
+                .unwrap_or_else(|| "??".into());
+
+            let file = frame.location.as_ref().and_then(|l| l.file).unwrap_or("??");
+            let line = frame.location.as_ref().and_then(|l| l.line).unwrap_or(0);
+
+            let inlined = if first { "" } else { " (inlined by)" };
+            let _ = writeln!(
+                out,
+                "{}:{} {} at {}:{}",
+                prefix, inlined, function, file, line
+            );
+            first = false;
+        }
+    }
+
+    /// Symbolize a single line
+    pub fn line(&mut self, out: &mut impl Write, line: &str) {
+        let _ = writeln!(out, "{}", line);
+
+        let trimmed = line.trim();
+
+        if trimmed.starts_with("TRACE:") {
+            self.trace = true;
+            return;
+        }
+
+        if self.trace {
+            // Payload offsets relative to the randomized `PAYLOAD_VIRT_ADDR`
+            if let Some(addr) = trimmed.strip_prefix("P ").and_then(hex) {
+                if let Some(ctx) = self.payload.as_ref() {
+                    Self::frames(out, ctx, "Payl", addr);
+                }
+                return;
+            }
+
+            // Shim offsets relative to `SHIM_VIRT_OFFSET`
+            if let Some(addr) = hex(trimmed) {
+                if let Some(ctx) = self.shim.as_ref() {
+                    Self::frames(out, ctx, "Shim", addr);
+                }
+                return;
+            }
+
+            self.trace = false;
+        }
+
+        // Registers of a register dump like `rip: 0xffffff8000230662,`
+        // The stack and flags registers never point to code.
+        let mut split = trimmed.trim_end_matches(',').splitn(2, ": ");
+        if let (Some(reg), Some(value)) = (split.next(), split.next().and_then(hex)) {
+            if ["rsp", "rbp", "rbx", "rflags"].contains(&reg) || value < SHIM_VIRT_OFFSET {
+                return;
+            }
+
+            if let Some(ctx) = self.shim.as_ref() {
+                Self::frames(out, ctx, "Shim", value - SHIM_VIRT_OFFSET);
+            }
+        }
+    }
+
+    /// Symbolize all lines of `text`
+    pub fn text(&mut self, out: &mut impl Write, text: &str) {
+        for line in text.lines() {
+            self.line(out, line);
+        }
+    }
+
+    /// Symbolize the bytes of a stream, which might end in a partial line
+    pub fn feed(&mut self, out: &mut impl Write, bytes: &[u8]) {
+        self.pending.extend_from_slice(bytes);
+
+        while let Some(end) = self.pending.iter().position(|b| *b == b'\n') {
+            let line: Vec<u8> = self.pending.drain(..=end).collect();
+            self.line(out, String::from_utf8_lossy(&line[..end]).as_ref());
+        }
+    }
+
+    /// Write out a pending partial line
+    pub fn flush(&mut self, out: &mut impl Write) {
+        let _ = out.write_all(&self.pending);
+        let _ = out.flush();
+        self.pending.clear();
+    }
+
+    /// Intercept the stderr output of the keep
+    ///
+    /// Returns the reply for writes to stderr, which were symbolized
+    /// instead of being executed.
+    pub fn syscall(&mut self, req: &Request) -> Option<sallyport::Result> {
+        let stderr = &mut std::io::stderr();
+        let fd = usize::from(req.arg[0]);
+
+        match i64::from(req.num) {
+            libc::SYS_write if fd == libc::STDERR_FILENO as usize => {
+                let len = usize::from(req.arg[2]);
+                let bytes = unsafe {
+                    std::slice::from_raw_parts(usize::from(req.arg[1]) as *const u8, len)
+                };
+
+                self.feed(stderr, bytes);
+                Some(Ok([len.into(), 0.into()]))
+            }
+
+            libc::SYS_writev if fd == libc::STDERR_FILENO as usize => {
+                let iovs = unsafe {
+                    std::slice::from_raw_parts(
+                        usize::from(req.arg[1]) as *const libc::iovec,
+                        usize::from(req.arg[2]),
+                    )
+                };
+
+                let mut len = 0;
+                for iov in iovs {
+                    let bytes = unsafe {
+                        std::slice::from_raw_parts(iov.iov_base as *const u8, iov.iov_len)
+                    };
+
+                    self.feed(stderr, bytes);
+                    len += iov.iov_len;
+                }
+
+                Some(Ok([len.into(), 0.into()]))
+            }
+
+            libc::SYS_exit | libc::SYS_exit_group => {
+                self.flush(stderr);
+                None
+            }
+
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,572 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Host side tracing of the requests of a keep
+//!
+//! Every `Command::SysCall` and `Command::CpuId` the loader services can be
+//! traced with its decoded name, arguments, return values and the time it
+//! took the host to service it. The trace is either printed strace-like or
+//! written as one JSON object per line.
+//!
+//! File descriptors, flags, signals and clocks are decoded by name, and
+//! strings and buffers are read from the memory of the keep. Each record is
+//! written with a single write and nothing is buffered, so the trace is
+//! complete however the loader exits.
+
+use std::io::Write;
+use std::time::{Duration, Instant};
+
+use sallyport::syscall::{
+    SYS_ENARX_BALLOON_MEMORY, SYS_ENARX_CPUID, SYS_ENARX_GETATT, SYS_ENARX_MEM_INFO,
+};
+use sallyport::Request;
+
+/// The format of the trace
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Format {
+    /// Human readable, like `strace`
+    Text,
+
+    /// One JSON object per line
+    Json,
+}
+
+/// Writes the trace of the requests of a keep
+pub struct Tracer {
+    out: Box<dyn Write + Send>,
+    format: Format,
+    start: Instant,
+}
+
+/// How an argument of a syscall is decoded
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum Arg {
+    /// A signed integer
+    Int,
+
+    /// An unsigned integer, like a length
+    Uint,
+
+    /// An address or an opaque value
+    Hex,
+
+    /// A file descriptor
+    Fd,
+
+    /// The address of a NUL terminated string
+    Str,
+
+    /// The address of a buffer passed to the host with the length in the argument at the index
+    In(usize),
+
+    /// The address of a buffer filled by the host with as many bytes as it returns
+    Out,
+
+    /// The flags of `open`
+    OpenFlags,
+
+    /// A file mode
+    Mode,
+
+    /// The protection of `mmap`
+    Prot,
+
+    /// The flags of `mmap`
+    MapFlags,
+
+    /// A signal number
+    Signal,
+
+    /// A clock id
+    Clock,
+
+    /// An address family
+    Family,
+}
+
+/// The number of bytes of strings and buffers in the trace
+const PEEK_MAX: usize = 32;
+
+/// The flags of `open` besides the access mode
+const OPEN_FLAGS: &[(libc::c_int, &str)] = &[
+    (libc::O_CREAT, "O_CREAT"),
+    (libc::O_EXCL, "O_EXCL"),
+    (libc::O_NOCTTY, "O_NOCTTY"),
+    (libc::O_TRUNC, "O_TRUNC"),
+    (libc::O_APPEND, "O_APPEND"),
+    (libc::O_NONBLOCK, "O_NONBLOCK"),
+    (libc::O_DSYNC, "O_DSYNC"),
+    (libc::O_DIRECT, "O_DIRECT"),
+    (libc::O_LARGEFILE, "O_LARGEFILE"),
+    (libc::O_DIRECTORY, "O_DIRECTORY"),
+    (libc::O_NOFOLLOW, "O_NOFOLLOW"),
+    (libc::O_NOATIME, "O_NOATIME"),
+    (libc::O_CLOEXEC, "O_CLOEXEC"),
+    (libc::O_PATH, "O_PATH"),
+];
+
+/// The protection flags of `mmap`
+const PROT_FLAGS: &[(libc::c_int, &str)] = &[
+    (libc::PROT_READ, "PROT_READ"),
+    (libc::PROT_WRITE, "PROT_WRITE"),
+    (libc::PROT_EXEC, "PROT_EXEC"),
+];
+
+/// The flags of `mmap`
+const MAP_FLAGS: &[(libc::c_int, &str)] = &[
+    (libc::MAP_SHARED, "MAP_SHARED"),
+    (libc::MAP_PRIVATE, "MAP_PRIVATE"),
+    (libc::MAP_FIXED, "MAP_FIXED"),
+    (libc::MAP_ANONYMOUS, "MAP_ANONYMOUS"),
+    (libc::MAP_GROWSDOWN, "MAP_GROWSDOWN"),
+    (libc::MAP_NORESERVE, "MAP_NORESERVE"),
+    (libc::MAP_POPULATE, "MAP_POPULATE"),
+    (libc::MAP_STACK, "MAP_STACK"),
+];
+
+/// The names of the signals
+const SIGNALS: &[(libc::c_int, &str)] = &[
+    (libc::SIGHUP, "SIGHUP"),
+    (libc::SIGINT, "SIGINT"),
+    (libc::SIGQUIT, "SIGQUIT"),
+    (libc::SIGILL, "SIGILL"),
+    (libc::SIGTRAP, "SIGTRAP"),
+    (libc::SIGABRT, "SIGABRT"),
+    (libc::SIGBUS, "SIGBUS"),
+    (libc::SIGFPE, "SIGFPE"),
+    (libc::SIGKILL, "SIGKILL"),
+    (libc::SIGUSR1, "SIGUSR1"),
+    (libc::SIGSEGV, "SIGSEGV"),
+    (libc::SIGUSR2, "SIGUSR2"),
+    (libc::SIGPIPE, "SIGPIPE"),
+    (libc::SIGALRM, "SIGALRM"),
+    (libc::SIGTERM, "SIGTERM"),
+    (libc::SIGCHLD, "SIGCHLD"),
+    (libc::SIGCONT, "SIGCONT"),
+    (libc::SIGSTOP, "SIGSTOP"),
+    (libc::SIGTSTP, "SIGTSTP"),
+    (libc::SIGWINCH, "SIGWINCH"),
+    (libc::SIGSYS, "SIGSYS"),
+];
+
+/// The names of the clocks
+const CLOCKS: &[(libc::clockid_t, &str)] = &[
+    (libc::CLOCK_REALTIME, "CLOCK_REALTIME"),
+    (libc::CLOCK_MONOTONIC, "CLOCK_MONOTONIC"),
+    (libc::CLOCK_PROCESS_CPUTIME_ID, "CLOCK_PROCESS_CPUTIME_ID"),
+    (libc::CLOCK_THREAD_CPUTIME_ID, "CLOCK_THREAD_CPUTIME_ID"),
+    (libc::CLOCK_MONOTONIC_RAW, "CLOCK_MONOTONIC_RAW"),
+    (libc::CLOCK_REALTIME_COARSE, "CLOCK_REALTIME_COARSE"),
+    (libc::CLOCK_MONOTONIC_COARSE, "CLOCK_MONOTONIC_COARSE"),
+    (libc::CLOCK_BOOTTIME, "CLOCK_BOOTTIME"),
+];
+
+/// The names of the address families
+const FAMILIES: &[(libc::c_int, &str)] = &[
+    (libc::AF_UNSPEC, "AF_UNSPEC"),
+    (libc::AF_UNIX, "AF_UNIX"),
+    (libc::AF_INET, "AF_INET"),
+    (libc::AF_INET6, "AF_INET6"),
+    (libc::AF_NETLINK, "AF_NETLINK"),
+];
+
+/// Returns the name of `value` in `names` or the number
+fn name<T: PartialEq + std::fmt::Display + Copy>(value: T, names: &[(T, &str)]) -> String {
+    match names.iter().find(|(v, _)| *v == value) {
+        Some((_, name)) => (*name).into(),
+        None => value.to_string(),
+    }
+}
+
+/// Returns the set `flags` of `names` joined with `|` and the unknown rest in hex
+fn flags(flags: libc::c_int, names: &[(libc::c_int, &str)]) -> Vec<String> {
+    let mut rest = flags;
+    let mut set = Vec::new();
+
+    for (flag, name) in names {
+        if *flag != 0 && flags & flag == *flag {
+            set.push((*name).to_string());
+            rest &= !flag;
+        }
+    }
+
+    if rest != 0 {
+        set.push(format!("{:#x}", rest));
+    }
+
+    set
+}
+
+/// Read up to `len` bytes at `addr` of the memory of the keep
+///
+/// The keep passes the address, so it is read with `process_vm_readv`,
+/// which fails instead of faulting for unmapped memory.
+fn peek(addr: usize, len: usize) -> Option<Vec<u8>> {
+    let mut buf = vec![0u8; len];
+
+    let local = libc::iovec {
+        iov_base: buf.as_mut_ptr() as _,
+        iov_len: len,
+    };
+
+    let remote = libc::iovec {
+        iov_base: addr as _,
+        iov_len: len,
+    };
+
+    let read = unsafe { libc::process_vm_readv(libc::getpid(), &local, 1, &remote, 1, 0) };
+    if read < 0 {
+        return None;
+    }
+
+    buf.truncate(read as usize);
+    Some(buf)
+}
+
+/// Quote `bytes` like a C string, with `...` if `more` bytes follow
+fn quote(bytes: &[u8], more: bool) -> String {
+    let mut out = String::from("\"");
+
+    for b in bytes {
+        match b {
+            b'\n' => out.push_str("\\n"),
+            b'\r' => out.push_str("\\r"),
+            b'\t' => out.push_str("\\t"),
+            b'"' => out.push_str("\\\""),
+            b'\\' => out.push_str("\\\\"),
+            0x20..=0x7e => out.push(*b as char),
+            _ => out.push_str(&format!("\\x{:02x}", b)),
+        }
+    }
+
+    out.push('"');
+    if more {
+        out.push_str("...");
+    }
+
+    out
+}
+
+/// Quote the buffer of `len` bytes at `addr` of the memory of the keep
+fn buffer(addr: usize, len: usize) -> String {
+    match peek(addr, len.min(PEEK_MAX)) {
+        Some(bytes) if addr != 0 => quote(&bytes, len > bytes.len()),
+        _ => format!("{:#x}", addr),
+    }
+}
+
+/// Quote the NUL terminated string at `addr` of the memory of the keep
+fn string(addr: usize) -> String {
+    match peek(addr, PEEK_MAX + 1) {
+        Some(bytes) if addr != 0 => match bytes.iter().position(|b| *b == 0) {
+            Some(len) => quote(&bytes[..len], false),
+            None => quote(&bytes[..bytes.len().min(PEEK_MAX)], true),
+        },
+        _ => format!("{:#x}", addr),
+    }
+}
+
+/// Decode the argument `index` of `args` of the kind `kind`
+///
+/// Buffers filled by the host are only read after the syscall succeeded.
+fn decode(kind: Arg, index: usize, args: &[usize], rep: Option<&sallyport::Result>) -> String {
+    let arg = args[index];
+
+    match kind {
+        Arg::Int => (arg as i64).to_string(),
+        Arg::Uint => arg.to_string(),
+        Arg::Hex => format!("{:#x}", arg),
+        Arg::Fd if arg as libc::c_int == libc::AT_FDCWD => "AT_FDCWD".into(),
+        Arg::Fd => (arg as libc::c_int).to_string(),
+        Arg::Str => string(arg),
+        Arg::In(len) => buffer(arg, args[len]),
+        Arg::Out => match rep {
+            Some(Ok(ret)) => buffer(arg, usize::from(ret[0])),
+            _ => format!("{:#x}", arg),
+        },
+        Arg::OpenFlags => {
+            let arg = arg as libc::c_int;
+            let mode = match arg & libc::O_ACCMODE {
+                libc::O_RDONLY => "O_RDONLY".into(),
+                libc::O_WRONLY => "O_WRONLY".into(),
+                libc::O_RDWR => "O_RDWR".into(),
+                mode => format!("{:#x}", mode),
+            };
+
+            let mut set = vec![mode];
+            set.extend(flags(arg & !libc::O_ACCMODE, OPEN_FLAGS));
+            set.join("|")
+        }
+        Arg::Mode => format!("0{:o}", arg),
+        Arg::Prot => match flags(arg as libc::c_int, PROT_FLAGS) {
+            set if set.is_empty() => "PROT_NONE".into(),
+            set => set.join("|"),
+        },
+        Arg::MapFlags => match flags(arg as libc::c_int, MAP_FLAGS) {
+            set if set.is_empty() => "0".into(),
+            set => set.join("|"),
+        },
+        Arg::Signal => name(arg as libc::c_int, SIGNALS),
+        Arg::Clock => name(arg as libc::clockid_t, CLOCKS),
+        Arg::Family => name(arg as libc::c_int, FAMILIES),
+    }
+}
+
+/// Escape `text` for a JSON string
+fn json(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+
+    for c in text.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            c if (c as u32) < 0x20 => out.push_str(&format!("\\u{:04x}", c as u32)),
+            c => out.push(c),
+        }
+    }
+
+    out
+}
+
+/// Returns the name and the arguments of a syscall
+fn syscall(num: i64) -> Option<(&'static str, &'static [Arg])> {
+    use Arg::*;
+
+    Some(match num {
+        libc::SYS_read => ("read", &[Fd, Out, Uint]),
+        libc::SYS_write => ("write", &[Fd, In(2), Uint]),
+        libc::SYS_open => ("open", &[Str, OpenFlags, Mode]),
+        libc::SYS_close => ("close", &[Fd]),
+        libc::SYS_stat => ("stat", &[Str, Hex]),
+        libc::SYS_fstat => ("fstat", &[Fd, Hex]),
+        libc::SYS_lstat => ("lstat", &[Str, Hex]),
+        libc::SYS_poll => ("poll", &[Hex, Uint, Int]),
+        libc::SYS_lseek => ("lseek", &[Fd, Int, Int]),
+        libc::SYS_mmap => ("mmap", &[Hex, Uint, Prot, MapFlags, Fd, Hex]),
+        libc::SYS_mprotect => ("mprotect", &[Hex, Uint, Prot]),
+        libc::SYS_munmap => ("munmap", &[Hex, Uint]),
+        libc::SYS_brk => ("brk", &[Hex]),
+        libc::SYS_rt_sigaction => ("rt_sigaction", &[Signal, Hex, Hex, Uint]),
+        libc::SYS_rt_sigprocmask => ("rt_sigprocmask", &[Int, Hex, Hex, Uint]),
+        libc::SYS_rt_sigreturn => ("rt_sigreturn", &[]),
+        libc::SYS_ioctl => ("ioctl", &[Fd, Hex, Hex]),
+        libc::SYS_pread64 => ("pread64", &[Fd, Out, Uint, Int]),
+        libc::SYS_pwrite64 => ("pwrite64", &[Fd, In(2), Uint, Int]),
+        libc::SYS_readv => ("readv", &[Fd, Hex, Uint]),
+        libc::SYS_writev => ("writev", &[Fd, Hex, Uint]),
+        libc::SYS_access => ("access", &[Str, Int]),
+        libc::SYS_pipe => ("pipe", &[Hex]),
+        libc::SYS_select => ("select", &[Int, Hex, Hex, Hex, Hex]),
+        libc::SYS_sched_yield => ("sched_yield", &[]),
+        libc::SYS_mremap => ("mremap", &[Hex, Uint, Uint, Hex, Hex]),
+        libc::SYS_madvise => ("madvise", &[Hex, Uint, Int]),
+        libc::SYS_dup => ("dup", &[Fd]),
+        libc::SYS_dup2 => ("dup2", &[Fd, Fd]),
+        libc::SYS_nanosleep => ("nanosleep", &[Hex, Hex]),
+        libc::SYS_getpid => ("getpid", &[]),
+        libc::SYS_socket => ("socket", &[Family, Int, Int]),
+        libc::SYS_connect => ("connect", &[Fd, Hex, Uint]),
+        libc::SYS_accept => ("accept", &[Fd, Hex, Hex]),
+        libc::SYS_sendto => ("sendto", &[Fd, In(2), Uint, Hex, Hex, Uint]),
+        libc::SYS_recvfrom => ("recvfrom", &[Fd, Out, Uint, Hex, Hex, Hex]),
+        libc::SYS_sendmsg => ("sendmsg", &[Fd, Hex, Hex]),
+        libc::SYS_recvmsg => ("recvmsg", &[Fd, Hex, Hex]),
+        libc::SYS_shutdown => ("shutdown", &[Fd, Int]),
+        libc::SYS_bind => ("bind", &[Fd, Hex, Uint]),
+        libc::SYS_listen => ("listen", &[Fd, Int]),
+        libc::SYS_getsockname => ("getsockname", &[Fd, Hex, Hex]),
+        libc::SYS_getpeername => ("getpeername", &[Fd, Hex, Hex]),
+        libc::SYS_socketpair => ("socketpair", &[Family, Int, Int, Hex]),
+        libc::SYS_setsockopt => ("setsockopt", &[Fd, Int, Int, Hex, Uint]),
+        libc::SYS_getsockopt => ("getsockopt", &[Fd, Int, Int, Hex, Hex]),
+        libc::SYS_clone => ("clone", &[Hex, Hex, Hex, Hex, Hex]),
+        libc::SYS_fork => ("fork", &[]),
+        libc::SYS_execve => ("execve", &[Str, Hex, Hex]),
+        libc::SYS_exit => ("exit", &[Int]),
+        libc::SYS_wait4 => ("wait4", &[Int, Hex, Hex, Hex]),
+        libc::SYS_kill => ("kill", &[Int, Signal]),
+        libc::SYS_uname => ("uname", &[Hex]),
+        libc::SYS_fcntl => ("fcntl", &[Fd, Int, Hex]),
+        libc::SYS_fsync => ("fsync", &[Fd]),
+        libc::SYS_getcwd => ("getcwd", &[Hex, Uint]),
+        libc::SYS_readlink => ("readlink", &[Str, Hex, Uint]),
+        libc::SYS_umask => ("umask", &[Mode]),
+        libc::SYS_gettimeofday => ("gettimeofday", &[Hex, Hex]),
+        libc::SYS_getrlimit => ("getrlimit", &[Int, Hex]),
+        libc::SYS_getuid => ("getuid", &[]),
+        libc::SYS_getgid => ("getgid", &[]),
+        libc::SYS_geteuid => ("geteuid", &[]),
+        libc::SYS_getegid => ("getegid", &[]),
+        libc::SYS_sigaltstack => ("sigaltstack", &[Hex, Hex]),
+        libc::SYS_arch_prctl => ("arch_prctl", &[Hex, Hex]),
+        libc::SYS_gettid => ("gettid", &[]),
+        libc::SYS_futex => ("futex", &[Hex, Int, Int, Hex, Hex, Int]),
+        libc::SYS_epoll_create => ("epoll_create", &[Int]),
+        libc::SYS_getdents64 => ("getdents64", &[Fd, Hex, Uint]),
+        libc::SYS_set_tid_address => ("set_tid_address", &[Hex]),
+        libc::SYS_clock_gettime => ("clock_gettime", &[Clock, Hex]),
+        libc::SYS_clock_nanosleep => ("clock_nanosleep", &[Clock, Int, Hex, Hex]),
+        libc::SYS_exit_group => ("exit_group", &[Int]),
+        libc::SYS_epoll_wait => ("epoll_wait", &[Fd, Hex, Int, Int]),
+        libc::SYS_epoll_ctl => ("epoll_ctl", &[Fd, Int, Fd, Hex]),
+        libc::SYS_tgkill => ("tgkill", &[Int, Int, Signal]),
+        libc::SYS_openat => ("openat", &[Fd, Str, OpenFlags, Mode]),
+        libc::SYS_newfstatat => ("newfstatat", &[Fd, Str, Hex, Hex]),
+        libc::SYS_readlinkat => ("readlinkat", &[Fd, Str, Hex, Uint]),
+        libc::SYS_pselect6 => ("pselect6", &[Int, Hex, Hex, Hex, Hex, Hex]),
+        libc::SYS_ppoll => ("ppoll", &[Hex, Uint, Hex, Hex, Uint]),
+        libc::SYS_set_robust_list => ("set_robust_list", &[Hex, Uint]),
+        libc::SYS_epoll_pwait => ("epoll_pwait", &[Fd, Hex, Int, Int, Hex, Uint]),
+        libc::SYS_eventfd => ("eventfd", &[Uint]),
+        libc::SYS_accept4 => ("accept4", &[Fd, Hex, Hex, Hex]),
+        libc::SYS_eventfd2 => ("eventfd2", &[Uint, Hex]),
+        libc::SYS_epoll_create1 => ("epoll_create1", &[Hex]),
+        libc::SYS_dup3 => ("dup3", &[Fd, Fd, Hex]),
+        libc::SYS_pipe2 => ("pipe2", &[Hex, Hex]),
+        libc::SYS_prlimit64 => ("prlimit64", &[Int, Int, Hex, Hex]),
+        libc::SYS_getrandom => ("getrandom", &[Hex, Uint, Hex]),
+        libc::SYS_statx => ("statx", &[Fd, Str, Hex, Hex, Hex]),
+        SYS_ENARX_GETATT => ("enarx_getatt", &[Hex, Uint, Hex, Uint]),
+        SYS_ENARX_CPUID => ("enarx_cpuid", &[Hex, Hex]),
+        SYS_ENARX_MEM_INFO => ("enarx_mem_info", &[]),
+        SYS_ENARX_BALLOON_MEMORY => ("enarx_balloon_memory", &[Uint, Uint, Hex]),
+        _ => return None,
+    })
+}
+
+impl Tracer {
+    /// Create a new tracer writing to `out`
+    ///
+    /// Every record is written with a single call, so `out` should not buffer.
+    pub fn new(out: Box<dyn Write + Send>, format: Format) -> Self {
+        Self {
+            out,
+            format,
+            start: Instant::now(),
+        }
+    }
+
+    /// Write out the trace
+    pub fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+
+    /// Write a complete record with a single write
+    fn record(&mut self, record: String) {
+        let _ = self.out.write_all(record.as_bytes());
+    }
+
+    /// Trace a syscall request before it is serviced
+    ///
+    /// Syscalls ending the keep never return, so they are traced here
+    /// without a result.
+    pub fn syscall_enter(&mut self, req: &Request) {
+        if matches!(i64::from(req.num), libc::SYS_exit | libc::SYS_exit_group) {
+            self.syscall(req, None, Duration::default());
+            self.flush();
+        }
+    }
+
+    /// Trace a syscall request and its result
+    pub fn syscall(&mut self, req: &Request, rep: Option<&sallyport::Result>, took: Duration) {
+        const UNKNOWN: [Arg; 6] = [Arg::Hex; 6];
+
+        let num = i64::from(req.num);
+        let (name, kinds) = syscall(num).unwrap_or(("", &UNKNOWN));
+        let all: Vec<usize> = req.arg.iter().map(|a| usize::from(*a)).collect();
+        let args = &all[..kinds.len()];
+        let decoded: Vec<String> = kinds
+            .iter()
+            .enumerate()
+            .map(|(i, kind)| decode(*kind, i, &all, rep))
+            .collect();
+        let time = self.start.elapsed();
+
+        let record = match self.format {
+            Format::Text => {
+                let name = match name {
+                    "" => format!("syscall_{}", num),
+                    name => name.into(),
+                };
+
+                let ret = match rep {
+                    None => "?".into(),
+                    Some(Ok(ret)) => match num {
+                        libc::SYS_mmap | libc::SYS_mremap | libc::SYS_brk => {
+                            format!("{:#x}", usize::from(ret[0]))
+                        }
+                        _ => (usize::from(ret[0]) as i64).to_string(),
+                    },
+                    Some(Err(errno)) => {
+                        format!("-1 ({})", std::io::Error::from_raw_os_error(*errno))
+                    }
+                };
+
+                format!(
+                    "{}({}) = {} <{:.6}>\n",
+                    name,
+                    decoded.join(", "),
+                    ret,
+                    took.as_secs_f64()
+                )
+            }
+
+            Format::Json => {
+                let ret = match rep {
+                    None => "\"ret\":null".into(),
+                    Some(Ok(ret)) => {
+                        format!("\"ret\":[{},{}]", usize::from(ret[0]), usize::from(ret[1]))
+                    }
+                    Some(Err(errno)) => format!("\"errno\":{}", errno),
+                };
+
+                let decoded = decoded
+                    .iter()
+                    .map(|d| format!("\"{}\"", json(d)))
+                    .collect::<Vec<_>>()
+                    .join(",");
+
+                format!(
+                    "{{\"time\":{},\"kind\":\"syscall\",\"num\":{},\"name\":\"{}\",\"args\":{:?},\"decoded\":[{}],{},\"took\":{}}}\n",
+                    time.as_nanos(),
+                    num,
+                    name,
+                    args,
+                    decoded,
+                    ret,
+                    took.as_nanos()
+                )
+            }
+        };
+
+        self.record(record);
+    }
+
+    /// Trace a cpuid request and its result
+    pub fn cpuid(&mut self, leaf: u32, subleaf: u32, res: &[u32; 4], took: Duration) {
+        let time = self.start.elapsed();
+
+        let record = match self.format {
+            Format::Text => format!(
+                "cpuid({:#x}, {:#x}) = {:#x}, {:#x}, {:#x}, {:#x} <{:.6}>\n",
+                leaf,
+                subleaf,
+                res[0],
+                res[1],
+                res[2],
+                res[3],
+                took.as_secs_f64()
+            ),
+
+            Format::Json => format!(
+                "{{\"time\":{},\"kind\":\"cpuid\",\"leaf\":{},\"subleaf\":{},\"ret\":{:?},\"took\":{}}}\n",
+                time.as_nanos(),
+                leaf,
+                subleaf,
+                res,
+                took.as_nanos()
+            ),
+        };
+
+        self.record(record);
+    }
+}
@@ -0,0 +1,31 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Compute with AVX, if `cpuid` and `xgetbv` report the extended state for it
+
+use std::arch::x86_64::*;
+
+#[target_feature(enable = "avx")]
+unsafe fn sum(values: &[f64]) -> f64 {
+    let mut acc = _mm256_setzero_pd();
+    for chunk in values.chunks_exact(4) {
+        acc = _mm256_add_pd(acc, _mm256_loadu_pd(chunk.as_ptr()));
+
+        // The upper halves of the registers survive the syscalls.
+        std::thread::yield_now();
+    }
+
+    let mut lanes = [0f64; 4];
+    _mm256_storeu_pd(lanes.as_mut_ptr(), acc);
+    lanes.iter().sum()
+}
+
+fn main() {
+    if !is_x86_feature_detected!("avx") {
+        print!("none");
+        return;
+    }
+
+    let values: Vec<f64> = (1..=1024).map(f64::from).collect();
+    assert_eq!(unsafe { sum(&values) }, 1024.0 * 1025.0 / 2.0);
+    print!("avx");
+}
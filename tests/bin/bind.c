@@ -27,5 +27,15 @@ int main(void) {
         return errno;
     }
 
+    /* Socket paths are host paths, which are not granted */
+    strcpy(sa.sun_path, "/tmp/enarx_bind_test");
+    fd = socket(AF_UNIX, SOCK_STREAM | SOCK_CLOEXEC, 0);
+
+    if (fd < 0 || bind(fd, (struct sockaddr *)&sa, sizeof(sa)) != -1 || errno != EACCES)
+        return 254;
+
+    if (connect(fd, (struct sockaddr *)&sa, sizeof(sa)) != -1 || errno != EACCES)
+        return 255;
+
     return 0;
 }
@@ -0,0 +1,69 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+#include <sys/time.h>
+
+static long sys_gettimeofday(struct timeval *tv, void *tz) {
+    long rax;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (SYS_gettimeofday), "D" (tv), "S" (tz)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
+
+static long sys_time(time_t *tloc) {
+    long rax;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (SYS_time), "D" (tloc)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
+
+static int before(const struct timespec *a, const struct timespec *b) {
+    return a->tv_sec < b->tv_sec || (a->tv_sec == b->tv_sec && a->tv_nsec < b->tv_nsec);
+}
+
+int main(void) {
+    struct timespec last, now, real;
+    struct timeval tv;
+    time_t t;
+
+    // The monotonic clock never goes backwards.
+    if (clock_gettime(CLOCK_MONOTONIC, &last) != 0)
+        return 1;
+
+    for (int i = 0; i < 100000; i++) {
+        if (clock_gettime(CLOCK_MONOTONIC, &now) != 0)
+            return 2;
+
+        if (now.tv_nsec < 0 || now.tv_nsec >= 1000000000 || before(&now, &last))
+            return 3;
+
+        last = now;
+    }
+
+    if (clock_gettime(CLOCK_REALTIME, &real) != 0)
+        return 4;
+
+    // All wall clocks agree.
+    if (sys_gettimeofday(&tv, NULL) != 0 || tv.tv_usec < 0 || tv.tv_usec >= 1000000)
+        return 5;
+
+    if (tv.tv_sec < real.tv_sec || tv.tv_sec > real.tv_sec + 1)
+        return 6;
+
+    if (sys_time(&t) != t || t < tv.tv_sec || t > tv.tv_sec + 1)
+        return 7;
+
+    return write(STDOUT_FILENO, &real, sizeof(real)) != sizeof(real);
+}
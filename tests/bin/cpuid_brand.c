@@ -0,0 +1,24 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+
+static void cpuid(unsigned int leaf, unsigned int *regs) {
+    asm volatile(
+        "cpuid"
+        : "=a" (regs[0]), "=b" (regs[1]), "=c" (regs[2]), "=d" (regs[3])
+        : "a" (leaf), "c" (0)
+    );
+}
+
+int main(void) {
+    unsigned int brand[13] = { 0 };
+    size_t len = 0;
+
+    for (unsigned int i = 0; i < 3; i++)
+        cpuid(0x80000002 + i, &brand[i * 4]);
+
+    while (len < 48 && ((char *) brand)[len])
+        len++;
+
+    return write(STDOUT_FILENO, brand, len) != (ssize_t) len;
+}
@@ -0,0 +1,22 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Write the data of `exec --data` to stdout
+
+use std::env;
+use std::io::Write;
+
+fn var(name: &str) -> usize {
+    let value = env::var(name).unwrap();
+    match value.strip_prefix("0x") {
+        Some(hex) => usize::from_str_radix(hex, 16).unwrap(),
+        None => value.parse().unwrap(),
+    }
+}
+
+fn main() {
+    let addr = var("ENARX_DATA_ADDR");
+    let size = var("ENARX_DATA_SIZE");
+
+    let data = unsafe { std::slice::from_raw_parts(addr as *const u8, size) };
+    std::io::stdout().write_all(data).unwrap();
+}
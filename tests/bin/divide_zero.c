@@ -0,0 +1,8 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+
+int main(void) {
+    volatile int zero = 0;
+    return 1 / zero;
+}
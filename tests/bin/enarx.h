@@ -9,5 +9,9 @@ enum tee_tech {
     TEE_SGX,
 };
 
+/* Key policies of `get_key` */
+#define KEYPOLICY_MRENCLAVE 0x1
+#define KEYPOLICY_MRSIGNER 0x2
+
 #endif
 
@@ -0,0 +1,123 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+#include <fcntl.h>
+#include <sys/stat.h>
+
+static long sys_lseek(int fd, long offset, int whence) {
+    long rax;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (SYS_lseek), "D" (fd), "S" (offset), "d" (whence)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
+
+static long sys_dup(int fd) {
+    long rax;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (SYS_dup), "D" (fd)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
+
+static long sys_fstat(int fd, struct stat *statbuf) {
+    long rax;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (SYS_fstat), "D" (fd), "S" (statbuf)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
+
+static long sys_sendfile(int out_fd, int in_fd, long *offset, size_t count) {
+    register long r10 __asm__("r10") = count;
+    long rax;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (SYS_sendfile), "D" (out_fd), "S" (in_fd), "d" (offset), "r" (r10)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
+
+static int equal(const char *a, const char *b, size_t len) {
+    for (size_t i = 0; i < len; i++)
+        if (a[i] != b[i])
+            return 0;
+
+    return 1;
+}
+
+int main(void) {
+    static const char secret[] = "top secret\n";
+    char buf[64];
+
+    /* Paths relative to a directory can't be checked against the prefix */
+    if (sys_openat(0, "secret.txt", O_RDONLY, 0) != -EACCES)
+        return 1;
+
+    long fd = sys_openat(AT_FDCWD, "/data/secret.txt", O_RDWR | O_CREAT | O_TRUNC, 0600);
+
+    /* The keep has no key to derive the file key from */
+    if (fd == -ENOKEY) {
+        write(STDOUT_FILENO, "nokey\n", 6);
+        return 0;
+    }
+
+    if (fd < 0)
+        return 2;
+
+    if (write(fd, secret, sizeof(secret) - 1) != sizeof(secret) - 1)
+        return 3;
+
+    /* The file position and size are those of the plaintext */
+    if (sys_lseek(fd, 0, SEEK_CUR) != sizeof(secret) - 1)
+        return 4;
+
+    if (sys_lseek(fd, 0, SEEK_END) != sizeof(secret) - 1)
+        return 5;
+
+    if (sys_lseek(fd, 4, SEEK_SET) != 4)
+        return 6;
+
+    if (read(fd, buf, sizeof(buf)) != sizeof(secret) - 5)
+        return 7;
+
+    if (!equal(buf, secret + 4, sizeof(secret) - 5))
+        return 8;
+
+    /* Duplicates would not share the file position */
+    if (sys_dup(fd) != -EOPNOTSUPP)
+        return 9;
+
+    /* The size is that of the plaintext, too */
+    struct stat st;
+    if (sys_fstat(fd, &st) != 0 || st.st_size != sizeof(secret) - 1)
+        return 10;
+
+    /* The plaintext never leaves the keep without the shim */
+    if (sys_sendfile(STDOUT_FILENO, fd, 0, sizeof(secret)) != -EOPNOTSUPP)
+        return 11;
+
+    if (close(fd))
+        return 12;
+
+    return 0;
+}
@@ -0,0 +1,68 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+#include <fcntl.h>
+#include <netinet/in.h>
+#include <sys/epoll.h>
+
+static long sys(long nr, long a, long b, long c, long d) {
+    long rax;
+    register long r10 asm("r10") = d;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (nr), "D" (a), "S" (b), "d" (c), "r" (r10)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
+
+static long add(int epfd, int fd, unsigned long data) {
+    struct epoll_event event = { .events = EPOLLOUT, .data.u64 = data };
+    return sys(SYS_epoll_ctl, epfd, EPOLL_CTL_ADD, fd, (long) &event);
+}
+
+int main(void) {
+    struct epoll_event event = {};
+
+    int epfd = sys(SYS_epoll_create1, 0, 0, 0, 0);
+    if (epfd < 0)
+        return 1;
+
+    int a = socket(AF_INET, SOCK_DGRAM, 0);
+    int b = socket(AF_INET, SOCK_DGRAM, 0);
+    if (a < 0 || b < 0)
+        return 2;
+
+    /* A file descriptor can be registered, once its duplicate is closed */
+    int copy = sys(SYS_dup, a, 0, 0, 0);
+    if (copy < 0)
+        return 3;
+
+    if (add(epfd, a, 1) != -EOPNOTSUPP)
+        return 4;
+
+    if (close(copy) != 0 || add(epfd, a, 1) != 0)
+        return 5;
+
+    /* Registered file descriptors and `epoll` instances can't be duplicated */
+    if (sys(SYS_dup, a, 0, 0, 0) != -EOPNOTSUPP)
+        return 6;
+
+    if (sys(SYS_dup2, epfd, b, 0, 0) != -EOPNOTSUPP)
+        return 7;
+
+    if (sys(SYS_fcntl, a, F_DUPFD_CLOEXEC, 0, 0) != -EOPNOTSUPP)
+        return 8;
+
+    /* The registration of a closed file descriptor is gone */
+    if (add(epfd, b, 2) != 0 || close(a) != 0)
+        return 9;
+
+    if (sys(SYS_epoll_wait, epfd, (long) &event, 1, 0) != 1 || event.data.u64 != 2)
+        return 10;
+
+    return 0;
+}
@@ -0,0 +1,150 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! An echo server on an edge-triggered `epoll` loop, like the one of `mio`
+
+use std::collections::HashMap;
+use std::io::{self, stdin, ErrorKind, Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::io::{AsRawFd, RawFd};
+
+const CLIENTS: usize = 2;
+
+const LISTENER: u64 = 0;
+
+const EPOLLIN: u32 = 0x001;
+const EPOLLRDHUP: u32 = 0x2000;
+const EPOLLET: u32 = 1 << 31;
+const EPOLL_CTL_ADD: i32 = 1;
+const EPOLL_CTL_DEL: i32 = 2;
+const EPOLL_CLOEXEC: i32 = 0o2000000;
+
+const F_GETFL: i32 = 3;
+const F_SETFL: i32 = 4;
+const O_NONBLOCK: i32 = 0o4000;
+
+const EINTR: i32 = 4;
+
+#[repr(C, packed)]
+#[derive(Copy, Clone)]
+struct EpollEvent {
+    events: u32,
+    data: u64,
+}
+
+extern "C" {
+    fn epoll_create1(flags: i32) -> i32;
+    fn epoll_ctl(epfd: i32, op: i32, fd: i32, event: *mut EpollEvent) -> i32;
+    fn epoll_wait(epfd: i32, events: *mut EpollEvent, maxevents: i32, timeout: i32) -> i32;
+    fn fcntl(fd: i32, cmd: i32, ...) -> i32;
+}
+
+fn check(ret: i32) -> io::Result<i32> {
+    match ret {
+        -1 => Err(io::Error::last_os_error()),
+        ret => Ok(ret),
+    }
+}
+
+fn set_nonblocking(fd: RawFd) -> io::Result<()> {
+    let flags = check(unsafe { fcntl(fd, F_GETFL) })?;
+    check(unsafe { fcntl(fd, F_SETFL, flags | O_NONBLOCK) })?;
+    Ok(())
+}
+
+struct Epoll(RawFd);
+
+impl Epoll {
+    fn new() -> io::Result<Self> {
+        check(unsafe { epoll_create1(EPOLL_CLOEXEC) }).map(Self)
+    }
+
+    fn add(&self, fd: RawFd, token: u64) -> io::Result<()> {
+        let mut event = EpollEvent {
+            events: EPOLLIN | EPOLLRDHUP | EPOLLET,
+            data: token,
+        };
+
+        check(unsafe { epoll_ctl(self.0, EPOLL_CTL_ADD, fd, &mut event) })?;
+        Ok(())
+    }
+
+    fn delete(&self, fd: RawFd) -> io::Result<()> {
+        check(unsafe { epoll_ctl(self.0, EPOLL_CTL_DEL, fd, std::ptr::null_mut()) })?;
+        Ok(())
+    }
+
+    fn wait<'a>(&self, events: &'a mut [EpollEvent]) -> io::Result<&'a [EpollEvent]> {
+        loop {
+            let ret = unsafe { epoll_wait(self.0, events.as_mut_ptr(), events.len() as _, -1) };
+            match check(ret) {
+                Ok(n) => return Ok(&events[..n as usize]),
+                Err(e) if e.raw_os_error() == Some(EINTR) => continue,
+                Err(e) => return Err(e),
+            }
+        }
+    }
+}
+
+/// Echo all available data, returns whether the peer closed the connection
+fn echo(stream: &mut TcpStream) -> io::Result<bool> {
+    let mut buffer = [0u8; 4096];
+
+    loop {
+        match stream.read(&mut buffer) {
+            Ok(0) => return Ok(true),
+            Ok(n) => stream.write_all(&buffer[..n])?,
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return Ok(false),
+            Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+fn main() -> io::Result<()> {
+    let mut port = String::new();
+    stdin().read_line(&mut port)?;
+
+    let port: u16 = port.trim().parse().unwrap();
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    set_nonblocking(listener.as_raw_fd())?;
+
+    let epoll = Epoll::new()?;
+    epoll.add(listener.as_raw_fd(), LISTENER)?;
+
+    let mut streams = HashMap::new();
+    let mut accepted = 0;
+    let mut next = LISTENER + 1;
+    let mut events = [EpollEvent { events: 0, data: 0 }; 16];
+
+    while accepted < CLIENTS || !streams.is_empty() {
+        for event in epoll.wait(&mut events)? {
+            let token = event.data;
+
+            match token {
+                LISTENER => loop {
+                    let stream = match listener.accept() {
+                        Ok((stream, _)) => stream,
+                        Err(e) if e.kind() == ErrorKind::WouldBlock => break,
+                        Err(e) => return Err(e),
+                    };
+
+                    set_nonblocking(stream.as_raw_fd())?;
+                    epoll.add(stream.as_raw_fd(), next)?;
+                    streams.insert(next, stream);
+                    accepted += 1;
+                    next += 1;
+                },
+
+                _ => {
+                    let stream = streams.get_mut(&token).unwrap();
+                    if echo(stream)? {
+                        epoll.delete(stream.as_raw_fd())?;
+                        streams.remove(&token);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+
+static long sys_fchdir(int fd) {
+    long rax;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (SYS_fchdir), "D" (fd)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
+
+static long sys_pwritev(int fd, const struct iovec *iov, int iovcnt, off_t offset) {
+    long rax;
+    register off_t r10 __asm__("r10") = offset;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (SYS_pwritev), "D" (fd), "S" (iov), "d" (iovcnt), "r" (r10)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
+
+int main(void) {
+    struct iovec iov = { "", 0 };
+    long ret;
+
+    /* The loader has host file descriptors, which were not granted */
+    ret = sys_pwritev(3, &iov, 1, 0);
+    if (ret != -EBADF && ret != -ENOSYS)
+        return 1;
+
+    /* A syscall, which is not classified, is refused */
+    ret = sys_fchdir(STDOUT_FILENO);
+    if (ret != -ENOSYS && ret != -EBADF)
+        return 2;
+
+    return 0;
+}
@@ -0,0 +1,105 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+#include <fcntl.h>
+#include <sys/random.h>
+
+#ifndef SYS_close_range
+#define SYS_close_range 436
+#endif
+
+static long sys(long nr, long a, long b, long c) {
+    long rax;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (nr), "D" (a), "S" (b), "d" (c)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
+
+static long sys_getrandom(void *buf, size_t len, unsigned int flags) {
+    long rax;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (SYS_getrandom), "D" (buf), "S" (len), "d" (flags)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
+
+/* Two independent 32 byte random strings are never equal or all zero */
+static int differ(const unsigned char *a, const unsigned char *b) {
+    int diff = 0, zero = 1;
+
+    for (int i = 0; i < 32; i++) {
+        diff |= a[i] != b[i];
+        zero &= a[i] == 0;
+    }
+
+    return diff && !zero;
+}
+
+int main(void) {
+    unsigned char a[32] = {}, b[32] = {};
+
+    if (sys_getrandom(a, sizeof(a), 0) != sizeof(a))
+        return 1;
+
+    if (sys_getrandom(b, sizeof(b), GRND_NONBLOCK) != sizeof(b))
+        return 2;
+
+    if (!differ(a, b))
+        return 3;
+
+    if (sys_getrandom(a, sizeof(a), 0x80) != -EINVAL)
+        return 4;
+
+    long fd = sys_openat(AT_FDCWD, "/dev/urandom", O_RDONLY | O_CLOEXEC, 0);
+    if (fd < 0)
+        return 5;
+
+    if (read(fd, a, sizeof(a)) != sizeof(a))
+        return 6;
+
+    if (!differ(a, b))
+        return 7;
+
+    if (close(fd) != 0)
+        return 8;
+
+    /* A random device replaced by a duplicate is gone */
+    fd = sys_openat(AT_FDCWD, "/dev/urandom", O_RDONLY | O_CLOEXEC, 0);
+    int sock = socket(AF_INET, SOCK_DGRAM | SOCK_NONBLOCK, 0);
+    if (fd < 0 || sock < 0)
+        return 9;
+
+    if (sys(SYS_dup2, sock, fd, 0) != fd)
+        return 10;
+
+    if (read(fd, a, sizeof(a)) != -1 || errno != EAGAIN)
+        return 11;
+
+    if (close(fd) != 0 || close(sock) != 0)
+        return 12;
+
+    /* So is one closed by `close_range`, if the host allows it */
+    fd = sys_openat(AT_FDCWD, "/dev/urandom", O_RDONLY | O_CLOEXEC, 0);
+    if (fd < 0)
+        return 13;
+
+    if (sys(SYS_close_range, fd, fd, 0) == 0) {
+        if (read(fd, a, sizeof(a)) != -1 || errno != EBADF)
+            return 14;
+    } else if (read(fd, a, sizeof(a)) != sizeof(a) || close(fd) != 0) {
+        return 15;
+    }
+
+    return 0;
+}
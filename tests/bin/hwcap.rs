@@ -0,0 +1,17 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Check that `AT_HWCAP` matches the `cpuid` the payload sees
+
+use std::arch::x86_64::__cpuid;
+
+const AT_HWCAP: u64 = 16;
+
+extern "C" {
+    fn getauxval(kind: u64) -> u64;
+}
+
+fn main() {
+    let hwcap = unsafe { getauxval(AT_HWCAP) };
+    let edx = unsafe { __cpuid(1) }.edx;
+    assert_eq!(hwcap, u64::from(edx));
+}
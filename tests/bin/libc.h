@@ -8,6 +8,9 @@
 #include <sys/types.h>
 #include <sys/socket.h>
 #include <sys/utsname.h>
+#include <sys/select.h>
+#include <poll.h>
+#include <signal.h>
 
 int *__errno_location(void) {
     static int errnum = 0;
@@ -84,6 +87,24 @@ ssize_t write(int fd, const void *buf, size_t count) {
     return rax;
 }
 
+ssize_t writev(int fd, const struct iovec *iov, int iovcnt) {
+    ssize_t rax;
+
+    asm(
+        "syscall"
+        : "=a" (rax)
+        : "a" (SYS_writev), "D" (fd), "S" (iov), "d" (iovcnt)
+        : "%rcx", "%r11"
+    );
+
+    if (rax < 0) {
+        errno = -rax;
+        return -1;
+    }
+
+    return rax;
+}
+
 int clock_gettime(clockid_t clk_id, struct timespec *tp) {
     int rax;
 
@@ -140,6 +161,25 @@ ssize_t get_att(void *nonce, size_t nonce_len, void *buf, size_t buf_len, size_t
     return rax;
 }
 
+ssize_t get_key(unsigned short policy, const void *key_id, void *buf, size_t buf_len) {
+    ssize_t rax;
+    register size_t r10 __asm__("r10") = buf_len;
+
+    asm(
+        "syscall"
+        : "=a" (rax)
+        : "a" (0xEA10), "D" ((unsigned long) policy), "S" (key_id), "d" (buf), "r" (r10)
+        : "%rcx", "%r11", "memory"
+    );
+
+    if (rax < 0) {
+        errno = -rax;
+        return -1;
+    }
+
+    return rax;
+}
+
 uid_t getuid() {
     uid_t rax;
     asm(
@@ -352,3 +392,159 @@ ssize_t recvfrom(int sockfd, void *buf, size_t len, int flags,
 
     return rax;
 }
+
+int poll(struct pollfd *fds, nfds_t nfds, int timeout) {
+    int rax;
+
+    asm(
+    "syscall"
+    : "=a" (rax)
+    : "a" (SYS_poll), "D" (fds), "S" (nfds), "d" (timeout)
+    : "%rcx", "%r11"
+    );
+
+    if (rax < 0) {
+        errno = -rax;
+        return -1;
+    }
+
+    return rax;
+}
+
+int select(int nfds, fd_set *readfds, fd_set *writefds, fd_set *exceptfds,
+        struct timeval *timeout) {
+    int rax;
+    register fd_set *r10 __asm__("r10") = exceptfds;
+    register struct timeval *r8 __asm__("r8") = timeout;
+
+    asm(
+    "syscall"
+    : "=a" (rax)
+    : "a" (SYS_select), "D" (nfds), "S" (readfds), "d" (writefds), "r" (r10), "r" (r8)
+    : "%rcx", "%r11"
+    );
+
+    if (rax < 0) {
+        errno = -rax;
+        return -1;
+    }
+
+    return rax;
+}
+
+#ifndef SA_RESTORER
+#define SA_RESTORER 0x04000000
+#endif
+
+/* `struct sigaction` of the kernel, which differs from the one of the C library */
+struct kernel_sigaction {
+    void (*handler)(int);
+    unsigned long flags;
+    void (*restorer)(void);
+    unsigned long mask;
+};
+
+/* The restorer of `SA_RESTORER`, which returns from a signal handler */
+void __restore_rt(void);
+asm(
+    "__restore_rt:\n"
+    "    mov $15, %rax\n" /* SYS_rt_sigreturn */
+    "    syscall\n"
+    "    ud2\n"
+);
+
+int rt_sigaction(int signum, const struct kernel_sigaction *act, struct kernel_sigaction *oldact) {
+    int rax;
+    register size_t r10 __asm__("r10") = sizeof(unsigned long);
+
+    asm(
+    "syscall"
+    : "=a" (rax)
+    : "a" (SYS_rt_sigaction), "D" (signum), "S" (act), "d" (oldact), "r" (r10)
+    : "%rcx", "%r11", "memory"
+    );
+
+    if (rax < 0) {
+        errno = -rax;
+        return -1;
+    }
+
+    return rax;
+}
+
+int rt_sigprocmask(int how, const unsigned long *set, unsigned long *oldset) {
+    int rax;
+    register size_t r10 __asm__("r10") = sizeof(unsigned long);
+
+    asm volatile(
+    "syscall"
+    : "=a" (rax)
+    : "a" (SYS_rt_sigprocmask), "D" (how), "S" (set), "d" (oldset), "r" (r10)
+    : "%rcx", "%r11", "memory"
+    );
+
+    if (rax < 0) {
+        errno = -rax;
+        return -1;
+    }
+
+    return rax;
+}
+
+/* Handle `signum` with `handler`, which returns with `__restore_rt` */
+int handle_signal(int signum, void (*handler)(int), unsigned long flags) {
+    struct kernel_sigaction act = {
+        .handler = handler,
+        .flags = flags | SA_RESTORER,
+        .restorer = __restore_rt,
+        .mask = 0,
+    };
+
+    return rt_sigaction(signum, &act, NULL);
+}
+
+pid_t getpid(void) {
+    pid_t rax;
+
+    asm(
+    "syscall"
+    : "=a" (rax)
+    : "a" (SYS_getpid)
+    : "%rcx", "%r11"
+    );
+
+    return rax;
+}
+
+int kill(pid_t pid, int sig) {
+    int rax;
+
+    asm volatile(
+    "syscall"
+    : "=a" (rax)
+    : "a" (SYS_kill), "D" (pid), "S" (sig)
+    : "%rcx", "%r11", "memory"
+    );
+
+    if (rax < 0) {
+        errno = -rax;
+        return -1;
+    }
+
+    return rax;
+}
+
+/* The raw syscall, which returns the negated error number on failure */
+long sys_openat(int dirfd, const char *path, int flags, int mode) {
+    long rax;
+    register long r10 asm("r10") = mode;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (SYS_openat), "D" (dirfd), "S" (path), "d" (flags), "r" (r10)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
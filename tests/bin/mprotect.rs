@@ -0,0 +1,79 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Change the permissions of mapped pages back and forth and check that
+//! their data survives, which changes the pages of SGX2 enclaves with EDMM
+
+use std::io::Error;
+use std::os::raw::{c_int, c_long, c_void};
+
+const PROT_READ: c_int = 1;
+const PROT_WRITE: c_int = 2;
+const MAP_PRIVATE: c_int = 0x02;
+const MAP_ANONYMOUS: c_int = 0x20;
+const MAP_FAILED: *mut c_void = !0 as _;
+const EINVAL: c_int = 22;
+const SYS_MPROTECT: c_long = 10;
+
+const PAGES: usize = 16;
+const PAGE_SIZE: usize = 4096;
+const LEN: usize = PAGES * PAGE_SIZE;
+
+extern "C" {
+    fn mmap(
+        addr: *mut c_void,
+        len: usize,
+        prot: c_int,
+        flags: c_int,
+        fd: c_int,
+        off: c_long,
+    ) -> *mut c_void;
+    fn mprotect(addr: *mut c_void, len: usize, prot: c_int) -> c_int;
+    fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    fn syscall(nr: c_long, ...) -> c_long;
+}
+
+fn main() {
+    let rw = PROT_READ | PROT_WRITE;
+
+    let addr = unsafe {
+        mmap(
+            std::ptr::null_mut(),
+            LEN,
+            rw,
+            MAP_PRIVATE | MAP_ANONYMOUS,
+            -1,
+            0,
+        )
+    };
+    assert_ne!(addr, MAP_FAILED);
+
+    let pages = unsafe { std::slice::from_raw_parts_mut(addr as *mut u8, LEN) };
+    for (i, page) in pages.chunks_mut(PAGE_SIZE).enumerate() {
+        page.fill(i as u8);
+    }
+
+    // The range has to start at a page. The libc rounds the address down,
+    // so the kernel only gets to see it without the libc.
+    let unaligned = unsafe { addr.add(1) };
+    let ret = unsafe { syscall(SYS_MPROTECT, unaligned, PAGE_SIZE, PROT_READ) };
+    assert_eq!(ret, -1);
+    assert_eq!(Error::last_os_error().raw_os_error(), Some(EINVAL));
+
+    assert_eq!(unsafe { mprotect(addr, LEN, PROT_READ) }, 0);
+    for (i, page) in pages.chunks(PAGE_SIZE).enumerate() {
+        assert!(page.iter().all(|b| *b == i as u8));
+    }
+
+    // Only the middle pages get writable again.
+    let middle = unsafe { addr.add(PAGE_SIZE) };
+    assert_eq!(unsafe { mprotect(middle, LEN - 2 * PAGE_SIZE, rw) }, 0);
+    for page in pages[PAGE_SIZE..LEN - PAGE_SIZE].chunks_mut(PAGE_SIZE) {
+        page.fill(0xff);
+    }
+
+    assert_eq!(pages[0], 0);
+    assert_eq!(pages[LEN - 1], (PAGES - 1) as u8);
+    assert!(pages[PAGE_SIZE..LEN - PAGE_SIZE].iter().all(|b| *b == 0xff));
+
+    assert_eq!(unsafe { munmap(addr, LEN) }, 0);
+}
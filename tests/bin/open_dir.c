@@ -0,0 +1,127 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+#include <fcntl.h>
+
+/* Only declared with `_GNU_SOURCE`, which `libc.h` does not build with */
+#ifndef O_TMPFILE
+#define O_TMPFILE (020000000 | O_DIRECTORY)
+#endif
+
+static long sys_getdents64(int fd, void *dirp, size_t count) {
+    long rax;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (SYS_getdents64), "D" (fd), "S" (dirp), "d" (count)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
+
+static long sys_fchmod(int fd, mode_t mode) {
+    long rax;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (SYS_fchmod), "D" (fd), "S" (mode)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
+
+static int same(const char *a, const char *b) {
+    while (*a && *a == *b)
+        a++, b++;
+
+    return *a == *b;
+}
+
+/* The entries of the directory `fd`, one bit for each of `hello.txt` and `world.txt` */
+static int list(int fd) {
+    char buf[1024] __attribute__((aligned(8)));
+    int found = 0;
+    long len;
+
+    while ((len = sys_getdents64(fd, buf, sizeof(buf))) > 0) {
+        for (long off = 0; off < len;) {
+            /* `d_ino`, `d_off`, `d_reclen` and `d_type` precede `d_name` */
+            unsigned short reclen = *(unsigned short *) &buf[off + 16];
+            const char *name = &buf[off + 19];
+
+            if (same(name, "hello.txt"))
+                found |= 1;
+            else if (same(name, "world.txt"))
+                found |= 2;
+
+            off += reclen;
+        }
+    }
+
+    return len < 0 ? -1 : found;
+}
+
+static int copy(int fd) {
+    char buf[64];
+    ssize_t len = read(fd, buf, sizeof(buf));
+
+    if (len <= 0)
+        return 1;
+
+    return write(STDOUT_FILENO, buf, len) != len;
+}
+
+int main(void) {
+    long fd = sys_openat(AT_FDCWD, "/data/hello.txt", O_RDONLY, 0);
+
+    if (fd < 0 || copy(fd) || close(fd))
+        return 1;
+
+    /* The file granted with `--fd` */
+    if (copy(3))
+        return 2;
+
+    /* Nothing outside of the mapped directories is visible */
+    if (sys_openat(AT_FDCWD, "/etc/passwd", O_RDONLY, 0) != -ENOENT)
+        return 3;
+
+    if (sys_openat(AT_FDCWD, "/data/../../etc/passwd", O_RDONLY, 0) >= 0)
+        return 4;
+
+    /* The directory is mapped read only */
+    if (sys_openat(AT_FDCWD, "/data/new.txt", O_WRONLY | O_CREAT, 0644) != -EROFS)
+        return 5;
+
+    /* File descriptors, which were not granted, are rejected */
+    if (write(42, "", 0) != -1 || errno != EBADF)
+        return 6;
+
+    /* Directories open read only, even though `O_DIRECTORY` shares a bit with `O_TMPFILE` */
+    long dir = sys_openat(AT_FDCWD, "/data", O_RDONLY | O_DIRECTORY, 0);
+    if (dir < 0)
+        return 7;
+
+    if (list(dir) != 3 || close(dir))
+        return 8;
+
+    if (sys_openat(AT_FDCWD, "/data", O_WRONLY | O_TMPFILE, 0600) != -EROFS)
+        return 9;
+
+    /* The directory granted with `--fd` can be listed, too */
+    if (list(4) != 3)
+        return 10;
+
+    /* The mode of read-only files and directories stays */
+    if (sys_fchmod(3, 0777) >= 0 || sys_fchmod(4, 0777) >= 0)
+        return 11;
+
+    fd = sys_openat(AT_FDCWD, "/data/hello.txt", O_RDONLY, 0);
+    if (fd < 0 || sys_fchmod(fd, 0777) >= 0 || close(fd))
+        return 12;
+
+    return 0;
+}
@@ -0,0 +1,36 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+
+int main(void) {
+    char buf[16] = {};
+    struct pollfd fds[2] = {
+        { .fd = STDIN_FILENO, .events = POLLIN },
+        { .fd = -1, .events = POLLIN },
+    };
+    struct timeval tv = { .tv_sec = 10 };
+    fd_set rfds;
+
+    /* Negative file descriptors are ignored */
+    if (poll(fds, 2, -1) != 1)
+        return 1;
+
+    if (!(fds[0].revents & POLLIN) || fds[1].revents != 0)
+        return 2;
+
+    FD_ZERO(&rfds);
+    FD_SET(STDIN_FILENO, &rfds);
+
+    if (select(STDIN_FILENO + 1, &rfds, NULL, NULL, &tv) != 1)
+        return 3;
+
+    if (!FD_ISSET(STDIN_FILENO, &rfds))
+        return 4;
+
+    ssize_t out = read(STDIN_FILENO, buf, sizeof(buf));
+    if (out <= 0)
+        return 5;
+
+    write(STDOUT_FILENO, buf, out);
+    return 0;
+}
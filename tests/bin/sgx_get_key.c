@@ -0,0 +1,64 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+#include "enarx.h"
+#include <errno.h>
+
+/* This test will be run only for SGX. It derives seal keys of the
+ * enclave and checks that they depend on the policy and key ID only. */
+
+static int equal(const unsigned char *a, const unsigned char *b) {
+    for (int i = 0; i < 16; i++)
+        if (a[i] != b[i])
+            return 0;
+
+    return 1;
+}
+
+/* `get_key()` with all 64 bits of the policy */
+static long sys_get_key(unsigned long policy, void *buf, size_t buf_len) {
+    long rax;
+    register size_t r10 __asm__("r10") = buf_len;
+
+    asm volatile(
+        "syscall"
+        : "=a" (rax)
+        : "a" (0xEA10), "D" (policy), "S" (NULL), "d" (buf), "r" (r10)
+        : "%rcx", "%r11", "memory"
+    );
+
+    return rax;
+}
+
+int main(void) {
+    unsigned char id[32] = { 1 };
+    unsigned char a[16], b[16], c[16], d[16];
+
+    /* this test is SGX-specific, so just return success if not running on SGX */
+    if (get_key(KEYPOLICY_MRENCLAVE, NULL, a, sizeof(a)) == -1)
+        return errno != ENOSYS;
+
+    /* The same key is derived again */
+    if (get_key(KEYPOLICY_MRENCLAVE, NULL, b, sizeof(b)) != 16 || !equal(a, b))
+        return 1;
+
+    /* The key ID and the policy select different keys */
+    if (get_key(KEYPOLICY_MRENCLAVE, id, c, sizeof(c)) != 16 || equal(a, c))
+        return 2;
+
+    if (get_key(KEYPOLICY_MRSIGNER, NULL, d, sizeof(d)) != 16 || equal(a, d))
+        return 3;
+
+    /* Invalid policies and short buffers are rejected */
+    if (get_key(0, NULL, d, sizeof(d)) != -1 || errno != EINVAL)
+        return 4;
+
+    if (get_key(KEYPOLICY_MRENCLAVE, NULL, d, 8) != -1 || errno != EINVAL)
+        return 5;
+
+    /* Policies with bits beyond the valid ones aren't truncated to them */
+    if (sys_get_key(0x10000 | KEYPOLICY_MRENCLAVE, d, sizeof(d)) != -EINVAL)
+        return 6;
+
+    return 0;
+}
@@ -0,0 +1,35 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+
+static volatile int caught = 0;
+
+static void handler(int signo) {
+    caught = signo;
+}
+
+int main(void) {
+    unsigned long set = 1UL << (SIGUSR1 - 1);
+
+    if (handle_signal(SIGUSR1, handler, 0) != 0)
+        return 1;
+
+    /* A blocked signal stays pending */
+    if (rt_sigprocmask(SIG_BLOCK, &set, NULL) != 0)
+        return 2;
+
+    if (kill(getpid(), SIGUSR1) != 0)
+        return 3;
+
+    if (caught != 0)
+        return 4;
+
+    /* ... and is delivered, when it is unblocked */
+    if (rt_sigprocmask(SIG_UNBLOCK, &set, NULL) != 0)
+        return 5;
+
+    if (caught != SIGUSR1)
+        return 6;
+
+    return 0;
+}
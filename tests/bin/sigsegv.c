@@ -0,0 +1,47 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+
+/* The index of `rip` in the registers of a signal frame */
+#define RIP 16
+
+/* The offset of `struct _fpx_sw_bytes` in the extended state */
+#define FP_XSTATE_SW_BYTES 464
+
+static int *const bad = (int *) 0x10;
+
+void resume(void);
+
+static void handler(int signo, siginfo_t *info, void *context) {
+    ucontext_t *uc = context;
+    char *fpstate = (char *) uc->uc_mcontext.fpregs;
+
+    /* The fault is delivered with its address */
+    if (signo != SIGSEGV || info->si_code != SEGV_MAPERR || info->si_addr != bad)
+        _exit(1);
+
+    /* The frame holds the extended state */
+    if (fpstate == NULL || *(unsigned int *) (fpstate + FP_XSTATE_SW_BYTES) != FP_XSTATE_MAGIC1)
+        _exit(2);
+
+    /* Skip the faulting instruction, when the handler returns */
+    uc->uc_mcontext.gregs[RIP] = (greg_t) resume;
+}
+
+int main(void) {
+    int value = 0;
+
+    if (handle_signal(SIGSEGV, (void (*)(int)) handler, SA_SIGINFO) != 0)
+        return 3;
+
+    asm volatile(
+        "movl (%1), %0\n"
+        "resume:\n"
+        : "+r" (value)
+        : "r" (bad)
+        : "memory"
+    );
+
+    /* `rt_sigreturn` restored the registers and the extended state */
+    return value == 0 ? 42 : 4;
+}
@@ -0,0 +1,17 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+
+int main(void) {
+    unsigned long set = 1UL << (SIGTERM - 1);
+
+    if (rt_sigprocmask(SIG_BLOCK, &set, NULL) != 0)
+        return 1;
+
+    if (write(STDOUT_FILENO, "ready\n", 6) != 6)
+        return 2;
+
+    /* The signal stays pending, until the grace period is over */
+    for (;;)
+        getuid();
+}
@@ -0,0 +1,23 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+
+static volatile int caught = 0;
+
+static void handler(int signo) {
+    caught = signo;
+}
+
+int main(void) {
+    if (handle_signal(SIGTERM, handler, 0) != 0)
+        return 1;
+
+    if (write(STDOUT_FILENO, "ready\n", 6) != 6)
+        return 2;
+
+    /* The loader interrupts the keep, which never makes a hostcall */
+    while (caught == 0)
+        ;
+
+    return caught == SIGTERM ? 42 : 3;
+}
@@ -0,0 +1,8 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+
+int main(void) {
+    /* Spin without ever leaving the keep, until GDB interrupts it */
+    for (;;) {}
+}
@@ -0,0 +1,10 @@
+// SPDX-License-Identifier: Apache-2.0
+
+#include "libc.h"
+
+int main(void) {
+    /* Spin in syscalls, until GDB interrupts it */
+    for (;;) {
+        getuid();
+    }
+}
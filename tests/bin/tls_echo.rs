@@ -0,0 +1,23 @@
+// SPDX-License-Identifier: Apache-2.0
+
+use std::io::{self, stdin, Read, Write};
+use std::net::TcpListener;
+
+fn main() -> io::Result<()> {
+    let mut port = String::new();
+    stdin().read_line(&mut port)?;
+
+    let port: u16 = port.trim().parse().unwrap();
+    let listener = TcpListener::bind(("127.0.0.1", port))?;
+    let (mut socket, _) = listener.accept()?;
+
+    let mut buffer = [0u8; 4096];
+    loop {
+        match socket.read(&mut buffer)? {
+            0 => break,
+            n => socket.write_all(&buffer[..n])?,
+        }
+    }
+
+    Ok(())
+}
@@ -0,0 +1,33 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! Query the clocks in a loop, which libc does with the vDSO, if there is one
+
+use std::time::{Instant, SystemTime, UNIX_EPOCH};
+
+const AT_SYSINFO_EHDR: u64 = 33;
+
+extern "C" {
+    fn getauxval(kind: u64) -> u64;
+}
+
+fn main() {
+    let vdso = unsafe { getauxval(AT_SYSINFO_EHDR) };
+    if vdso != 0 {
+        let magic = unsafe { std::slice::from_raw_parts(vdso as *const u8, 4) };
+        assert_eq!(magic, b"\x7fELF");
+    }
+
+    let mut last = Instant::now();
+    for _ in 0..100_000 {
+        let now = Instant::now();
+        assert!(now >= last);
+        last = now;
+    }
+
+    assert!(SystemTime::now().duration_since(UNIX_EPOCH).is_ok());
+
+    match vdso {
+        0 => print!("none"),
+        _ => print!("vdso"),
+    }
+}
@@ -0,0 +1,35 @@
+// SPDX-License-Identifier: Apache-2.0
+
+//! An echo server on the `epoll` reactor of a single threaded `tokio` runtime
+
+use std::io::{self, stdin};
+
+use tokio::io::copy;
+use tokio::net::TcpListener;
+
+const CLIENTS: usize = 2;
+
+#[tokio::main(flavor = "current_thread")]
+async fn main() -> io::Result<()> {
+    let mut port = String::new();
+    stdin().read_line(&mut port)?;
+
+    let port: u16 = port.trim().parse().unwrap();
+    let listener = TcpListener::bind(("127.0.0.1", port)).await?;
+
+    let mut tasks = Vec::with_capacity(CLIENTS);
+    for _ in 0..CLIENTS {
+        let (mut stream, _) = listener.accept().await?;
+
+        tasks.push(tokio::spawn(async move {
+            let (mut reader, mut writer) = stream.split();
+            copy(&mut reader, &mut writer).await
+        }));
+    }
+
+    for task in tasks {
+        task.await.unwrap()?;
+    }
+
+    Ok(())
+}
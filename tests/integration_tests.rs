@@ -4,7 +4,9 @@
 use std::fs;
 use std::io::{Read, Write};
 use std::mem::{size_of, MaybeUninit};
+use std::net::{Shutdown, TcpStream};
 use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::PermissionsExt;
 use std::os::unix::net::{UnixListener, UnixStream};
 use std::path::Path;
 use std::process::{Command, Stdio};
@@ -12,6 +14,11 @@ use std::slice::from_raw_parts_mut;
 use std::thread;
 use std::time::Duration;
 
+use openssl::hash::MessageDigest;
+use openssl::pkey::PKey;
+use openssl::sha::sha256;
+use openssl::sign::Signer;
+use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode};
 use process_control::{ChildExt, Output, Timeout};
 use serial_test::serial;
 use std::sync::Arc;
@@ -54,6 +61,18 @@ fn run_test<'a>(
     input: impl Into<Option<&'a [u8]>>,
     expected_stdout: impl Into<Option<&'a [u8]>>,
     expected_stderr: impl Into<Option<&'a [u8]>>,
+) -> Output {
+    run_test_args(&[], bin, status, input, expected_stdout, expected_stderr)
+}
+
+/// Like `run_test`, but passes additional `args` to `enarx-keepldr exec`.
+fn run_test_args<'a>(
+    args: &[&str],
+    bin: &str,
+    status: i32,
+    input: impl Into<Option<&'a [u8]>>,
+    expected_stdout: impl Into<Option<&'a [u8]>>,
+    expected_stderr: impl Into<Option<&'a [u8]>>,
 ) -> Output {
     let expected_stdout = expected_stdout.into();
     let expected_stderr = expected_stderr.into();
@@ -62,6 +81,7 @@ fn run_test<'a>(
     let mut child = Command::new(&String::from(KEEP_BIN))
         .current_dir(CRATE)
         .arg("exec")
+        .args(args)
         .arg(bin_path)
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
@@ -145,6 +165,12 @@ fn exit_zero() {
     run_test("exit_zero", 0, None, None, None);
 }
 
+#[test]
+#[serial]
+fn exit_zero_debug() {
+    run_test_args(&["--debug"], "exit_zero", 0, None, None, None);
+}
+
 #[test]
 #[serial]
 fn exit_one() {
@@ -180,18 +206,202 @@ fn clock_gettime() {
     assert!(nsec < MAX_SEC * NSEC_PER_SEC);
 }
 
+#[test]
+#[serial]
+fn clock_realtime() {
+    use libc::{clock_gettime, CLOCK_REALTIME};
+
+    // Get the wall clock time from inside the keep, which must not jump backwards.
+    let stdout = run_test_args(&["--strict-time"], "clock_realtime", 0, None, None, None).stdout;
+    let theirs: libc::timespec = read_item(stdout.as_slice()).unwrap();
+
+    // Get the time from outside the keep.
+    let ours = unsafe {
+        let mut ts = MaybeUninit::uninit();
+        assert_eq!(0, clock_gettime(CLOCK_REALTIME, ts.as_mut_ptr()));
+        ts.assume_init()
+    };
+
+    // The wall clock of the keep only takes hints from the host, but it follows them.
+    const NSEC_PER_SEC: libc::c_long = 1_000_000_000;
+    const MAX_SEC: libc::c_long = 2;
+
+    let nsec = (ours.tv_sec - theirs.tv_sec) * NSEC_PER_SEC + ours.tv_nsec - theirs.tv_nsec;
+    assert!(nsec > -MAX_SEC * NSEC_PER_SEC);
+    assert!(nsec < MAX_SEC * NSEC_PER_SEC);
+}
+
+#[test]
+#[serial]
+fn hwcap() {
+    run_test("hwcap", 0, None, None, None);
+}
+
+#[test]
+#[serial]
+fn avx() {
+    // SGX keeps have the extended state of AVX, if the CPU supports it in enclaves.
+    let stdout = run_test("avx", 0, None, None, None).stdout;
+    assert!(stdout == b"avx" || stdout == b"none");
+}
+
+#[test]
+#[serial]
+fn vdso() {
+    let tmpdir = TempDir::new("vdso").unwrap();
+    let trace = tmpdir.path().join("trace.jsonl");
+
+    let stdout = run_test_args(
+        &["--trace-file", trace.to_str().unwrap()],
+        "vdso",
+        0,
+        None,
+        None,
+        None,
+    )
+    .stdout;
+
+    // Only the calibration and the hints for the wall clock reach the host.
+    if stdout == b"vdso" {
+        let trace = fs::read_to_string(trace).unwrap();
+        let count = trace
+            .lines()
+            .filter(|l| l.contains("\"name\":\"clock_gettime\""))
+            .count();
+        assert!(count < 100);
+    }
+}
+
+#[test]
+#[serial]
+fn cpuid_brand() {
+    let tmpdir = TempDir::new("cpuid_brand").unwrap();
+    let policy = tmpdir.path().join("cpuid");
+    fs::write(&policy, "brand Enarx Test CPU  # without a model number\n").unwrap();
+
+    run_test_args(
+        &["--cpuid", policy.to_str().unwrap()],
+        "cpuid_brand",
+        0,
+        None,
+        &b"Enarx Test CPU"[..],
+        None,
+    );
+}
+
+/// The SGX loader refuses CPUID policies, whose answers the shim would refuse.
+#[cfg(feature = "backend-sgx")]
+#[test]
+#[serial]
+fn sgx_cpuid_policy() {
+    if std::env::var_os("ENARX_BACKEND").map_or(false, |b| b != "sgx") {
+        return;
+    }
+
+    // The extended state is limited to what the profile exposes.
+    run_test_args(&["--cpuid", "x86-64-v2"], "exit_zero", 0, None, None, None);
+
+    let tmpdir = TempDir::new("sgx_cpuid_policy").unwrap();
+    let policy = tmpdir.path().join("cpuid");
+
+    for rules in &[
+        "0x1 ecx and 0xfbffffff  # without XSAVE\n",
+        "0x0 eax max 0x11  # without the SGX leaf\n",
+        "profile x86-64-v2\n",
+    ] {
+        fs::write(&policy, rules).unwrap();
+
+        let mut args = vec!["--cpuid", policy.to_str().unwrap()];
+        if rules.starts_with("profile") {
+            // The profile hides the AVX state.
+            args.extend(&["--xstate", "avx"]);
+        }
+
+        let output = run_test_args(&args, "exit_zero", 1, None, None, None);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("which the SGX shim refuses"), "{}", stderr);
+    }
+}
+
 #[test]
 #[serial]
 fn close() {
     run_test("close", 0, None, None, None);
 }
 
+/// File descriptors, which were not granted, and unknown syscalls are refused.
+#[test]
+#[serial]
+fn fd_policy() {
+    run_test("fd_policy", 0, None, None, None);
+}
+
 #[test]
 #[serial]
 fn write_stdout() {
     run_test("write_stdout", 0, None, &b"hi\n"[..], None);
 }
 
+#[test]
+#[serial]
+fn write_stdout_trace_file() {
+    let tmpdir = TempDir::new("trace_file").unwrap();
+    let trace = tmpdir.path().join("trace.jsonl");
+
+    run_test_args(
+        &["--trace-file", trace.to_str().unwrap()],
+        "write_stdout",
+        0,
+        None,
+        &b"hi\n"[..],
+        None,
+    );
+
+    let trace = fs::read_to_string(trace).unwrap();
+    assert!(trace.lines().any(|l| l.contains("\"name\":\"write\"")
+        && l.contains("\"decoded\":[\"1\",")
+        && l.contains("\"ret\":[3,")));
+    assert!(trace
+        .lines()
+        .any(|l| l.contains("\"name\":\"exit\"") || l.contains("\"name\":\"exit_group\"")));
+}
+
+/// The trace decodes file descriptors and the buffers of the keep.
+#[test]
+#[serial]
+fn write_stdout_trace() {
+    let output = run_test_args(&["--trace"], "write_stdout", 0, None, &b"hi\n"[..], None);
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("write(1, \"hi\\n\", 3) = 3 <"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+#[serial]
+fn getrandom() {
+    let tmpdir = TempDir::new("getrandom").unwrap();
+    let trace = tmpdir.path().join("trace.jsonl");
+
+    run_test_args(
+        &["--trace-file", trace.to_str().unwrap()],
+        "getrandom",
+        0,
+        None,
+        None,
+        None,
+    );
+
+    // Neither `getrandom` nor opening `/dev/urandom` reach the host.
+    let trace = fs::read_to_string(trace).unwrap();
+    assert!(!trace
+        .lines()
+        .any(|l| l.contains("\"name\":\"getrandom\"") || l.contains("\"name\":\"openat\"")));
+}
+
 #[test]
 #[serial]
 fn write_stderr() {
@@ -207,6 +417,134 @@ fn write_emsgsize() {
     run_test("write_emsgsize", 0, None, None, None);
 }
 
+/// Returns the address of the first function in the ELF file at `path`, whose name `matches`
+fn function(path: &Path, matches: impl Fn(&str) -> bool) -> u64 {
+    let data = fs::read(path).unwrap();
+    let elf = goblin::elf::Elf::parse(&data).unwrap();
+
+    elf.syms
+        .iter()
+        .find(|sym| {
+            sym.is_function()
+                && sym.st_value != 0
+                && sym.st_size != 0
+                && elf.strtab.get_at(sym.st_name).map_or(false, &matches)
+        })
+        .unwrap_or_else(|| panic!("no matching function in {:?}", path))
+        .st_value
+}
+
+/// Run `enarx-keepldr symbolize` with the `args` on the `log`
+fn symbolize(args: &[&std::ffi::OsStr], log: &str) -> String {
+    let mut child = Command::new(KEEP_BIN)
+        .arg("symbolize")
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(log.as_bytes())
+        .unwrap();
+
+    let output = child.wait_with_output().unwrap();
+    assert!(output.status.success());
+    String::from_utf8(output.stdout).unwrap()
+}
+
+/// Frames of the payload in a saved stack trace are resolved to the source.
+#[test]
+fn symbolize_payload() {
+    let payload = Path::new(OUT_DIR).join(TEST_BINS_OUT).join("exit_zero");
+    let main = function(&payload, |name| name == "main");
+
+    let log = format!("TRACE:\nP {:#x}\n", main);
+    let out = symbolize(&["--payload".as_ref(), payload.as_os_str()], &log);
+
+    assert!(out.starts_with(&log), "{}", out);
+    assert!(
+        out.lines()
+            .any(|l| l.starts_with("Payl: main at ") && l.contains("exit_zero.c:")),
+        "{}",
+        out
+    );
+}
+
+/// Has to match `SHIM_VIRT_OFFSET` in `internal/shim-sev/src/addr.rs`.
+#[cfg(feature = "backend-kvm")]
+const SHIM_VIRT_OFFSET: u64 = 0xFFFF_FF80_0000_0000;
+
+/// The unstripped shim-sev, as `build.rs` built it before stripping it
+#[cfg(feature = "backend-kvm")]
+fn unstripped_shim_sev() -> std::path::PathBuf {
+    let profile = if cfg!(debug_assertions) {
+        "debug"
+    } else {
+        "release"
+    };
+
+    Path::new(OUT_DIR)
+        .join("internal/shim-sev/x86_64-unknown-linux-musl")
+        .join(profile)
+        .join("shim-sev")
+}
+
+/// Registers of a register dump pointing into the shim are resolved, the stack pointer is not.
+#[cfg(feature = "backend-kvm")]
+#[test]
+fn symbolize_shim_registers() {
+    let shim = unstripped_shim_sev();
+    let addr = function(&shim, |_| true) + SHIM_VIRT_OFFSET;
+
+    let log = format!("rip: {:#x},\n", addr);
+    let out = symbolize(&["--shim".as_ref(), shim.as_os_str()], &log);
+    assert!(
+        out.lines()
+            .nth(1)
+            .map_or(false, |l| l.starts_with("Shim: ")),
+        "{}",
+        out
+    );
+
+    let log = format!("rsp: {:#x},\n", addr);
+    let out = symbolize(&["--shim".as_ref(), shim.as_os_str()], &log);
+    assert_eq!(out, log);
+}
+
+/// A stack trace written with `writev` to stderr is symbolized, too.
+#[cfg(feature = "backend-kvm")]
+#[test]
+#[serial]
+fn symbolize_stderr() {
+    if std::env::var_os("ENARX_BACKEND").map_or(false, |b| b != "kvm") {
+        return;
+    }
+
+    let shim = unstripped_shim_sev();
+    let output = run_test_args(
+        &["--debug-shim", shim.to_str().unwrap()],
+        "symbolize_stderr",
+        0,
+        None,
+        None,
+        None,
+    );
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("TRACE:\nP 0x"), "{}", stderr);
+    assert!(
+        stderr
+            .lines()
+            .any(|l| l.starts_with("Payl: main at ") && l.contains("symbolize_stderr.c:")),
+        "{}",
+        stderr
+    );
+}
+
 #[test]
 #[serial]
 fn read() {
@@ -232,6 +570,311 @@ fn echo() {
     run_test("echo", 0, input.as_slice(), input.as_slice(), None);
 }
 
+#[cfg(feature = "backend-kvm")]
+#[test]
+#[serial]
+fn echo_ring() {
+    if std::env::var_os("ENARX_BACKEND").map_or(false, |b| b != "kvm") {
+        return;
+    }
+
+    let mut input: Vec<u8> = Vec::with_capacity(2 * 1024 * 1024);
+
+    for i in 0..input.capacity() {
+        input.push(i as _);
+    }
+    run_test_args(
+        &["--ring"],
+        "echo",
+        0,
+        input.as_slice(),
+        input.as_slice(),
+        None,
+    );
+}
+
+/// The hostcalls serviced by the ring are traced like all others.
+#[cfg(feature = "backend-kvm")]
+#[test]
+#[serial]
+fn echo_ring_trace() {
+    if std::env::var_os("ENARX_BACKEND").map_or(false, |b| b != "kvm") {
+        return;
+    }
+
+    const INPUT: &[u8] = b"hello, world";
+
+    let tmpdir = TempDir::new("ring_trace").unwrap();
+    let trace = tmpdir.path().join("trace.jsonl");
+
+    run_test_args(
+        &["--ring", "--trace-file", trace.to_str().unwrap()],
+        "echo",
+        0,
+        INPUT,
+        INPUT,
+        None,
+    );
+
+    let trace = fs::read_to_string(trace).unwrap();
+    assert!(trace
+        .lines()
+        .any(|l| l.contains("\"name\":\"read\"") && l.contains("\"ret\":[12,")));
+    assert!(trace
+        .lines()
+        .any(|l| l.contains("\"name\":\"write\"") && l.contains("\"ret\":[12,")));
+}
+
+/// Compares the hostcall ring with exiting the keep for every hostcall.
+///
+/// Run with `cargo test -- --ignored --nocapture echo_ring_bench`.
+#[cfg(feature = "backend-kvm")]
+#[test]
+#[serial]
+#[ignore]
+fn echo_ring_bench() {
+    const ROUNDS: u32 = 10;
+
+    let mut input: Vec<u8> = Vec::with_capacity(16 * 1024 * 1024);
+
+    for i in 0..input.capacity() {
+        input.push(i as _);
+    }
+
+    for args in [&[][..], &["--ring"][..]] {
+        let start = std::time::Instant::now();
+        for _ in 0..ROUNDS {
+            run_test_args(args, "echo", 0, input.as_slice(), input.as_slice(), None);
+        }
+        eprintln!("echo {:?}: {:?} per run", args, start.elapsed() / ROUNDS);
+    }
+}
+
+/// Sizes the sallyport segment of KVM keeps with `--sallyport-blocks`.
+#[cfg(feature = "backend-kvm")]
+#[test]
+#[serial]
+fn sallyport_blocks() {
+    if std::env::var_os("ENARX_BACKEND").map_or(false, |b| b != "kvm") {
+        return;
+    }
+
+    const INPUT: &[u8] = b"hello, world";
+
+    // A single block is enough without debug output, 29 fill the sallyport segment.
+    for blocks in ["1", "29"] {
+        run_test_args(
+            &["--sallyport-blocks", blocks],
+            "echo",
+            0,
+            INPUT,
+            INPUT,
+            None,
+        );
+        run_test_args(
+            &["--ring", "--sallyport-blocks", blocks],
+            "echo",
+            0,
+            INPUT,
+            INPUT,
+            None,
+        );
+    }
+
+    for (blocks, error) in [("0", "at least one"), ("30", "room for 29 blocks")] {
+        let output = run_test_args(
+            &["--sallyport-blocks", blocks],
+            "exit_zero",
+            1,
+            None,
+            None,
+            None,
+        );
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains(error), "{}", stderr);
+    }
+}
+
+/// A minimal GDB client of the remote serial protocol
+#[cfg(feature = "backend-kvm")]
+struct Gdb(TcpStream);
+
+#[cfg(feature = "backend-kvm")]
+impl Gdb {
+    /// Run the test binary `bin` in a keep with the GDB stub and connect to it
+    fn attach(bin: &str) -> (std::process::Child, Self) {
+        let port = std::net::TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let addr = format!("127.0.0.1:{}", port);
+
+        let child = Command::new(KEEP_BIN)
+            .current_dir(CRATE)
+            .args(&["exec", "--gdb", &addr])
+            .arg(Path::new(OUT_DIR).join(TEST_BINS_OUT).join(bin))
+            .stdin(Stdio::null())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .unwrap();
+
+        let stream = (0..100)
+            .find_map(|_| {
+                TcpStream::connect(&addr)
+                    .map_err(|_| thread::sleep(Duration::from_millis(100)))
+                    .ok()
+            })
+            .expect("failed to connect to the GDB stub");
+
+        (child, Self(stream))
+    }
+
+    /// Send the `packet` without waiting for the reply
+    fn send(&mut self, packet: &str) {
+        let checksum = packet.bytes().fold(0u8, |sum, b| sum.wrapping_add(b));
+        write!(self.0, "${}#{:02x}", packet, checksum).unwrap();
+    }
+
+    /// Receive a packet and acknowledge it
+    fn recv(&mut self) -> String {
+        let mut byte = [0u8];
+
+        // Skip the acknowledgements
+        while byte[0] != b'$' {
+            self.0.read_exact(&mut byte).unwrap();
+        }
+
+        let mut data = Vec::new();
+        loop {
+            self.0.read_exact(&mut byte).unwrap();
+            match byte[0] {
+                b'#' => break,
+                byte => data.push(byte),
+            }
+        }
+
+        let mut checksum = [0u8; 2];
+        self.0.read_exact(&mut checksum).unwrap();
+        self.0.write_all(b"+").unwrap();
+
+        String::from_utf8(data).unwrap()
+    }
+
+    /// Send the `packet` and return the reply
+    fn request(&mut self, packet: &str) -> String {
+        self.send(packet);
+        self.recv()
+    }
+}
+
+/// GDB reads the registers and the memory of the keep and interrupts it with Ctrl-C.
+#[cfg(feature = "backend-kvm")]
+#[test]
+#[serial]
+fn gdb() {
+    if std::env::var_os("ENARX_BACKEND").map_or(false, |b| b != "kvm") {
+        return;
+    }
+
+    let (child, mut gdb) = Gdb::attach("spin");
+
+    assert!(gdb.request("qSupported:swbreak+").contains("PacketSize="));
+    assert_eq!(gdb.request("?"), "S05");
+    assert_eq!(gdb.request("vMustReplyEmpty"), "");
+
+    // The core registers, `eflags` and the six segment registers
+    let regs = gdb.request("g");
+    assert_eq!(regs.len(), (17 * 8 + 4 + 6 * 4) * 2, "{}", regs);
+    assert!(regs.bytes().all(|b| b.is_ascii_hexdigit()), "{}", regs);
+
+    let rip = u64::from_str_radix(&regs[16 * 16..17 * 16], 16)
+        .unwrap()
+        .swap_bytes();
+    let code = gdb.request(&format!("m{:x},4", rip));
+    assert_eq!(code.len(), 8, "{}", code);
+
+    // Malformed packets are refused.
+    assert_eq!(gdb.request("mzz,4"), "E01");
+    assert_eq!(gdb.request(&format!("m{:x}", rip)), "E01");
+    assert_eq!(gdb.request("Gzz"), "E01");
+    assert_eq!(gdb.request(&format!("M{:x},2:00", rip)), "E01");
+
+    // The payload spins without exits, until the interrupt kicks the vCPU.
+    gdb.send("c");
+    thread::sleep(Duration::from_millis(500));
+    gdb.0.write_all(&[0x03]).unwrap();
+    assert_eq!(gdb.recv(), "S02");
+    assert_eq!(gdb.request("?"), "S02");
+
+    gdb.send("k");
+
+    let output = child
+        .with_output_timeout(Duration::from_secs(TIMEOUT_SECS))
+        .terminating()
+        .wait()
+        .unwrap()
+        .expect("the keep was not killed by GDB");
+    assert_eq!(output.status.code(), Some(1));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("killed by GDB"), "{}", stderr);
+}
+
+/// A shutdown in `_enarx_asm_triple_fault` of the shim is reported as an attack.
+#[cfg(feature = "backend-kvm")]
+#[test]
+#[serial]
+fn attack() {
+    if std::env::var_os("ENARX_BACKEND").map_or(false, |b| b != "kvm") {
+        return;
+    }
+
+    let shim = unstripped_shim_sev();
+    let syscall = function(&shim, |name| name.contains("syscall_rust")) + SHIM_VIRT_OFFSET;
+    let fault = function(&shim, |name| name.contains("_enarx_asm_triple_fault")) + SHIM_VIRT_OFFSET;
+
+    let (child, mut gdb) = Gdb::attach("spin_syscall");
+    assert_eq!(gdb.request("?"), "S05");
+
+    // Stop the payload with paging enabled and break in the shim on its stack.
+    gdb.send("c");
+    thread::sleep(Duration::from_millis(500));
+    gdb.0.write_all(&[0x03]).unwrap();
+    assert_eq!(gdb.recv(), "S02");
+
+    assert_eq!(gdb.request(&format!("Z0,{:x},1", syscall)), "OK");
+    assert_eq!(gdb.request("c"), "S05");
+    assert_eq!(gdb.request(&format!("z0,{:x},1", syscall)), "OK");
+
+    // Jump to the shutdown of the shim, as if it detected an attack.
+    let regs = gdb.request("g");
+    let rip: String = fault
+        .to_le_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let regs = format!("{}{}{}", &regs[..16 * 16], rip, &regs[17 * 16..]);
+    assert_eq!(gdb.request(&format!("G{}", regs)), "OK");
+    gdb.send("c");
+
+    let output = child
+        .with_output_timeout(Duration::from_secs(TIMEOUT_SECS))
+        .terminating()
+        .wait()
+        .unwrap()
+        .expect("the keep did not shut down");
+    assert_eq!(output.status.code(), Some(1));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Keep fault: The shim detected an attack"),
+        "{}",
+        stderr
+    );
+}
+
 #[test]
 #[serial]
 fn uname() {
@@ -324,6 +967,388 @@ fn sgx_get_att_quote_size() {
     run_test("sgx_get_att_quote_size", 0, None, None, None);
 }
 
+#[cfg(feature = "backend-sgx")]
+#[test]
+#[serial]
+fn sgx_get_key() {
+    run_test("sgx_get_key", 0, None, None, None);
+}
+
+#[cfg(feature = "backend-sgx")]
+#[test]
+#[serial]
+fn sgx_divide_zero() {
+    if std::env::var_os("ENARX_BACKEND").map_or(false, |b| b != "sgx") {
+        return;
+    }
+
+    // Killed by `SIGFPE`
+    let output = run_test("divide_zero", 128 + libc::SIGFPE, None, None, None);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("divide error"), "{}", stderr);
+}
+
+#[test]
+#[serial]
+fn null_deref() {
+    // Killed by `SIGSEGV`
+    let output = run_test("null_deref", 128 + libc::SIGSEGV, None, None, None);
+
+    // The SGX loader reports the exception, which ended the keep.
+    if std::env::var_os("ENARX_BACKEND").map_or(false, |b| b == "sgx") {
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("page fault"), "{}", stderr);
+    }
+}
+
+#[test]
+#[serial]
+fn sigaction() {
+    run_test("sigaction", 0, None, None, None);
+}
+
+/// A fault is delivered to the handler with its address, which returns from it.
+#[test]
+#[serial]
+fn sigsegv() {
+    run_test("sigsegv", 42, None, None, None);
+}
+
+/// Run `bin` with `args`, send it `SIGTERM`, once it is ready, and wait for the loader to exit
+fn terminate(bin: &str, args: &[&str]) -> Output {
+    let bin_path = Path::new(CRATE).join(OUT_DIR).join(TEST_BINS_OUT).join(bin);
+
+    let mut child = Command::new(&String::from(KEEP_BIN))
+        .current_dir(CRATE)
+        .arg("exec")
+        .args(args)
+        .arg(bin_path)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap_or_else(|e| panic!("failed to run `{}`: {:#?}", bin, e));
+
+    // Wait for the payload to set up its signal handling.
+    let mut ready = [0u8; 6];
+    child
+        .stdout
+        .as_mut()
+        .unwrap()
+        .read_exact(&mut ready)
+        .unwrap();
+    assert_eq!(&ready, b"ready\n");
+
+    unsafe { libc::kill(child.id() as _, libc::SIGTERM) };
+
+    child
+        .with_output_timeout(Duration::from_secs(TIMEOUT_SECS))
+        .terminating()
+        .wait()
+        .unwrap_or_else(|e| panic!("failed to run `{}`: {:#?}", bin, e))
+        .unwrap_or_else(|| panic!("process `{}` timed out", bin))
+}
+
+#[test]
+#[serial]
+fn sigterm() {
+    // The handler of the payload ran and the payload exited on its own.
+    let output = terminate("sigterm", &[]);
+    assert_eq!(output.status.code(), Some(42));
+}
+
+/// A payload, which never makes a hostcall, gets the signal, too.
+#[cfg(feature = "backend-kvm")]
+#[test]
+#[serial]
+fn sigterm_spin() {
+    if std::env::var_os("ENARX_BACKEND").map_or(false, |b| b != "kvm") {
+        return;
+    }
+
+    let output = terminate("sigterm_spin", &[]);
+    assert_eq!(output.status.code(), Some(42));
+}
+
+/// The loader exits forcibly, if the payload ignores the signal for the grace period.
+#[test]
+#[serial]
+fn sigterm_grace() {
+    let output = terminate("sigterm_blocked", &["--grace", "1"]);
+    assert_eq!(output.status.code(), Some(128 + libc::SIGTERM));
+
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("The keep did not exit within the grace period"),
+        "{}",
+        stderr
+    );
+}
+
+#[test]
+#[serial]
+fn open_dir() {
+    let tmpdir = TempDir::new("open_dir").unwrap();
+    fs::write(tmpdir.path().join("hello.txt"), "hello\n").unwrap();
+    fs::write(tmpdir.path().join("world.txt"), "world\n").unwrap();
+
+    let fd = format!("3={}:ro", tmpdir.path().join("world.txt").display());
+    let fd_dir = format!("4={}:ro", tmpdir.path().display());
+    let dir = format!("/data={}:ro", tmpdir.path().display());
+
+    // The payload tries to change the modes of the read-only files.
+    let modes = || {
+        ["hello.txt", "world.txt", ""].map(|path| {
+            let meta = fs::metadata(tmpdir.path().join(path)).unwrap();
+            meta.permissions().mode() & 0o777
+        })
+    };
+    let before = modes();
+
+    run_test_args(
+        &["--fd", &fd, "--fd", &fd_dir, "--dir", &dir],
+        "open_dir",
+        0,
+        None,
+        &b"hello\nworld\n"[..],
+        None,
+    );
+
+    assert_eq!(modes(), before);
+}
+
+#[test]
+#[serial]
+fn encrypt() {
+    let tmpdir = TempDir::new("encrypt").unwrap();
+    let dir = format!("/data={}:rw", tmpdir.path().display());
+    let secretdir = TempDir::new("encrypt-secret").unwrap();
+    let secret = secretdir.path().join("secret");
+    fs::write(&secret, b"the secret of the keep").unwrap();
+
+    // SGX keeps derive the key from a sealing key, KVM keeps from the secret.
+    let mut args = vec!["--dir", &dir, "--encrypt", "/data"];
+    if std::env::var("ENARX_BACKEND").ok().as_deref() != Some("sgx") {
+        args.extend(&["--secret", secret.to_str().unwrap()]);
+    }
+
+    let output = run_test_args(&args, "encrypt", 0, None, None, None);
+    assert_ne!(output.stdout, b"nokey\n");
+
+    // One block of nonce, ciphertext and tag
+    let stored = fs::read(tmpdir.path().join("secret.txt")).unwrap();
+    assert_eq!(stored.len(), 12 + b"top secret\n".len() + 16);
+    assert!(!stored.windows(6).any(|w| w == b"secret"));
+}
+
+/// The contents of the DER element at the start of `der`, which has to have `tag`, and the rest
+fn der(tag: u8, der: &[u8]) -> (&[u8], &[u8]) {
+    assert_eq!(der[0], tag);
+    let (len, start) = match der[1] {
+        len @ 0..=0x7f => (usize::from(len), 2),
+        0x81 => (usize::from(der[2]), 3),
+        0x82 => (usize::from(u16::from_be_bytes([der[2], der[3]])), 4),
+        n => panic!("unexpected DER length {:#x}", n),
+    };
+
+    der[start..].split_at(len)
+}
+
+/// The type and data of the evidence extension in the certificate `der`
+fn evidence(der: &[u8]) -> (u8, &[u8]) {
+    // 1.3.6.1.4.1.58270.1 without its last arc
+    const OID: &[u8] = &[
+        0x06, 0x0a, 0x2b, 0x06, 0x01, 0x04, 0x01, 0x83, 0xc7, 0x1e, 0x01,
+    ];
+
+    let at = der
+        .windows(OID.len())
+        .position(|w| w == OID)
+        .expect("no evidence in the certificate");
+
+    let (value, _) = self::der(0x04, &der[at + OID.len() + 1..]);
+    let (data, _) = self::der(0x04, value);
+    (der[at + OID.len()], data)
+}
+
+/// HMAC-SHA256 of the concatenated `data`
+fn hmac(key: &[u8], data: &[&[u8]]) -> Vec<u8> {
+    let key = PKey::hmac(key).unwrap();
+    let mut signer = Signer::new(MessageDigest::sha256(), &key).unwrap();
+    for data in data {
+        signer.update(data).unwrap();
+    }
+    signer.sign_to_vec().unwrap()
+}
+
+#[test]
+#[serial]
+fn tls() {
+    const PORT: u16 = 54321;
+    const SECRET: &[u8] = b"the secret of the keep";
+    let mut input: Vec<u8> = Vec::with_capacity(64 * 1024);
+
+    for i in 0..input.capacity() {
+        input.push(i as _);
+    }
+
+    let sgx = std::env::var("ENARX_BACKEND").ok().as_deref() == Some("sgx");
+
+    let handle = thread::spawn(move || {
+        let mut cnt = 0;
+        let stream = loop {
+            match TcpStream::connect(("127.0.0.1", PORT)) {
+                Ok(stream) => break stream,
+                Err(_) if cnt < 100 => {
+                    cnt += 1;
+                    thread::sleep(Duration::from_millis(100))
+                }
+                Err(e) => panic!("failed to connect to the keep: {}", e),
+            }
+        };
+
+        // The certificate of the keep is self-signed, so it is checked by hand.
+        let mut connector = SslConnector::builder(SslMethod::tls()).unwrap();
+        connector.set_verify(SslVerifyMode::NONE);
+        let mut stream = connector.build().connect("localhost", stream).unwrap();
+
+        let cert = stream.ssl().peer_certificate().unwrap();
+        assert!(cert.verify(&cert.public_key().unwrap()).unwrap());
+
+        // The evidence binds the raw Ed25519 key at the end of the `SubjectPublicKeyInfo`.
+        let spki = cert.public_key().unwrap().public_key_to_der().unwrap();
+        let public = &spki[spki.len() - 32..];
+        let der = cert.to_der().unwrap();
+
+        match evidence(&der) {
+            // The `REPORTDATA` of the report in the quote, after its 48 byte header
+            (3, quote) if sgx => {
+                assert_eq!(&quote[48 + 320..][..32], &sha256(public)[..]);
+                assert_eq!(&quote[48 + 352..][..32], &[0; 32]);
+            }
+
+            // HKDF-SHA256 of the injected secret, which the shim sees CBOR encoded
+            (2, mac) if !sgx => {
+                let ikm = [&[0x40 | SECRET.len() as u8], SECRET].concat();
+                let prk = hmac(&[0; 32], &[&ikm]);
+                assert_eq!(mac, &hmac(&prk, &[b"enarx tls evidence", public, &[1]])[..]);
+            }
+
+            (kind, _) => panic!("unexpected evidence type {}", kind),
+        }
+
+        let mut output = vec![0u8; input.len()];
+        for (i, o) in input.chunks(4096).zip(output.chunks_mut(4096)) {
+            stream.write_all(i).unwrap();
+            stream.read_exact(o).unwrap();
+        }
+        assert_eq_slices(&input, &output, "stream output");
+
+        // The keep closes the session, after it got the `close_notify`.
+        stream.shutdown().unwrap();
+        let mut rest = Vec::new();
+        stream.read_to_end(&mut rest).unwrap();
+        assert!(rest.is_empty());
+    });
+
+    let secretdir = TempDir::new("tls-secret").unwrap();
+    let secret = secretdir.path().join("secret");
+    fs::write(&secret, SECRET).unwrap();
+
+    // SGX keeps get a quote, KVM keeps a MAC with a key from the secret.
+    let port = PORT.to_string();
+    let mut args = vec!["--tls", &port, "--attest"];
+    if !sgx {
+        args.extend(&["--secret", secret.to_str().unwrap()]);
+    }
+
+    run_test_args(
+        &args,
+        "tls_echo",
+        0,
+        format!("{}\n", PORT).as_bytes(),
+        None,
+        None,
+    );
+
+    handle.join().unwrap();
+}
+
+#[test]
+#[serial]
+fn poll() {
+    const INPUT: &[u8; 12] = b"hello world\n";
+    run_test("poll", 0, &INPUT[..], &INPUT[..], None);
+}
+
+#[test]
+#[serial]
+fn epoll_dup() {
+    run_test("epoll_dup", 0, None, None, None);
+}
+
+/// Echo data over two connections to an event loop serving them both
+fn run_echo_server(bin: &str, port: u16) {
+    let mut input: Vec<u8> = Vec::with_capacity(64 * 1024);
+
+    for i in 0..input.capacity() {
+        input.push(i as _);
+    }
+
+    let handle = thread::spawn(move || {
+        let connect = || {
+            let mut cnt = 0;
+            loop {
+                match TcpStream::connect(("127.0.0.1", port)) {
+                    Ok(stream) => break stream,
+                    Err(_) if cnt < 100 => {
+                        cnt += 1;
+                        thread::sleep(Duration::from_millis(100))
+                    }
+                    Err(e) => panic!("failed to connect to the keep: {}", e),
+                }
+            }
+        };
+
+        // Both connections are served by the same event loop.
+        let mut streams = [connect(), connect()];
+        let mut outputs = [vec![0u8; input.len()], vec![0u8; input.len()]];
+
+        for (n, i) in input.chunks(4096).enumerate() {
+            for (stream, output) in streams.iter_mut().zip(outputs.iter_mut()) {
+                stream.write_all(i).unwrap();
+                stream
+                    .read_exact(&mut output[n * 4096..][..i.len()])
+                    .unwrap();
+            }
+        }
+
+        for (stream, output) in streams.iter_mut().zip(outputs.iter()) {
+            assert_eq_slices(&input, output, "stream output");
+
+            stream.shutdown(Shutdown::Write).unwrap();
+            let mut rest = Vec::new();
+            stream.read_to_end(&mut rest).unwrap();
+            assert!(rest.is_empty());
+        }
+    });
+
+    run_test(bin, 0, format!("{}\n", port).as_bytes(), None, None);
+
+    handle.join().unwrap();
+}
+
+#[test]
+#[serial]
+fn epoll_echo() {
+    run_echo_server("epoll_echo", 54322);
+}
+
+#[test]
+#[serial]
+fn tokio_echo() {
+    run_echo_server("tokio_echo", 54323);
+}
+
 #[test]
 #[serial]
 fn getuid() {
@@ -372,8 +1397,78 @@ fn memspike() {
     run_test("memspike", 0, None, None, None);
 }
 
+#[cfg(feature = "backend-sgx")]
+#[test]
+#[serial]
+fn sgx_memory() {
+    if std::env::var_os("ENARX_BACKEND").map_or(false, |b| b != "sgx") {
+        return;
+    }
+
+    // A smaller heap than the default still fits the allocation.
+    run_test_args(&["--memory", "64M"], "memspike", 0, None, None, None);
+}
+
+#[cfg(feature = "backend-sgx")]
+#[test]
+#[serial]
+fn sgx_data() {
+    if std::env::var_os("ENARX_BACKEND").map_or(false, |b| b != "sgx") {
+        return;
+    }
+
+    let tmpdir = TempDir::new("sgx_data").unwrap();
+    let path = tmpdir.path().join("data");
+    let data: Vec<u8> = (0..10000u32).map(|i| i as u8).collect();
+    fs::write(&path, &data).unwrap();
+
+    for args in [&[][..], &["--measure-data"][..]] {
+        let args: Vec<_> = ["--data", path.to_str().unwrap()]
+            .iter()
+            .chain(args)
+            .copied()
+            .collect();
+        run_test_args(&args, "data", 0, None, data.as_slice(), None);
+    }
+}
+
+/// With `--edmm`, the heap is added on demand, if the host supports SGX2.
+#[cfg(feature = "backend-sgx")]
+#[test]
+#[serial]
+fn sgx_edmm() {
+    if std::env::var_os("ENARX_BACKEND").map_or(false, |b| b != "sgx") {
+        return;
+    }
+
+    let sgx2 = unsafe { std::arch::x86_64::__cpuid_count(0x12, 0) }.eax & (1 << 1) != 0;
+    let release = fs::read_to_string("/proc/sys/kernel/osrelease").unwrap();
+    let linux6 = release
+        .split('.')
+        .next()
+        .and_then(|major| major.parse::<u32>().ok())
+        .map_or(false, |major| major >= 6);
+
+    if !(sgx2 && linux6) {
+        let output = run_test_args(&["--edmm"], "exit_zero", 1, None, None, None);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        assert!(stderr.contains("EDMM needs SGX2"), "{}", stderr);
+        return;
+    }
+
+    for bin in &["mprotect", "memspike"] {
+        run_test_args(&["--edmm"], bin, 0, None, None, None);
+    }
+}
+
 #[test]
 #[serial]
 fn memory_stress_test() {
     run_test("memory_stress_test", 0, None, None, None);
 }
+
+#[test]
+#[serial]
+fn mprotect() {
+    run_test("mprotect", 0, None, None, None);
+}